@@ -0,0 +1,109 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use vec3::Vec3;
+use ray::Ray;
+
+///
+/// An axis-aligned bounding box.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// A box containing nothing, which no ray hits.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vec3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::new(self.min.x().min(other.min.x()),
+                           self.min.y().min(other.min.y()),
+                           self.min.z().min(other.min.z())),
+            max: Vec3::new(self.max.x().max(other.max.x()),
+                           self.max.y().max(other.max.y()),
+                           self.max.z().max(other.max.z())),
+        }
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let e = self.extent();
+        2.0 * (e.x() * e.y() + e.y() * e.z() + e.z() * e.x())
+    }
+
+    pub fn diagonal(&self) -> f32 {
+        self.extent().length()
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    ///
+    /// The range of the ray's parameter over which it is inside the
+    /// box, clipped to [`t_min`, `t_max`], or None if it misses.
+    ///
+    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let d = r.direction();
+        let inv_d = Vec3::new(1.0 / d.x(), 1.0 / d.y(), 1.0 / d.z());
+        self.hit_inv(&r.origin(), &inv_d, t_min, t_max)
+    }
+
+    /// Like `hit`, for a ray whose direction has already been inverted.
+    pub fn hit_inv(&self, origin: &Vec3, inv_d: &Vec3, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+
+        for axis in 0..3 {
+            let mut near = (self.min.e[axis] - origin.e[axis]) * inv_d.e[axis];
+            let mut far = (self.max.e[axis] - origin.e[axis]) * inv_d.e[axis];
+            if inv_d.e[axis] < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+
+            // Written so that NaNs (a ray in the plane of a face) are ignored
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t1 < t0 {
+                return None;
+            }
+        }
+
+        Some((t0, t1))
+    }
+}
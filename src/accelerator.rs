@@ -0,0 +1,218 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::path::Path;
+
+use ray::Ray;
+use aabb::Aabb;
+use bvh::Bvh;
+use grid::Grid;
+use kdtree::KdTree;
+use hittable::{Hit, Hittable};
+
+// Objects this many times bigger than the typical object are kept
+// out of grids and BVHs (they'd overlap everything); every ray
+// tests them
+const LARGE_OBJECT_FACTOR: f32 = 8.0;
+
+// Scenes this small are fastest to just test object by object
+const LIST_MAX_OBJECTS: usize = 16;
+
+// A grid is only chosen when the objects are of similar size: the
+// 90th percentile size is at most this many times the 10th...
+const GRID_MAX_SIZE_SPREAD: f32 = 4.0;
+
+// ...and spread out evenly enough that the cells holding any objects
+// hold no more than this many on average
+const GRID_MAX_OBJECTS_PER_CELL: f32 = 8.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AcceleratorKind {
+    Auto,
+    List,
+    Grid,
+    Bvh,
+    KdTree,
+}
+
+impl AcceleratorKind {
+    pub fn from_name(name: &str) -> Option<AcceleratorKind> {
+        match name {
+            "auto" => Some(AcceleratorKind::Auto),
+            "none" => Some(AcceleratorKind::List),
+            "grid" => Some(AcceleratorKind::Grid),
+            "bvh" => Some(AcceleratorKind::Bvh),
+            "kdtree" => Some(AcceleratorKind::KdTree),
+            _ => None,
+        }
+    }
+}
+
+///
+/// A structure for finding the objects a ray hits without testing
+/// every object in the scene. `List` does just test every object.
+///
+pub enum Accelerator {
+    List,
+    Grid(Grid),
+    Bvh(Bvh),
+    KdTree(KdTree),
+}
+
+impl Accelerator {
+    ///
+    /// Build the kind of accelerator asked for. BVHs are loaded from
+    /// and saved to `cache`, if it's given, rather than always built.
+    ///
+    pub fn build(kind: AcceleratorKind, objects: &[Box<dyn Hittable+Sync+Send>], cache: Option<&Path>) -> Accelerator {
+        match kind {
+            AcceleratorKind::Auto => Accelerator::choose(objects, cache),
+            AcceleratorKind::List => Accelerator::List,
+            AcceleratorKind::Grid => Accelerator::Grid(Grid::new(objects)),
+            AcceleratorKind::Bvh => Accelerator::Bvh(Bvh::build(objects, cache)),
+            AcceleratorKind::KdTree => Accelerator::KdTree(KdTree::new(objects)),
+        }
+    }
+
+    ///
+    /// Pick an accelerator to suit the scene: a plain list for a few
+    /// objects, a grid for many similar objects spread evenly around,
+    /// and a BVH for everything else.
+    ///
+    fn choose(objects: &[Box<dyn Hittable+Sync+Send>], cache: Option<&Path>) -> Accelerator {
+        if objects.len() <= LIST_MAX_OBJECTS {
+            return Accelerator::List;
+        }
+
+        let (bounded, _) = partition(objects);
+        let mut sizes: Vec<f32> = bounded.iter().map(|&(_, b)| b.diagonal()).collect();
+        if sizes.len() <= LIST_MAX_OBJECTS {
+            return Accelerator::List;
+        }
+        sizes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let small = sizes[sizes.len() / 10];
+        let large = sizes[sizes.len() * 9 / 10];
+
+        if large <= GRID_MAX_SIZE_SPREAD * small {
+            let grid = Grid::new(objects);
+            if grid.objects_per_cell() <= GRID_MAX_OBJECTS_PER_CELL {
+                return Accelerator::Grid(grid);
+            }
+        }
+
+        Accelerator::Bvh(Bvh::build(objects, cache))
+    }
+
+    ///
+    /// Catch up with changes to the objects. `changed` lists objects
+    /// that were replaced; `grown` is true if objects were added.
+    /// BVHs are refitted around replaced objects where possible,
+    /// which is fast but makes the tree a little less efficient each
+    /// time; anything else is rebuilt.
+    ///
+    pub fn update(&mut self, objects: &[Box<dyn Hittable+Sync+Send>], changed: &[usize], grown: bool) {
+        if changed.is_empty() && !grown {
+            return;
+        }
+
+        let rebuilt = match *self {
+            Accelerator::List => return,
+            Accelerator::Grid(_) => Accelerator::Grid(Grid::new(objects)),
+            Accelerator::KdTree(_) => Accelerator::KdTree(KdTree::new(objects)),
+            Accelerator::Bvh(ref mut bvh) => {
+                if !grown && bvh.refit(objects, changed) {
+                    return;
+                }
+                Accelerator::Bvh(Bvh::new(objects))
+            },
+        };
+
+        *self = rebuilt;
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Accelerator::List => "none",
+            Accelerator::Grid(_) => "grid",
+            Accelerator::Bvh(_) => "bvh",
+            Accelerator::KdTree(_) => "kdtree",
+        }
+    }
+
+    pub fn hit<'a>(&self, objects: &'a [Box<dyn Hittable+Sync+Send>], r: &Ray,
+                   t_min: f32, t_max: f32) -> Option<Hit<'a>> {
+        match *self {
+            Accelerator::List => hit_list(objects, r, t_min, t_max),
+            Accelerator::Grid(ref grid) => grid.hit(objects, r, t_min, t_max),
+            Accelerator::Bvh(ref bvh) => bvh.hit(objects, r, t_min, t_max),
+            Accelerator::KdTree(ref tree) => tree.hit(objects, r, t_min, t_max),
+        }
+    }
+}
+
+fn hit_list<'a>(objects: &'a [Box<dyn Hittable+Sync+Send>], r: &Ray,
+                t_min: f32, t_max: f32) -> Option<Hit<'a>> {
+    let mut closest: Option<Hit<'a>> = None;
+    let mut closest_so_far: f32 = t_max;
+
+    for object in objects {
+        if let Some(h) = object.hit(r, t_min, closest_so_far) {
+            closest_so_far = h.t;
+            closest = Some(h);
+        }
+    }
+
+    closest
+}
+
+///
+/// The bounds of the objects that make up the subject of a scene,
+/// leaving out very large ones like the ground (see `partition`).
+/// Empty if there are no such objects.
+///
+pub fn subject_bounds(objects: &[Box<dyn Hittable+Sync+Send>]) -> Aabb {
+    let (bounded, _) = partition(objects);
+    bounded.iter().fold(Aabb::empty(), |b, o| b.union(&o.1))
+}
+
+///
+/// Split the objects into those worth putting in an acceleration
+/// structure, with their bounds, and those every ray should just
+/// test: ones without bounds, and ones much larger than the rest.
+///
+pub fn partition(objects: &[Box<dyn Hittable+Sync+Send>]) -> (Vec<(usize, Aabb)>, Vec<usize>) {
+    let boxes: Vec<Option<Aabb>> = objects.iter().map(|o| o.bounding_box()).collect();
+
+    let mut diagonals: Vec<f32> = boxes.iter()
+        .filter_map(|b| b.and_then(|b| if b.is_empty() { None } else { Some(b.diagonal()) }))
+        .collect();
+    diagonals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let typical = diagonals.get(diagonals.len() / 2).cloned().unwrap_or(0.0);
+
+    let mut bounded: Vec<(usize, Aabb)> = Vec::new();
+    let mut always: Vec<usize> = Vec::new();
+    for (i, b) in boxes.iter().enumerate() {
+        match *b {
+            Some(b) if !b.is_empty() && b.diagonal() <= LARGE_OBJECT_FACTOR * typical => bounded.push((i, b)),
+            _ => always.push(i),
+        }
+    }
+
+    (bounded, always)
+}
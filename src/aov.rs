@@ -0,0 +1,188 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use camera::Camera;
+use hittable::World;
+use ray::Ray;
+use settings::Settings;
+use vec3::Vec3;
+
+// Camera rays per pixel, on a square grid, for the passes that are
+// averaged over the pixel
+const GRID: usize = 4;
+
+///
+/// An arbitrary output variable: an image of something other than
+/// the light reaching the camera, saved alongside the render for
+/// denoising and compositing. Normals and albedo are averaged over
+/// each pixel, like the render, so edges are antialiased the same
+/// way; depth and object ID are taken through the middle of the
+/// pixel, since averaging distances or IDs across an edge gives
+/// something that is in neither object.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aov {
+    /// The surface normal first seen, in world space.
+    Normal,
+    /// The distance to the first surface seen, along the view
+    /// direction, or infinity for the sky.
+    Depth,
+    /// The color of the first surface seen, or the sky's color where
+    /// nothing is.
+    Albedo,
+    /// The stable ID of the first object seen (see ObjectIds).
+    ObjectId,
+}
+
+impl Aov {
+    pub fn from_name(name: &str) -> Option<Aov> {
+        match name {
+            "normal" => Some(Aov::Normal),
+            "depth" => Some(Aov::Depth),
+            "albedo" => Some(Aov::Albedo),
+            "id" => Some(Aov::ObjectId),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The file an AOV is saved to next to the render's `path`:
+    /// "out.png" gets "out_normal.pfm" and so on. Object IDs can't be
+    /// stored as floats exactly, so they go in a PNG, "out_id.png".
+    ///
+    pub fn path(self, path: &Path) -> PathBuf {
+        let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let name = match self {
+            Aov::Normal => "normal.pfm",
+            Aov::Depth => "depth.pfm",
+            Aov::Albedo => "albedo.pfm",
+            Aov::ObjectId => "id.png",
+        };
+        path.with_file_name(format!("{}_{}", stem, name))
+    }
+}
+
+///
+/// Every AOV of an image, bottom row first like the film.
+///
+pub struct AovBuffers {
+    pub width: usize,
+    pub height: usize,
+    pub normal: Vec<Vec3>,
+    pub depth: Vec<f32>,
+    pub albedo: Vec<Vec3>,
+    pub id: Vec<u32>,
+}
+
+impl AovBuffers {
+    /// A float AOV as linear RGB, with depth as a grey.
+    pub fn rgb(&self, aov: Aov) -> Vec<Vec3> {
+        match aov {
+            Aov::Normal => self.normal.clone(),
+            Aov::Albedo => self.albedo.clone(),
+            Aov::Depth => self.depth.iter().map(|&z| Vec3::new(z, z, z)).collect(),
+            Aov::ObjectId => self.id.iter().map(|&id| id_color(id)).collect(),
+        }
+    }
+
+    ///
+    /// Object IDs as 8-bit RGB, top row first. Each object gets the
+    /// flat color made of the low 24 bits of its ID, so it can be
+    /// keyed out exactly, and keeps it from one render to the next.
+    /// Nothing, or the sky, is black.
+    ///
+    pub fn id_rgb8(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(self.id.len() * 3);
+
+        for row in self.id.chunks(self.width).rev() {
+            for id in row {
+                data.extend_from_slice(&id.to_be_bytes()[1..]);
+            }
+        }
+
+        data
+    }
+}
+
+fn id_color(id: u32) -> Vec3 {
+    let [_, r, g, b] = id.to_be_bytes();
+    Vec3::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+}
+
+// What one camera ray sees: the normal, depth, albedo and ID of the
+// surface it hits
+fn trace(world: &World, camera: &Camera, r: &Ray) -> (Vec3, f32, Vec3, u32) {
+    match world.hit(r, 0.001, f32::MAX) {
+        Some(h) => (h.normal, Vec3::dot(&(h.p - r.origin()), &camera.forward()),
+                    h.object.material().albedo(), h.object.id()),
+        None => {
+            let sky = world.background(&r.direction());
+            (Vec3::new(0.0, 0.0, 0.0), f32::INFINITY,
+             Vec3::new(sky.r().min(1.0), sky.g().min(1.0), sky.b().min(1.0)), 0)
+        }
+    }
+}
+
+///
+/// Render every AOV of the image the camera sees. Only camera rays
+/// are traced, so this is much quicker than the render itself.
+///
+pub fn render_aovs(settings: &Settings, world: &World, camera: &Camera) -> AovBuffers {
+    let (width, height) = (settings.width as usize, settings.height as usize);
+    let mut pixels: Vec<(Vec3, f32, Vec3, u32)> = vec![(Vec3::new(0.0, 0.0, 0.0), 0.0, Vec3::new(0.0, 0.0, 0.0), 0);
+                                                       width * height];
+    let rows_per_thread = height.div_ceil(settings.threads as usize).max(1);
+
+    thread::scope(|scope| {
+        for (chunk, rows) in pixels.chunks_mut(rows_per_thread * width).enumerate() {
+            scope.spawn(move || {
+                for (i, pixel) in rows.iter_mut().enumerate() {
+                    let p = chunk * rows_per_thread * width + i;
+                    let (x, y) = ((p % width) as f32, (p / width) as f32);
+                    let ray = |dx: f32, dy: f32| camera.get_ray((x + dx) / width as f32, (y + dy) / height as f32);
+
+                    let (_, depth, _, id) = trace(world, camera, &ray(0.5, 0.5));
+                    let (mut normal, mut albedo) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+                    for s in 0..GRID * GRID {
+                        let dx = ((s % GRID) as f32 + 0.5) / GRID as f32;
+                        let dy = ((s / GRID) as f32 + 0.5) / GRID as f32;
+                        let (n, _, a, _) = trace(world, camera, &ray(dx, dy));
+                        normal += n;
+                        albedo += a;
+                    }
+
+                    let samples = (GRID * GRID) as f32;
+                    *pixel = (normal / samples, depth, albedo / samples, id);
+                }
+            });
+        }
+    });
+
+    AovBuffers {
+        width,
+        height,
+        normal: pixels.iter().map(|p| p.0).collect(),
+        depth: pixels.iter().map(|p| p.1).collect(),
+        albedo: pixels.iter().map(|p| p.2).collect(),
+        id: pixels.iter().map(|p| p.3).collect(),
+    }
+}
@@ -0,0 +1,59 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+///
+/// Saves a render in progress every so often, so that a long render
+/// that crashes or gets killed doesn't lose everything. Each save is
+/// written to a hidden file next to the output first and then renamed
+/// over it, so the output is never left half written.
+///
+pub struct Autosave {
+    interval: Duration,
+    last: Instant,
+}
+
+impl Autosave {
+    /// Save every `interval` seconds.
+    pub fn new(interval: f32) -> Autosave {
+        Autosave { interval: Duration::from_secs_f32(interval), last: Instant::now() }
+    }
+
+    /// True once it's time for the image to be saved again.
+    pub fn due(&self) -> bool {
+        self.last.elapsed() >= self.interval
+    }
+
+    /// Save to `path` with `write`, which is given the file to write.
+    pub fn save<F>(&mut self, path: &Path, write: F) -> io::Result<()>
+        where F: FnOnce(&Path) -> io::Result<()> {
+        self.last = Instant::now();
+
+        // Keep the extension, which picks the format
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let temporary = path.with_file_name(format!(".{}", name));
+        write(&temporary)?;
+        fs::rename(&temporary, path)
+    }
+}
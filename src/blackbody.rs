@@ -0,0 +1,66 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use film;
+use vec3::Vec3;
+
+/// The coolest color temperature, in Kelvin, that colors can be worked out for.
+pub const MIN_KELVIN: f32 = 1667.0;
+
+/// The hottest color temperature, in Kelvin, that colors can be worked out for.
+pub const MAX_KELVIN: f32 = 25000.0;
+
+///
+/// The color of the light a black body glows with at `kelvin`
+/// degrees, as linear RGB with a luminance of 1: deep orange for a
+/// candle around 1900K, warm for a tungsten bulb around 3200K,
+/// roughly white around 6500K and blue beyond that. Colors outside
+/// the RGB gamut, like the reddest temperatures, are clipped.
+///
+/// The chromaticity comes from Kang et al.'s cubic fit to the
+/// Planckian locus, which holds from MIN_KELVIN to MAX_KELVIN.
+///
+pub fn blackbody(kelvin: f32) -> Vec3 {
+    let t = kelvin.clamp(MIN_KELVIN, MAX_KELVIN);
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_038e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_2 * x2 + 2.185_558_3 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_9 * x2 + 2.091_37 * x - 0.167_488_67
+    } else {
+        3.081_758 * x3 - 5.873_387 * x2 + 3.751_13 * x - 0.370_014_83
+    };
+
+    // CIE XYZ with a luminance of 1, then linear sRGB
+    let (cx, cy, cz) = (x / y, 1.0, (1.0 - x - y) / y);
+    let col = Vec3::new((3.2406 * cx - 1.5372 * cy - 0.4986 * cz).max(0.0),
+                        (-0.9689 * cx + 1.8758 * cy + 0.0415 * cz).max(0.0),
+                        (0.0557 * cx - 0.2040 * cy + 1.0570 * cz).max(0.0));
+
+    col / film::luminance(&col)
+}
@@ -0,0 +1,66 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use glare;
+use vec3::Vec3;
+
+// Number of blurs making up the bloom, each twice as wide as the last,
+// so that the glow fades out gradually instead of having a visible
+// edge
+const BLOOM_LEVELS: usize = 4;
+
+///
+/// A soft glow around the bright parts of an image, like the light a
+/// camera's lens and sensor, or an eye, scatter around bright lights
+/// and highlights. The light over a threshold is blurred several times
+/// over, each wider than the last, and added back to the image. It
+/// spreads much further than the glow of `Glare`, but has no spikes.
+///
+pub struct Bloom {
+    strength: f32,
+    threshold: f32,
+    radius: f32,
+}
+
+impl Bloom {
+    ///
+    /// Bloom from everything brighter than `threshold`, with `strength`
+    /// of the light over it spread into the glow. The narrowest blur is
+    /// `radius` times the image's diagonal across.
+    ///
+    pub fn new(strength: f32, threshold: f32, radius: f32) -> Bloom {
+        Bloom { strength, threshold, radius }
+    }
+
+    /// Add bloom to an image of linear RGB, bottom row first.
+    pub fn apply(&self, width: usize, height: usize, data: &mut [Vec3]) {
+        let excess = glare::excess(data, self.threshold);
+        let diagonal = ((width * width + height * height) as f32).sqrt();
+        let share = self.strength / BLOOM_LEVELS as f32;
+
+        for level in 0..BLOOM_LEVELS {
+            let radius = self.radius * diagonal * (1 << level) as f32 / glare::BLUR_PASSES as f32;
+            let glow = glare::blur(&excess, width, height, radius.round().max(1.0) as usize);
+            for (col, g) in data.iter_mut().zip(glow.iter()) {
+                *col += share * *g;
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::sync::Arc;
+
+use sampler::{hash, hash_combine, Sampler};
+
+const MASK_SIZE: usize = 64;
+const SIGMA: f32 = 1.5;
+
+///
+/// A tileable blue-noise dither mask, generated with Ulichney's
+/// void-and-cluster method. Every value in [0, 1) appears exactly
+/// once, and values that are close together are spread far apart
+/// in the mask.
+///
+pub struct BlueNoiseMask {
+    size: usize,
+    values: Vec<f32>,
+}
+
+// Toroidal Gaussian splat energy for a fixed mask size
+struct Energy {
+    size: usize,
+    kernel: Vec<f32>,
+    energy: Vec<f32>,
+}
+
+impl Energy {
+    fn new(size: usize) -> Energy {
+        let mut kernel: Vec<f32> = vec![0.0; size * size];
+
+        for dy in 0..size {
+            for dx in 0..size {
+                let x = dx.min(size - dx) as f32;
+                let y = dy.min(size - dy) as f32;
+                kernel[dy * size + dx] = (-(x * x + y * y) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+
+        Energy { size, kernel, energy: vec![0.0; size * size] }
+    }
+
+    fn splat(&mut self, index: usize, sign: f32) {
+        let (px, py) = (index % self.size, index / self.size);
+
+        for y in 0..self.size {
+            let dy = (y + self.size - py) % self.size;
+            for x in 0..self.size {
+                let dx = (x + self.size - px) % self.size;
+                self.energy[y * self.size + x] += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+    }
+
+    // Highest energy among set pixels, lowest among unset ones
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        self.find(pattern, true, |a, b| a > b)
+    }
+
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        self.find(pattern, false, |a, b| a < b)
+    }
+
+    fn find<F: Fn(f32, f32) -> bool>(&self, pattern: &[bool], set: bool, better: F) -> usize {
+        let mut best: Option<usize> = None;
+
+        for (i, &p) in pattern.iter().enumerate() {
+            if p == set {
+                match best {
+                    Some(b) if !better(self.energy[i], self.energy[b]) => {},
+                    _ => best = Some(i),
+                }
+            }
+        }
+
+        best.unwrap()
+    }
+}
+
+impl BlueNoiseMask {
+    pub fn new() -> BlueNoiseMask {
+        let size = MASK_SIZE;
+        let n = size * size;
+        let mut pattern: Vec<bool> = vec![false; n];
+        let mut energy = Energy::new(size);
+        let mut ranks: Vec<usize> = vec![0; n];
+
+        // Deterministic initial pattern with about 10% of pixels set
+        let mut ones = 0;
+        for (i, p) in pattern.iter_mut().enumerate() {
+            if hash(i as u32).is_multiple_of(10) {
+                *p = true;
+                energy.splat(i, 1.0);
+                ones += 1;
+            }
+        }
+
+        // Relax the initial pattern: move the tightest cluster into the
+        // largest void until that no longer changes anything
+        for _ in 0..n {
+            let cluster = energy.tightest_cluster(&pattern);
+            pattern[cluster] = false;
+            energy.splat(cluster, -1.0);
+
+            let void = energy.largest_void(&pattern);
+            pattern[void] = true;
+            energy.splat(void, 1.0);
+
+            if void == cluster {
+                break;
+            }
+        }
+
+        let initial: Vec<bool> = pattern.clone();
+        let initial_energy: Vec<f32> = energy.energy.clone();
+
+        // Phase 1: rank the initial points by removing clusters
+        let mut rank = ones;
+        while rank > 0 {
+            let cluster = energy.tightest_cluster(&pattern);
+            pattern[cluster] = false;
+            energy.splat(cluster, -1.0);
+            rank -= 1;
+            ranks[cluster] = rank;
+        }
+
+        // Phase 2: fill the remaining pixels, largest void first
+        pattern = initial;
+        energy.energy = initial_energy;
+        for r in ones..n {
+            let void = energy.largest_void(&pattern);
+            pattern[void] = true;
+            energy.splat(void, 1.0);
+            ranks[void] = r;
+        }
+
+        BlueNoiseMask {
+            size,
+            values: ranks.iter().map(|&r| (r as f32 + 0.5) / n as f32).collect(),
+        }
+    }
+
+    pub fn value(&self, x: u32, y: u32) -> f32 {
+        let x = x as usize % self.size;
+        let y = y as usize % self.size;
+        self.values[y * self.size + x]
+    }
+}
+
+impl Default for BlueNoiseMask {
+    fn default() -> BlueNoiseMask {
+        BlueNoiseMask::new()
+    }
+}
+
+///
+/// Wraps another sampler so that every pixel draws the same sample
+/// sequence, shifted per pixel by the blue-noise mask. Each
+/// dimension reads the mask at a different offset, so dimensions
+/// stay decorrelated from one another. With few samples, the
+/// remaining error is spread out as high-frequency noise rather than
+/// clumps.
+///
+pub struct DitheredSampler {
+    inner: Box<dyn Sampler>,
+    mask: Arc<BlueNoiseMask>,
+    x: u32,
+    y: u32,
+    dimension: u32,
+}
+
+impl DitheredSampler {
+    pub fn new(inner: Box<dyn Sampler>, mask: Arc<BlueNoiseMask>) -> DitheredSampler {
+        DitheredSampler { inner, mask, x: 0, y: 0, dimension: 0 }
+    }
+
+    fn dither(&mut self, value: f32) -> f32 {
+        let offset = hash_combine(0x0b1e_4015, self.dimension);
+        let shift = self.mask.value(self.x + (offset & 0xffff), self.y + (offset >> 16));
+        self.dimension += 1;
+
+        let shifted = value + shift;
+        if shifted >= 1.0 {
+            (shifted - 1.0).max(0.0)
+        } else {
+            shifted
+        }
+    }
+}
+
+impl Sampler for DitheredSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.inner.start_sample(0, 0, index);
+        self.x = x;
+        self.y = y;
+        self.dimension = 0;
+    }
+
+    fn start_dimension(&mut self, dimension: u32) {
+        self.inner.start_dimension(dimension);
+        self.dimension = dimension;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let value = self.inner.next_1d();
+        self.dither(value)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let (u, v) = self.inner.next_2d();
+        (self.dither(u), self.dither(v))
+    }
+}
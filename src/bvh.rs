@@ -0,0 +1,615 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::thread;
+
+use vec3::Vec3;
+use ray::Ray;
+use aabb::Aabb;
+use hittable::{Hit, Hittable};
+use spheres::{Spheres, BATCH};
+use accelerator;
+
+// Nodes with this many objects or fewer aren't split any further...
+const MAX_LEAF_OBJECTS: usize = 2;
+
+// ...unless they're all spheres, which are cheap to test a batch at
+// a time
+const MAX_LEAF_SPHERES: usize = BATCH;
+
+// ...or when splitting them isn't worth it by the surface area
+// heuristic, if there are no more than this many
+const MAX_SAH_LEAF_OBJECTS: usize = 4;
+
+// Bins per axis the centroids are sorted into when looking for the
+// best split
+const BINS: usize = 16;
+
+// The cost of visiting a node, relative to testing an object
+const TRAVERSAL_COST: f32 = 1.0;
+
+// Below this depth, nodes are split at the median instead of by the
+// surface area heuristic, which keeps the tree shallow enough for
+// MAX_DEPTH however the objects are arranged
+const SAH_MAX_DEPTH: usize = 24;
+
+// Deep enough for any tree, with median splits past SAH_MAX_DEPTH
+const MAX_DEPTH: usize = 64;
+
+// Nodes with at least this many objects, no deeper than
+// PARALLEL_MAX_DEPTH, have their halves built on separate threads
+const PARALLEL_MIN_OBJECTS: usize = 4096;
+const PARALLEL_MAX_DEPTH: usize = 6;
+
+// Marks objects that aren't in any leaf, and the root's parent
+const NONE: usize = usize::MAX;
+
+// Starts every cached BVH file; the last byte is the version of the
+// layout, and of the builder, since a cached tree is only as good as
+// the builder that made it
+const CACHE_MAGIC: &[u8; 8] = b"RTBVH\0\0\x01";
+
+// A node is a leaf if `count` is nonzero, holding objects
+// `order[first..first + count]`; otherwise its children are at
+// `index + 1` and `first`, and `axis` is the axis it was split on.
+struct Node {
+    bounds: Aabb,
+    first: usize,
+    count: usize,
+    axis: usize,
+}
+
+///
+/// A bounding volume hierarchy: a binary tree of boxes, each holding
+/// the objects inside it, split in two where the surface area
+/// heuristic expects rays to test the fewest objects, until only a
+/// few objects are left, or a batch of spheres. A ray only visits the
+/// boxes it passes through. Copes well with scenes of any size and
+/// layout. The top of the tree is split up between threads, so even
+/// millions of objects build quickly.
+///
+pub struct Bvh {
+    nodes: Vec<Node>,
+    parents: Vec<usize>,
+    order: Vec<usize>,
+    // The objects in `order` that are spheres, in the same order
+    spheres: Spheres,
+    // The leaf holding each object, for refitting
+    leaves: Vec<usize>,
+    // Unbounded and very large objects, which every ray tests
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(objects: &[Box<dyn Hittable+Sync+Send>]) -> Bvh {
+        let (mut items, unbounded) = accelerator::partition(objects);
+
+        let tree = if items.is_empty() {
+            Subtree::empty()
+        } else {
+            Subtree::new(objects, &mut items, 0)
+        };
+
+        Bvh::from_tree(objects, tree, unbounded)
+    }
+
+    fn from_tree(objects: &[Box<dyn Hittable+Sync+Send>], tree: Subtree, unbounded: Vec<usize>) -> Bvh {
+        let mut leaves = vec![NONE; objects.len()];
+        for (index, node) in tree.nodes.iter().enumerate() {
+            for &i in &tree.order[node.first..node.first + node.count] {
+                leaves[i] = index;
+            }
+        }
+        let mut spheres = Spheres::new();
+        for &i in &tree.order {
+            spheres.push(&*objects[i]);
+        }
+
+        Bvh {
+            nodes: tree.nodes,
+            parents: tree.parents,
+            order: tree.order,
+            spheres,
+            leaves,
+            unbounded,
+        }
+    }
+
+    /// A new BVH, or one from `cache` if it's given (see `cached`).
+    pub fn build(objects: &[Box<dyn Hittable+Sync+Send>], cache: Option<&Path>) -> Bvh {
+        match cache {
+            Some(dir) => Bvh::cached(objects, dir),
+            None => Bvh::new(objects),
+        }
+    }
+
+    ///
+    /// The BVH for the objects from the cache in `dir` if it holds
+    /// one, or else a new one, saved there for next time. Trees are
+    /// cached by a hash of the bounds of the objects, which is all a
+    /// tree is built from, so a change to the scene that moves, adds
+    /// or removes any object builds a new one. Problems with the cache
+    /// are reported, and the tree built as usual.
+    ///
+    pub fn cached(objects: &[Box<dyn Hittable+Sync+Send>], dir: &Path) -> Bvh {
+        let key = cache_key(objects);
+        let path = dir.join(format!("{:016x}.bvh", key));
+
+        match Bvh::read(&path, objects, key) {
+            Ok(bvh) => return bvh,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => eprintln!("Ignoring cached BVH {}: {}", path.display(), e),
+        }
+
+        let bvh = Bvh::new(objects);
+        if let Err(e) = bvh.write(&path, key) {
+            eprintln!("Unable to cache BVH in {}: {}", path.display(), e);
+        }
+        bvh
+    }
+
+    fn write(&self, path: &Path, key: u64) -> io::Result<()> {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(CACHE_MAGIC);
+        push_u64(&mut data, key);
+        push_u64(&mut data, self.leaves.len() as u64);
+
+        push_u64(&mut data, self.nodes.len() as u64);
+        for (node, &parent) in self.nodes.iter().zip(&self.parents) {
+            for corner in [node.bounds.min, node.bounds.max] {
+                for axis in 0..3 {
+                    data.extend_from_slice(&corner.e[axis].to_le_bytes());
+                }
+            }
+            for value in [node.first, node.count, node.axis, parent] {
+                push_u64(&mut data, value as u64);
+            }
+        }
+        for list in [&self.order, &self.unbounded] {
+            push_u64(&mut data, list.len() as u64);
+            for &i in list.iter() {
+                push_u64(&mut data, i as u64);
+            }
+        }
+
+        // Written whole and then moved into place, so that other
+        // renders never read half a file
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let partial = path.with_extension(format!("{}.partial", process::id()));
+        fs::write(&partial, &data)?;
+        fs::rename(&partial, path)
+    }
+
+    fn read(path: &Path, objects: &[Box<dyn Hittable+Sync+Send>], key: u64) -> io::Result<Bvh> {
+        let data = fs::read(path)?;
+        let mut reader = CacheReader { data: &data, at: 0 };
+
+        if reader.take(CACHE_MAGIC.len())? != CACHE_MAGIC {
+            return Err(invalid_data("not a BVH cache file of this version"));
+        }
+        if reader.u64()? != key || reader.u64()? != objects.len() as u64 {
+            return Err(invalid_data("made for other objects"));
+        }
+
+        let count = reader.length()?;
+        let mut tree = Subtree::empty();
+        for _ in 0..count {
+            let mut corners = [Vec3::new(0.0, 0.0, 0.0); 2];
+            for corner in corners.iter_mut() {
+                for axis in 0..3 {
+                    corner.e[axis] = f32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+                }
+            }
+            let (first, count, axis, parent) = (reader.usize()?, reader.usize()?, reader.usize()?, reader.usize()?);
+            tree.nodes.push(Node { bounds: Aabb::new(corners[0], corners[1]), first, count, axis });
+            tree.parents.push(parent);
+        }
+        let order = reader.list()?;
+        let unbounded = reader.list()?;
+
+        tree.order = order;
+        if !unbounded.iter().all(|&i| i < objects.len()) || !tree.well_formed(objects.len()) {
+            return Err(invalid_data("damaged"));
+        }
+        Ok(Bvh::from_tree(objects, tree, unbounded))
+    }
+
+    ///
+    /// Update the bounds of the leaves holding the given objects, and
+    /// of every node above them, after the objects were replaced.
+    /// Returns false if that isn't possible, because an object in a
+    /// leaf was replaced by one without bounds; the tree must then be
+    /// rebuilt.
+    ///
+    pub fn refit(&mut self, objects: &[Box<dyn Hittable+Sync+Send>], changed: &[usize]) -> bool {
+        for &i in changed {
+            // Objects outside the tree are tested by every ray anyway
+            let leaf = self.leaves[i];
+            if leaf == NONE {
+                continue;
+            }
+
+            let node = &self.nodes[leaf];
+            let mut bounds = Aabb::empty();
+            for k in node.first..node.first + node.count {
+                let object = &*objects[self.order[k]];
+                match object.bounding_box() {
+                    Some(b) => bounds = bounds.union(&b),
+                    None => return false,
+                }
+                self.spheres.set(k, object);
+            }
+            self.nodes[leaf].bounds = bounds;
+
+            let mut index = self.parents[leaf];
+            while index != NONE {
+                let right = self.nodes[index].first;
+                self.nodes[index].bounds = self.nodes[index + 1].bounds.union(&self.nodes[right].bounds);
+                index = self.parents[index];
+            }
+        }
+
+        true
+    }
+
+    pub fn hit<'a>(&self, objects: &'a [Box<dyn Hittable+Sync+Send>], r: &Ray,
+                   t_min: f32, t_max: f32) -> Option<Hit<'a>> {
+        let mut closest: Option<Hit<'a>> = None;
+        let mut closest_so_far = t_max;
+
+        for &i in &self.unbounded {
+            if let Some(h) = objects[i].hit(r, t_min, closest_so_far) {
+                closest_so_far = h.t;
+                closest = Some(h);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let origin = r.origin();
+        let d = r.direction();
+        let inv_d = Vec3::new(1.0 / d.x(), 1.0 / d.y(), 1.0 / d.z());
+        let mut stack = [0; MAX_DEPTH];
+        let mut depth = 1;
+
+        while depth > 0 {
+            depth -= 1;
+            let index = stack[depth];
+            let node = &self.nodes[index];
+            if node.bounds.hit_inv(&origin, &inv_d, t_min, closest_so_far).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                if let Some((k, t)) = self.spheres.hit(node.first, node.count, r, t_min, closest_so_far) {
+                    closest_so_far = t;
+                    closest = Some(self.spheres.hit_on(k, t, r, &*objects[self.order[k]]));
+                }
+                for k in node.first..node.first + node.count {
+                    if self.spheres.is_sphere(k) {
+                        continue;
+                    }
+                    if let Some(h) = objects[self.order[k]].hit(r, t_min, closest_so_far) {
+                        closest_so_far = h.t;
+                        closest = Some(h);
+                    }
+                }
+            } else if d.e[node.axis] < 0.0 {
+                // Visit the nearer child first, so the farther one can often be skipped
+                stack[depth] = index + 1;
+                stack[depth + 1] = node.first;
+                depth += 2;
+            } else {
+                stack[depth] = node.first;
+                stack[depth + 1] = index + 1;
+                depth += 2;
+            }
+        }
+
+        closest
+    }
+}
+
+// Part of a BVH, laid out like a whole one, built on its own so that
+// it can be built on another thread and then joined to the rest
+struct Subtree {
+    nodes: Vec<Node>,
+    parents: Vec<usize>,
+    order: Vec<usize>,
+}
+
+impl Subtree {
+    // Whether this is a tree the builder could have made over that
+    // many objects, with every node and object in it once, so that a
+    // damaged cache file can't make hits panic or loop
+    fn well_formed(&self, objects: usize) -> bool {
+        let mut seen = vec![false; objects];
+        if !self.order.iter().all(|&i| i < objects && !std::mem::replace(&mut seen[i], true)) {
+            return false;
+        }
+        if self.nodes.is_empty() {
+            return self.order.is_empty();
+        }
+
+        let mut visited = vec![false; self.nodes.len()];
+        let mut stack = vec![(0, NONE, 1)];
+        while let Some((index, parent, depth)) = stack.pop() {
+            if index >= self.nodes.len() || visited[index] || self.parents[index] != parent || depth >= MAX_DEPTH {
+                return false;
+            }
+            visited[index] = true;
+
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                if node.first.checked_add(node.count).is_none_or(|end| end > self.order.len()) {
+                    return false;
+                }
+            } else if node.axis < 3 {
+                stack.push((index + 1, index, depth + 1));
+                stack.push((node.first, index, depth + 1));
+            } else {
+                return false;
+            }
+        }
+
+        visited.iter().all(|&v| v)
+    }
+
+    fn empty() -> Subtree {
+        Subtree { nodes: Vec::new(), parents: Vec::new(), order: Vec::new() }
+    }
+
+    fn new(objects: &[Box<dyn Hittable+Sync+Send>], items: &mut [(usize, Aabb)], depth: usize) -> Subtree {
+        let mut tree = Subtree::empty();
+        tree.build(objects, items, NONE, depth);
+        tree
+    }
+
+    fn build(&mut self, objects: &[Box<dyn Hittable+Sync+Send>], items: &mut [(usize, Aabb)], parent: usize,
+             depth: usize) -> usize {
+        let bounds = items.iter().skip(1).fold(items[0].1, |acc, &(_, b)| acc.union(&b));
+        let index = self.nodes.len();
+        self.nodes.push(Node { bounds, first: self.order.len(), count: 0, axis: 0 });
+        self.parents.push(parent);
+
+        let leaf = items.len() <= MAX_LEAF_OBJECTS || (items.len() <= MAX_LEAF_SPHERES &&
+            items.iter().all(|&(i, _)| objects[i].as_sphere().is_some()));
+        let split = if leaf { None } else { split(items, &bounds, depth) };
+        let (axis, mid) = match split {
+            Some(s) => s,
+            None => {
+                self.order.extend(items.iter().map(|&(i, _)| i));
+                self.nodes[index].count = items.len();
+                return index;
+            },
+        };
+
+        let parallel = items.len() >= PARALLEL_MIN_OBJECTS && depth < PARALLEL_MAX_DEPTH;
+        let (left, right) = items.split_at_mut(mid);
+        let right_index = if parallel {
+            let (left, right) = thread::scope(|s| {
+                let right = s.spawn(|| Subtree::new(objects, right, depth + 1));
+                (Subtree::new(objects, left, depth + 1), right.join().unwrap())
+            });
+            self.append(left, index);
+            self.append(right, index)
+        } else {
+            self.build(objects, left, index, depth + 1);
+            self.build(objects, right, index, depth + 1)
+        };
+
+        self.nodes[index].first = right_index;
+        self.nodes[index].axis = axis;
+        index
+    }
+
+    // Add another subtree as a child of node `parent`, returning the
+    // index of its root
+    fn append(&mut self, other: Subtree, parent: usize) -> usize {
+        let offset = self.nodes.len();
+        let order_offset = self.order.len();
+
+        for (mut node, p) in other.nodes.into_iter().zip(other.parents) {
+            node.first += if node.count > 0 { order_offset } else { offset };
+            self.nodes.push(node);
+            self.parents.push(if p == NONE { parent } else { p + offset });
+        }
+        self.order.extend(other.order);
+
+        offset
+    }
+}
+
+// Choose how to split the items of a node with the given bounds, and
+// sort them into the two halves. Returns the axis split along and the
+// number of items in the first half, or None if the node is better
+// off as a leaf. Splits are placed between bins of the items'
+// centroids, where the surface area heuristic (the chance of a ray
+// passing through the node visiting each half, times the objects in
+// it) is least.
+fn split(items: &mut [(usize, Aabb)], bounds: &Aabb, depth: usize) -> Option<(usize, usize)> {
+    let first = items[0].1.centroid();
+    let centroids = items.iter().fold(Aabb::new(first, first), |acc, &(_, b)| {
+        acc.union(&Aabb::new(b.centroid(), b.centroid()))
+    });
+    let extent = centroids.extent();
+
+    if depth >= SAH_MAX_DEPTH {
+        return Some(median_split(items, &extent));
+    }
+
+    let area = bounds.surface_area().max(f32::MIN_POSITIVE);
+    let scale: Vec3 = Vec3::new(BINS as f32 / extent.x(), BINS as f32 / extent.y(), BINS as f32 / extent.z());
+    let bin_of = |b: &Aabb, axis: usize| {
+        (((b.centroid().e[axis] - centroids.min.e[axis]) * scale.e[axis]) as usize).min(BINS - 1)
+    };
+
+    // The cheapest split so far, as (cost, axis, last bin of the first half)
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        if extent.e[axis] <= 0.0 {
+            continue;
+        }
+
+        let mut counts = [0; BINS];
+        let mut boxes = [Aabb::empty(); BINS];
+        for &(_, b) in items.iter() {
+            let bin = bin_of(&b, axis);
+            counts[bin] += 1;
+            boxes[bin] = boxes[bin].union(&b);
+        }
+
+        // The area times the count of everything after each bin
+        let mut after = [0.0; BINS];
+        let (mut count, mut b) = (0, Aabb::empty());
+        for bin in (1..BINS).rev() {
+            count += counts[bin];
+            b = b.union(&boxes[bin]);
+            after[bin - 1] = if count > 0 { b.surface_area() * count as f32 } else { f32::NAN };
+        }
+
+        let (mut count, mut b) = (0, Aabb::empty());
+        for bin in 0..BINS - 1 {
+            count += counts[bin];
+            b = b.union(&boxes[bin]);
+            if count == 0 || after[bin].is_nan() {
+                continue;
+            }
+            let cost = TRAVERSAL_COST + (b.surface_area() * count as f32 + after[bin]) / area;
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, bin));
+            }
+        }
+    }
+
+    let (cost, axis, last) = match best {
+        Some(b) => b,
+        // Every centroid is in the same place; any split will do
+        None => return Some((0, items.len() / 2)),
+    };
+    if cost >= items.len() as f32 && items.len() <= MAX_SAH_LEAF_OBJECTS {
+        return None;
+    }
+
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if bin_of(&items[i].1, axis) <= last {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    Some((axis, mid))
+}
+
+// A hash of everything a BVH over the objects is built from: the
+// bounds of each object, and whether it's a sphere (FNV-1a)
+fn cache_key(objects: &[Box<dyn Hittable+Sync+Send>]) -> u64 {
+    let add = |hash: u64, value: u32| {
+        value.to_le_bytes().iter().fold(hash, |h, &b| (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3))
+    };
+
+    let mut hash = add(0xcbf2_9ce4_8422_2325, objects.len() as u32);
+    for object in objects {
+        match object.bounding_box() {
+            Some(b) => {
+                for axis in 0..3 {
+                    hash = add(add(hash, b.min.e[axis].to_bits()), b.max.e[axis].to_bits());
+                }
+            },
+            None => hash = add(hash, u32::MAX),
+        }
+        hash = add(hash, object.as_sphere().is_some() as u32);
+    }
+    hash
+}
+
+fn push_u64(data: &mut Vec<u8>, value: u64) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Reads the numbers in a cached BVH file in turn
+struct CacheReader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> CacheReader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.at < n {
+            return Err(invalid_data("cut short"));
+        }
+        self.at += n;
+        Ok(&self.data[self.at - n..self.at])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> io::Result<usize> {
+        Ok(self.u64()? as usize)
+    }
+
+    // A count of things to follow, which can't be more than the bytes left
+    fn length(&mut self) -> io::Result<usize> {
+        let n = self.usize()?;
+        if n > self.data.len() - self.at {
+            return Err(invalid_data("cut short"));
+        }
+        Ok(n)
+    }
+
+    fn list(&mut self) -> io::Result<Vec<usize>> {
+        let n = self.length()?;
+        (0..n).map(|_| self.usize()).collect()
+    }
+}
+
+// Split the items in half at the median centroid, along the axis the
+// centroids spread furthest
+fn median_split(items: &mut [(usize, Aabb)], extent: &Vec3) -> (usize, usize) {
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+        0
+    } else if extent.y() > extent.z() {
+        1
+    } else {
+        2
+    };
+
+    let mid = items.len() / 2;
+    items.select_nth_unstable_by(mid, |a, b| {
+        a.1.centroid().e[axis].partial_cmp(&b.1.centroid().e[axis]).unwrap()
+    });
+
+    (axis, mid)
+}
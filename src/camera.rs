@@ -18,13 +18,104 @@
 
 use ray::Ray;
 use vec3::Vec3;
+use aabb::Aabb;
+use lens::LensSystem;
 use std::f32::consts;
+use std::sync::Arc;
 
+///
+/// How the camera maps points on the film to rays. A perspective
+/// camera sends rays out from a single point; an orthographic one
+/// sends parallel rays, all in the same direction, from across the
+/// film, so objects keep their size however far away they are. A
+/// fisheye camera maps distance from the middle of the film to the
+/// angle away from the view direction, so it can see `fov` degrees
+/// across, even all the way round to behind itself. An
+/// equirectangular camera sees in every direction at once, with
+/// longitude across the film and latitude up it.
+///
+#[derive(Copy, Clone, Debug)]
+pub enum Projection {
+    Perspective,
+    Orthographic { direction: Vec3 },
+    Fisheye { fov: f32, mapping: FisheyeMapping },
+    Equirectangular,
+}
+
+///
+/// How a fisheye lens maps angles to distances on the film.
+/// Equidistant lenses keep angles evenly spaced across the image;
+/// equisolid ones keep areas in proportion to solid angles,
+/// squeezing the edge of the view together.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    Equidistant,
+    Equisolid,
+}
+
+impl FisheyeMapping {
+    pub fn from_name(name: &str) -> Option<FisheyeMapping> {
+        match name {
+            "equidistant" => Some(FisheyeMapping::Equidistant),
+            "equisolid" => Some(FisheyeMapping::Equisolid),
+            _ => None,
+        }
+    }
+
+    // The angle from the view direction seen at distance r from the
+    // middle of the film, where r is 1 at the edge of the field of
+    // view; and back again. Angles are in radians.
+    fn angle(self, r: f32, half_fov: f32) -> f32 {
+        match self {
+            FisheyeMapping::Equidistant => r * half_fov,
+            FisheyeMapping::Equisolid => 2.0 * (r * (half_fov / 2.0).sin()).min(1.0).asin(),
+        }
+    }
+
+    fn radius(self, theta: f32, half_fov: f32) -> f32 {
+        match self {
+            FisheyeMapping::Equidistant => theta / half_fov,
+            FisheyeMapping::Equisolid => (theta / 2.0).sin() / (half_fov / 2.0).sin(),
+        }
+    }
+}
+
+///
+/// A thin lens of the given radius, which brings only one plane of
+/// the scene into sharp focus. Normally that plane faces the camera;
+/// tilting the lens tilts the plane too. The aperture is round, or a
+/// polygon made by the given number of straight blades, turned by
+/// `blade_rotation` radians; out of focus highlights take its shape.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct ThinLens {
+    pub radius: f32,
+    // A point on the plane in focus, and its normal
+    pub focus_point: Vec3,
+    pub focus_normal: Vec3,
+    pub blades: Option<u32>,
+    pub blade_rotation: f32,
+}
+
+///
+/// The lens rays pass through on their way from the film into the
+/// scene: an ideal thin lens, or a system of real lens elements.
+///
+#[derive(Clone, Debug)]
+pub enum Lens {
+    Thin(ThinLens),
+    System(Arc<LensSystem>),
+}
+
+#[derive(Clone)]
 pub struct Camera {
     pub lower_left_corner: Vec3,
     pub horizontal: Vec3,
     pub vertical: Vec3,
     pub origin: Vec3,
+    pub projection: Projection,
+    pub lens: Option<Lens>,
 }
 
 impl Camera {
@@ -42,6 +133,75 @@ impl Camera {
             horizontal: 2.0 * half_width * u,
             vertical: 2.0 * half_height * v,
             origin: lookfrom,
+            projection: Projection::Perspective,
+            lens: None,
+        }
+    }
+
+    ///
+    /// An orthographic camera at `lookfrom`, looking towards `lookat`,
+    /// whose view is `height` units tall.
+    ///
+    pub fn orthographic(lookfrom: Vec3, lookat: Vec3, vup: Vec3, height: f32, aspect: f32) -> Camera {
+        let half_height: f32 = height / 2.0;
+        let half_width: f32 = aspect * half_height;
+
+        let w: Vec3 = Vec3::unit_vector(&(lookfrom - lookat));
+        let u: Vec3 = Vec3::unit_vector(&Vec3::cross(&vup, &w));
+        let v: Vec3 = Vec3::cross(&w, &u);
+
+        Camera {
+            lower_left_corner: lookfrom - half_width*u - half_height*v,
+            horizontal: 2.0 * half_width * u,
+            vertical: 2.0 * half_height * v,
+            origin: lookfrom,
+            projection: Projection::Orthographic { direction: -w },
+            lens: None,
+        }
+    }
+
+    ///
+    /// A fisheye camera at `lookfrom`, looking towards `lookat`. The
+    /// `fov` degree field of view (up to 360) spans the shorter side
+    /// of the frame, so the image circle just fits inside it; the
+    /// corners of a wider frame see further round.
+    ///
+    pub fn fisheye(lookfrom: Vec3, lookat: Vec3, vup: Vec3, fov: f32, mapping: FisheyeMapping, aspect: f32) -> Camera {
+        let (half_width, half_height) = if aspect >= 1.0 { (aspect, 1.0) } else { (1.0, 1.0 / aspect) };
+
+        let w: Vec3 = Vec3::unit_vector(&(lookfrom - lookat));
+        let u: Vec3 = Vec3::unit_vector(&Vec3::cross(&vup, &w));
+        let v: Vec3 = Vec3::cross(&w, &u);
+
+        Camera {
+            lower_left_corner: lookfrom - half_width*u - half_height*v - w,
+            horizontal: 2.0 * half_width * u,
+            vertical: 2.0 * half_height * v,
+            origin: lookfrom,
+            projection: Projection::Fisheye { fov, mapping },
+            lens: None,
+        }
+    }
+
+    ///
+    /// An equirectangular camera at `lookfrom`, for rendering
+    /// environment maps. The middle of the film looks towards
+    /// `lookat`, turned level with the horizon so that `vup` is
+    /// always straight up; the left and right edges meet behind the
+    /// camera. The film should be twice as wide as it is tall.
+    ///
+    pub fn equirectangular(lookfrom: Vec3, lookat: Vec3, vup: Vec3) -> Camera {
+        let v: Vec3 = Vec3::unit_vector(&vup);
+        let u: Vec3 = Vec3::unit_vector(&Vec3::cross(&v, &(lookfrom - lookat)));
+        let w: Vec3 = Vec3::cross(&u, &v);
+
+        Camera {
+            lower_left_corner: lookfrom - u - v - w,
+            horizontal: 2.0 * u,
+            vertical: 2.0 * v,
+            origin: lookfrom,
+            projection: Projection::Equirectangular,
+            lens: None,
         }
     }
 
@@ -51,13 +211,341 @@ impl Camera {
             horizontal: Vec3::new(4.0, 0.0, 0.0),
             vertical: Vec3::new(0.0, 2.0, 0.0),
             origin: Vec3::new(0.0, 0.0, 0.0),
+            projection: Projection::Perspective,
+            lens: None,
         }
     }
 
     pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        return Ray::new(
-            self.origin,
-            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin
-        );
+        match self.projection {
+            Projection::Perspective => Ray::new(
+                self.origin,
+                self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin
+            ),
+            Projection::Orthographic { direction } => Ray::new(
+                self.lower_left_corner + u * self.horizontal + v * self.vertical,
+                direction
+            ),
+            Projection::Fisheye { fov, mapping } => {
+                // The film sits one unit in front of the camera, with
+                // the edge of the field of view one unit from its middle
+                let center: Vec3 = self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical;
+                let offset: Vec3 = self.lower_left_corner + u * self.horizontal + v * self.vertical - center;
+                let r: f32 = offset.length();
+                let theta: f32 = mapping.angle(r, fov.to_radians() / 2.0).min(consts::PI);
+
+                let forward: Vec3 = center - self.origin;
+                let direction = if r > 0.0 {
+                    theta.cos() * forward + theta.sin() * (offset / r)
+                } else {
+                    forward
+                };
+
+                Ray::new(self.origin, direction)
+            },
+            Projection::Equirectangular => {
+                let longitude: f32 = (u - 0.5) * 2.0 * consts::PI;
+                let latitude: f32 = (v - 0.5) * consts::PI;
+
+                let (right, up, forward) = self.basis();
+                let direction: Vec3 = latitude.cos() * (longitude.sin() * right + longitude.cos() * forward)
+                    + latitude.sin() * up;
+
+                Ray::new(self.origin, direction)
+            },
+        }
     }
+
+    ///
+    /// A ray through film coordinates (u, v) that passes through the
+    /// lens at the point picked by `lens`, a pair of numbers in
+    /// [0, 1), and how much the light along it counts for, which a
+    /// system of lens elements makes less towards the edges of the
+    /// frame. None if the lens blocks the ray. Without a lens this is
+    /// the same as `get_ray`.
+    ///
+    pub fn get_lens_ray(&self, u: f32, v: f32, lens: (f32, f32)) -> Option<(Ray, f32)> {
+        let pinhole: Ray = self.get_ray(u, v);
+        let thin_lens = match self.lens {
+            Some(Lens::Thin(thin_lens)) => thin_lens,
+            Some(Lens::System(ref system)) => {
+                let (ray, weight) = system.ray(u - 0.5, v - 0.5, lens)?;
+                let (o, d) = (ray.origin(), ray.direction());
+                let (right, up, forward) = (Vec3::unit_vector(&self.horizontal), Vec3::unit_vector(&self.vertical),
+                                            self.forward());
+
+                return Some((Ray::new(self.origin + o.x() * right + o.y() * up + o.z() * forward,
+                                      d.x() * right + d.y() * up + d.z() * forward), weight));
+            },
+            None => return Some((pinhole, 1.0)),
+        };
+
+        let (dx, dy) = match thin_lens.blades {
+            Some(blades) => polygon(lens, blades, thin_lens.blade_rotation),
+            None => concentric_disk(lens),
+        };
+        let origin: Vec3 = pinhole.origin() + thin_lens.radius * (dx * Vec3::unit_vector(&self.horizontal)
+                                                                 + dy * Vec3::unit_vector(&self.vertical));
+
+        // Rays running parallel to the plane in focus, or away from
+        // it, are in focus at infinity
+        let direction: Vec3 = pinhole.direction();
+        let facing: f32 = Vec3::dot(&direction, &thin_lens.focus_normal);
+        let t: f32 = Vec3::dot(&(thin_lens.focus_point - pinhole.origin()), &thin_lens.focus_normal) / facing;
+
+        if facing > 0.0 && t > 0.0 {
+            Some((Ray::new(origin, pinhole.point_at_parameter(t) - origin), 1.0))
+        } else {
+            Some((Ray::new(origin, direction), 1.0))
+        }
+    }
+
+    // Unit vectors to the right, up and forward of an equirectangular
+    // camera, whose film is two units square and one unit in front.
+    fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        let center: Vec3 = self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical;
+        (self.horizontal / 2.0, self.vertical / 2.0, center - self.origin)
+    }
+
+    ///
+    /// This camera with its lens shifted across the film, by `x` times
+    /// the width of the frame and `y` times its height. Shifting moves
+    /// the view without turning the camera, so a camera kept level can
+    /// look up at a building while its verticals stay parallel.
+    ///
+    pub fn shifted(&self, x: f32, y: f32) -> Camera {
+        Camera {
+            lower_left_corner: self.lower_left_corner + x * self.horizontal + y * self.vertical,
+            ..self.clone()
+        }
+    }
+
+    ///
+    /// This camera with a thin lens `aperture` units across, focused
+    /// `focus_distance` units ahead. Tilting the lens by `tilt`
+    /// degrees swings the top of the plane in focus away from the
+    /// camera (or towards it, if negative), e.g. to keep the ground
+    /// in focus from near to far when looking down on it.
+    ///
+    pub fn with_lens(&self, aperture: f32, focus_distance: f32, tilt: f32) -> Camera {
+        let forward: Vec3 = self.forward();
+        let up: Vec3 = Vec3::unit_vector(&self.vertical);
+        let tilt: f32 = tilt.to_radians();
+
+        Camera {
+            lens: Some(Lens::Thin(ThinLens {
+                radius: aperture / 2.0,
+                focus_point: self.origin + focus_distance * forward,
+                focus_normal: tilt.cos() * forward - tilt.sin() * up,
+                blades: None,
+                blade_rotation: 0.0,
+            })),
+            ..self.clone()
+        }
+    }
+
+    ///
+    /// This camera with the aperture of its thin lens made of
+    /// `blades` straight blades, turned `rotation` degrees
+    /// anticlockwise from having a corner at the top. Without a thin
+    /// lens this is the same camera.
+    ///
+    pub fn with_blades(&self, blades: u32, rotation: f32) -> Camera {
+        let lens = match self.lens {
+            Some(Lens::Thin(thin_lens)) => Some(Lens::Thin(ThinLens {
+                blades: Some(blades),
+                blade_rotation: rotation.to_radians(),
+                ..thin_lens
+            })),
+            ref other => other.clone(),
+        };
+
+        Camera {
+            lens,
+            ..self.clone()
+        }
+    }
+
+    ///
+    /// This camera looking through a system of lens elements, focused
+    /// `focus_distance` units ahead. The lens, rather than the
+    /// camera's field of view, decides how much of the scene is seen.
+    ///
+    pub fn with_lens_system(&self, system: &LensSystem, focus_distance: f32) -> Result<Camera, String> {
+        let system = system.focused(focus_distance, self.aspect())?;
+
+        Ok(Camera {
+            lens: Some(Lens::System(Arc::new(system))),
+            ..self.clone()
+        })
+    }
+
+    ///
+    /// One eye of a stereo pair: this camera moved `offset` units to
+    /// the right (to the left, if negative), still looking the same
+    /// way, with its film shifted so that things `convergence` units
+    /// ahead line up in both eyes. Anything nearer then appears in
+    /// front of the screen, and anything further away behind it.
+    ///
+    pub fn stereo_eye(&self, offset: f32, convergence: f32) -> Camera {
+        let right: Vec3 = Vec3::unit_vector(&self.horizontal);
+
+        Camera {
+            lower_left_corner: self.lower_left_corner + offset * right - (offset / convergence) * right,
+            origin: self.origin + offset * right,
+            ..self.clone()
+        }
+    }
+
+    /// The direction the camera is looking in.
+    pub fn forward(&self) -> Vec3 {
+        -Vec3::unit_vector(&Vec3::cross(&self.horizontal, &self.vertical))
+    }
+
+    /// Vertical field of view, in degrees.
+    pub fn vfov(&self) -> f32 {
+        2.0 * (self.vertical.length() / 2.0).atan() * 180.0 / consts::PI
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.horizontal.length() / self.vertical.length()
+    }
+
+    ///
+    /// The film coordinates (u, v) that a ray leaving a perspective
+    /// camera in the given direction passes through, or None if the
+    /// direction points behind the camera. Coordinates outside [0, 1]
+    /// lie outside the frame.
+    ///
+    pub fn project(&self, direction: &Vec3) -> Option<(f32, f32)> {
+        let distance: f32 = Vec3::dot(direction, &self.forward());
+        if distance <= 0.0 {
+            return None;
+        }
+
+        let q: Vec3 = *direction / distance - (self.lower_left_corner - self.origin);
+        let u: f32 = Vec3::dot(&q, &self.horizontal) / self.horizontal.squared_length();
+        let v: f32 = Vec3::dot(&q, &self.vertical) / self.vertical.squared_length();
+
+        Some((u, v))
+    }
+
+    /// The film coordinates (u, v) at which a point appears, or None
+    /// if it's behind the camera.
+    pub fn project_point(&self, p: &Vec3) -> Option<(f32, f32)> {
+        match self.projection {
+            Projection::Perspective => self.project(&(*p - self.origin)),
+            Projection::Orthographic { direction } => {
+                if Vec3::dot(&(*p - self.origin), &direction) <= 0.0 {
+                    return None;
+                }
+
+                let q: Vec3 = *p - self.lower_left_corner;
+                Some((Vec3::dot(&q, &self.horizontal) / self.horizontal.squared_length(),
+                      Vec3::dot(&q, &self.vertical) / self.vertical.squared_length()))
+            },
+            Projection::Fisheye { fov, mapping } => {
+                let center: Vec3 = self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical;
+                let forward: Vec3 = center - self.origin;
+                let direction: Vec3 = Vec3::unit_vector(&(*p - self.origin));
+
+                let cos_theta: f32 = Vec3::dot(&direction, &forward).clamp(-1.0, 1.0);
+                let sideways: Vec3 = direction - cos_theta * forward;
+                let r: f32 = mapping.radius(cos_theta.acos(), fov.to_radians() / 2.0);
+
+                let mut q: Vec3 = center - self.lower_left_corner;
+                if sideways.length() > 0.0 {
+                    q += r * Vec3::unit_vector(&sideways);
+                }
+
+                Some((Vec3::dot(&q, &self.horizontal) / self.horizontal.squared_length(),
+                      Vec3::dot(&q, &self.vertical) / self.vertical.squared_length()))
+            },
+            Projection::Equirectangular => {
+                let (right, up, forward) = self.basis();
+                let direction: Vec3 = Vec3::unit_vector(&(*p - self.origin));
+
+                let longitude: f32 = Vec3::dot(&direction, &right).atan2(Vec3::dot(&direction, &forward));
+                let latitude: f32 = Vec3::dot(&direction, &up).clamp(-1.0, 1.0).asin();
+
+                Some((longitude / (2.0 * consts::PI) + 0.5, latitude / consts::PI + 0.5))
+            },
+        }
+    }
+
+    ///
+    /// The part of the frame, as (u0, v0, u1, v1) film coordinates,
+    /// covered by a box; None if the box is out of view. A box that
+    /// reaches behind the camera may cover any part of the frame, as
+    /// may any box seen through a fisheye or equirectangular camera,
+    /// or a system of lens elements, which bend its edges.
+    ///
+    pub fn screen_bounds(&self, bounds: &Aabb) -> Option<(f32, f32, f32, f32)> {
+        match (self.projection, &self.lens) {
+            (Projection::Fisheye { .. }, _) | (Projection::Equirectangular, _)
+                | (_, &Some(Lens::System(_))) => return Some((0.0, 0.0, 1.0, 1.0)),
+            _ => {},
+        }
+
+        let (mut u0, mut v0, mut u1, mut v1) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+
+        for corner in 0..8 {
+            let p = Vec3::new(if corner & 1 == 0 { bounds.min.x() } else { bounds.max.x() },
+                              if corner & 2 == 0 { bounds.min.y() } else { bounds.max.y() },
+                              if corner & 4 == 0 { bounds.min.z() } else { bounds.max.z() });
+
+            match self.project_point(&p) {
+                Some((u, v)) => {
+                    u0 = u0.min(u);
+                    v0 = v0.min(v);
+                    u1 = u1.max(u);
+                    v1 = v1.max(v);
+                },
+                None => return Some((0.0, 0.0, 1.0, 1.0)),
+            }
+        }
+
+        if u1 < 0.0 || v1 < 0.0 || u0 > 1.0 || v0 > 1.0 {
+            None
+        } else {
+            Some((u0.max(0.0), v0.max(0.0), u1.min(1.0), v1.min(1.0)))
+        }
+    }
+}
+
+// Map a point in the unit square onto the unit disk, keeping points
+// that are evenly spread over the square evenly spread over the disk
+fn concentric_disk((u, v): (f32, f32)) -> (f32, f32) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, consts::FRAC_PI_2 - consts::FRAC_PI_4 * (a / b))
+    };
+
+    (r * theta.cos(), r * theta.sin())
+}
+
+// Map a point in the unit square onto a regular polygon with the given
+// number of sides, its corners on the unit circle and the first one
+// `rotation` radians anticlockwise from straight up, keeping points
+// evenly spread. The polygon is cut into triangles meeting in the
+// middle, one of which is picked by `u`.
+fn polygon((u, v): (f32, f32), sides: u32, rotation: f32) -> (f32, f32) {
+    let position = u * sides as f32;
+    let side = (position as u32).min(sides - 1);
+    let t = (position - side as f32).sqrt();
+
+    let corner = |k: u32| {
+        let angle = consts::FRAC_PI_2 + rotation + 2.0 * consts::PI * k as f32 / sides as f32;
+        (angle.cos(), angle.sin())
+    };
+    let (a, b) = (corner(side), corner(side + 1));
+
+    (t * ((1.0 - v) * a.0 + v * b.0), t * ((1.0 - v) * a.1 + v * b.1))
 }
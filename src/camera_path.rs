@@ -0,0 +1,179 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::fs;
+use std::ops::{Add, Mul, Sub};
+use std::path::Path;
+
+use vec3::Vec3;
+
+///
+/// Where the camera is at one moment of a flythrough: its position,
+/// the point it looks at, and its vertical field of view in degrees.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vfov: f32,
+}
+
+///
+/// A camera moving smoothly through a list of keyframes. Between
+/// keyframes the position, look-at point and field of view follow
+/// Catmull-Rom splines, which pass through every keyframe without
+/// the jolts in speed that straight lines between them would give.
+///
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    keys: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    ///
+    /// Read a camera path: one keyframe per line, each given as its
+    /// time in seconds, the camera's position, the point it looks at,
+    /// and its vertical field of view. Blank lines and anything after
+    /// a `#` are ignored.
+    ///
+    /// ```text
+    /// # time  lookfrom        lookat        vfov
+    /// 0       -2.0 2.0 1.0    0.0 0.0 -1.0  50
+    /// 2.5     2.0 1.0 1.0     0.0 0.0 -1.0  40
+    /// ```
+    ///
+    pub fn load(path: &Path) -> Result<CameraPath, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let keys = parse_keyframes(&text).map_err(|e| format!("{}:{}", path.display(), e))?;
+        CameraPath::new(keys).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// A path through the given keyframes, whose times must increase.
+    pub fn new(keys: Vec<Keyframe>) -> Result<CameraPath, String> {
+        if keys.is_empty() {
+            return Err("the path has no keyframes".to_string());
+        }
+
+        if keys.windows(2).any(|k| k[1].time <= k[0].time) {
+            return Err("keyframe times must increase".to_string());
+        }
+
+        Ok(CameraPath { keys })
+    }
+
+    pub fn start(&self) -> f32 {
+        self.keys[0].time
+    }
+
+    pub fn end(&self) -> f32 {
+        self.keys[self.keys.len() - 1].time
+    }
+
+    /// The number of frames needed to show the whole path at `fps`
+    /// frames per second, counting both its first and last moments.
+    pub fn frames(&self, fps: f32) -> u32 {
+        ((self.end() - self.start()) * fps).round() as u32 + 1
+    }
+
+    ///
+    /// Where the camera is at `time`. Before the first keyframe and
+    /// after the last, the camera stays where those keyframes put it.
+    ///
+    pub fn at(&self, time: f32) -> Keyframe {
+        let last = self.keys.len() - 1;
+        if time <= self.start() {
+            return self.keys[0];
+        }
+        if time >= self.end() {
+            return self.keys[last];
+        }
+
+        let i = self.keys.iter().rposition(|k| k.time <= time).unwrap();
+        let (k0, k1) = (&self.keys[i], &self.keys[i + 1]);
+        let s = (time - k0.time) / (k1.time - k0.time);
+
+        Keyframe {
+            time,
+            lookfrom: self.spline(i, s, |k| k.lookfrom),
+            lookat: self.spline(i, s, |k| k.lookat),
+            vfov: self.spline(i, s, |k| k.vfov),
+        }
+    }
+
+    // A value taken from each keyframe, interpolated a fraction `s` of
+    // the way from keyframe `i` to the next with a cubic Hermite curve.
+    // Its slope at each keyframe is that of the line between the
+    // keyframes either side, which makes the curve a Catmull-Rom spline
+    // even when the keyframes aren't evenly spaced in time.
+    fn spline<T, F>(&self, i: usize, s: f32, value: F) -> T
+        where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+              F: Fn(&Keyframe) -> T
+    {
+        let dt = self.keys[i + 1].time - self.keys[i].time;
+        let m0 = self.slope(i, &value) * dt;
+        let m1 = self.slope(i + 1, &value) * dt;
+        let p0 = value(&self.keys[i]);
+        let p1 = value(&self.keys[i + 1]);
+
+        let s2 = s * s;
+        let s3 = s2 * s;
+        p0 * (2.0 * s3 - 3.0 * s2 + 1.0) + m0 * (s3 - 2.0 * s2 + s)
+            + p1 * (3.0 * s2 - 2.0 * s3) + m1 * (s3 - s2)
+    }
+
+    // The rate of change of a value at keyframe `i`, from its
+    // neighbours, or from the one neighbour of the first and last
+    fn slope<T, F>(&self, i: usize, value: &F) -> T
+        where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+              F: Fn(&Keyframe) -> T
+    {
+        let a = &self.keys[if i > 0 { i - 1 } else { i }];
+        let b = &self.keys[(i + 1).min(self.keys.len() - 1)];
+        (value(b) - value(a)) * (1.0 / (b.time - a.time))
+    }
+}
+
+fn parse_keyframes(text: &str) -> Result<Vec<Keyframe>, String> {
+    let mut keys: Vec<Keyframe> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let fields: Result<Vec<f32>, _> = line.split_whitespace().map(|f| f.parse::<f32>()).collect();
+
+        let key = match fields {
+            Ok(ref f) if f.is_empty() => continue,
+            Ok(ref f) if f.len() == 8 => Keyframe {
+                time: f[0],
+                lookfrom: Vec3::new(f[1], f[2], f[3]),
+                lookat: Vec3::new(f[4], f[5], f[6]),
+                vfov: f[7],
+            },
+            _ => return Err(format!("{}: expected time, lookfrom, lookat and vfov", n + 1)),
+        };
+
+        if key.vfov <= 0.0 || key.vfov >= 180.0 {
+            return Err(format!("{}: vfov must be between 0 and 180 degrees", n + 1));
+        }
+
+        keys.push(key);
+    }
+
+    Ok(keys)
+}
@@ -0,0 +1,164 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::f32::consts;
+
+use sampler::{hash_combine, to_unit_float};
+use shader::fractal_noise;
+use sky::Sky;
+use vec3::Vec3;
+
+// The cloud layer, in units of the height of its base above the
+// camera. Clouds are far enough away that where the camera stands in
+// the scene makes no difference, so only the view direction matters.
+const BASE: f32 = 1.0;
+const THICKNESS: f32 = 0.5;
+// Size of the largest puffs
+const FEATURE_SIZE: f32 = 0.6;
+// Past this, clouds fade into the haze at the horizon
+const HAZE_DISTANCE: f32 = 8.0;
+// Rays close to the horizon cross the layer over a long way, so the
+// march is cut off here
+const MAX_DISTANCE: f32 = 20.0;
+
+const VIEW_STEPS: u32 = 32;
+const LIGHT_STEPS: u32 = 4;
+const LIGHT_STEP: f32 = 0.05;
+const OCTAVES: u32 = 5;
+// The light march only needs the rough shape of the cloud
+const LIGHT_OCTAVES: u32 = 3;
+// Extinction per unit at full density, with a --cloud-density of 1
+const EXTINCTION: f32 = 40.0;
+// Forward scattering, which gives clouds their bright edges when seen
+// towards the sun
+const ANISOTROPY: f32 = 0.6;
+
+///
+/// A layer of procedural clouds over the sky, ray marched through
+/// fractal noise. Each step of the march is lit by the sun, dimmed by
+/// the cloud between it and the sun, and by the sky above. Light
+/// scattered more than once is approximated by adding weaker and less
+/// forward-scattering copies of the sunlight, which lets the insides
+/// of thick clouds glow rather than going black.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Clouds {
+    /// How much of the sky is covered, from 0 to 1.
+    pub coverage: f32,
+    /// How thick the clouds are, 1 being ordinary cumulus.
+    pub density: f32,
+    pub seed: u32,
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Henyey-Greenstein phase function
+fn phase(g: f32, cosine: f32) -> f32 {
+    let denominator = 1.0 + g * g - 2.0 * g * cosine;
+    (1.0 - g * g) / (4.0 * consts::PI * denominator * denominator.sqrt())
+}
+
+impl Clouds {
+    pub fn new(coverage: f32, density: f32, seed: u32) -> Clouds {
+        Clouds { coverage, density, seed }
+    }
+
+    /// The sky seen in the given direction through the clouds.
+    pub fn color(&self, sky: &Sky, direction: &Vec3) -> Vec3 {
+        let background = sky.color(direction);
+        let d = Vec3::unit_vector(direction);
+
+        if d.y() <= 0.0 || self.coverage <= 0.0 {
+            return background;
+        }
+
+        let start = BASE / d.y();
+        let end = ((BASE + THICKNESS) / d.y()).min(start + MAX_DISTANCE);
+        let step = (end - start) / VIEW_STEPS as f32;
+
+        let (sun, sunlight) = sky.sun();
+        let ambient = sky.color(&Vec3::new(0.0, 1.0, 0.0));
+        let cosine = Vec3::dot(&d, &sun);
+
+        // Start each march a different fraction of a step in, so the
+        // steps don't show up as bands
+        let bits = hash_combine(hash_combine(hash_combine(self.seed, d.x().to_bits()), d.y().to_bits()),
+                                d.z().to_bits());
+        let mut t = start + step * to_unit_float(bits);
+
+        let mut transmittance = 1.0;
+        let mut scattered = Vec3::new(0.0, 0.0, 0.0);
+
+        while t < end && transmittance > 0.01 {
+            let p = t * d;
+            let extinction = self.extinction(&p, OCTAVES);
+
+            if extinction > 0.0 {
+                let depth = self.optical_depth(&p, &sun);
+                let height = (p.y() - BASE) / THICKNESS;
+
+                let mut light = 0.0;
+                let mut scale = 1.0;
+                for _ in 0..3 {
+                    light += scale * phase(scale * ANISOTROPY, cosine) * (-scale * depth).exp();
+                    scale *= 0.5;
+                }
+
+                let inscattered = 4.0 * consts::PI * light * sunlight + (0.3 + 0.7 * height) * ambient;
+                let absorbed = 1.0 - (-extinction * step).exp();
+                scattered += transmittance * absorbed * inscattered;
+                transmittance *= 1.0 - absorbed;
+            }
+
+            t += step;
+        }
+
+        let clouded = transmittance * background + scattered;
+        let haze = (-start / HAZE_DISTANCE).exp();
+        background + haze * (clouded - background)
+    }
+
+    // How quickly light is lost at p
+    fn extinction(&self, p: &Vec3, octaves: u32) -> f32 {
+        let height = (p.y() - BASE) / THICKNESS;
+        if !(0.0..=1.0).contains(&height) {
+            return 0.0;
+        }
+
+        // Flat bottoms, rounded tops
+        let profile = smoothstep(0.0, 0.1, height) * (1.0 - smoothstep(0.4, 1.0, height));
+        let noise = fractal_noise(&(*p / FEATURE_SIZE), octaves, self.seed);
+
+        // Fractal noise mostly lies between 0.25 and 0.75
+        let threshold = 0.75 - 0.5 * self.coverage.min(1.0);
+        let density = ((noise * profile - threshold) / (1.0 - threshold)).max(0.0);
+
+        EXTINCTION * self.density * density
+    }
+
+    // The amount of cloud between p and the sun
+    fn optical_depth(&self, p: &Vec3, sun: &Vec3) -> f32 {
+        (1..=LIGHT_STEPS).map(|i| {
+            self.extinction(&(*p + (i as f32 - 0.5) * LIGHT_STEP * *sun), LIGHT_OCTAVES) * LIGHT_STEP
+        }).sum()
+    }
+}
@@ -0,0 +1,86 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+use rng::Pcg32;
+use vec3::Vec3;
+
+// The PCG stream cover scenes are drawn from, apart from the render's
+const COVER_STREAM: u64 = 0xc0e7;
+
+// Where the camera looks from and at to frame the cover
+pub const LOOKFROM: [f32; 3] = [13.0, 2.0, 3.0];
+pub const LOOKAT: [f32; 3] = [0.0, 0.0, 0.0];
+
+///
+/// The scene on the cover of "Ray Tracing in One Weekend", as the
+/// text of a scene file: a field of small spheres, mostly diffuse and
+/// the rest metal or glass, around three big ones, on a giant ground
+/// sphere. The small spheres are laid out on a 22 by 22 grid and
+/// jittered within their cells, and their materials drawn, from
+/// `seed`, so the same seed always gives the same scene. Materials
+/// are named `cover:<name>`, so they don't clash with the scene's
+/// own.
+///
+pub fn scene(seed: u32) -> String {
+    let mut rng = Pcg32::new(u64::from(seed), COVER_STREAM);
+    let mut lines = vec![
+        "material cover:ground lambertian 0.5 0.5 0.5".to_string(),
+        "material cover:glass dielectric 1.5".to_string(),
+        "sphere cover:ground 0 -1000 0 1000".to_string(),
+    ];
+
+    let clear = Vec3::new(4.0, 0.2, 0.0);
+    let mut n = 0;
+    for a in -11..11 {
+        for b in -11..11 {
+            let choice = rng.next_f32();
+            let center = Vec3::new(a as f32 + 0.9 * rng.next_f32(), 0.2, b as f32 + 0.9 * rng.next_f32());
+            // Leave room around the big metal sphere
+            if (center - clear).length() <= 0.9 {
+                continue;
+            }
+
+            let material = if choice < 0.8 {
+                let mut r = || rng.next_f32() * rng.next_f32();
+                let (red, green, blue) = (r(), r(), r());
+                n += 1;
+                lines.push(format!("material cover:{} lambertian {} {} {}", n, red, green, blue));
+                format!("cover:{}", n)
+            } else if choice < 0.95 {
+                let mut r = || 0.5 * (1.0 + rng.next_f32());
+                let (red, green, blue) = (r(), r(), r());
+                n += 1;
+                lines.push(format!("material cover:{} metal {} {} {}", n, red, green, blue));
+                format!("cover:{}", n)
+            } else {
+                "cover:glass".to_string()
+            };
+            lines.push(format!("sphere {} {} {} {} 0.2", material, center.x(), center.y(), center.z()));
+        }
+    }
+
+    lines.extend([
+        "material cover:brown lambertian 0.4 0.2 0.1",
+        "material cover:bronze metal 0.7 0.6 0.5",
+        "sphere cover:glass 0 1 0 1",
+        "sphere cover:brown -4 1 0 1",
+        "sphere cover:bronze 4 1 0 1",
+    ].iter().map(|l| l.to_string()));
+
+    lines.join("\n") + "\n"
+}
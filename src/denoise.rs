@@ -0,0 +1,179 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use std::thread;
+
+use aov::AovBuffers;
+use film;
+use oidn;
+use vec3::Vec3;
+
+// Passes of the filter. Each spreads its taps twice as far apart as
+// the one before, so five passes reach 62 pixels away
+const ITERATIONS: u32 = 5;
+
+// The B3 spline each pass weights its taps by, along each axis
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// How sharply differences in normal, albedo and light between two
+// pixels stop them being averaged together. The light's tolerance is
+// in standard deviations of the pixel's noise, so it shrinks as each
+// pass removes some of the noise.
+const SIGMA_NORMAL: f32 = 0.3;
+const SIGMA_ALBEDO: f32 = 0.1;
+const SIGMA_LIGHT: f32 = 4.0;
+
+// Keeps dark albedos from blowing up the light divided by them, and
+// black pixels from dividing by zero
+const EPSILON: f32 = 0.01;
+
+///
+/// An edge-aware à-trous wavelet denoiser, the spatial part of SVGF.
+/// Each pass averages every pixel with 25 neighbors spread on a grid
+/// that doubles in size from one pass to the next, so a few cheap
+/// passes cover a wide area. Neighbors only count for much where
+/// their normal, albedo and light are like the pixel's own, which
+/// keeps the edges of objects and shadows sharp while the noise in
+/// between is smoothed out. The light falling on each surface is
+/// filtered rather than its color, so textures keep their detail.
+///
+/// Intel Open Image Denoise can be used instead, when it's available.
+///
+pub struct Denoiser {
+    width: usize,
+    height: usize,
+    normal: Vec<Vec3>,
+    albedo: Vec<Vec3>,
+    oidn: bool,
+}
+
+impl Denoiser {
+    /// A denoiser guided by the normal and albedo AOVs of an image, as from `aov::render_aovs`.
+    pub fn new(guides: AovBuffers) -> Denoiser {
+        Denoiser { width: guides.width, height: guides.height, normal: guides.normal, albedo: guides.albedo,
+                   oidn: false }
+    }
+
+    /// Denoise with Open Image Denoise, falling back on the built-in filter if it fails.
+    pub fn with_oidn(self) -> Denoiser {
+        Denoiser { oidn: true, ..self }
+    }
+
+    /// Denoise an image of linear RGB, bottom row first, spread over `threads` threads.
+    pub fn apply(&self, data: &mut [Vec3], threads: usize) {
+        if self.oidn {
+            match oidn::denoise(self.width, self.height, data, &self.albedo, &self.normal) {
+                Ok(()) => return,
+                Err(e) => eprintln!("{}; using the built-in denoiser instead", e),
+            }
+        }
+
+        let rows_per_thread = self.height.div_ceil(threads.max(1)).max(1);
+        let epsilon = Vec3::new(EPSILON, EPSILON, EPSILON);
+        let mut light: Vec<Vec3> = data.iter().zip(&self.albedo).map(|(col, a)| {
+            Vec3::new(col.r() / (a.r() + EPSILON), col.g() / (a.g() + EPSILON), col.b() / (a.b() + EPSILON))
+        }).collect();
+
+        for pass in 0..ITERATIONS {
+            let source: Vec<Vec3> = light.clone();
+            let deviation = self.deviation(&source);
+            let step = 1i64 << pass;
+
+            thread::scope(|scope| {
+                for (chunk, rows) in light.chunks_mut(rows_per_thread * self.width).enumerate() {
+                    let (source, deviation) = (&source, &deviation);
+                    scope.spawn(move || {
+                        for (i, col) in rows.iter_mut().enumerate() {
+                            let p = chunk * rows_per_thread * self.width + i;
+                            *col = self.filter(source, deviation, p % self.width, p / self.width, step);
+                        }
+                    });
+                }
+            });
+        }
+
+        for ((col, l), a) in data.iter_mut().zip(&light).zip(&self.albedo) {
+            *col = *l * (*a + epsilon);
+        }
+    }
+
+    // How noisy each pixel's light is, as the standard deviation of
+    // the brightness of it and its eight neighbors
+    fn deviation(&self, source: &[Vec3]) -> Vec<f32> {
+        let luma: Vec<f32> = source.iter().map(film::luminance).collect();
+        let mut deviation: Vec<f32> = Vec::with_capacity(luma.len());
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (mut sum, mut squares, mut n) = (0.0, 0.0, 0.0);
+                for ny in y.saturating_sub(1)..(y + 2).min(self.height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(self.width) {
+                        let l = luma[ny * self.width + nx];
+                        sum += l;
+                        squares += l * l;
+                        n += 1.0;
+                    }
+                }
+                let mean = sum / n;
+                deviation.push((squares / n - mean * mean).max(0.0).sqrt());
+            }
+        }
+
+        deviation
+    }
+
+    // One pass at pixel (x, y), with taps `step` pixels apart
+    fn filter(&self, source: &[Vec3], deviation: &[f32], x: usize, y: usize, step: i64) -> Vec3 {
+        let p = y * self.width + x;
+        let luma = film::luminance(&source[p]);
+        let tolerance = SIGMA_LIGHT * deviation[p] + EPSILON;
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let mut total = 0.0;
+
+        for (j, ky) in KERNEL.iter().enumerate() {
+            let ny = y as i64 + (j as i64 - 2) * step;
+            if ny < 0 || ny >= self.height as i64 {
+                continue;
+            }
+
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let nx = x as i64 + (i as i64 - 2) * step;
+                if nx < 0 || nx >= self.width as i64 {
+                    continue;
+                }
+
+                let q = ny as usize * self.width + nx as usize;
+                let neighbor = film::luminance(&source[q]);
+                let light = (luma - neighbor).abs() / tolerance;
+                let normal = (self.normal[p] - self.normal[q]).squared_length();
+                let albedo = (self.albedo[p] - self.albedo[q]).squared_length();
+
+                let w = kx * ky * (-normal / (SIGMA_NORMAL * SIGMA_NORMAL)
+                                   - albedo / (SIGMA_ALBEDO * SIGMA_ALBEDO)
+                                   - light).exp();
+                sum += w * source[q];
+                total += w;
+            }
+        }
+
+        sum / total
+    }
+}
@@ -0,0 +1,134 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::thread;
+
+use camera::Camera;
+use vec3::Vec3;
+
+// The largest blur, as a fraction of the image's diagonal. Gathering
+// costs the square of the radius for every pixel, so very out of
+// focus parts of the image are blurred a little less than they should
+// be rather than taking forever.
+const MAX_BLUR: f32 = 0.03;
+
+// Pixels blurred less than this radius are treated as one pixel across
+const MIN_BLUR: f32 = 0.5;
+
+///
+/// Depth of field faked after rendering, from the distance to what
+/// every pixel sees. A thin lens blurs each point of the scene into a
+/// disk, the circle of confusion, which grows with the point's
+/// distance from the plane in focus. Each pixel of the finished image
+/// gathers the neighbors whose circles reach it, so in-focus pixels
+/// stay sharp and out of focus ones spread. Neighbors further away
+/// than a pixel are hidden by it, unless it is blurred enough to
+/// reach them itself, which keeps sharp objects from being smeared
+/// over by the background.
+///
+/// This is much quicker than tracing rays through a lens, but can't
+/// see around the edges of foreground objects the way a real lens
+/// does, and reflections and refractions are blurred at the depth of
+/// the surface showing them.
+///
+pub struct DepthOfField {
+    width: usize,
+    height: usize,
+    depth: Vec<f32>,
+    blur: Vec<f32>,
+    reach: i64,
+}
+
+impl DepthOfField {
+    ///
+    /// Depth of field for an image taken by a perspective `camera`
+    /// through a lens `aperture` units across, focused
+    /// `focus_distance` units ahead. `depth` holds the distance to
+    /// what each pixel sees, as from `render::render_depth`.
+    ///
+    pub fn new(camera: &Camera, aperture: f32, focus_distance: f32, width: usize, height: usize,
+               depth: Vec<f32>) -> DepthOfField {
+        // The circle of confusion on the plane in focus, scaled down
+        // onto the film one unit in front of the camera, in pixels
+        let pixels_per_unit = height as f32 / camera.vertical.length();
+        let diagonal = ((width * width + height * height) as f32).sqrt();
+        let radius = aperture / 2.0;
+
+        let blur: Vec<f32> = depth.iter().map(|&z| {
+            let coc = if z.is_finite() {
+                radius * (z - focus_distance).abs() / (z * focus_distance)
+            } else {
+                radius / focus_distance
+            };
+            (coc * pixels_per_unit).clamp(MIN_BLUR, MAX_BLUR * diagonal)
+        }).collect();
+
+        let reach = blur.iter().fold(0.0f32, |m, b| m.max(*b)).ceil() as i64;
+
+        DepthOfField { width, height, depth, blur, reach }
+    }
+
+    /// Blur an image of linear RGB, bottom row first, by its depth,
+    /// spread over `threads` threads.
+    pub fn apply(&self, data: &mut [Vec3], threads: usize) {
+        let source: Vec<Vec3> = data.to_vec();
+        let rows_per_thread = self.height.div_ceil(threads.max(1)).max(1);
+
+        thread::scope(|scope| {
+            for (chunk, rows) in data.chunks_mut(rows_per_thread * self.width).enumerate() {
+                let source = &source;
+                scope.spawn(move || {
+                    for (i, col) in rows.iter_mut().enumerate() {
+                        let p = chunk * rows_per_thread * self.width + i;
+                        *col = self.gather(source, p % self.width, p / self.width);
+                    }
+                });
+            }
+        });
+    }
+
+    // The color of pixel (x, y) after blurring, from every neighbor
+    // whose circle of confusion covers it. Each neighbor's light is
+    // spread evenly over its circle, so it counts for less the
+    // bigger the circle is.
+    fn gather(&self, source: &[Vec3], x: usize, y: usize) -> Vec3 {
+        let p = y * self.width + x;
+        let reach = self.reach;
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let mut total = 0.0;
+
+        let (x, y) = (x as i64, y as i64);
+        for ny in (y - reach).max(0)..=(y + reach).min(self.height as i64 - 1) {
+            for nx in (x - reach).max(0)..=(x + reach).min(self.width as i64 - 1) {
+                let q = ny as usize * self.width + nx as usize;
+                let d = (((nx - x) * (nx - x) + (ny - y) * (ny - y)) as f32).sqrt();
+
+                if d > self.blur[q] || (self.depth[q] > self.depth[p] && d > self.blur[p]) {
+                    continue;
+                }
+
+                let w = 1.0 / (self.blur[q] * self.blur[q]);
+                sum += w * source[q];
+                total += w;
+            }
+        }
+
+        sum / total
+    }
+}
@@ -0,0 +1,84 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use std::sync::OnceLock;
+
+use blue_noise::BlueNoiseMask;
+
+///
+/// How pixel values are rounded to 8 bits. Without dithering, smooth
+/// gradients such as the sky break up into visible bands a level
+/// apart; dithering nudges each pixel up or down by a pattern of
+/// thresholds, so the average of nearby pixels follows the gradient.
+/// `Ordered` uses a Bayer matrix, which leaves a faint cross-hatch;
+/// `BlueNoise` uses the blue-noise mask, which looks like fine grain.
+/// `None` rounds every pixel the same way, for exact values.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dither {
+    None,
+    Ordered,
+    BlueNoise,
+}
+
+// Bits of each coordinate the Bayer matrix is indexed by, for an 8x8
+// matrix
+const BAYER_BITS: usize = 3;
+
+// The blue-noise mask takes a moment to generate, so every image
+// shares one
+static MASK: OnceLock<BlueNoiseMask> = OnceLock::new();
+
+impl Dither {
+    pub fn from_name(name: &str) -> Option<Dither> {
+        match name {
+            "none" => Some(Dither::None),
+            "ordered" => Some(Dither::Ordered),
+            "blue-noise" => Some(Dither::BlueNoise),
+            _ => None,
+        }
+    }
+
+    /// The 8-bit level of a channel between 0 and 1, for the pixel at (x, y).
+    pub fn level(self, c: f32, x: usize, y: usize) -> u8 {
+        let threshold = match self {
+            Dither::None => return (255.99 * c) as u8,
+            Dither::Ordered => bayer(x, y),
+            Dither::BlueNoise => MASK.get_or_init(BlueNoiseMask::new).value(x as u32, y as u32),
+        };
+
+        (255.0 * c + threshold) as u8
+    }
+}
+
+// The Bayer matrix threshold at (x, y): each level of the matrix
+// splits its cells between the four quadrants of a 2x2 matrix, with
+// the finest level deciding the most
+fn bayer(x: usize, y: usize) -> f32 {
+    let mut rank = 0;
+
+    for bit in 0..BAYER_BITS {
+        let (bx, by) = ((x >> bit) & 1, (y >> bit) & 1);
+        rank = (rank << 2) | (2 * (bx ^ by) + by);
+    }
+
+    (rank as f32 + 0.5) / (1 << (2 * BAYER_BITS)) as f32
+}
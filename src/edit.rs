@@ -0,0 +1,152 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::sync::Arc;
+
+use vec3::Vec3;
+use ray::Ray;
+use aabb::Aabb;
+use hittable::*;
+
+///
+/// A change to the objects in a World. Objects keep their index for
+/// as long as they exist, so edits can refer to them by it.
+///
+pub enum SceneEdit {
+    Add(Box<dyn Hittable+Sync+Send>),
+    Replace(usize, Box<dyn Hittable+Sync+Send>),
+    Remove(usize),
+}
+
+///
+/// What part of the scene an edit touched, for callers deciding what
+/// to render again. Since light bounces around, a change anywhere can
+/// show up anywhere in the image; `Region` is where the change
+/// itself is, which is what shows first and most.
+///
+#[derive(Copy, Clone, Debug)]
+pub enum Invalidation {
+    Nothing,
+    Region(Aabb),
+    Everything,
+}
+
+impl Invalidation {
+    fn include(self, bounds: Option<Aabb>) -> Invalidation {
+        match (self, bounds) {
+            (Invalidation::Everything, _) | (_, None) => Invalidation::Everything,
+            (Invalidation::Nothing, Some(b)) => Invalidation::Region(b),
+            (Invalidation::Region(r), Some(b)) => Invalidation::Region(r.union(&b)),
+        }
+    }
+}
+
+///
+/// Edits applied to a World since its accelerator was last brought
+/// up to date.
+///
+#[derive(Default)]
+pub struct PendingEdits {
+    // Objects whose slot now holds something else
+    changed: Vec<usize>,
+    // True if objects were appended, which changes the layout of
+    // every accelerator
+    grown: bool,
+    // Slots left by removed objects, reused by later additions
+    free: Vec<usize>,
+    invalidation: Option<Invalidation>,
+}
+
+// Stands in for a removed object so that other objects keep their
+// indices. It is never hit.
+struct Removed {
+    material: SharedMaterial,
+}
+
+impl Hittable for Removed {
+    fn hit(&self, _: &Ray, _: f32, _: f32) -> Option<Hit<'_>> {
+        None
+    }
+
+    fn material(&self) -> &dyn Material {
+        &*self.material
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::empty())
+    }
+}
+
+impl World {
+    ///
+    /// Apply an edit to the objects. Returns the index of the object
+    /// edited or added. Rays won't see the change until `commit` is
+    /// called; a World can only be edited while nothing else is using
+    /// it (for a shared World, once `Arc::get_mut` succeeds), so edits
+    /// are naturally applied between progressive passes.
+    ///
+    pub fn apply(&mut self, edit: SceneEdit) -> usize {
+        match edit {
+            SceneEdit::Add(object) => match self.pending.free.pop() {
+                Some(index) => self.apply(SceneEdit::Replace(index, object)),
+                None => {
+                    let bounds = object.bounding_box();
+                    self.objects.push(object);
+                    self.pending.grown = true;
+                    self.invalidate(bounds);
+                    self.objects.len() - 1
+                }
+            },
+            SceneEdit::Replace(index, object) => {
+                let old = self.objects[index].bounding_box();
+                let new = object.bounding_box();
+                self.objects[index] = object;
+                self.pending.changed.push(index);
+                self.invalidate(old);
+                self.invalidate(new);
+                index
+            },
+            SceneEdit::Remove(index) => {
+                let removed = Removed { material: Arc::new(Lambertian::new(Vec3::new(0.0, 0.0, 0.0))) };
+                self.apply(SceneEdit::Replace(index, Box::new(removed)));
+                self.pending.free.push(index);
+                index
+            },
+        }
+    }
+
+    fn invalidate(&mut self, bounds: Option<Aabb>) {
+        let current = self.pending.invalidation.unwrap_or(Invalidation::Nothing);
+        self.pending.invalidation = Some(current.include(bounds));
+    }
+
+    ///
+    /// Bring the accelerator up to date with the edits applied since
+    /// the last commit, touching as little of it as possible, and
+    /// report what the edits invalidated.
+    ///
+    pub fn commit(&mut self) -> Invalidation {
+        let changed = std::mem::take(&mut self.pending.changed);
+        let grown = self.pending.grown;
+        self.accelerator.update(&self.objects, &changed, grown);
+
+        self.pending.grown = false;
+        self.pending.invalidation.take().unwrap_or(Invalidation::Nothing)
+    }
+}
@@ -0,0 +1,641 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use half::f16;
+
+use vec3::Vec3;
+use filter::Filter;
+use render::RenderResult;
+use tile::Tile;
+use post::PostChain;
+
+// Keeps the relative error of near-black pixels from blowing up
+const ERROR_EPSILON: f32 = 0.01;
+
+// Adaptive sampling never gives up on a pixel before this many samples
+const ADAPTIVE_MIN_SAMPLES: u32 = 16;
+
+///
+/// The samples taken for one pixel during one pass: the sum of their
+/// colors, the sum of their squared luminances, and how many there
+/// were. A pixel that was skipped has a count of zero.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct PixelSamples {
+    pub sum: Vec3,
+    pub sum_sq: f32,
+    pub count: u32,
+}
+
+impl PixelSamples {
+    pub fn new() -> PixelSamples {
+        PixelSamples { sum: Vec3::new(0.0, 0.0, 0.0), sum_sq: 0.0, count: 0 }
+    }
+
+    pub fn add(&mut self, col: Vec3) {
+        let y = luminance(&col);
+        self.sum += col;
+        self.sum_sq += y * y;
+        self.count += 1;
+    }
+
+    pub fn merge(&mut self, other: &PixelSamples) {
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        self.count += other.count;
+    }
+
+    pub fn mean(&self) -> Vec3 {
+        if self.count == 0 {
+            Vec3::new(0.0, 0.0, 0.0)
+        } else {
+            self.sum / self.count as f32
+        }
+    }
+
+    ///
+    /// True once the 95% confidence interval of the pixel's mean
+    /// luminance is narrower than `threshold` times that mean.
+    ///
+    pub fn converged(&self, threshold: f32) -> bool {
+        if self.count < ADAPTIVE_MIN_SAMPLES {
+            return false;
+        }
+
+        let n = self.count as f32;
+        let mean = luminance(&self.sum) / n;
+        let variance = ((self.sum_sq - mean * mean * n) / (n - 1.0)).max(0.0);
+        let half_width = 1.96 * (variance / n).sqrt();
+
+        half_width <= threshold * (mean + ERROR_EPSILON)
+    }
+}
+
+impl Default for PixelSamples {
+    fn default() -> PixelSamples {
+        PixelSamples::new()
+    }
+}
+
+pub fn luminance(col: &Vec3) -> f32 {
+    0.2126 * col.r() + 0.7152 * col.g() + 0.0722 * col.b()
+}
+
+///
+/// Samples spread over the pixels around them by a reconstruction
+/// filter. A sample taken in one tile can land on the pixels just
+/// around it, so this covers the tile grown by the filter's reach:
+/// a block of lines starting at `first_line`, each `width` pixels
+/// long starting at `first_column`, holding the weighted sum of the
+/// samples and the sum of the weights for every pixel, and optionally
+/// the weighted sum of the samples' alpha.
+///
+#[derive(Debug)]
+pub struct Splats {
+    pub first_line: u32,
+    pub first_column: u32,
+    pub width: usize,
+    pub color: Vec<Vec3>,
+    pub weight: Vec<f32>,
+    pub alpha: Vec<f32>,
+}
+
+impl Splats {
+    ///
+    /// An empty block around `tile`, wide enough for `filter`, within
+    /// an image `width` by `height` pixels.
+    ///
+    pub fn new(tile: &Tile, width: usize, height: usize, filter: &dyn Filter) -> Splats {
+        let reach = (filter.radius() - 0.5).ceil().max(0.0) as u32;
+        let first_line = tile.y.saturating_sub(reach);
+        let last_line = (tile.y + tile.height - 1 + reach).min(height as u32 - 1);
+        let first_column = tile.x.saturating_sub(reach);
+        let last_column = (tile.x + tile.width - 1 + reach).min(width as u32 - 1);
+        let columns = (last_column - first_column + 1) as usize;
+        let size = (last_line - first_line + 1) as usize * columns;
+
+        Splats {
+            first_line,
+            first_column,
+            width: columns,
+            color: vec![Vec3::new(0.0, 0.0, 0.0); size],
+            weight: vec![0.0; size],
+            alpha: Vec::new(),
+        }
+    }
+
+    /// Keep track of the alpha of the samples too.
+    pub fn record_alpha(&mut self) {
+        self.alpha = vec![0.0; self.weight.len()];
+    }
+
+    pub fn rows(&self) -> usize {
+        self.weight.len() / self.width
+    }
+
+    ///
+    /// Spread a sample taken at offset (`dx`, `dy`) within pixel
+    /// (`x`, `y`) over the block. `alpha` is ignored unless alpha is
+    /// being recorded.
+    ///
+    pub fn add(&mut self, filter: &dyn Filter, x: u32, y: u32, (dx, dy): (f32, f32), col: Vec3, alpha: f32) {
+        let radius = filter.radius();
+        let first = self.first_line as i64;
+        let last = first + self.rows() as i64 - 1;
+        let first_column = self.first_column as i64;
+        let last_column = first_column + self.width as i64 - 1;
+        let (x, y) = (x as i64, y as i64);
+
+        // Offsets from the sample to the centers of the pixels it reaches
+        let x0 = (x + (dx - 0.5 - radius).ceil() as i64).max(first_column);
+        let x1 = (x + (dx - 0.5 + radius).floor() as i64).min(last_column);
+        let y0 = (y + (dy - 0.5 - radius).ceil() as i64).max(first);
+        let y1 = (y + (dy - 0.5 + radius).floor() as i64).min(last);
+
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let w = filter.evaluate((px - x) as f32 + 0.5 - dx, (py - y) as f32 + 0.5 - dy);
+                if w != 0.0 {
+                    let i = (py - first) as usize * self.width + (px - first_column) as usize;
+                    self.color[i] += w * col;
+                    self.weight[i] += w;
+                    if !self.alpha.is_empty() {
+                        self.alpha[i] += w * alpha;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Splats) {
+        for (c, o) in self.color.iter_mut().zip(other.color.iter()) {
+            *c += *o;
+        }
+        for (w, o) in self.weight.iter_mut().zip(other.weight.iter()) {
+            *w += *o;
+        }
+        for (a, o) in self.alpha.iter_mut().zip(other.alpha.iter()) {
+            *a += *o;
+        }
+    }
+}
+
+/// The filtered color of a pixel from its weighted sum of samples.
+pub fn filtered_color(sum: &Vec3, weight: f32) -> Vec3 {
+    if weight <= 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+
+    // Filters with negative lobes can ring below zero next to edges
+    let col: Vec3 = *sum / weight;
+    Vec3::new(col.r().max(0.0), col.g().max(0.0), col.b().max(0.0))
+}
+
+///
+/// Per-pixel sums of colors. At full precision these are plain f32
+/// sums. At half precision each pixel keeps the mean of what was
+/// added to it as three f16s, half the size, and the caller keeps the
+/// total weight of what was added in full precision; a sum is read
+/// back by scaling the mean up by that weight. Keeping means rather
+/// than sums stops the stored values from growing with the number of
+/// samples, which f16 could only hold to about three significant
+/// digits.
+///
+enum ColorBuffer {
+    Full(Vec<Vec3>),
+    Half(Vec<[f16; 3]>),
+}
+
+impl ColorBuffer {
+    fn new(size: usize, half_float: bool) -> ColorBuffer {
+        if half_float {
+            ColorBuffer::Half(vec![[f16::ZERO; 3]; size])
+        } else {
+            ColorBuffer::Full(vec![Vec3::new(0.0, 0.0, 0.0); size])
+        }
+    }
+
+    /// Add `sum`, made up of `weight` worth of samples, to pixel `i`,
+    /// whose total weight is now `total`.
+    fn add(&mut self, i: usize, sum: Vec3, weight: f32, total: f32) {
+        match *self {
+            ColorBuffer::Full(ref mut data) => data[i] += sum,
+            ColorBuffer::Half(ref mut data) => {
+                if weight == 0.0 || total == 0.0 {
+                    return;
+                }
+
+                let mean: Vec3 = half_to_vec3(&data[i]);
+                let mean: Vec3 = mean + (sum - weight * mean) / total;
+                data[i] = [f16::from_f32(mean.r()), f16::from_f32(mean.g()), f16::from_f32(mean.b())];
+            },
+        }
+    }
+
+    /// The sum of everything added to pixel `i`, whose total weight is `total`.
+    fn sum(&self, i: usize, total: f32) -> Vec3 {
+        match *self {
+            ColorBuffer::Full(ref data) => data[i],
+            ColorBuffer::Half(ref data) => total * half_to_vec3(&data[i]),
+        }
+    }
+}
+
+fn half_to_vec3(col: &[f16; 3]) -> Vec3 {
+    Vec3::new(col[0].to_f32(), col[1].to_f32(), col[2].to_f32())
+}
+
+///
+/// A Film accumulates radiance samples for every pixel across
+/// progressive passes. Samples from even-numbered passes are also
+/// summed into a second "half" buffer, so that the image can be split
+/// into two independent estimates and its noise level measured.
+/// The displayed image is reconstructed from the filtered splats.
+/// Optionally, the contribution of each light group is kept too, and
+/// the alpha of the samples, once any arrive.
+/// Colors can be kept at half precision to save memory; sample
+/// counts and filter weights are always kept at full precision.
+///
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub light_groups: usize,
+    sum: ColorBuffer,
+    sum_sq: Vec<f32>,
+    count: Vec<u32>,
+    filtered: ColorBuffer,
+    filter_weight: Vec<f32>,
+    alpha: Vec<f32>,
+    groups: ColorBuffer,
+    half: ColorBuffer,
+    half_count: Vec<u32>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, light_groups: usize, half_float: bool) -> Film {
+        let size = width * height;
+
+        Film {
+            width,
+            height,
+            light_groups,
+            sum: ColorBuffer::new(size, half_float),
+            sum_sq: vec![0.0; size],
+            count: vec![0; size],
+            filtered: ColorBuffer::new(size, half_float),
+            filter_weight: vec![0.0; size],
+            alpha: Vec::new(),
+            groups: ColorBuffer::new(size * light_groups, half_float),
+            half: ColorBuffer::new(size, half_float),
+            half_count: vec![0; size],
+        }
+    }
+
+    ///
+    /// The memory a film of this size will take, in bytes: three
+    /// color buffers, the per-pixel statistics, and the light groups
+    /// and alpha if they're kept.
+    ///
+    pub fn bytes_needed(width: usize, height: usize, light_groups: usize, half_float: bool, alpha: bool) -> usize {
+        let color = if half_float { 6 } else { 12 };
+        let per_pixel = color * (3 + light_groups) + 4 * 4 + if alpha { 4 } else { 0 };
+        width * height * per_pixel
+    }
+
+    /// Add one pass worth of samples for a tile.
+    pub fn add_result(&mut self, result: &RenderResult) {
+        let tile = &result.tile;
+        let pass = result.pass;
+        let width = self.width;
+
+        let splats = &result.splats;
+        let splat_index = |i: usize| {
+            (splats.first_line as usize + i / splats.width) * width + splats.first_column as usize + i % splats.width
+        };
+        for (i, col) in splats.color.iter().enumerate() {
+            let p = splat_index(i);
+            self.filter_weight[p] += splats.weight[i];
+            self.filtered.add(p, *col, splats.weight[i], self.filter_weight[p]);
+        }
+
+        if !splats.alpha.is_empty() {
+            if self.alpha.is_empty() {
+                self.alpha = vec![0.0; self.width * self.height];
+            }
+            for (i, a) in splats.alpha.iter().enumerate() {
+                self.alpha[splat_index(i)] += *a;
+            }
+        }
+
+        let tile_width = tile.width as usize;
+        let pixel_index = |i: usize| {
+            (tile.y as usize + i / tile_width) * width + tile.x as usize + i % tile_width
+        };
+        for (i, samples) in result.data.iter().enumerate() {
+            let p = pixel_index(i);
+            self.count[p] += samples.count;
+            self.sum_sq[p] += samples.sum_sq;
+            self.sum.add(p, samples.sum, samples.count as f32, self.count[p] as f32);
+
+            if pass & 1 == 0 {
+                self.half_count[p] += samples.count;
+                self.half.add(p, samples.sum, samples.count as f32, self.half_count[p] as f32);
+            }
+        }
+
+        if self.light_groups > 0 {
+            for (i, sums) in result.groups.chunks(self.light_groups).enumerate() {
+                let p = pixel_index(i);
+                let count = result.data[i].count as f32;
+                for (g, sum) in sums.iter().enumerate() {
+                    self.groups.add(p * self.light_groups + g, *sum, count, self.count[p] as f32);
+                }
+            }
+        }
+    }
+
+    /// The number of samples taken so far, over all pixels.
+    pub fn samples_taken(&self) -> u64 {
+        self.count.iter().map(|&c| c as u64).sum()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> PixelSamples {
+        let i = y * self.width + x;
+        PixelSamples { sum: self.sum.sum(i, self.count[i] as f32), sum_sq: self.sum_sq[i], count: self.count[i] }
+    }
+
+    pub fn color(&self, x: usize, y: usize) -> Vec3 {
+        let i = y * self.width + x;
+        filtered_color(&self.filtered.sum(i, self.filter_weight[i]), self.filter_weight[i])
+    }
+
+    /// Mean contribution of one light group to a pixel.
+    pub fn light_group(&self, x: usize, y: usize, group: usize) -> Vec3 {
+        let i = y * self.width + x;
+        let count = self.count[i] as f32;
+
+        if count == 0.0 {
+            Vec3::new(0.0, 0.0, 0.0)
+        } else {
+            self.groups.sum(i * self.light_groups + group, count) / count
+        }
+    }
+
+    /// The image as linear float RGB, bottom row first.
+    pub fn rgb(&self) -> Vec<Vec3> {
+        let mut data: Vec<Vec3> = Vec::with_capacity(self.width * self.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                data.push(self.color(x, y));
+            }
+        }
+
+        data
+    }
+
+    ///
+    /// How much of each pixel is covered by the scene rather than the
+    /// background, bottom row first; 1 everywhere if no alpha was
+    /// recorded. Colors are premultiplied by it.
+    ///
+    pub fn alpha(&self) -> Vec<f32> {
+        if self.alpha.is_empty() {
+            return vec![1.0; self.width * self.height];
+        }
+
+        self.alpha.iter().zip(self.filter_weight.iter())
+            .map(|(a, w)| if *w > 0.0 { (a / w).clamp(0.0, 1.0) } else { 0.0 })
+            .collect()
+    }
+
+    /// One light group as linear float RGB, bottom row first.
+    pub fn light_group_rgb(&self, group: usize) -> Vec<Vec3> {
+        let mut data: Vec<Vec3> = Vec::with_capacity(self.width * self.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                data.push(self.light_group(x, y, group));
+            }
+        }
+
+        data
+    }
+
+    /// 8-bit RGB values for one scanline, through a post-processing chain.
+    pub fn line_rgb8(&self, line: usize, post: &PostChain) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(self.width * 3);
+
+        for x in 0..self.width {
+            push_rgb8(&self.color(x, line), line * self.width + x, post, &mut data);
+        }
+
+        data
+    }
+
+    ///
+    /// 8-bit RGB values for the pixels of one tile, through a
+    /// post-processing chain, top row first.
+    ///
+    pub fn tile_rgb8(&self, tile: &Tile, post: &PostChain) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(tile.pixels() * 3);
+
+        for line in (tile.y..tile.y + tile.height).rev() {
+            let line = line as usize;
+            for x in tile.x as usize..(tile.x + tile.width) as usize {
+                push_rgb8(&self.color(x, line), line * self.width + x, post, &mut data);
+            }
+        }
+
+        data
+    }
+
+    /// The whole image as 8-bit RGB, top row first.
+    pub fn rgb8(&self, post: &PostChain) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(self.width * self.height * 3);
+
+        for line in (0..self.height).rev() {
+            data.extend(self.line_rgb8(line, post));
+        }
+
+        data
+    }
+
+    ///
+    /// A false-color image of how many samples each pixel received, as
+    /// 8-bit RGB, top row first. Colors run from dark blue for no
+    /// samples through cyan, green and yellow to red for `max_samples`.
+    ///
+    pub fn heatmap_rgb8(&self, max_samples: u32) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(self.width * self.height * 3);
+
+        for line in (0..self.height).rev() {
+            for x in 0..self.width {
+                let t = self.count[line * self.width + x] as f32 / max_samples.max(1) as f32;
+                let col = heat_color(t.min(1.0));
+                data.push((255.99 * col.r()) as u8);
+                data.push((255.99 * col.g()) as u8);
+                data.push((255.99 * col.b()) as u8);
+            }
+        }
+
+        data
+    }
+
+    ///
+    /// Estimate the relative error of the image by comparing the
+    /// two half buffers, each of which is an independent estimate of
+    /// the same image. Pixels without samples in both halves, e.g.
+    /// ones adaptive sampling stopped early, are left out. Returns
+    /// None if no pixel has them yet.
+    ///
+    pub fn error(&self) -> Option<f32> {
+        mean_error((0..self.count.len()).map(|i| {
+            let half: Vec3 = self.half.sum(i, self.half_count[i] as f32);
+            pixel_error(half, self.half_count[i], self.sum.sum(i, self.count[i] as f32), self.count[i])
+        }))
+    }
+}
+
+///
+/// Estimate the relative error of a tile rendered without a Film, the
+/// same way as `Film::error`, from the samples of its even passes in
+/// `half` and those of all its passes in `total`.
+///
+pub fn tile_error(half: &[PixelSamples], total: &[PixelSamples]) -> Option<f32> {
+    mean_error(half.iter().zip(total.iter()).map(|(h, t)| pixel_error(h.sum, h.count, t.sum, t.count)))
+}
+
+// The relative difference between the mean of a pixel's samples in
+// even passes and in odd ones, or None if either has no samples
+fn pixel_error(half: Vec3, half_count: u32, sum: Vec3, count: u32) -> Option<f32> {
+    let odd_count = count - half_count;
+    if half_count == 0 || odd_count == 0 {
+        return None;
+    }
+
+    let a: Vec3 = half / half_count as f32;
+    let b: Vec3 = (sum - half) / odd_count as f32;
+
+    let difference = (a.r() - b.r()).abs() + (a.g() - b.g()).abs() + (a.b() - b.b()).abs();
+    let magnitude = a.r() + a.g() + a.b() + b.r() + b.g() + b.b();
+
+    Some(difference / (ERROR_EPSILON + magnitude))
+}
+
+// The mean of the errors of the pixels that have one
+fn mean_error<I: Iterator<Item = Option<f32>>>(errors: I) -> Option<f32> {
+    let (sum, count) = errors.flatten().fold((0.0, 0), |(sum, count), e| (sum + e, count + 1));
+    if count == 0 {
+        None
+    } else {
+        Some(sum / count as f32)
+    }
+}
+
+// Stops of the heatmap's color scale, evenly spaced over [0, 1]
+const HEAT_COLORS: [[f32; 3]; 6] = [
+    [0.0, 0.0, 0.3],
+    [0.0, 0.2, 1.0],
+    [0.0, 0.9, 1.0],
+    [0.1, 0.9, 0.1],
+    [1.0, 0.9, 0.0],
+    [1.0, 0.0, 0.0],
+];
+
+fn heat_color(t: f32) -> Vec3 {
+    let position = t * (HEAT_COLORS.len() - 1) as f32;
+    let i = (position as usize).min(HEAT_COLORS.len() - 2);
+    let f = position - i as f32;
+    let (a, b) = (HEAT_COLORS[i], HEAT_COLORS[i + 1]);
+
+    Vec3::new(a[0] + f * (b[0] - a[0]), a[1] + f * (b[1] - a[1]), a[2] + f * (b[2] - a[2]))
+}
+
+/// Linear float RGB, bottom row first, as 8-bit RGB, top row first.
+pub fn rgb8(data: &[Vec3], width: usize, post: &PostChain) -> Vec<u8> {
+    let mut rgb: Vec<u8> = Vec::with_capacity(data.len() * 3);
+
+    for (y, row) in data.chunks(width).enumerate().rev() {
+        for (x, col) in row.iter().enumerate() {
+            push_rgb8(col, y * width + x, post, &mut rgb);
+        }
+    }
+
+    rgb
+}
+
+/// Add the pixel `index` pixels into the image, bottom row first, to 8-bit RGB data.
+pub fn push_rgb8(col: &Vec3, index: usize, post: &PostChain, data: &mut Vec<u8>) {
+    data.extend_from_slice(&post.pixel8(col, index));
+}
+
+// A premultiplied color with the alpha divided back out, as PNG files
+// expect
+fn straight(col: &Vec3, alpha: f32) -> Vec3 {
+    if alpha > 0.0 { *col / alpha } else { Vec3::new(0.0, 0.0, 0.0) }
+}
+
+///
+/// Premultiplied linear float RGB and alpha, bottom row first, as
+/// 8-bit RGBA with straight alpha, top row first.
+///
+pub fn rgba8(data: &[Vec3], alpha: &[f32], width: usize, post: &PostChain) -> Vec<u8> {
+    let mut rgba: Vec<u8> = Vec::with_capacity(data.len() * 4);
+
+    for (y, (row, alpha)) in data.chunks(width).zip(alpha.chunks(width)).enumerate().rev() {
+        for (x, (col, a)) in row.iter().zip(alpha).enumerate() {
+            push_rgb8(&straight(col, *a), y * width + x, post, &mut rgba);
+            rgba.push((255.99 * a) as u8);
+        }
+    }
+
+    rgba
+}
+
+/// As `rgba8`, but with 16 bits per channel.
+pub fn rgba16(data: &[Vec3], alpha: &[f32], width: usize, post: &PostChain) -> Vec<u16> {
+    let mut rgba: Vec<u16> = Vec::with_capacity(data.len() * 4);
+
+    for (y, (row, alpha)) in data.chunks(width).zip(alpha.chunks(width)).enumerate().rev() {
+        for (x, (col, a)) in row.iter().zip(alpha).enumerate() {
+            for c in &post.pixel(&straight(col, *a), y * width + x).e {
+                rgba.push((65535.99 * c) as u16);
+            }
+            rgba.push((65535.99 * a) as u16);
+        }
+    }
+
+    rgba
+}
+
+/// Linear float RGB, bottom row first, as 16-bit RGB, top row first.
+pub fn rgb16(data: &[Vec3], width: usize, post: &PostChain) -> Vec<u16> {
+    let mut rgb: Vec<u16> = Vec::with_capacity(data.len() * 3);
+
+    for (y, row) in data.chunks(width).enumerate().rev() {
+        for (x, col) in row.iter().enumerate() {
+            for c in &post.pixel(col, y * width + x).e {
+                rgb.push((65535.99 * c) as u16);
+            }
+        }
+    }
+
+    rgb
+}
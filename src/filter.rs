@@ -0,0 +1,174 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+///
+/// A pixel reconstruction filter. Every sample is spread over the
+/// pixels whose centers lie within `radius` of it, weighted by the
+/// filter, and each pixel is the weighted average of the samples it
+/// received. `x` and `y` are the offset from the pixel center to the
+/// sample, in pixels.
+///
+pub trait Filter: Send + Sync {
+    fn radius(&self) -> f32;
+
+    fn evaluate(&self, x: f32, y: f32) -> f32;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::Mitchell),
+            _ => None,
+        }
+    }
+
+    pub fn create(self) -> Box<dyn Filter> {
+        match self {
+            FilterKind::Box => Box::new(BoxFilter { radius: 0.5 }),
+            FilterKind::Tent => Box::new(TentFilter { radius: 1.0 }),
+            FilterKind::Gaussian => Box::new(GaussianFilter::new(1.5, 0.5)),
+            FilterKind::Mitchell => Box::new(MitchellFilter { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 }),
+        }
+    }
+}
+
+///
+/// Every sample counts fully towards the pixel it falls in, and not
+/// at all towards any other. This is the same as simply averaging
+/// the samples taken in each pixel.
+///
+pub struct BoxFilter {
+    radius: f32,
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        // Half-open, so a sample on a pixel edge only counts once
+        if x > -self.radius && x <= self.radius && y > -self.radius && y <= self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A pyramid falling off linearly to zero at the radius.
+pub struct TentFilter {
+    radius: f32,
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        (1.0 - x.abs() / self.radius).max(0.0) * (1.0 - y.abs() / self.radius).max(0.0)
+    }
+}
+
+///
+/// A Gaussian, shifted down so that it reaches zero at the radius
+/// instead of being cut off abruptly.
+///
+pub struct GaussianFilter {
+    radius: f32,
+    sigma: f32,
+    edge: f32,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f32, sigma: f32) -> GaussianFilter {
+        GaussianFilter { radius, sigma, edge: gaussian(radius, sigma) }
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        (gaussian(x, self.sigma) - self.edge).max(0.0)
+    }
+}
+
+fn gaussian(x: f32, sigma: f32) -> f32 {
+    (-x * x / (2.0 * sigma * sigma)).exp()
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
+
+///
+/// The Mitchell-Netravali cubic filter. Its small negative lobes
+/// sharpen edges a little; B = C = 1/3 is the authors' recommended
+/// balance between blurring and ringing.
+///
+pub struct MitchellFilter {
+    radius: f32,
+    b: f32,
+    c: f32,
+}
+
+impl MitchellFilter {
+    // The cubic itself is defined over [-2, 2]
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+
+        let value = if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+        } else {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        };
+
+        value / 6.0
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
@@ -0,0 +1,271 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::collections::HashMap;
+use std::f32::consts;
+
+use vec3::Vec3;
+
+// Length of the diffraction spikes, as a fraction of the image's
+// diagonal, and the distance along them over which they fade, as a
+// fraction of their length
+const SPIKE_LENGTH: f32 = 0.25;
+const SPIKE_FALLOFF: f32 = 0.04;
+
+// Relative lengths of the spikes in red, green and blue light, which
+// diffract more the longer their wavelength
+const SPIKE_SPREAD: [f32; 3] = [1.0, 0.82, 0.66];
+
+// Share of the glare in the soft glow around each light, rather than
+// in its spikes, and the glow's radius in pixels
+const GLOW_SHARE: f32 = 0.5;
+const GLOW_RADIUS: f32 = 1.5;
+
+// Radius of the halo of light scattered by dirt on the lens, as a
+// fraction of the image's diagonal
+const HALO_RADIUS: f32 = 0.04;
+
+/// The number of box blurs that make up a `blur`.
+pub const BLUR_PASSES: usize = 3;
+
+///
+/// Glare from bright lights, added to a finished image. A little of
+/// the light passing through any lens spreads out around bright
+/// points, into a soft glow and, where the aperture has straight
+/// blades, into spikes: one per blade for an even number of blades,
+/// two per blade for an odd number. Diffraction spreads red light
+/// further than blue, so the spikes have colored fringes. Dirt on the
+/// lens scatters light further still: a dirt texture, stretched over
+/// the frame, smudges the spikes and lights up with a broad halo
+/// around bright parts of the image.
+///
+pub struct Glare {
+    strength: f32,
+    threshold: f32,
+    blades: Option<u32>,
+    blade_rotation: f32,
+    dirt: Option<(usize, usize, Vec<Vec3>)>,
+}
+
+impl Glare {
+    ///
+    /// Glare from everything brighter than `threshold`, for an
+    /// aperture with the given number of blades (or a round one),
+    /// turned as in `Camera::with_blades`, with `strength` of the
+    /// light over the threshold spread into the glare.
+    ///
+    pub fn new(strength: f32, threshold: f32, blades: Option<u32>, blade_rotation: f32) -> Glare {
+        Glare { strength, threshold, blades, blade_rotation: blade_rotation.to_radians(), dirt: None }
+    }
+
+    /// The same glare, on a lens with dirt on it, given as an image
+    /// `width` by `height` pixels, bottom row first.
+    pub fn with_dirt(self, width: usize, height: usize, data: Vec<Vec3>) -> Glare {
+        Glare { dirt: Some((width, height, data)), ..self }
+    }
+
+    /// Add glare to an image of linear RGB, bottom row first.
+    pub fn apply(&self, width: usize, height: usize, data: &mut [Vec3]) {
+        let excess = excess(data, self.threshold);
+
+        let diagonal = ((width * width + height * height) as f32).sqrt();
+        let kernel = self.kernel(diagonal);
+        let mut spread: Vec<Vec3> = vec![Vec3::new(0.0, 0.0, 0.0); data.len()];
+
+        for y in 0..height {
+            for x in 0..width {
+                let light = excess[y * width + x];
+                if light.r() == 0.0 && light.g() == 0.0 && light.b() == 0.0 {
+                    continue;
+                }
+
+                for &(dx, dy, weight) in &kernel {
+                    let (tx, ty) = (x as i32 + dx, y as i32 + dy);
+                    if tx >= 0 && ty >= 0 && (tx as usize) < width && (ty as usize) < height {
+                        spread[ty as usize * width + tx as usize] += weight * light;
+                    }
+                }
+            }
+        }
+
+        match self.dirt {
+            Some((dirt_width, dirt_height, ref dirt)) => {
+                let radius = (HALO_RADIUS * diagonal / BLUR_PASSES as f32).round().max(1.0) as usize;
+                let halo = blur(&excess, width, height, radius);
+
+                for y in 0..height {
+                    for x in 0..width {
+                        let i = y * width + x;
+                        let smudge = sample(dirt_width, dirt_height, dirt,
+                                            (x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+                        data[i] += self.strength * (spread[i] * (Vec3::new(1.0, 1.0, 1.0) + smudge) + halo[i] * smudge);
+                    }
+                }
+            },
+            None => {
+                for (col, glare) in data.iter_mut().zip(spread.iter()) {
+                    *col += self.strength * *glare;
+                }
+            },
+        }
+    }
+
+    // The glare around a single bright pixel, as offsets to the pixels
+    // it spreads to and the share of the light each one gets
+    fn kernel(&self, diagonal: f32) -> Vec<(i32, i32, Vec3)> {
+        // Both the glow and the spikes taper off to nothing at the end
+        // of their reach, so that even very bright lights don't show
+        // where they stop
+        let taper = |d: f32, reach: f32| (1.0 - d / reach).max(0.0).powi(2);
+
+        let mut glow: HashMap<(i32, i32), Vec3> = HashMap::new();
+        let reach = (3.0 * GLOW_RADIUS).ceil() as i32;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let d = ((dx * dx + dy * dy) as f32).sqrt();
+                let w = (-d * d / (2.0 * GLOW_RADIUS * GLOW_RADIUS)).exp() * taper(d, reach as f32 + 1.0);
+                glow.insert((dx, dy), Vec3::new(w, w, w));
+            }
+        }
+
+        // Each spike runs at right angles to a blade. With an even
+        // number of blades, opposite blades share their spikes.
+        let mut spikes: HashMap<(i32, i32), Vec3> = HashMap::new();
+        if let Some(blades) = self.blades {
+            let length = SPIKE_LENGTH * diagonal;
+            let falloff = SPIKE_FALLOFF * length;
+            let count = if blades % 2 == 0 { blades } else { 2 * blades };
+
+            // The first spike runs out through the middle of the
+            // blade between the first two corners of the aperture
+            let first = consts::FRAC_PI_2 + self.blade_rotation + consts::PI / blades as f32;
+            for k in 0..count {
+                let angle = first + 2.0 * consts::PI * k as f32 / count as f32;
+                let (dx, dy) = (angle.cos(), angle.sin());
+
+                for step in 1..length as usize {
+                    let s = step as f32;
+                    let mut w = Vec3::new(0.0, 0.0, 0.0);
+                    for (c, spread) in SPIKE_SPREAD.iter().enumerate() {
+                        let t = s / (falloff * spread);
+                        w.e[c] = taper(s, length * spread) / (1.0 + t * t);
+                    }
+                    splat(&mut spikes, s * dx, s * dy, w);
+                }
+            }
+        }
+
+        let glow_share = if spikes.is_empty() { 1.0 } else { GLOW_SHARE };
+        let mut kernel: Vec<(i32, i32, Vec3)> = Vec::new();
+        for (part, share) in [(glow, glow_share), (spikes, 1.0 - glow_share)].iter() {
+            let total = part.values().fold(Vec3::new(0.0, 0.0, 0.0), |sum, w| sum + *w);
+            for (&(dx, dy), w) in part {
+                let w = Vec3::new(w.r() / total.r(), w.g() / total.g(), w.b() / total.b());
+                kernel.push((dx, dy, *share * w));
+            }
+        }
+
+        kernel
+    }
+}
+
+// Spread a weight over the four pixels around a point
+fn splat(weights: &mut HashMap<(i32, i32), Vec3>, x: f32, y: f32, w: Vec3) {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    for &(ox, oy, share) in &[(0, 0, (1.0 - fx) * (1.0 - fy)), (1, 0, fx * (1.0 - fy)),
+                              (0, 1, (1.0 - fx) * fy), (1, 1, fx * fy)] {
+        *weights.entry((x0 as i32 + ox, y0 as i32 + oy)).or_insert(Vec3::new(0.0, 0.0, 0.0)) += share * w;
+    }
+}
+
+// A texture at (u, v), where both run from 0 to 1 across it, blending
+// the four nearest pixels
+fn sample(width: usize, height: usize, data: &[Vec3], u: f32, v: f32) -> Vec3 {
+    let x = (u * width as f32 - 0.5).max(0.0);
+    let y = (v * height as f32 - 0.5).max(0.0);
+    let (x0, y0) = ((x as usize).min(width - 1), (y as usize).min(height - 1));
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let bottom = (1.0 - fx) * data[y0 * width + x0] + fx * data[y0 * width + x1];
+    let top = (1.0 - fx) * data[y1 * width + x0] + fx * data[y1 * width + x1];
+    (1.0 - fy) * bottom + fy * top
+}
+
+///
+/// The light in every pixel over `threshold`, keeping its hue: a pixel
+/// whose brightest channel is twice the threshold keeps half of its
+/// color, and pixels under it are black.
+///
+pub fn excess(data: &[Vec3], threshold: f32) -> Vec<Vec3> {
+    data.iter().map(|col| {
+        let brightest = col.r().max(col.g()).max(col.b());
+        if brightest > threshold {
+            ((brightest - threshold) / brightest) * *col
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+    }).collect()
+}
+
+///
+/// An approximately Gaussian blur of an image `width` by `height`
+/// pixels, made of `BLUR_PASSES` box blurs of `radius` pixels across
+/// and then down the image.
+///
+pub fn blur(data: &[Vec3], width: usize, height: usize, radius: usize) -> Vec<Vec3> {
+    let mut image: Vec<Vec3> = data.to_vec();
+    let mut scratch: Vec<Vec3> = vec![Vec3::new(0.0, 0.0, 0.0); data.len()];
+
+    for _ in 0..BLUR_PASSES {
+        box_blur(&image, &mut scratch, width, height, width, 1, radius);
+        box_blur(&scratch, &mut image, height, width, 1, width, radius);
+    }
+
+    image
+}
+
+// Average every pixel with the `radius` pixels on either side of it,
+// along `lines` lines of `length` pixels each, which start `spacing`
+// apart in the data and have their pixels `step` apart. Pixels off the
+// edge of the image count as black.
+fn box_blur(src: &[Vec3], dst: &mut [Vec3], length: usize, lines: usize, spacing: usize, step: usize,
+            radius: usize) {
+    let scale = 1.0 / (2 * radius + 1) as f32;
+
+    for line in 0..lines {
+        let at = |i: usize| line * spacing + i * step;
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..radius.min(length) {
+            sum += src[at(i)];
+        }
+
+        for i in 0..length {
+            if i + radius < length {
+                sum += src[at(i + radius)];
+            }
+            dst[at(i)] = scale * sum;
+            if i >= radius {
+                sum -= src[at(i - radius)];
+            }
+        }
+    }
+}
@@ -0,0 +1,261 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use vec3::Vec3;
+use ray::Ray;
+use aabb::Aabb;
+use hittable::{Hit, Hittable};
+use accelerator;
+
+// Roughly how many cells to make per object
+const CELLS_PER_OBJECT: f32 = 2.0;
+
+// No axis is split into more cells than this
+const MAX_RESOLUTION: usize = 128;
+
+// Cells holding at least this many objects get a grid of their own...
+const SUBGRID_MIN_OBJECTS: usize = 16;
+
+// ...if it spreads them out so that its cells hold no more than this
+// fraction of them on average
+const SUBGRID_MAX_CROWDING: f32 = 0.5;
+
+///
+/// A uniform grid over the scene. Each cell lists the objects that
+/// overlap it, and rays step through the cells they pass in order
+/// (Amanatides and Woo's 3D DDA), so only the objects near the ray
+/// are tested. Works best for many small objects of similar size,
+/// spread evenly through the scene. Cells that still end up crowded,
+/// where objects bunch up, get a finer grid of their own, which rays
+/// step through in the same way while they're in the cell.
+///
+pub struct Grid {
+    bounds: Aabb,
+    resolution: [usize; 3],
+    cell_size: Vec3,
+    // Objects in cell i are cell_objects[cell_start[i]..cell_start[i + 1]]
+    cell_start: Vec<usize>,
+    cell_objects: Vec<usize>,
+    // The grid of each crowded cell, by cell; empty in a cell's own grid
+    subgrids: Vec<Option<Box<Grid>>>,
+    // Unbounded and very large objects
+    large: Vec<usize>,
+}
+
+impl Grid {
+    pub fn new(objects: &[Box<dyn Hittable+Sync+Send>]) -> Grid {
+        let (small, large) = accelerator::partition(objects);
+
+        let bounds = match small.first() {
+            Some(&(_, first)) => small.iter().fold(first, |acc, &(_, b)| acc.union(&b)),
+            None => Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0)),
+        };
+
+        Grid::build(&small, bounds, large, true)
+    }
+
+    // A grid over `bounds` of the given objects and their bounds,
+    // giving crowded cells grids of their own if `nested`
+    fn build(items: &[(usize, Aabb)], bounds: Aabb, large: Vec<usize>, nested: bool) -> Grid {
+        // Pick cells that are as close to cubes as possible
+        let extent = bounds.extent();
+        let volume = (extent.x() * extent.y() * extent.z()).max(1e-9);
+        let cells_per_unit = (CELLS_PER_OBJECT * items.len() as f32 / volume).cbrt();
+        let mut resolution = [1; 3];
+        for (axis, r) in resolution.iter_mut().enumerate() {
+            *r = ((extent.e[axis] * cells_per_unit).round() as usize).clamp(1, MAX_RESOLUTION);
+        }
+        let cell_size = Vec3::new(extent.x() / resolution[0] as f32,
+                                  extent.y() / resolution[1] as f32,
+                                  extent.z() / resolution[2] as f32);
+
+        let mut grid = Grid {
+            bounds,
+            resolution,
+            cell_size,
+            cell_start: Vec::new(),
+            cell_objects: Vec::new(),
+            subgrids: Vec::new(),
+            large,
+        };
+
+        let cell_count = resolution[0] * resolution[1] * resolution[2];
+        let mut cells: Vec<Vec<(usize, Aabb)>> = vec![Vec::new(); cell_count];
+        for &(i, b) in items {
+            let lo = grid.cell_of(&b.min);
+            let hi = grid.cell_of(&b.max);
+            for z in lo[2]..=hi[2] {
+                for y in lo[1]..=hi[1] {
+                    for x in lo[0]..=hi[0] {
+                        cells[grid.index(x, y, z)].push((i, b));
+                    }
+                }
+            }
+        }
+
+        grid.cell_start.reserve(cell_count + 1);
+        for cell in &cells {
+            grid.cell_start.push(grid.cell_objects.len());
+            grid.cell_objects.extend(cell.iter().map(|&(i, _)| i));
+        }
+        grid.cell_start.push(grid.cell_objects.len());
+
+        if nested && cells.iter().any(|cell| cell.len() >= SUBGRID_MIN_OBJECTS) {
+            grid.subgrids = cells.iter().enumerate().map(|(index, cell)| {
+                if cell.len() < SUBGRID_MIN_OBJECTS {
+                    return None;
+                }
+                let subgrid = Grid::build(cell, grid.cell_bounds(index), Vec::new(), false);
+                if subgrid.objects_per_cell() <= SUBGRID_MAX_CROWDING * cell.len() as f32 {
+                    Some(Box::new(subgrid))
+                } else {
+                    None
+                }
+            }).collect();
+        }
+
+        grid
+    }
+
+    ///
+    /// The average number of objects in the cells that aren't empty.
+    /// A ray that enters a cell has to test all of them, so this
+    /// grows when objects bunch up in a few places.
+    ///
+    pub fn objects_per_cell(&self) -> f32 {
+        let occupied = self.cell_start.windows(2).filter(|w| w[1] > w[0]).count();
+        self.cell_objects.len() as f32 / occupied.max(1) as f32
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (z * self.resolution[1] + y) * self.resolution[0] + x
+    }
+
+    fn cell_bounds(&self, index: usize) -> Aabb {
+        let x = index % self.resolution[0];
+        let y = index / self.resolution[0] % self.resolution[1];
+        let z = index / (self.resolution[0] * self.resolution[1]);
+        let min = self.bounds.min + Vec3::new(x as f32 * self.cell_size.x(),
+                                              y as f32 * self.cell_size.y(),
+                                              z as f32 * self.cell_size.z());
+        Aabb::new(min, min + self.cell_size)
+    }
+
+    // The cell containing a point, clamped to the grid
+    fn cell_of(&self, p: &Vec3) -> [usize; 3] {
+        let mut cell = [0; 3];
+        for (axis, c) in cell.iter_mut().enumerate() {
+            let offset = (p.e[axis] - self.bounds.min.e[axis]) / self.cell_size.e[axis];
+            *c = if offset.is_finite() {
+                (offset.max(0.0) as usize).min(self.resolution[axis] - 1)
+            } else {
+                0
+            };
+        }
+        cell
+    }
+
+    pub fn hit<'a>(&self, objects: &'a [Box<dyn Hittable+Sync+Send>], r: &Ray,
+                   t_min: f32, t_max: f32) -> Option<Hit<'a>> {
+        let mut closest: Option<Hit<'a>> = None;
+        let mut closest_so_far = t_max;
+
+        for &i in &self.large {
+            if let Some(h) = objects[i].hit(r, t_min, closest_so_far) {
+                closest_so_far = h.t;
+                closest = Some(h);
+            }
+        }
+
+        self.traverse(objects, r, t_min, &mut closest_so_far, &mut closest);
+        closest
+    }
+
+    // Step the ray through the cells of the grid, testing the objects
+    // in each, until it leaves the grid or passes the closest hit
+    fn traverse<'a>(&self, objects: &'a [Box<dyn Hittable+Sync+Send>], r: &Ray, t_min: f32,
+                    closest_so_far: &mut f32, closest: &mut Option<Hit<'a>>) {
+        let (t_enter, t_exit) = match self.bounds.hit(r, t_min, *closest_so_far) {
+            Some(range) => range,
+            None => return,
+        };
+
+        let origin = r.origin();
+        let direction = r.direction();
+        let mut cell = self.cell_of(&r.point_at_parameter(t_enter));
+        let mut step = [0i64; 3];
+        let mut t_next = [f32::MAX; 3];
+        let mut t_delta = [f32::MAX; 3];
+
+        for axis in 0..3 {
+            let d = direction.e[axis];
+            if d > 0.0 {
+                step[axis] = 1;
+                let boundary = self.bounds.min.e[axis] + (cell[axis] + 1) as f32 * self.cell_size.e[axis];
+                t_next[axis] = (boundary - origin.e[axis]) / d;
+                t_delta[axis] = self.cell_size.e[axis] / d;
+            } else if d < 0.0 {
+                step[axis] = -1;
+                let boundary = self.bounds.min.e[axis] + cell[axis] as f32 * self.cell_size.e[axis];
+                t_next[axis] = (boundary - origin.e[axis]) / d;
+                t_delta[axis] = -self.cell_size.e[axis] / d;
+            }
+        }
+
+        loop {
+            let index = self.index(cell[0], cell[1], cell[2]);
+            // A ray grazing the cell can miss its grid by rounding,
+            // and then just tests the objects in the cell
+            match self.subgrids.get(index) {
+                Some(Some(subgrid)) if subgrid.bounds.hit(r, t_min, *closest_so_far).is_some() => {
+                    subgrid.traverse(objects, r, t_min, closest_so_far, closest)
+                },
+                _ => {
+                    for &i in &self.cell_objects[self.cell_start[index]..self.cell_start[index + 1]] {
+                        if let Some(h) = objects[i].hit(r, t_min, *closest_so_far) {
+                            *closest_so_far = h.t;
+                            *closest = Some(h);
+                        }
+                    }
+                },
+            }
+
+            // Step to the neighboring cell across the nearest boundary
+            let axis = if t_next[0] < t_next[1] {
+                if t_next[0] < t_next[2] { 0 } else { 2 }
+            } else if t_next[1] < t_next[2] {
+                1
+            } else {
+                2
+            };
+
+            // Anything hit so far is closer than whatever lies beyond this cell
+            if *closest_so_far <= t_next[axis] || t_next[axis] > t_exit {
+                return;
+            }
+
+            let next = cell[axis] as i64 + step[axis];
+            if next < 0 || next >= self.resolution[axis] as i64 {
+                return;
+            }
+            cell[axis] = next as usize;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
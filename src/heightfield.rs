@@ -0,0 +1,136 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+use std::path::Path;
+
+use import::Import;
+use output;
+use shader;
+use vec3::Vec3;
+
+// Points along each side of a noise terrain, and the number of
+// features across it
+const NOISE_RESOLUTION: usize = 128;
+const NOISE_FEATURES: f32 = 4.0;
+const NOISE_OCTAVES: u32 = 6;
+
+///
+/// The heights of a patch of terrain, sampled on a square grid
+/// `width` points across (along x) and `depth` points deep (along z),
+/// nearest row first. Heights mostly run from 0 to 1, and are scaled
+/// to the range the terrain is given when it's triangulated.
+///
+pub struct Heightfield {
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+}
+
+impl Heightfield {
+    ///
+    /// Read the heights from an image, black being lowest and white
+    /// highest, with the image's bottom row nearest and its top
+    /// furthest away. PFM files (`.pfm`) keep their float values, so
+    /// they can go beyond 1 for finer steps than 8 bits; anything
+    /// else is read as a PNG. Colors are averaged to grey.
+    ///
+    pub fn load(path: &Path) -> Result<Heightfield, String> {
+        let pfm = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pfm"));
+        let (width, depth, pixels) = if pfm { output::read_pfm(path) } else { output::read_png(path) }
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        if width < 2 || depth < 2 {
+            return Err(format!("{}: a heightfield needs at least 2x2 pixels", path.display()));
+        }
+
+        let heights = pixels.iter().map(|p| (p.r() + p.g() + p.b()) / 3.0).collect();
+        Ok(Heightfield { width, depth, heights })
+    }
+
+    ///
+    /// Rolling hills of fractal noise (see shader::fractal_noise) drawn
+    /// from `seed`, stretched so the lowest point is 0 and the highest
+    /// is 1.
+    ///
+    pub fn noise(seed: u32) -> Heightfield {
+        let n = NOISE_RESOLUTION;
+        let scale = NOISE_FEATURES / (n - 1) as f32;
+        let mut heights: Vec<f32> = (0..n * n)
+            .map(|i| {
+                let p = Vec3::new((i % n) as f32 * scale, 0.0, (i / n) as f32 * scale);
+                shader::fractal_noise(&p, NOISE_OCTAVES, seed)
+            })
+            .collect();
+
+        let low = heights.iter().cloned().fold(f32::INFINITY, f32::min);
+        let high = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let range = (high - low).max(f32::EPSILON);
+        for h in &mut heights {
+            *h = (*h - low) / range;
+        }
+
+        Heightfield { width: n, depth: n, heights }
+    }
+
+    fn height(&self, x: usize, z: usize) -> f32 {
+        self.heights[z * self.width + x]
+    }
+
+    ///
+    /// Triangulate the terrain, `size` units across along x and as deep
+    /// along z as keeps the grid square, centered on the origin, with
+    /// heights running from `low` to `high`. Each grid square is split
+    /// into two triangles facing up, smoothed by normals worked out
+    /// from the heights around each point.
+    ///
+    pub fn triangulate(&self, size: f32, low: f32, high: f32) -> Import {
+        let step = size / (self.width - 1) as f32;
+        let half_depth = 0.5 * step * (self.depth - 1) as f32;
+
+        let point = |x: usize, z: usize| Vec3::new(
+            x as f32 * step - 0.5 * size,
+            low + (high - low) * self.height(x, z),
+            half_depth - z as f32 * step,
+        );
+
+        // The slope at a point, from its neighbors on either side, or
+        // from the point itself at the edges. Rows run towards -z
+        let normal = |x: usize, z: usize| {
+            let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+            let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+            let dx = (point(x1, z).y() - point(x0, z).y()) / ((x1 - x0) as f32 * step);
+            let dz = (point(x, z0).y() - point(x, z1).y()) / ((z1 - z0) as f32 * step);
+            Vec3::unit_vector(&Vec3::new(-dx, 1.0, -dz))
+        };
+
+        let mut import = Import::default();
+        for z in 0..self.depth - 1 {
+            for x in 0..self.width - 1 {
+                let corners = [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)];
+                for triangle in [[0, 1, 2], [1, 3, 2]] {
+                    let c = triangle.map(|i| corners[i]);
+                    let params: Vec<f32> = c.iter().map(|&(x, z)| point(x, z))
+                        .chain(c.iter().map(|&(x, z)| normal(x, z)))
+                        .flat_map(|v| [v.x(), v.y(), v.z()])
+                        .collect();
+                    import.triangles.push((0, params));
+                }
+            }
+        }
+        import
+    }
+}
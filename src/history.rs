@@ -0,0 +1,74 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+///
+/// A list of edits that can be undone and redone. Each edit should
+/// carry whatever it needs to be reversed, e.g. the state of the
+/// thing it changed from before and after.
+///
+pub struct History<T> {
+    done: Vec<T>,
+    undone: Vec<T>,
+}
+
+impl<T> Default for History<T> {
+    fn default() -> History<T> {
+        History {
+            done: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+}
+
+impl<T> History<T> {
+    /// Record an edit that has just been made. Anything that had been
+    /// undone can no longer be redone.
+    pub fn record(&mut self, edit: T) {
+        self.done.push(edit);
+        self.undone.clear();
+    }
+
+    /// Drop the edits that have been undone, so they can no longer be
+    /// redone, and return them, most recently undone first, in case the
+    /// caller has anything to clean up. Recording an edit drops them too.
+    pub fn forget_undone(&mut self) -> Vec<T> {
+        self.undone.drain(..).rev().collect()
+    }
+
+    /// Take back the most recent edit, returning it so the caller can
+    /// reverse it.
+    pub fn undo(&mut self) -> Option<&T> {
+        let edit = self.done.pop()?;
+        self.undone.push(edit);
+        self.undone.last()
+    }
+
+    /// Make the most recently undone edit again, returning it so the
+    /// caller can reapply it.
+    pub fn redo(&mut self) -> Option<&T> {
+        let edit = self.undone.pop()?;
+        self.done.push(edit);
+        self.done.last()
+    }
+
+    /// The edits currently in effect, oldest first.
+    pub fn done(&self) -> &[T] {
+        &self.done
+    }
+}
@@ -16,25 +16,34 @@
 /// <https://www.gnu.org/licenses/>.
 ///
 
-use rand::prelude::*;
 use vec3::Vec3;
 use ray::Ray;
+use aabb::Aabb;
+use accelerator::{Accelerator, AcceleratorKind};
+use edit::PendingEdits;
+use sampler::Sampler;
+use clouds::Clouds;
+use sky::Sky;
+use spectrum;
+use spectrum::Dispersion;
 use std::vec::Vec;
 use std::i32;
-
-fn random_in_unit_sphere() -> Vec3 {
-    let mut rng = thread_rng();
-    let mut vec: Vec3 = 2.0 * Vec3::new(rng.gen(), rng.gen(), rng.gen());
-
-    loop {
-        if vec.squared_length() >= 1.0 {
-            return vec
-        }
-
-        vec.e[0] = rng.gen();
-        vec.e[1] = rng.gen();
-        vec.e[2] = rng.gen();
-    }
+use std::f32::consts;
+use std::path::Path;
+use std::sync::Arc;
+
+// Uniformly distributed point inside the unit sphere, built from
+// three sample dimensions (a direction and a radius) rather than by
+// rejection, so that it works with low-discrepancy samplers.
+fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u, v) = sampler.next_2d();
+    let w = sampler.next_1d();
+
+    let z: f32 = 1.0 - 2.0 * u;
+    let r: f32 = (1.0 - z * z).max(0.0).sqrt();
+    let phi: f32 = 2.0 * consts::PI * v;
+
+    w.cbrt() * Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
 pub struct Reflection {
@@ -44,9 +53,25 @@ pub struct Reflection {
 }
 
 pub trait Material {
-    fn scatter(&self, r_in: &Ray, hit: &Hit) -> Reflection;
+    fn scatter(&self, r_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Reflection;
 
     fn albedo(&self) -> Vec3;
+
+    // A cheaper stand-in for scatter, used for the quick passes
+    // rendered while the camera moves with --simple-preview
+    fn preview_scatter(&self, r_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Reflection {
+        self.scatter(r_in, hit, sampler)
+    }
+
+    // Light given off by the surface, and the light group it counts
+    // towards (see World::light_group)
+    fn emitted(&self) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn light_group(&self) -> usize {
+        SKY_LIGHT_GROUP
+    }
 }
 
 // Lambertian (diffuse) Material
@@ -59,9 +84,16 @@ pub struct Metal {
     albedo: Vec3,
 }
 
-// Dialectric Material
+// Dialectric Material, whose index of refraction can vary with
+// wavelength
 pub struct Dialectric {
-    ref_idx: f32,
+    dispersion: Dispersion,
+}
+
+// Diffuse light (emissive) Material
+pub struct DiffuseLight {
+    emit: Vec3,
+    group: usize,
 }
 
 impl Lambertian {
@@ -78,13 +110,29 @@ impl Metal {
 
 impl Dialectric {
     pub fn new(ref_idx: f32) -> Dialectric {
-        Dialectric { ref_idx }
+        Dialectric { dispersion: Dispersion::None(ref_idx) }
+    }
+
+    pub fn dispersive(dispersion: Dispersion) -> Dialectric {
+        Dialectric { dispersion }
+    }
+
+    // The index of refraction for the wavelength a ray carries, or at
+    // the d line for a ray carrying every wavelength
+    fn ref_idx(&self, r: &Ray) -> f32 {
+        self.dispersion.index(r.wavelength().unwrap_or(spectrum::D_LINE))
+    }
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Vec3, group: usize) -> DiffuseLight {
+        DiffuseLight { emit, group }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _: &Ray, hit: &Hit) -> Reflection {
-        let target: Vec3 = hit.p + hit.normal + random_in_unit_sphere();
+    fn scatter(&self, _: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Reflection {
+        let target: Vec3 = hit.p + hit.normal + random_in_unit_sphere(sampler);
 
         Reflection {
             scattered: Ray::new(hit.p, target - hit.p),
@@ -99,7 +147,7 @@ impl Material for Lambertian {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, hit: &Hit) -> Reflection {
+    fn scatter(&self, r_in: &Ray, hit: &Hit, _: &mut dyn Sampler) -> Reflection {
         let reflected: Vec3 = Vec3::reflect(&Vec3::unit_vector(&r_in.direction()), &hit.normal);
         let scattered: Ray = Ray::new(hit.p, reflected);
         let direction: Vec3 = scattered.direction();
@@ -141,7 +189,8 @@ fn schlick(cosine: f32, ref_idx: f32) -> f32 {
 
 
 impl Material for Dialectric {
-    fn scatter(&self, r_in: &Ray, hit: &Hit) -> Reflection {
+    fn scatter(&self, r_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Reflection {
+        let ref_idx: f32 = self.ref_idx(r_in);
         let reflected: Vec3 = Vec3::reflect(&r_in.direction(), &hit.normal);
         let dot_positive: bool = Vec3::dot(&r_in.direction(), &hit.normal) > 0.0;
 
@@ -152,13 +201,13 @@ impl Material for Dialectric {
         };
 
         let ni_over_nt: f32 = if dot_positive {
-            self.ref_idx
+            ref_idx
         } else {
-            1.0 / self.ref_idx
+            1.0 / ref_idx
         };
 
         let cosine: f32 = if dot_positive {
-            self.ref_idx * Vec3::dot(&r_in.direction(), &hit.normal) / r_in.direction().length()
+            ref_idx * Vec3::dot(&r_in.direction(), &hit.normal) / r_in.direction().length()
         } else {
             -Vec3::dot(&r_in.direction(), &hit.normal) / r_in.direction().length()
         };
@@ -167,7 +216,7 @@ impl Material for Dialectric {
 
         let reflect_prob: f32 = match refraction {
             Some(_) => {
-                schlick(cosine, self.ref_idx)
+                schlick(cosine, ref_idx)
             },
             None => {
                 1.0
@@ -183,7 +232,7 @@ impl Material for Dialectric {
             }
         };
 
-        let scattered: Ray = if random::<f32>() < reflect_prob {
+        let scattered: Ray = if sampler.next_1d() < reflect_prob {
             Ray::new(hit.p, reflected)
         } else {
             Ray::new(hit.p, refracted)
@@ -199,6 +248,38 @@ impl Material for Dialectric {
     fn albedo(&self) -> Vec3 {
         Vec3::new(1.0, 1.0, 1.0)
     }
+
+    // A perfect mirror: one bounce instead of a noisy random choice
+    // between reflecting and refracting
+    fn preview_scatter(&self, r_in: &Ray, hit: &Hit, _: &mut dyn Sampler) -> Reflection {
+        Reflection {
+            scattered: Ray::new(hit.p, Vec3::reflect(&r_in.direction(), &hit.normal)),
+            attenuation: Vec3::new(1.0, 1.0, 1.0),
+            reflected: true,
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, r_in: &Ray, hit: &Hit, _: &mut dyn Sampler) -> Reflection {
+        Reflection {
+            scattered: Ray::new(hit.p, r_in.direction()),
+            attenuation: self.albedo(),
+            reflected: false,
+        }
+    }
+
+    fn albedo(&self) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn emitted(&self) -> Vec3 {
+        self.emit
+    }
+
+    fn light_group(&self) -> usize {
+        self.group
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -206,26 +287,60 @@ pub struct Hit<'a> {
     pub t: f32,
     pub p: Vec3,
     pub normal: Vec3,
+    // The color painted on the surface there, for meshes with colored
+    // vertices
+    pub color: Option<Vec3>,
     pub object: &'a Hittable,
 }
 
+///
+/// A material that any number of objects can share, such as every
+/// object in a scene given the same named material.
+///
+pub type SharedMaterial = Arc<dyn Material+Sync+Send>;
+
 pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit>;
-    fn material(&self) -> &Box<Material+Sync+Send>;
+    fn material(&self) -> &dyn Material;
+
+    // None for objects without a finite extent
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    // The object's stable ID (see ObjectIds), or 0 if it has none
+    fn id(&self) -> u32 {
+        0
+    }
+
+    // The center and radius of the object if hitting it is exactly
+    // hitting a Sphere there, so it can be tested along with other
+    // spheres (see Spheres)
+    fn as_sphere(&self) -> Option<(Vec3, f32)> {
+        None
+    }
 }
 
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
-    pub material: Box<Material+Sync+Send>,
+    pub material: SharedMaterial,
 }
 
 pub struct World {
     pub objects: Vec<Box<Hittable+Sync+Send>>,
+    pub sky: Sky,
+    pub clouds: Option<Clouds>,
+    pub light_groups: Vec<String>,
+    pub accelerator: Accelerator,
+    pub pending: PendingEdits,
 }
 
+// Light from the sky always goes to the first light group
+pub const SKY_LIGHT_GROUP: usize = 0;
+
 impl Sphere {
-    pub fn new(center: Vec3, radius: f32, material: Box<Material+Sync+Send>) -> Sphere {
+    pub fn new(center: Vec3, radius: f32, material: SharedMaterial) -> Sphere {
         Sphere { center, radius, material }
     }
 }
@@ -242,15 +357,24 @@ impl Hittable for Sphere {
             let tmp: f32 = (-b - (b * b - a * c).sqrt()) / a;
             if tmp < t_max && tmp > t_min {
                 let p: Vec3 = r.point_at_parameter(tmp);
-                return Some(Hit { t: tmp, p: p, normal: (p - self.center) / self.radius, object: self })
+                return Some(Hit { t: tmp, p: p, normal: (p - self.center) / self.radius, color: None, object: self })
             }
         }
 
         None
     }
 
-    fn material(&self) -> &Box<Material+Sync+Send> {
-        &self.material
+    fn material(&self) -> &dyn Material {
+        &*self.material
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
+    fn as_sphere(&self) -> Option<(Vec3, f32)> {
+        Some((self.center, self.radius))
     }
 }
 
@@ -262,27 +386,45 @@ impl Hittable for Sphere {
 
 impl World {
     pub fn new() -> World {
-        World { objects: Vec::new() }
+        World {
+            objects: Vec::new(),
+            sky: Sky::default(),
+            clouds: None,
+            light_groups: vec!["sky".to_string()],
+            accelerator: Accelerator::List,
+            pending: PendingEdits::default(),
+        }
     }
-}
 
-impl World {
-    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
-        let mut hits: Vec<Hit> = Vec::new();
-        let mut closest_so_far: f32 = t_max;
-
-        for object in &self.objects {
-            let hit: Option<Hit> = object.hit(r, t_min, closest_so_far);
-
-            match hit {
-                Some(h) => {
-                    closest_so_far = h.t;
-                    hits.push(h);
-                },
-                None => {}
+    /// What a ray going in `direction` sees once it leaves the scene.
+    pub fn background(&self, direction: &Vec3) -> Vec3 {
+        match self.clouds {
+            Some(ref clouds) => clouds.color(&self.sky, direction),
+            None => self.sky.color(direction),
+        }
+    }
+
+    /// Build an acceleration structure over the objects, using the
+    /// BVH cache in `cache` if it's given. After that, objects should
+    /// only be changed with `apply` and `commit`.
+    pub fn build_accelerator(&mut self, kind: AcceleratorKind, cache: Option<&Path>) {
+        self.accelerator = Accelerator::build(kind, &self.objects, cache);
+    }
+
+    /// The index of the named light group, creating it if needed.
+    pub fn light_group(&mut self, name: &str) -> usize {
+        match self.light_groups.iter().position(|g| g == name) {
+            Some(i) => i,
+            None => {
+                self.light_groups.push(name.to_string());
+                self.light_groups.len() - 1
             }
         }
+    }
+}
 
-        hits.pop()
+impl World {
+    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        self.accelerator.hit(&self.objects, r, t_min, t_max)
     }
 }
@@ -0,0 +1,728 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use std::fs;
+use std::path::Path;
+use std::str;
+
+use vec3::Vec3;
+
+///
+/// A material read from another format, as one of the registered
+/// material types and the numbers it takes, or as `shaded`: a diffuse
+/// surface with a mirror coat, given by the diffuse color, the weight
+/// of the mirror and its color.
+///
+pub struct ImportedMaterial {
+    pub name: String,
+    pub kind: String,
+    pub params: Vec<f32>,
+}
+
+///
+/// What a scene gets from a file in another format: its materials,
+/// its meshes as triangles (each with the index of its material and
+/// the numbers of a `triangle`), and where each of its cameras looks
+/// from and at. Formats without materials leave `materials` empty,
+/// and all their triangles take the one material the scene gives.
+///
+#[derive(Default)]
+pub struct Import {
+    pub materials: Vec<ImportedMaterial>,
+    pub triangles: Vec<(usize, Vec<f32>)>,
+    pub cameras: Vec<(Vec3, Vec3)>,
+}
+
+///
+/// Read the default scene of a glTF 2.0 file, `.gltf` or `.glb`. Every
+/// mesh is placed where its node's transforms put it, in triangles
+/// with smooth normals if the file has them. Materials keep only the
+/// factors of their metallic-roughness model, mapped onto the closest
+/// built in material: glass if they're mostly transmissive, metal if
+/// they're mostly metallic and smooth, and diffuse otherwise, with the
+/// base color as their albedo. Textures and emission are left out.
+/// Perspective cameras become where to look from and at.
+///
+/// glTF files need the `gltf` feature.
+///
+pub fn load_gltf(path: &Path) -> Result<Import, String> {
+    imp::load_gltf(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// The smallest area of a triangle worth importing
+const MIN_AREA: f32 = 1e-12;
+
+// Add a triangle with corners `p` and, optionally, normals `n` and
+// colors `c` at them, unless it's too small to be hit. Colors come
+// after normals, so a triangle with colors and no normals is given
+// its own flat normal at each corner.
+fn push_triangle(import: &mut Import, material: usize, p: [Vec3; 3], n: Option<[Vec3; 3]>, c: Option<[Vec3; 3]>) {
+    let face = Vec3::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+    if face.length() < MIN_AREA {
+        return;
+    }
+
+    let n = match (n, c) {
+        (None, Some(_)) => Some([Vec3::unit_vector(&face); 3]),
+        _ => n,
+    };
+    let mut params: Vec<f32> = p.iter().flat_map(|v| [v.x(), v.y(), v.z()]).collect();
+    for v in n.iter().chain(c.iter()).flatten() {
+        params.extend([v.x(), v.y(), v.z()]);
+    }
+    import.triangles.push((material, params));
+}
+
+///
+/// Read an STL file, binary or ASCII, as is common for models made
+/// for 3D printing. STL files have no materials, and the normals they
+/// give are often wrong, so triangles face the way their corners wind
+/// instead, which is what printers go by too.
+///
+pub fn load_stl(path: &Path) -> Result<Import, String> {
+    let data = fs::read(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let mut import = Import::default();
+
+    // Binary files start with an 80 byte header, which can itself
+    // start with "solid", so they're told apart by their length
+    let binary = data.len() >= 84 && {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        data.len() == 84 + count * 50
+    };
+
+    if binary {
+        for facet in data[84..].chunks_exact(50) {
+            let number = |i: usize| f32::from_le_bytes([facet[i], facet[i + 1], facet[i + 2], facet[i + 3]]);
+            let corner = |i: usize| Vec3::new(number(12 + i * 12), number(16 + i * 12), number(20 + i * 12));
+            push_triangle(&mut import, 0, [corner(0), corner(1), corner(2)], None, None);
+        }
+    } else {
+        let text = str::from_utf8(&data).map_err(|_| format!("{}: not an STL file", path.display()))?;
+        let mut corners: Vec<Vec3> = Vec::with_capacity(3);
+
+        for (n, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.first() {
+                Some(&"vertex") => {
+                    let v: Vec<f32> = fields[1..].iter().filter_map(|f| f.parse().ok()).collect();
+                    if v.len() != 3 || fields.len() != 4 {
+                        return Err(format!("{}:{}: vertex needs an x, y and z", path.display(), n + 1));
+                    }
+                    corners.push(Vec3::new(v[0], v[1], v[2]));
+                },
+                Some(&"endloop") => {
+                    if corners.len() != 3 {
+                        return Err(format!("{}:{}: facets need 3 vertices", path.display(), n + 1));
+                    }
+                    push_triangle(&mut import, 0, [corners[0], corners[1], corners[2]], None, None);
+                    corners.clear();
+                },
+                _ => {},
+            }
+        }
+    }
+
+    if import.triangles.is_empty() {
+        return Err(format!("{}: no triangles", path.display()));
+    }
+    Ok(import)
+}
+
+///
+/// Read a PLY file, ASCII or binary, as 3D scanners make them. Faces
+/// with more than three corners are split into fans of triangles.
+/// Vertex normals (`nx`, `ny`, `nz`) smooth the mesh where the file
+/// has them, and vertex colors (`red`, `green`, `blue`, as bytes or
+/// as numbers from 0 to 1) are kept for the material to use (see
+/// Node::VertexColor). Colors are stored for display, so they're
+/// squared back to linear light, undoing the gamma of 2 the renderer
+/// gives its images. PLY files have no materials.
+///
+pub fn load_ply(path: &Path) -> Result<Import, String> {
+    let data = fs::read(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    read_ply(&data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+// The number types PLY properties can have
+#[derive(Copy, Clone, PartialEq)]
+enum PlyType {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+struct PlyProperty {
+    name: String,
+    kind: PlyType,
+    // The type of the length of a list property
+    list: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyType {
+    fn from_name(name: &str) -> Option<PlyType> {
+        match name {
+            "char" | "int8" => Some(PlyType::Char),
+            "uchar" | "uint8" => Some(PlyType::UChar),
+            "short" | "int16" => Some(PlyType::Short),
+            "ushort" | "uint16" => Some(PlyType::UShort),
+            "int" | "int32" => Some(PlyType::Int),
+            "uint" | "uint32" => Some(PlyType::UInt),
+            "float" | "float32" => Some(PlyType::Float),
+            "double" | "float64" => Some(PlyType::Double),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::Char | PlyType::UChar => 1,
+            PlyType::Short | PlyType::UShort => 2,
+            PlyType::Int | PlyType::UInt | PlyType::Float => 4,
+            PlyType::Double => 8,
+        }
+    }
+
+    // A color channel of this type as a fraction of full brightness
+    fn fraction(self, value: f64) -> f32 {
+        match self {
+            PlyType::UChar => (value / 255.0) as f32,
+            PlyType::UShort => (value / 65535.0) as f32,
+            _ => value as f32,
+        }
+    }
+}
+
+// The values of a PLY file's elements, one after the other
+struct PlyValues<'a> {
+    format: PlyFormat,
+    body: &'a [u8],
+    words: Option<str::SplitWhitespace<'a>>,
+}
+
+impl<'a> PlyValues<'a> {
+    fn next(&mut self, kind: PlyType) -> Result<f64, String> {
+        if let Some(ref mut words) = self.words {
+            let word = words.next().ok_or("the file ends too soon")?;
+            return word.parse::<f64>().map_err(|_| format!("Invalid number: {}", word));
+        }
+
+        let size = kind.size();
+        if self.body.len() < size {
+            return Err("the file ends too soon".to_string());
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.body[..size]);
+        if self.format == PlyFormat::BigEndian {
+            bytes[..size].reverse();
+        }
+        self.body = &self.body[size..];
+
+        let value = match kind {
+            PlyType::Char => bytes[0] as i8 as f64,
+            PlyType::UChar => bytes[0] as f64,
+            PlyType::Short => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::UShort => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::Int => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::UInt => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::Float => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::Double => f64::from_le_bytes(bytes),
+        };
+        Ok(value)
+    }
+}
+
+fn read_ply(data: &[u8]) -> Result<Import, String> {
+    const END: &[u8] = b"end_header";
+    let end = data.windows(END.len()).position(|w| w == END).ok_or("not a PLY file")?;
+    let header = str::from_utf8(&data[..end]).map_err(|_| "not a PLY file")?;
+    // The body starts on the line after end_header
+    let start = data[end..].iter().position(|&b| b == b'\n').map_or(data.len(), |i| end + i + 1);
+
+    let mut lines = header.lines();
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["format", name, _] => format = Some(match *name {
+                "ascii" => PlyFormat::Ascii,
+                "binary_little_endian" => PlyFormat::LittleEndian,
+                "binary_big_endian" => PlyFormat::BigEndian,
+                _ => return Err(format!("Unknown PLY format: {}", name)),
+            }),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| format!("Invalid element count: {}", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, kind, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::from_name(kind).ok_or(format!("Unknown property type: {}", kind))?,
+                    list: Some(PlyType::from_name(count).ok_or(format!("Unknown property type: {}", count))?),
+                });
+            },
+            ["property", kind, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::from_name(kind).ok_or(format!("Unknown property type: {}", kind))?,
+                    list: None,
+                });
+            },
+            _ => {},
+        }
+    }
+
+    let format = format.ok_or("no format given")?;
+    let mut values = PlyValues { format, body: &data[start..], words: None };
+    if format == PlyFormat::Ascii {
+        values.words = Some(str::from_utf8(&data[start..]).map_err(|_| "not a PLY file")?.split_whitespace());
+    }
+
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut colors: Vec<Vec3> = Vec::new();
+    let mut faces: Vec<Vec<usize>> = Vec::new();
+
+    for element in &elements {
+        // Elements and properties other than these are read past
+        for _ in 0..element.count {
+            let mut vertex = [0.0f32; 9];
+            let mut seen = [false; 9];
+            for property in &element.properties {
+                if let Some(count_type) = property.list {
+                    let count = values.next(count_type)? as usize;
+                    let mut items = Vec::new();
+                    for _ in 0..count {
+                        items.push(values.next(property.kind)? as usize);
+                    }
+                    if element.name == "face" && (property.name == "vertex_indices" || property.name == "vertex_index") {
+                        faces.push(items);
+                    }
+                    continue;
+                }
+
+                let value = values.next(property.kind)?;
+                let slot = ["x", "y", "z", "nx", "ny", "nz", "red", "green", "blue"].iter()
+                    .position(|&name| name == property.name);
+                if let (Some(i), "vertex") = (slot, element.name.as_str()) {
+                    vertex[i] = if i >= 6 { property.kind.fraction(value) } else { value as f32 };
+                    seen[i] = true;
+                }
+            }
+
+            if element.name == "vertex" {
+                positions.push(Vec3::new(vertex[0], vertex[1], vertex[2]));
+                if seen[3..6].iter().all(|&s| s) {
+                    normals.push(Vec3::unit_vector(&Vec3::new(vertex[3], vertex[4], vertex[5])));
+                }
+                if seen[6..9].iter().all(|&s| s) {
+                    colors.push(Vec3::new(vertex[6] * vertex[6], vertex[7] * vertex[7], vertex[8] * vertex[8]));
+                }
+            }
+        }
+    }
+
+    let mut import = Import::default();
+    let has_normals = normals.len() == positions.len();
+    let has_colors = colors.len() == positions.len();
+    for face in &faces {
+        if face.len() < 3 || face.iter().any(|&i| i >= positions.len()) {
+            continue;
+        }
+        for i in 1..face.len() - 1 {
+            let corners = [face[0], face[i], face[i + 1]];
+            push_triangle(&mut import, 0, corners.map(|c| positions[c]),
+                          if has_normals { Some(corners.map(|c| normals[c])) } else { None },
+                          if has_colors { Some(corners.map(|c| colors[c])) } else { None });
+        }
+    }
+
+    if import.triangles.is_empty() {
+        return Err("no triangles".to_string());
+    }
+    Ok(import)
+}
+
+///
+/// Read a Wavefront OBJ file, with the materials of the MTL files it
+/// names (`mtllib`, next to the OBJ file). Polygons are split into
+/// fans of triangles, smoothed by the normals given with their
+/// corners. Materials are mapped onto the closest built in material:
+/// glass if they're mostly see-through (`d` or `Tr`, bent by `Ni`),
+/// metal if they have a sharp highlight (`Ns`) and no diffuse color,
+/// a diffuse surface with a mirror coat if they have both, and
+/// diffuse otherwise. Image textures (`map_Kd` and the like) can't be
+/// used yet, so surfaces take their plain color (`Kd`). Faces before
+/// any `usemtl`, or with a material no MTL file defines, are a plain
+/// grey.
+///
+pub fn load_obj(path: &Path) -> Result<Import, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let file = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut import = Import::default();
+    import.materials.push(ImportedMaterial {
+        name: format!("{}:default", file),
+        kind: "lambertian".to_string(),
+        params: vec![0.8, 0.8, 0.8],
+    });
+    let mut material = 0;
+
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let at = |e: String| format!("{}:{}: {}", path.display(), n + 1, e);
+
+        match fields.first() {
+            Some(&"v") | Some(&"vn") => {
+                let v: Vec<f32> = fields[1..].iter().take(3).map(|f| f.parse::<f32>())
+                    .collect::<Result<_, _>>().map_err(|_| at(format!("Invalid {}", fields[0])))?;
+                if v.len() != 3 {
+                    return Err(at(format!("{} needs an x, y and z", fields[0])));
+                }
+                if fields[0] == "v" {
+                    positions.push(Vec3::new(v[0], v[1], v[2]));
+                } else {
+                    normals.push(Vec3::unit_vector(&Vec3::new(v[0], v[1], v[2])));
+                }
+            },
+            Some(&"f") => {
+                // Corners are v, v/vt, v//vn or v/vt/vn, counting from
+                // 1, or back from the last one read if negative
+                let index = |field: Option<&str>, count: usize| -> Result<Option<usize>, String> {
+                    match field {
+                        None | Some("") => Ok(None),
+                        Some(f) => match f.parse::<i64>() {
+                            Ok(i) if i > 0 && i as usize <= count => Ok(Some(i as usize - 1)),
+                            Ok(i) if i < 0 && (-i) as usize <= count => Ok(Some(count - (-i) as usize)),
+                            _ => Err(at(format!("Invalid index: {}", f))),
+                        },
+                    }
+                };
+                let mut corners: Vec<(usize, Option<usize>)> = Vec::with_capacity(fields.len() - 1);
+                for field in &fields[1..] {
+                    let mut parts = field.split('/');
+                    let v = index(parts.next(), positions.len())?.ok_or_else(|| at("Face corner without a vertex".to_string()))?;
+                    parts.next();
+                    corners.push((v, index(parts.next(), normals.len())?));
+                }
+
+                let smooth = corners.iter().all(|c| c.1.is_some());
+                for i in 1..corners.len().saturating_sub(1) {
+                    let c = [corners[0], corners[i], corners[i + 1]];
+                    let n = if smooth { Some(c.map(|c| normals[c.1.unwrap()])) } else { None };
+                    push_triangle(&mut import, material, c.map(|c| positions[c.0]), n, None);
+                }
+            },
+            Some(&"mtllib") => {
+                for name in &fields[1..] {
+                    read_mtl(&mut import, &dir.join(name), &file)?;
+                }
+            },
+            Some(&"usemtl") if fields.len() == 2 => {
+                let name = format!("{}:{}", file, fields[1]);
+                material = import.materials.iter().position(|m| m.name == name).unwrap_or(0);
+            },
+            _ => {},
+        }
+    }
+
+    if import.triangles.is_empty() {
+        return Err(format!("{}: no triangles", path.display()));
+    }
+    Ok(import)
+}
+
+// MTL colors and weights at or below this count as none
+const NONE: f32 = 1e-3;
+
+// MTL materials less opaque than this are glass, and ones with a
+// specular exponent at least this high have a sharp enough highlight
+// to be a mirror
+const GLASS_OPACITY: f32 = 0.5;
+const SHARP_HIGHLIGHT: f32 = 100.0;
+
+// The index of refraction of glass in an MTL file that gives none
+const GLASS_IOR: f32 = 1.5;
+
+// Add the materials of an MTL file, named after the OBJ file `file`
+fn read_mtl(import: &mut Import, path: &Path, file: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+
+    // The material being read: its name, Kd, Ks, Ns, d and Ni
+    struct Mtl {
+        name: String,
+        diffuse: Vec3,
+        specular: Vec3,
+        exponent: f32,
+        opacity: f32,
+        ior: f32,
+    }
+
+    let mut materials: Vec<Mtl> = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let numbers: Vec<f32> = fields.iter().skip(1).filter_map(|f| f.parse().ok()).collect();
+        let at = |e: &str| format!("{}:{}: {}", path.display(), n + 1, e);
+
+        if fields.first() == Some(&"newmtl") {
+            if fields.len() != 2 {
+                return Err(at("newmtl takes a single name"));
+            }
+            materials.push(Mtl {
+                name: fields[1].to_string(),
+                diffuse: Vec3::new(0.8, 0.8, 0.8),
+                specular: Vec3::new(0.0, 0.0, 0.0),
+                exponent: 0.0,
+                opacity: 1.0,
+                ior: GLASS_IOR,
+            });
+            continue;
+        }
+
+        let m = match (fields.first(), materials.last_mut()) {
+            (None, _) => continue,
+            (Some(_), Some(m)) => m,
+            (Some(_), None) => return Err(at("material settings before any newmtl")),
+        };
+        let color = || match numbers.len() {
+            1 => Ok(Vec3::new(numbers[0], numbers[0], numbers[0])),
+            3 => Ok(Vec3::new(numbers[0], numbers[1], numbers[2])),
+            _ => Err(at("colors take a red, green and blue")),
+        };
+        match fields[0] {
+            "Kd" => m.diffuse = color()?,
+            "Ks" => m.specular = color()?,
+            "Ns" => m.exponent = *numbers.first().ok_or_else(|| at("Ns takes an exponent"))?,
+            "d" => m.opacity = *numbers.first().ok_or_else(|| at("d takes an opacity"))?,
+            "Tr" => m.opacity = 1.0 - *numbers.first().ok_or_else(|| at("Tr takes a transparency"))?,
+            "Ni" => m.ior = *numbers.first().ok_or_else(|| at("Ni takes an index of refraction"))?,
+            _ => {},
+        }
+    }
+
+    for m in materials {
+        let brightest = |c: &Vec3| c.r().max(c.g()).max(c.b());
+        let weight = brightest(&m.specular).min(1.0);
+        let sharp = m.exponent >= SHARP_HIGHLIGHT && weight > NONE;
+
+        let (kind, params) = if m.opacity < GLASS_OPACITY {
+            ("dielectric", vec![if m.ior > 1.0 { m.ior } else { GLASS_IOR }])
+        } else if sharp && brightest(&m.diffuse) <= NONE {
+            ("metal", vec![m.specular.r(), m.specular.g(), m.specular.b()])
+        } else if sharp {
+            let coat = m.specular / brightest(&m.specular);
+            ("shaded", vec![m.diffuse.r(), m.diffuse.g(), m.diffuse.b(), weight, coat.r(), coat.g(), coat.b()])
+        } else {
+            ("lambertian", vec![m.diffuse.r(), m.diffuse.g(), m.diffuse.b()])
+        };
+        import.materials.push(ImportedMaterial {
+            name: format!("{}:{}", file, m.name),
+            kind: kind.to_string(),
+            params,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "gltf")]
+mod imp {
+    use std::path::Path;
+
+    use gltf::{self, Gltf, Node};
+    use gltf::camera::Projection;
+    use gltf::mesh::Mode;
+
+    use vec3::Vec3;
+    use super::{push_triangle, Import, ImportedMaterial};
+
+    // Column major, as glTF stores them
+    type Matrix = [[f32; 4]; 4];
+
+    const IDENTITY: Matrix = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+    // Materials at least this transmissive are glass, and at least
+    // this metallic and less than this rough are metal
+    const GLASS: f32 = 0.5;
+    const METAL: f32 = 0.5;
+    const ROUGH: f32 = 0.5;
+
+    // The index of refraction glTF assumes when a material gives none
+    const DEFAULT_IOR: f32 = 1.5;
+
+    pub fn load_gltf(path: &Path) -> Result<Import, String> {
+        let Gltf { document, blob } = Gltf::open(path).map_err(|e| e.to_string())?;
+        let buffers = gltf::import_buffers(&document, path.parent(), blob).map_err(|e| e.to_string())?;
+        let file = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+
+        let mut import = Import::default();
+
+        // Primitives without a material get glTF's default one, a
+        // white rough metal, which goes last
+        for (i, material) in document.materials().enumerate() {
+            let name = format!("{}:{}", file, material.name().map_or(i.to_string(), |n| n.to_string()));
+            let pbr = material.pbr_metallic_roughness();
+            let [r, g, b, _] = pbr.base_color_factor();
+            let transmission = material.transmission().map_or(0.0, |t| t.transmission_factor());
+
+            let (kind, params) = if transmission >= GLASS {
+                ("dielectric", vec![material.ior().unwrap_or(DEFAULT_IOR)])
+            } else if pbr.metallic_factor() >= METAL && pbr.roughness_factor() < ROUGH {
+                ("metal", vec![r, g, b])
+            } else {
+                ("lambertian", vec![r, g, b])
+            };
+            import.materials.push(ImportedMaterial { name, kind: kind.to_string(), params });
+        }
+        let default_material = import.materials.len();
+        import.materials.push(ImportedMaterial {
+            name: format!("{}:default", file),
+            kind: "lambertian".to_string(),
+            params: vec![1.0, 1.0, 1.0],
+        });
+
+        let scene = match document.default_scene().or_else(|| document.scenes().next()) {
+            Some(scene) => scene,
+            None => return Err("no scene to import".to_string()),
+        };
+        for node in scene.nodes() {
+            add_node(&mut import, &node, &IDENTITY, &buffers, default_material);
+        }
+
+        Ok(import)
+    }
+
+    // Add a node and its children, placed by their transforms on top
+    // of `parent`
+    fn add_node(import: &mut Import, node: &Node, parent: &Matrix, buffers: &[gltf::buffer::Data],
+                default_material: usize) {
+        let m = multiply(parent, &node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            // Normals follow the inverse transpose, which is the
+            // cofactor matrix up to scale. Mirroring turns triangles
+            // inside out, so their corners are put back in order.
+            let axes = [column(&m, 0), column(&m, 1), column(&m, 2)];
+            let cofactors = [Vec3::cross(&axes[1], &axes[2]), Vec3::cross(&axes[2], &axes[0]),
+                             Vec3::cross(&axes[0], &axes[1])];
+            let mirrored = Vec3::dot(&axes[0], &cofactors[0]) < 0.0;
+            let sign = if mirrored { -1.0 } else { 1.0 };
+
+            for primitive in mesh.primitives().filter(|p| p.mode() == Mode::Triangles) {
+                let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|d| &d.0[..]));
+                let positions: Vec<Vec3> = match reader.read_positions() {
+                    Some(positions) => positions.map(|p| point(&m, p)).collect(),
+                    None => continue,
+                };
+                let normals: Option<Vec<Vec3>> = reader.read_normals().map(|normals| normals.map(|n| {
+                    sign * Vec3::unit_vector(&(n[0] * cofactors[0] + n[1] * cofactors[1] + n[2] * cofactors[2]))
+                }).collect());
+                let indices: Vec<usize> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+                    None => (0..positions.len()).collect(),
+                };
+                let material = primitive.material().index().unwrap_or(default_material);
+
+                for corners in indices.chunks_exact(3) {
+                    if corners.iter().any(|&i| i >= positions.len()) {
+                        continue;
+                    }
+                    let order = if mirrored { [corners[0], corners[2], corners[1]] } else { [corners[0], corners[1], corners[2]] };
+                    let p = order.map(|i| positions[i]);
+                    let n = normals.as_ref().filter(|n| n.len() == positions.len()).map(|n| order.map(|i| n[i]));
+                    push_triangle(import, material, p, n, None);
+                }
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            if let Projection::Perspective(_) = camera.projection() {
+                let from = point(&m, [0.0, 0.0, 0.0]);
+                let forward = -column(&m, 2);
+                import.cameras.push((from, from + Vec3::unit_vector(&forward)));
+            }
+        }
+
+        for child in node.children() {
+            add_node(import, &child, &m, buffers, default_material);
+        }
+    }
+
+    fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+        let mut m = [[0.0; 4]; 4];
+        for (col, b_col) in b.iter().enumerate() {
+            for row in 0..4 {
+                m[col][row] = (0..4).map(|k| a[k][row] * b_col[k]).sum();
+            }
+        }
+        m
+    }
+
+    fn column(m: &Matrix, i: usize) -> Vec3 {
+        Vec3::new(m[i][0], m[i][1], m[i][2])
+    }
+
+    fn point(m: &Matrix, p: [f32; 3]) -> Vec3 {
+        p[0] * column(m, 0) + p[1] * column(m, 1) + p[2] * column(m, 2) + column(m, 3)
+    }
+}
+
+#[cfg(not(feature = "gltf"))]
+mod imp {
+    use std::path::Path;
+
+    use super::Import;
+
+    pub fn load_gltf(_: &Path) -> Result<Import, String> {
+        Err("glTF files need the renderer to be built with --features gltf".to_string())
+    }
+}
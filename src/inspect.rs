@@ -0,0 +1,135 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use std::f32::consts::PI;
+use std::fmt;
+
+use camera::Camera;
+use film::Film;
+use hittable::World;
+use vec3::Vec3;
+
+///
+/// The first surface the ray through the middle of a pixel hits.
+///
+pub struct SurfaceInfo {
+    /// The object's index in the world, in the order the scene gave
+    /// them.
+    pub index: Option<usize>,
+    /// The object's stable ID (see ObjectIds), or 0 if it has none.
+    pub id: u32,
+    /// Where the ray hit, in world space.
+    pub point: Vec3,
+    pub normal: Vec3,
+    /// The distance to the hit along the ray, and along the view
+    /// direction.
+    pub distance: f32,
+    pub depth: f32,
+    /// The spherical texture coordinates of the hit, for spheres.
+    pub uv: Option<(f32, f32)>,
+    pub albedo: Vec3,
+    pub emitted: Vec3,
+}
+
+///
+/// What's known about one pixel of a render: what the camera sees
+/// through it, and the light gathered there so far.
+///
+pub struct PixelInfo {
+    pub x: usize,
+    pub y: usize,
+    /// None where the ray goes on to the sky.
+    pub surface: Option<SurfaceInfo>,
+    pub radiance: Vec3,
+    pub samples: u32,
+}
+
+///
+/// Inspect pixel (`x`, `y`) of `film`, counting lines from the bottom
+/// like the film does, by tracing the ray through its middle again.
+///
+pub fn inspect(world: &World, camera: &Camera, film: &Film, x: usize, y: usize) -> PixelInfo {
+    let r = camera.get_ray((x as f32 + 0.5) / film.width as f32, (y as f32 + 0.5) / film.height as f32);
+
+    let surface = world.hit(&r, 0.001, f32::MAX).map(|h| {
+        let material = h.object.material();
+        // The hit reports the object's outermost wrapper only when
+        // it's identified, so find it again by what it hits
+        let index = world.objects.iter().position(|o| o.hit(&r, 0.001, f32::MAX).is_some_and(|o| o.t == h.t));
+
+        SurfaceInfo {
+            index,
+            id: h.object.id(),
+            point: h.p,
+            normal: h.normal,
+            distance: h.t * r.direction().length(),
+            depth: Vec3::dot(&(h.p - r.origin()), &camera.forward()),
+            uv: h.object.as_sphere().map(|(center, radius)| sphere_uv(&((h.p - center) / radius))),
+            albedo: material.albedo(),
+            emitted: material.emitted(),
+        }
+    });
+
+    PixelInfo {
+        x,
+        y,
+        surface,
+        radiance: film.color(x, y),
+        samples: film.pixel(x, y).count,
+    }
+}
+
+// Texture coordinates of a point on the unit sphere: u goes once
+// around the sphere from -x, and v from the bottom to the top
+fn sphere_uv(p: &Vec3) -> (f32, f32) {
+    let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z()).atan2(p.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+fn triple(v: &Vec3) -> String {
+    format!("({:.4}, {:.4}, {:.4})", v.x(), v.y(), v.z())
+}
+
+impl fmt::Display for PixelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Pixel {}, {} (from the bottom left):", self.x, self.y)?;
+        match self.surface {
+            Some(ref s) => {
+                match s.index {
+                    Some(i) => writeln!(f, "  object:   {} (ID {})", i, s.id)?,
+                    None => writeln!(f, "  object:   ? (ID {})", s.id)?,
+                }
+                writeln!(f, "  albedo:   {}", triple(&s.albedo))?;
+                writeln!(f, "  emitted:  {}", triple(&s.emitted))?;
+                writeln!(f, "  point:    {}", triple(&s.point))?;
+                writeln!(f, "  normal:   {}", triple(&s.normal))?;
+                writeln!(f, "  distance: {:.4} (depth {:.4})", s.distance, s.depth)?;
+                match s.uv {
+                    Some((u, v)) => writeln!(f, "  uv:       ({:.4}, {:.4})", u, v)?,
+                    None => writeln!(f, "  uv:       none")?,
+                }
+            }
+            None => writeln!(f, "  nothing hit: sky")?,
+        }
+        write!(f, "  radiance: {} from {} samples", triple(&self.radiance), self.samples)
+    }
+}
@@ -0,0 +1,244 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use vec3::Vec3;
+use ray::Ray;
+use aabb::Aabb;
+use hittable::{Hit, Hittable};
+use accelerator;
+
+// Nodes with this many objects or fewer aren't split any further
+const MAX_LEAF_OBJECTS: usize = 2;
+
+// Candidate split planes per axis, evenly spaced across the node
+const BINS: usize = 32;
+
+// The costs of visiting a node and of testing an object
+const TRAVERSAL_COST: f32 = 0.5;
+const INTERSECTION_COST: f32 = 1.0;
+
+// How much cheaper a split is made if one side is empty, so that
+// empty space is cut away early
+const EMPTY_BONUS: f32 = 0.5;
+
+// Deep enough for the deepest tree the builder makes, of 8 + 1.3
+// log2(n) levels for n objects
+const MAX_DEPTH: usize = 64;
+
+// A leaf holds objects `indices[first..first + count]`. An interior
+// node's children are the space below `split` on `axis`, at
+// `index + 1`, and the space above it, at `above`.
+enum Node {
+    Leaf { first: usize, count: usize },
+    Interior { axis: usize, split: f32, above: usize },
+}
+
+///
+/// A kd-tree: space is cut in two again and again by planes along
+/// the axes, placed by the surface area heuristic, until each piece
+/// holds only a few objects. Unlike a BVH's boxes, the pieces never
+/// overlap, and splits that cut away empty space are favored, so a
+/// ray crossing the large empty rooms of an architectural scene
+/// skips them in a few steps; objects straddling a plane are listed
+/// on both sides of it. Rays visit the pieces they pass through in
+/// order, and stop at the first piece beyond the nearest hit so far.
+///
+pub struct KdTree {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+    bounds: Aabb,
+    // Unbounded and very large objects, which every ray tests
+    unbounded: Vec<usize>,
+}
+
+impl KdTree {
+    pub fn new(objects: &[Box<dyn Hittable+Sync+Send>]) -> KdTree {
+        let (items, unbounded) = accelerator::partition(objects);
+        let bounds = items.iter().fold(Aabb::empty(), |acc, &(_, b)| acc.union(&b));
+
+        let mut tree = KdTree { nodes: Vec::new(), indices: Vec::new(), bounds, unbounded };
+        if !items.is_empty() {
+            let max_depth = (8.0 + 1.3 * (items.len() as f32).log2()).round() as usize;
+            tree.build(items, bounds, max_depth.min(MAX_DEPTH - 1));
+        }
+        tree
+    }
+
+    fn build(&mut self, items: Vec<(usize, Aabb)>, bounds: Aabb, depth: usize) -> usize {
+        let index = self.nodes.len();
+
+        let split = if items.len() <= MAX_LEAF_OBJECTS || depth == 0 {
+            None
+        } else {
+            choose_split(&items, &bounds)
+        };
+        let (axis, split) = match split {
+            Some(s) => s,
+            None => {
+                self.nodes.push(Node::Leaf { first: self.indices.len(), count: items.len() });
+                self.indices.extend(items.iter().map(|&(i, _)| i));
+                return index;
+            },
+        };
+
+        // Objects lying in the plane go on both sides
+        let below: Vec<(usize, Aabb)> = items.iter().cloned()
+            .filter(|&(_, b)| b.min.e[axis] < split || b.max.e[axis] <= split).collect();
+        let above: Vec<(usize, Aabb)> = items.into_iter()
+            .filter(|&(_, b)| b.max.e[axis] > split || b.min.e[axis] >= split).collect();
+        let (mut below_bounds, mut above_bounds) = (bounds, bounds);
+        below_bounds.max.e[axis] = split;
+        above_bounds.min.e[axis] = split;
+
+        self.nodes.push(Node::Interior { axis, split, above: 0 });
+        self.build(below, below_bounds, depth - 1);
+        let above_index = self.build(above, above_bounds, depth - 1);
+        self.nodes[index] = Node::Interior { axis, split, above: above_index };
+        index
+    }
+
+    pub fn hit<'a>(&self, objects: &'a [Box<dyn Hittable+Sync+Send>], r: &Ray,
+                   t_min: f32, t_max: f32) -> Option<Hit<'a>> {
+        let mut closest: Option<Hit<'a>> = None;
+        let mut closest_so_far = t_max;
+
+        for &i in &self.unbounded {
+            if let Some(h) = objects[i].hit(r, t_min, closest_so_far) {
+                closest_so_far = h.t;
+                closest = Some(h);
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let origin = r.origin();
+        let d = r.direction();
+        let inv_d = Vec3::new(1.0 / d.x(), 1.0 / d.y(), 1.0 / d.z());
+        let (mut t0, mut t1) = match self.bounds.hit_inv(&origin, &inv_d, t_min, closest_so_far) {
+            Some(range) => range,
+            None => return closest,
+        };
+
+        // Pieces still to visit, farther along the ray, with the range
+        // of the ray inside each
+        let mut stack = [(0, 0.0, 0.0); MAX_DEPTH];
+        let mut depth = 0;
+        let mut index = 0;
+
+        loop {
+            if closest_so_far < t0 {
+                break;
+            }
+
+            match self.nodes[index] {
+                Node::Interior { axis, split, above } => {
+                    // Visit the side the ray starts on first, and the
+                    // other only if the ray crosses the plane in range
+                    let t_split = (split - origin.e[axis]) * inv_d.e[axis];
+                    let below_first = origin.e[axis] < split || (origin.e[axis] == split && d.e[axis] <= 0.0);
+                    let (near, far) = if below_first { (index + 1, above) } else { (above, index + 1) };
+
+                    if t_split > t1 || t_split <= 0.0 {
+                        index = near;
+                    } else if t_split < t0 {
+                        index = far;
+                    } else {
+                        stack[depth] = (far, t_split, t1);
+                        depth += 1;
+                        index = near;
+                        t1 = t_split;
+                    }
+                    continue;
+                },
+                Node::Leaf { first, count } => {
+                    for &i in &self.indices[first..first + count] {
+                        if let Some(h) = objects[i].hit(r, t_min, closest_so_far) {
+                            closest_so_far = h.t;
+                            closest = Some(h);
+                        }
+                    }
+                },
+            }
+
+            if depth == 0 {
+                break;
+            }
+            depth -= 1;
+            (index, t0, t1) = stack[depth];
+        }
+
+        closest
+    }
+}
+
+// The axis and position of the plane the surface area heuristic says
+// is best for splitting a node, or None if it's better left a leaf.
+// Candidate planes are evenly spaced across the node; the objects
+// starting and ending between each pair of them are counted, which
+// tells how many lie on each side of every plane.
+fn choose_split(items: &[(usize, Aabb)], bounds: &Aabb) -> Option<(usize, f32)> {
+    let extent = bounds.extent();
+    let area = bounds.surface_area().max(f32::MIN_POSITIVE);
+    let n = items.len();
+
+    // The cheapest split so far, as (cost, axis, position)
+    let mut best: Option<(f32, usize, f32)> = None;
+    for axis in 0..3 {
+        if extent.e[axis] <= 0.0 {
+            continue;
+        }
+
+        let scale = BINS as f32 / extent.e[axis];
+        let bin_of = |x: f32| (((x - bounds.min.e[axis]) * scale).max(0.0) as usize).min(BINS - 1);
+        let mut starts = [0; BINS];
+        let mut ends = [0; BINS];
+        for &(_, b) in items {
+            starts[bin_of(b.min.e[axis])] += 1;
+            ends[bin_of(b.max.e[axis])] += 1;
+        }
+
+        let (mut started, mut ended) = (0, 0);
+        for bin in 1..BINS {
+            started += starts[bin - 1];
+            ended += ends[bin - 1];
+            let (below, above) = (started, n - ended);
+
+            let split = bounds.min.e[axis] + bin as f32 / scale;
+            let (mut below_bounds, mut above_bounds) = (*bounds, *bounds);
+            below_bounds.max.e[axis] = split;
+            above_bounds.min.e[axis] = split;
+
+            let bonus = if below == 0 || above == 0 { EMPTY_BONUS } else { 0.0 };
+            let cost = TRAVERSAL_COST + INTERSECTION_COST * (1.0 - bonus) *
+                (below_bounds.surface_area() * below as f32 + above_bounds.surface_area() * above as f32) / area;
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    match best {
+        Some((cost, axis, split)) if cost < INTERSECTION_COST * n as f32 => Some((axis, split)),
+        _ => None,
+    }
+}
@@ -0,0 +1,408 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::fs;
+use std::path::Path;
+
+use ray::Ray;
+use vec3::Vec3;
+
+// Lens prescriptions are in millimetres, and scene units are taken to
+// be metres
+const MM_PER_UNIT: f32 = 1000.0;
+
+// Diagonal of a 35 mm film frame, 36 by 24 mm
+const FILM_DIAGONAL: f32 = 43.27;
+
+// Height above the axis, as a fraction of the rear element's radius,
+// of the rays traced to find the focal length and to focus the lens
+const PARAXIAL_HEIGHT: f32 = 0.01;
+
+// Number of film distances tried when focusing the lens, each halving
+// the range the right one lies in
+const FOCUS_STEPS: u32 = 64;
+
+// The exit pupil, the part of the back of the lens that light gets
+// through, is found for this many distances from the middle of the
+// film, by trying rays through a grid of this many points across
+const PUPIL_BINS: usize = 64;
+const PUPIL_GRID: usize = 64;
+
+///
+/// One surface of a lens prescription: a spherical surface with the
+/// given radius of curvature, or the aperture stop if the radius is
+/// zero. A positive radius curves towards the scene, like the front
+/// of a convex lens. `thickness` is the distance to the next surface
+/// (or the film, for the last one) along the axis, `ior` the index of
+/// refraction of the glass between them (1, or 0, for air), and
+/// `aperture` the diameter of the surface. Lengths are in millimetres.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct LensElement {
+    pub radius: f32,
+    pub thickness: f32,
+    pub ior: f32,
+    pub aperture: f32,
+}
+
+///
+/// A camera lens made of several elements, listed from the front of
+/// the lens to the back. Rays are traced from the film through every
+/// surface, refracting at each one, so the lens blurs what is out of
+/// focus, distorts straight lines, and darkens the corners of the
+/// frame where its barrel blocks part of the light (vignetting), all
+/// as the real lens would.
+///
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+    // Distance from the film to the rear surface
+    film_distance: f32,
+    // Bounds of the exit pupil on the rear surface, as (x0, y0, x1, y1),
+    // for film points from the middle of the film out to its corners
+    // along the x axis
+    pupil_bounds: Vec<[f32; 4]>,
+    // Light reaching the middle of the film, used to scale the rest
+    axis_irradiance: f32,
+    film_width: f32,
+    film_height: f32,
+}
+
+impl LensSystem {
+    ///
+    /// Read a lens prescription: one surface per line, from the front
+    /// of the lens to the back, each given as its radius of curvature,
+    /// thickness, index of refraction and aperture, as in
+    /// `LensElement`. Blank lines and anything after a `#` are ignored.
+    ///
+    pub fn load(path: &Path) -> Result<LensSystem, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let elements = parse_elements(&text).map_err(|e| format!("{}:{}", path.display(), e))?;
+        LensSystem::new(elements).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// A lens made of the given elements, from front to back. It has
+    /// to be `focused` before rays can be traced through it.
+    pub fn new(elements: Vec<LensElement>) -> Result<LensSystem, String> {
+        let film_distance = match elements.last() {
+            Some(last) => last.thickness,
+            None => return Err("the lens has no elements".to_string()),
+        };
+
+        Ok(LensSystem { elements, film_distance, pupil_bounds: Vec::new(), axis_irradiance: 0.0,
+                        film_width: 0.0, film_height: 0.0 })
+    }
+
+    ///
+    /// The effective focal length of the lens, in millimetres, found by
+    /// tracing a ray that leaves the film parallel to the axis. None if
+    /// the lens doesn't bring parallel light to a focus.
+    ///
+    pub fn focal_length(&self) -> Option<f32> {
+        let h = PARAXIAL_HEIGHT * self.rear_radius();
+        let ray = self.trace(&Ray::new(Vec3::new(h, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)), self.film_distance)?;
+
+        // Where the ray crosses the axis, and where it would have been
+        // bent if all its bending happened in one plane
+        let d = ray.direction();
+        if d.x() >= 0.0 {
+            return None;
+        }
+
+        let focus: f32 = ray.origin().z() - ray.origin().x() * d.z() / d.x();
+        let principal: f32 = ray.origin().z() + (h - ray.origin().x()) * d.z() / d.x();
+
+        Some(focus - principal)
+    }
+
+    ///
+    /// This lens, with the film moved so that things `distance` units
+    /// in front of the lens are in focus. The film is as big across
+    /// as a 35 mm frame, with the given aspect, so the lens sees as
+    /// much of the scene as it would on a 35 mm camera.
+    ///
+    pub fn focused(&self, distance: f32, aspect: f32) -> Result<LensSystem, String> {
+        let focal_length = self.focal_length().ok_or_else(|| "The lens doesn't focus light".to_string())?;
+        let target: f32 = distance * MM_PER_UNIT;
+
+        // Moving the film away from the lens brings nearer things into
+        // focus; close in on the film distance where the focus reaches
+        // the target
+        let too_far = |film_distance: f32| match self.axis_crossing(film_distance) {
+            Some(z) => z > target,
+            None => true,
+        };
+
+        let (mut near, mut far) = (0.0, 2.0 * focal_length);
+        while too_far(far) {
+            far *= 2.0;
+            if far > 64.0 * focal_length {
+                return Err(format!("The lens can't focus at {} units", distance));
+            }
+        }
+
+        for _ in 0..FOCUS_STEPS {
+            let middle = 0.5 * (near + far);
+            if too_far(middle) {
+                near = middle;
+            } else {
+                far = middle;
+            }
+        }
+
+        let film_distance = far;
+        let pupil_bounds: Vec<[f32; 4]> = (0..PUPIL_BINS).map(|bin| {
+            let r = 0.5 * FILM_DIAGONAL * bin as f32 / (PUPIL_BINS - 1) as f32;
+            self.exit_pupil(r, film_distance).0
+        }).collect();
+
+        let axis_irradiance = self.exit_pupil(0.0, film_distance).1;
+        if axis_irradiance == 0.0 {
+            return Err("No light gets through the lens".to_string());
+        }
+
+        let film_height = FILM_DIAGONAL / (1.0 + aspect * aspect).sqrt();
+
+        Ok(LensSystem {
+            elements: self.elements.clone(),
+            film_distance,
+            pupil_bounds,
+            axis_irradiance,
+            film_width: aspect * film_height,
+            film_height,
+        })
+    }
+
+    ///
+    /// The ray leaving the front of the lens for light arriving at
+    /// (x, y) on the film, where both run from -0.5 to 0.5 across the
+    /// frame, through the point on the back of the lens picked by
+    /// `pupil`, a pair of numbers in [0, 1). Also returns how much the
+    /// light along the ray counts for, compared to light reaching the
+    /// middle of the film, which is less towards the edges of the
+    /// frame, where the light arrives at an angle and more of it is
+    /// blocked. None if the ray is blocked inside the lens.
+    ///
+    /// The ray is in the camera's own space, in scene units: x to the
+    /// right, y up and z forward, starting from the middle of the
+    /// front surface. The lens turns the image upside down, so the
+    /// film is read upside down too.
+    ///
+    pub fn ray(&self, x: f32, y: f32, pupil: (f32, f32)) -> Option<(Ray, f32)> {
+        let film = Vec3::new(-x * self.film_width, -y * self.film_height, 0.0);
+        let r: f32 = (film.x() * film.x() + film.y() * film.y()).sqrt();
+
+        // The exit pupil moves as the film point moves off the axis, so
+        // sample the bounds found for the two nearest distances,
+        // turned round to face the film point
+        let position: f32 = r / (0.5 * FILM_DIAGONAL) * (PUPIL_BINS - 1) as f32;
+        let bin: usize = (position as usize).min(PUPIL_BINS - 2);
+        let (a, b) = (self.pupil_bounds[bin], self.pupil_bounds[bin + 1]);
+        let bounds = [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])];
+        if bounds[0] > bounds[2] {
+            return None;
+        }
+
+        let px: f32 = bounds[0] + pupil.0 * (bounds[2] - bounds[0]);
+        let py: f32 = bounds[1] + pupil.1 * (bounds[3] - bounds[1]);
+        let (sin, cos) = if r > 0.0 { (film.y() / r, film.x() / r) } else { (0.0, 1.0) };
+        let rear = Vec3::new(px * cos - py * sin, px * sin + py * cos, self.film_distance);
+
+        let direction: Vec3 = rear - film;
+        let ray = self.trace(&Ray::new(film, direction), self.film_distance)?;
+        let front = self.front_z(self.film_distance);
+        let origin = ray.origin();
+
+        let cos_theta: f32 = direction.z() / direction.length();
+        let area: f32 = (bounds[2] - bounds[0]) * (bounds[3] - bounds[1]);
+        let weight: f32 = area * cos_theta.powi(4) / self.axis_irradiance;
+
+        Some((Ray::new(Vec3::new(origin.x(), origin.y(), origin.z() - front) / MM_PER_UNIT, ray.direction()),
+              weight))
+    }
+
+    // The bounds of the exit pupil for a film point `r` from the middle
+    // of the film along the x axis, and the light reaching that point
+    // through it, up to a constant factor. The bounds are empty, with
+    // their minimum above their maximum, if no light gets through.
+    fn exit_pupil(&self, r: f32, film_distance: f32) -> ([f32; 4], f32) {
+        let rear_radius = self.rear_radius();
+        let cell: f32 = 2.0 * rear_radius / PUPIL_GRID as f32;
+        let film = Vec3::new(r, 0.0, 0.0);
+
+        let mut bounds = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+        let mut irradiance: f32 = 0.0;
+
+        for i in 0..PUPIL_GRID {
+            for j in 0..PUPIL_GRID {
+                let x: f32 = -rear_radius + (i as f32 + 0.5) * cell;
+                let y: f32 = -rear_radius + (j as f32 + 0.5) * cell;
+                let direction = Vec3::new(x, y, film_distance) - film;
+
+                if x * x + y * y > rear_radius * rear_radius
+                    || self.trace(&Ray::new(film, direction), film_distance).is_none() {
+                    continue;
+                }
+
+                bounds = [bounds[0].min(x), bounds[1].min(y), bounds[2].max(x), bounds[3].max(y)];
+                irradiance += cell * cell * (direction.z() / direction.length()).powi(4);
+            }
+        }
+
+        // Grow the bounds by a grid cell, to take in the parts of the
+        // pupil between the points tried
+        if irradiance > 0.0 {
+            bounds = [bounds[0] - cell, bounds[1] - cell, bounds[2] + cell, bounds[3] + cell];
+        }
+
+        (bounds, irradiance)
+    }
+
+    fn rear_radius(&self) -> f32 {
+        self.elements[self.elements.len() - 1].aperture / 2.0
+    }
+
+    // Distance from the film to the front surface
+    fn front_z(&self, film_distance: f32) -> f32 {
+        let n = self.elements.len();
+        film_distance + self.elements[..n - 1].iter().map(|e| e.thickness).sum::<f32>()
+    }
+
+    // How far in front of the lens a ray leaving the middle of the
+    // film, just off the axis, crosses the axis; None if it never does
+    fn axis_crossing(&self, film_distance: f32) -> Option<f32> {
+        let h = PARAXIAL_HEIGHT * self.rear_radius();
+        let ray = self.trace(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(h, 0.0, film_distance)), film_distance)?;
+        let d = ray.direction();
+
+        if ray.origin().x() * d.x() >= 0.0 {
+            return None;
+        }
+
+        Some(ray.origin().z() - ray.origin().x() * d.z() / d.x() - self.front_z(film_distance))
+    }
+
+    // Follow a ray from the film, which is at z = 0 and looks along +z,
+    // through every surface from the back of the lens to the front.
+    // Returns the ray leaving the front surface, or None if it's blocked.
+    fn trace(&self, ray: &Ray, film_distance: f32) -> Option<Ray> {
+        let mut origin: Vec3 = ray.origin();
+        let mut direction: Vec3 = Vec3::unit_vector(&ray.direction());
+        let mut z: f32 = film_distance;
+
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            if i + 1 < self.elements.len() {
+                z += element.thickness;
+            }
+
+            let (p, normal) = if element.radius == 0.0 {
+                // The aperture stop is a flat opening
+                let t = (z - origin.z()) / direction.z();
+                if t < 0.0 {
+                    return None;
+                }
+                (origin + t * direction, None)
+            } else {
+                let center = Vec3::new(0.0, 0.0, z - element.radius);
+                let p = intersect_surface(&origin, &direction, &center, element.radius)?;
+                (p, Some((p - center) / element.radius.abs()))
+            };
+
+            let half = element.aperture / 2.0;
+            if p.x() * p.x() + p.y() * p.y() > half * half {
+                return None;
+            }
+
+            if let Some(normal) = normal {
+                let inside = air(element.ior);
+                let outside = if i == 0 { 1.0 } else { air(self.elements[i - 1].ior) };
+                direction = refract(&direction, &normal, inside / outside)?;
+            }
+
+            origin = p;
+        }
+
+        Some(Ray::new(origin, direction))
+    }
+}
+
+// The elements of a lens prescription, one per line
+fn parse_elements(text: &str) -> Result<Vec<LensElement>, String> {
+    let mut elements: Vec<LensElement> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let fields: Result<Vec<f32>, _> = line.split_whitespace().map(|f| f.parse::<f32>()).collect();
+
+        let element = match fields {
+            Ok(ref f) if f.is_empty() => continue,
+            Ok(ref f) if f.len() == 4 => LensElement { radius: f[0], thickness: f[1], ior: f[2], aperture: f[3] },
+            _ => return Err(format!("{}: expected radius, thickness, index of refraction and aperture", n + 1)),
+        };
+
+        if element.thickness < 0.0 || element.aperture <= 0.0 || element.ior < 0.0 {
+            return Err(format!("{}: the aperture must be greater than zero, and the rest can't be negative", n + 1));
+        }
+
+        elements.push(element);
+    }
+
+    Ok(elements)
+}
+
+// An index of refraction of zero stands for air
+fn air(ior: f32) -> f32 {
+    if ior == 0.0 { 1.0 } else { ior }
+}
+
+// Where a ray crosses a spherical surface whose vertex lies `radius`
+// along the axis from its center, on the same side of the center as
+// the vertex
+fn intersect_surface(origin: &Vec3, direction: &Vec3, center: &Vec3, radius: f32) -> Option<Vec3> {
+    let oc: Vec3 = *origin - *center;
+    let b: f32 = Vec3::dot(&oc, direction);
+    let c: f32 = Vec3::dot(&oc, &oc) - radius * radius;
+    let discriminant: f32 = b * b - c;
+
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    [-b - root, -b + root].iter()
+        .filter(|&&t| t > 0.0)
+        .map(|&t| *origin + t * *direction)
+        .find(|p| (p.z() - center.z()) * radius > 0.0)
+}
+
+// Bend a unit direction crossing a surface with the given normal, going
+// from glass of one index of refraction to the other; None if it is
+// reflected back instead
+fn refract(direction: &Vec3, normal: &Vec3, eta: f32) -> Option<Vec3> {
+    let n: Vec3 = if Vec3::dot(direction, normal) > 0.0 { -*normal } else { *normal };
+    let cos_i: f32 = -Vec3::dot(direction, &n);
+    let sin2_t: f32 = eta * eta * (1.0 - cos_i * cos_i);
+
+    if sin2_t > 1.0 {
+        return None;
+    }
+
+    Some(Vec3::unit_vector(&(eta * *direction + (eta * cos_i - (1.0 - sin2_t).sqrt()) * n)))
+}
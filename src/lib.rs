@@ -0,0 +1,103 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+#[cfg(feature = "openexr")]
+extern crate exr;
+extern crate half;
+#[cfg(feature = "jpeg")]
+extern crate jpeg_encoder;
+#[cfg(feature = "gif")]
+extern crate gif;
+#[cfg(feature = "gltf")]
+extern crate gltf;
+#[cfg(feature = "oidn")]
+extern crate libloading;
+#[cfg(feature = "panel")]
+extern crate egui;
+#[cfg(feature = "png")]
+extern crate png;
+extern crate rand;
+#[cfg(feature = "scripting")]
+extern crate rhai;
+
+pub mod vec3;
+pub mod ray;
+pub mod aabb;
+pub mod accelerator;
+pub mod aov;
+pub mod autosave;
+pub mod hittable;
+pub mod camera;
+pub mod camera_path;
+pub mod clouds;
+pub mod cover;
+pub mod denoise;
+pub mod depth_of_field;
+pub mod dither;
+pub mod edit;
+pub mod blackbody;
+pub mod bloom;
+pub mod blue_noise;
+pub mod bvh;
+pub mod film;
+pub mod filter;
+pub mod glare;
+pub mod grid;
+pub mod heightfield;
+pub mod history;
+pub mod import;
+pub mod inspect;
+pub mod kdtree;
+pub mod lens;
+pub mod lut;
+pub mod machine;
+pub mod medium;
+pub mod memory;
+pub mod mesh;
+pub mod metering;
+pub mod motion;
+pub mod object_id;
+pub mod oidn;
+pub mod output;
+pub mod panel;
+pub mod panorama;
+pub mod post;
+pub mod progress;
+pub mod proxy;
+pub mod registry;
+pub mod render;
+pub mod response;
+pub mod rig;
+pub mod rng;
+pub mod sampler;
+pub mod script;
+pub mod scene;
+pub mod settings;
+pub mod shader;
+pub mod spheres;
+pub mod spectrum;
+pub mod stats;
+pub mod stereo;
+pub mod tile;
+pub mod tone_map;
+pub mod transform;
+pub mod turntable;
+pub mod video;
+pub mod sky;
+pub mod visibility;
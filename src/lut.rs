@@ -0,0 +1,133 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::fs;
+use std::path::Path;
+
+use post::{PixelRun, PostProcess};
+use vec3::Vec3;
+
+///
+/// A 3D color lookup table, as saved in the `.cube` files that grading
+/// tools such as DaVinci Resolve export. The table gives the output
+/// color at every point of an evenly spaced lattice over the input
+/// colors, and colors in between are interpolated from the eight
+/// lattice points around them. Input colors outside the table's domain
+/// (0 to 1 unless it says otherwise) are clamped to it.
+///
+pub struct Lut {
+    size: usize,
+    domain_min: Vec3,
+    domain_max: Vec3,
+    table: Vec<Vec3>,
+}
+
+impl Lut {
+    pub fn load(path: &Path) -> Result<Lut, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        Lut::parse(&text).map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    ///
+    /// Read a LUT from the text of a `.cube` file. The table's entries
+    /// run through red fastest, then green, then blue.
+    ///
+    pub fn parse(text: &str) -> Result<Lut, String> {
+        let mut size: Option<usize> = None;
+        let mut domain_min = Vec3::new(0.0, 0.0, 0.0);
+        let mut domain_max = Vec3::new(1.0, 1.0, 1.0);
+        let mut table: Vec<Vec3> = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+            let error = |e: &str| format!("{}: {}", n + 1, e);
+
+            match fields.first() {
+                None | Some(&"TITLE") => (),
+                Some(&"LUT_3D_SIZE") => {
+                    size = match fields.get(1).and_then(|s| s.parse::<usize>().ok()) {
+                        Some(s) if s >= 2 => Some(s),
+                        _ => return Err(error("LUT_3D_SIZE must be a whole number of at least 2")),
+                    };
+                },
+                Some(&"LUT_1D_SIZE") => return Err(error("1D LUTs aren't supported")),
+                Some(&"DOMAIN_MIN") => domain_min = parse_color(&fields[1..]).ok_or_else(|| error("Invalid DOMAIN_MIN"))?,
+                Some(&"DOMAIN_MAX") => domain_max = parse_color(&fields[1..]).ok_or_else(|| error("Invalid DOMAIN_MAX"))?,
+                Some(_) => table.push(parse_color(&fields).ok_or_else(|| error("Expected a color, as three numbers"))?),
+            }
+        }
+
+        let size = size.ok_or("Missing LUT_3D_SIZE")?;
+        if table.len() != size * size * size {
+            return Err(format!("Expected {} colors for a LUT of size {}, found {}", size * size * size, size,
+                               table.len()));
+        }
+        if (0..3).any(|i| domain_max.e[i] <= domain_min.e[i]) {
+            return Err("DOMAIN_MAX must be above DOMAIN_MIN".to_string());
+        }
+
+        Ok(Lut { size, domain_min, domain_max, table })
+    }
+
+    /// The color the table gives for `col`.
+    pub fn lookup(&self, col: &Vec3) -> Vec3 {
+        let last = (self.size - 1) as f32;
+        let mut lower = [0usize; 3];
+        let mut t = [0.0f32; 3];
+
+        for i in 0..3 {
+            let x = (col.e[i] - self.domain_min.e[i]) / (self.domain_max.e[i] - self.domain_min.e[i]);
+            let x = x.clamp(0.0, 1.0) * last;
+            lower[i] = (x as usize).min(self.size - 2);
+            t[i] = x - lower[i] as f32;
+        }
+
+        let mut result = Vec3::new(0.0, 0.0, 0.0);
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0usize; 3];
+            for i in 0..3 {
+                let upper = (corner >> i) & 1 == 1;
+                index[i] = lower[i] + upper as usize;
+                weight *= if upper { t[i] } else { 1.0 - t[i] };
+            }
+            result += weight * self.table[(index[2] * self.size + index[1]) * self.size + index[0]];
+        }
+
+        result
+    }
+}
+
+impl PostProcess for Lut {
+    fn apply(&self, data: &mut [Vec3], _: &PixelRun) {
+        for col in data.iter_mut() {
+            *col = self.lookup(col);
+        }
+    }
+}
+
+fn parse_color(fields: &[&str]) -> Option<Vec3> {
+    let values: Vec<f32> = fields.iter().map(|f| f.parse::<f32>().ok()).collect::<Option<Vec<f32>>>()?;
+    if values.len() == 3 {
+        Some(Vec3::new(values[0], values[1], values[2]))
+    } else {
+        None
+    }
+}
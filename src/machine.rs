@@ -0,0 +1,81 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::thread;
+
+// Threads to use if the number of cores can't be found out
+const FALLBACK_THREADS: u32 = 4;
+
+// Tiles are made smaller, down to the smallest size, until every
+// thread has at least this many to take in a pass, so none is left
+// idle while the others finish the last few
+const TILES_PER_THREAD: u32 = 8;
+const MIN_TILE_SIZE: u32 = 16;
+const MAX_TILE_SIZE: u32 = 64;
+
+///
+/// What the renderer found out about the machine it's running on,
+/// used to pick defaults that suit it.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Machine {
+    pub logical_cores: Option<u32>,
+}
+
+impl Machine {
+    pub fn detect() -> Machine {
+        Machine {
+            logical_cores: thread::available_parallelism().ok().map(|n| n.get() as u32),
+        }
+    }
+
+    /// Render threads to start by default: one per logical core.
+    pub fn default_threads(&self) -> u32 {
+        self.logical_cores.unwrap_or(FALLBACK_THREADS)
+    }
+
+    ///
+    /// The width and height of the tiles to render an image of
+    /// `width` by `height` pixels in by default, with `threads`
+    /// threads: as large as they can be while still giving every
+    /// thread several tiles to take.
+    ///
+    pub fn default_tile_size(&self, width: u32, height: u32, threads: u32) -> u32 {
+        let mut size = MAX_TILE_SIZE;
+        while size > MIN_TILE_SIZE && width.div_ceil(size) * height.div_ceil(size) < threads * TILES_PER_THREAD {
+            size /= 2;
+        }
+        size
+    }
+
+    /// What rays are traced with. There's only the CPU path tracer,
+    /// so it's always chosen; a GPU would be looked for here.
+    pub fn backend(&self) -> &'static str {
+        "CPU"
+    }
+
+    /// A line describing the machine, for printing at startup.
+    pub fn describe(&self) -> String {
+        match self.logical_cores {
+            Some(n) => format!("{} logical cores", n),
+            None => "an unknown number of cores".to_string(),
+        }
+    }
+}
@@ -16,216 +16,1981 @@
 /// <https://www.gnu.org/licenses/>.
 ///
 
-extern crate rand;
+extern crate raytracer;
 extern crate sdl2;
 
-pub mod vec3;
-pub mod ray;
-pub mod hittable;
-pub mod camera;
-
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::thread;
 
 use std::time;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use rand::prelude::*;
-use vec3::Vec3;
-use ray::Ray;
-use hittable::*;
-use camera::Camera;
+use raytracer::vec3::Vec3;
+use raytracer::accelerator::AcceleratorKind;
+use raytracer::aov::{render_aovs, Aov};
+use raytracer::autosave::Autosave;
+use raytracer::hittable::*;
+use raytracer::camera::Camera;
+use raytracer::camera_path::CameraPath;
+use raytracer::clouds::Clouds;
+use raytracer::cover;
+use raytracer::denoise::Denoiser;
+use raytracer::depth_of_field::DepthOfField;
+use raytracer::edit::SceneEdit as WorldEdit;
+use raytracer::bloom::Bloom;
+use raytracer::blue_noise::BlueNoiseMask;
+use raytracer::film;
+use raytracer::film::Film;
+use raytracer::glare::Glare;
+use raytracer::history::History;
+use raytracer::lens::LensSystem;
+use raytracer::machine::Machine;
+use raytracer::memory;
+use raytracer::memory::MemoryEstimate;
+use raytracer::object_id::Identified;
+use raytracer::oidn;
+use raytracer::output;
+use raytracer::output::{ImageFormat, ImageStream};
+use raytracer::panel::{Panel, PanelInput, PanelKey, PanelValues};
+use raytracer::panorama::Panorama;
+use raytracer::post::PostChain;
+use raytracer::progress::ProgressReport;
+use raytracer::proxy::Proxy;
+use raytracer::registry::{BoxedObject, Registry};
+use raytracer::render::*;
+use raytracer::scene;
+use raytracer::scene::{Bookmark, Light, Scene};
+use raytracer::settings::Settings;
+use raytracer::sky::{Sky, TimeOfDay};
+use raytracer::stereo;
+use raytracer::inspect::inspect;
+use raytracer::stats::RenderStats;
+use raytracer::tile::Tile;
+use raytracer::turntable::Turntable;
+use raytracer::video;
+use raytracer::video::Video;
 
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::render::{BlendMode, Canvas, Texture};
+use sdl2::video::Window;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::mouse::{MouseButton, MouseWheelDirection};
+use sdl2::keyboard::{Keycode, Mod, LCTRLMOD, RCTRLMOD, LSHIFTMOD, RSHIFTMOD};
 use std::sync::Arc;
-use std::sync::mpsc::channel;
+use std::sync::atomic::Ordering;
+
+fn now() -> u64 {
+    let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    t.as_secs() * 1000 + t.subsec_nanos() as u64 / 1_000_000
+}
 
-const NX: u32 = 640;
-const NY: u32 = 480;
-const NS: u32 = 100;
-const NUM_THREADS: u32 = 6;
+// How often, in ms, to update the statistics in the window's title
+const STATS_INTERVAL: u64 = 250;
 
-fn color(r: &Ray, world: &World, depth: i32) -> Vec3 {
-    let hit: Option<Hit> = world.hit(r, 0.001, std::f32::MAX);
+// How often, in ms, to look for changes to the scene file and script
+// materials
+const SCRIPT_CHECK_INTERVAL: u64 = 500;
 
-    match hit {
-        Some(h) => {
-            let reflection: Reflection = h.object.material().scatter(r, &h);
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
 
-            if depth < 50 && reflection.reflected {
-                reflection.attenuation * color(&reflection.scattered, world, depth + 1)
-            } else {
-                Vec3::new(0.0, 0.0, 0.0)
+// The scene file, if there is one, and the files it includes and runs
+fn scene_files(path: &Option<PathBuf>, scene: &Scene) -> Vec<PathBuf> {
+    path.iter().chain(scene.sources()).cloned().collect()
+}
+
+// The scene rendered when no scene file is given
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.scene");
+
+// The world `time` seconds into the scene's animation
+fn build_world(scene: &Scene, registry: &Registry, sky: Sky, clouds: Option<Clouds>, lights: &[Light],
+               settings: &Settings, time: f32) -> World {
+    let mut world = World::new();
+    world.sky = sky;
+    world.clouds = clouds;
+    world.objects = scene.objects_at(registry, time);
+
+    // Lights go last, so they can be found again by their index
+    for light in lights {
+        let sphere = light_sphere(&mut world, light);
+        world.objects.push(sphere);
+    }
+
+    world.build_accelerator(settings.accelerator, settings.bvh_cache.as_deref());
+    world
+}
+
+fn clouds(settings: &Settings) -> Option<Clouds> {
+    settings.clouds.map(|coverage| Clouds::new(coverage, settings.cloud_density, settings.seed))
+}
+
+fn light_sphere(world: &mut World, light: &Light) -> BoxedObject {
+    let group = world.light_group(&light.name);
+    let sphere = Sphere::new(light.center, light.radius, Arc::new(DiffuseLight::new(light.emit, group)));
+    Box::new(Identified::new(Box::new(sphere), light.id))
+}
+
+// How far one key press moves a light, and how much it changes its color
+const LIGHT_STEP: f32 = 0.25;
+const LIGHT_SCALE: f32 = 1.25;
+const LIGHT_TINT: f32 = 1.1;
+
+///
+/// Apply a preview window key press to a light. Arrow keys and
+/// Page Up/Down move it, +/- change its brightness, and R, G and B
+/// add more of one color (less with Shift). Returns the edited
+/// light, or None if the key isn't a light editing key.
+///
+fn edit_light(light: &Light, keycode: Keycode, keymod: Mod) -> Option<Light> {
+    let tint = if keymod.intersects(LSHIFTMOD | RSHIFTMOD) { 1.0 / LIGHT_TINT } else { LIGHT_TINT };
+    let mut light = light.clone();
+
+    match keycode {
+        Keycode::Left => light.center.e[0] -= LIGHT_STEP,
+        Keycode::Right => light.center.e[0] += LIGHT_STEP,
+        Keycode::Up => light.center.e[2] -= LIGHT_STEP,
+        Keycode::Down => light.center.e[2] += LIGHT_STEP,
+        Keycode::PageUp => light.center.e[1] += LIGHT_STEP,
+        Keycode::PageDown => light.center.e[1] -= LIGHT_STEP,
+        Keycode::Equals | Keycode::KpPlus => light.emit *= LIGHT_SCALE,
+        Keycode::Minus | Keycode::KpMinus => light.emit /= LIGHT_SCALE,
+        Keycode::R => light.emit.e[0] *= tint,
+        Keycode::G => light.emit.e[1] *= tint,
+        Keycode::B => light.emit.e[2] *= tint,
+        _ => return None,
+    }
+
+    Some(light)
+}
+
+///
+/// Apply a preview window key press to the object with ID `id`. Arrow
+/// keys and Page Up/Down move it, M gives it the next of the scene's
+/// materials (the one before with Shift), and Delete takes it out of
+/// the scene. Returns the edit, or None if the key isn't an object
+/// editing key.
+///
+fn edit_object(scene: &Scene, id: u32, keycode: Keycode, keymod: Mod) -> Option<SceneEdit> {
+    let (material, offset, _) = scene.object(id)?;
+
+    let step = match keycode {
+        Keycode::Left => Vec3::new(-LIGHT_STEP, 0.0, 0.0),
+        Keycode::Right => Vec3::new(LIGHT_STEP, 0.0, 0.0),
+        Keycode::Up => Vec3::new(0.0, 0.0, -LIGHT_STEP),
+        Keycode::Down => Vec3::new(0.0, 0.0, LIGHT_STEP),
+        Keycode::PageUp => Vec3::new(0.0, LIGHT_STEP, 0.0),
+        Keycode::PageDown => Vec3::new(0.0, -LIGHT_STEP, 0.0),
+        Keycode::Delete => return Some(SceneEdit::Delete { id }),
+        Keycode::M => {
+            let names = scene.material_names();
+            let i = names.iter().position(|&n| n == material)?;
+            let next = if keymod.intersects(LSHIFTMOD | RSHIFTMOD) { i + names.len() - 1 } else { i + 1 };
+            return Some(SceneEdit::Material {
+                id,
+                before: material.to_string(),
+                after: names[next % names.len()].to_string(),
+            });
+        },
+        _ => return None,
+    };
+
+    Some(SceneEdit::Move { id, before: offset, after: offset + step })
+}
+
+// The object after `selected`, in the order they come in the scene,
+// skipping hidden ones
+fn next_object(scene: &Scene, selected: Option<u32>) -> Option<u32> {
+    let ids = scene.object_ids();
+    let start = selected.and_then(|id| ids.iter().position(|&i| i == id)).map_or(0, |i| i + 1);
+    (start..start + ids.len()).map(|i| ids[i % ids.len()])
+        .find(|&id| scene.object(id).is_some_and(|(_, _, hidden)| !hidden))
+}
+
+// The radius of the spheres added with Insert
+const ADDED_RADIUS: f32 = 0.5;
+
+///
+/// A change made to the scene from the preview window, keeping what
+/// was there before so the change can be undone. Objects are found
+/// by their stable ID (see ObjectIds), which an added object gets
+/// from its definition like any other, so replaying the same edits
+/// onto the same scene finds the same objects.
+///
+#[derive(Clone)]
+enum SceneEdit {
+    Light { index: usize, before: Light, after: Light },
+    // Added objects are added to the scene when the edit is made, and
+    // only hidden while it's undone, so they keep their ID
+    Add { id: u32, line: String },
+    Move { id: u32, before: Vec3, after: Vec3 },
+    Delete { id: u32 },
+    Material { id: u32, before: String, after: String },
+}
+
+impl SceneEdit {
+    // Make the edit, or with `undo` take it back. Edits made, undone,
+    // redone and replayed from a log all go through here.
+    fn apply(&self, scene: &mut Scene, lights: &mut [Light], undo: bool) -> Result<(), String> {
+        match *self {
+            SceneEdit::Light { index, ref before, ref after } => {
+                lights[index] = if undo { before } else { after }.clone();
+                Ok(())
+            },
+            SceneEdit::Add { id, .. } => scene.show_object(id, !undo),
+            SceneEdit::Move { id, before, after } => scene.place_object(id, if undo { before } else { after }),
+            SceneEdit::Delete { id } => scene.show_object(id, undo),
+            SceneEdit::Material { id, ref before, ref after } => {
+                scene.set_material(id, if undo { before } else { after })
+            },
+        }
+    }
+
+    // True if the edit changes the scene's objects, which then have to
+    // be built again, rather than just a light
+    fn changes_objects(&self) -> bool {
+        !matches!(*self, SceneEdit::Light { .. })
+    }
+
+    // The edit as a line of an edit log (see read_edit_log)
+    fn log_line(&self) -> String {
+        match *self {
+            SceneEdit::Light { index, ref after, .. } => {
+                format!("light {} {} {} {} {} {} {}", index, after.center.x(), after.center.y(), after.center.z(),
+                        after.emit.r(), after.emit.g(), after.emit.b())
+            },
+            SceneEdit::Add { ref line, .. } => format!("add {}", line),
+            SceneEdit::Move { id, after, .. } => format!("move {} {} {} {}", id, after.x(), after.y(), after.z()),
+            SceneEdit::Delete { id } => format!("delete {}", id),
+            SceneEdit::Material { id, ref after, .. } => format!("material {} {}", id, after),
+        }
+    }
+}
+
+///
+/// Drop the undone edits before a new edit is made, since they can't
+/// be redone after it. Objects added by them are taken out of the
+/// scene again, handing back their IDs, so the next object added gets
+/// the same ID when the log is replayed.
+///
+fn forget_undone(history: &mut History<SceneEdit>, scene: &mut Scene) {
+    for edit in history.forget_undone() {
+        if let SceneEdit::Add { id, .. } = edit {
+            if let Err(e) = scene.remove_object(id) {
+                eprintln!("{}", e);
             }
+        }
+    }
+}
+
+///
+/// Undo the last edit, or redo the last one undone, and return it.
+///
+fn step_history(history: &mut History<SceneEdit>, scene: &mut Scene, lights: &mut [Light],
+                redo: bool) -> Option<SceneEdit> {
+    let edit = if redo { history.redo()?.clone() } else { history.undo()?.clone() };
+
+    match edit.apply(scene, lights, !redo) {
+        Ok(()) => Some(edit),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+///
+/// Replay the edits saved in an edit log onto `scene` and `lights`,
+/// and return them as a history, so they can still be undone. A log
+/// that doesn't exist yet is empty. Each line of the log is one edit:
+///
+/// ```text
+/// light <index> <x> <y> <z> <r> <g> <b>
+/// add <object>
+/// move <id> <x> <y> <z>
+/// delete <id>
+/// material <id> <material>
+/// ```
+///
+/// giving the center and color of a light after the edit, an object
+/// added as it would be written in a scene file, how far an object
+/// has been moved from where the scene puts it, an object taken out
+/// of the scene, or the material an object was given. Objects are
+/// given by their ID.
+///
+fn read_edit_log(path: &Path, scene: &mut Scene, lights: &mut [Light],
+                 registry: &Registry) -> Result<History<SceneEdit>, String> {
+    let mut history = History::default();
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(history),
+        Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+    };
+
+    for (n, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let edit = match parse_edit(line, scene, lights, registry) {
+            Some(edit) => edit,
+            None => Err(format!("Invalid edit: {}", line)),
+        };
+        match edit.and_then(|edit| edit.apply(scene, lights, false).map(|()| edit)) {
+            Ok(edit) => history.record(edit),
+            Err(e) => return Err(format!("{}:{}: {}", path.display(), n + 1, e)),
+        }
+    }
+
+    Ok(history)
+}
+
+// The edit on a line of an edit log, or None if it isn't one. Added
+// objects are added to the scene here, which can go wrong.
+fn parse_edit(line: &str, scene: &mut Scene, lights: &[Light],
+              registry: &Registry) -> Option<Result<SceneEdit, String>> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let numbers = |fields: &[&str]| -> Option<Vec<f32>> { fields.iter().map(|f| f.parse().ok()).collect() };
+
+    let edit = match *fields.as_slice() {
+        ["light", index, ..] if fields.len() == 8 => {
+            let index: usize = index.parse().ok()?;
+            let v = numbers(&fields[2..])?;
+            let before = lights.get(index)?.clone();
+
+            let mut after = before.clone();
+            after.center = Vec3::new(v[0], v[1], v[2]);
+            after.emit = Vec3::new(v[3], v[4], v[5]);
+            SceneEdit::Light { index, before, after }
+        },
+        ["add", ..] => {
+            let object = fields[1..].join(" ");
+            return Some(scene.add_object(&object, registry).map(|id| SceneEdit::Add { id, line: object }));
+        },
+        ["move", id, x, y, z] => {
+            let id: u32 = id.parse().ok()?;
+            let v = numbers(&[x, y, z])?;
+            let (_, before, _) = scene.object(id)?;
+            SceneEdit::Move { id, before, after: Vec3::new(v[0], v[1], v[2]) }
+        },
+        ["delete", id] => {
+            let id: u32 = id.parse().ok()?;
+            scene.object(id)?;
+            SceneEdit::Delete { id }
         },
-        None => {
-            let unit_direction: Vec3 = Vec3::unit_vector(&r.direction());
-            let t: f32 = 0.5 * (unit_direction.y() + 1.0);
-            (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+        ["material", id, material] => {
+            let id: u32 = id.parse().ok()?;
+            let (before, _, _) = scene.object(id)?;
+            SceneEdit::Material { id, before: before.to_string(), after: material.to_string() }
+        },
+        _ => return None,
+    };
+
+    Some(Ok(edit))
+}
+
+// How far one key press moves the camera, how far it turns for each
+// pixel the mouse is dragged, in degrees, and how long after the last
+// move the full quality render starts, in ms
+const CAMERA_STEP: f32 = 0.25;
+const CAMERA_TURN: f32 = 0.25;
+const CAMERA_SETTLE: u64 = 500;
+
+// How much one click of the mouse wheel zooms the image in the
+// window, and how far it can zoom in
+const ZOOM_STEP: f32 = 1.25;
+const MAX_ZOOM: f32 = 64.0;
+
+///
+/// Apply a preview window key press to the camera. W and S move it
+/// forward and back, A and D to the left and right, and Q and E down
+/// and up, taking the point it looks at along. Returns the new
+/// `lookfrom` and `lookat`, or None if the key isn't a camera key.
+///
+fn move_camera(lookfrom: Vec3, lookat: Vec3, vup: Vec3, keycode: Keycode) -> Option<(Vec3, Vec3)> {
+    let forward: Vec3 = Vec3::unit_vector(&(lookat - lookfrom));
+    let right: Vec3 = Vec3::unit_vector(&Vec3::cross(&forward, &vup));
+    let up: Vec3 = Vec3::unit_vector(&vup);
+
+    let step: Vec3 = CAMERA_STEP * match keycode {
+        Keycode::W => forward,
+        Keycode::S => -forward,
+        Keycode::D => right,
+        Keycode::A => -right,
+        Keycode::E => up,
+        Keycode::Q => -up,
+        _ => return None,
+    };
+
+    Some((lookfrom + step, lookat + step))
+}
+
+///
+/// Turn the camera for a mouse drag of (`dx`, `dy`) pixels: across
+/// turns it around `vup`, and down and up tilts it, stopping short of
+/// looking straight up or down. Returns the new `lookat`, which stays
+/// as far from the camera as it was.
+///
+fn turn_camera(lookfrom: Vec3, lookat: Vec3, vup: Vec3, dx: i32, dy: i32) -> Vec3 {
+    let up: Vec3 = Vec3::unit_vector(&vup);
+    let direction: Vec3 = lookat - lookfrom;
+    let right: Vec3 = Vec3::unit_vector(&Vec3::cross(&direction, &up));
+
+    let turned: Vec3 = rotate(&direction, &up, -(dx as f32 * CAMERA_TURN).to_radians());
+    let tilted: Vec3 = rotate(&turned, &right, -(dy as f32 * CAMERA_TURN).to_radians());
+
+    if Vec3::dot(&Vec3::unit_vector(&tilted), &up).abs() < 0.99 {
+        lookfrom + tilted
+    } else {
+        lookfrom + turned
+    }
+}
+
+// Rotate `v` by `angle` radians around the unit vector `axis`
+fn rotate(v: &Vec3, axis: &Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    cos * *v + sin * Vec3::cross(axis, v) + (1.0 - cos) * Vec3::dot(axis, v) * *axis
+}
+
+// Save the image shown in the window, as far as it has got, as a PNG
+// next to the executable named for the time it's taken
+fn save_screenshot(width: u32, height: u32, rgb: &[u8]) -> io::Result<PathBuf> {
+    let exe = env::current_exe()?;
+    let dir = exe.parent().unwrap_or_else(|| Path::new("."));
+    let name = format!("screenshot-{}", timestamp(now() / 1000));
+
+    let mut path = dir.join(format!("{}.png", name));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{}-{}.png", name, n));
+    }
+
+    output::write_png(&path, width, height, rgb)?;
+    Ok(path)
+}
+
+// A time in seconds since 1970 as YYYYMMDD-HHMMSS, in UTC
+fn timestamp(seconds: u64) -> String {
+    let (days, time) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Days to a date in the proleptic Gregorian calendar, counting
+    // 400-year eras from March 1st of the year 0
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+// The settings for the single quick pass rendered while the camera moves
+fn preview(settings: &Settings) -> Settings {
+    Settings {
+        samples: SAMPLES_PER_PASS,
+        adaptive: None,
+        quality: None,
+        simple_materials: settings.simple_preview,
+        ..settings.clone()
+    }
+}
+
+// The settings panel's view of a window event, if it's one it uses.
+// The panel covers the image, which is `view` in the window but
+// `width` by `height` pixels to the panel.
+fn panel_input(event: &Event, view: Rect, width: u32, height: u32) -> Option<PanelInput> {
+    let to_image = |x: i32, y: i32| {
+        ((x - view.x()) as f32 * width as f32 / view.width() as f32,
+         (y - view.y()) as f32 * height as f32 / view.height() as f32)
+    };
+
+    match *event {
+        Event::MouseMotion { x, y, .. } => {
+            let (x, y) = to_image(x, y);
+            Some(PanelInput::PointerMoved(x, y))
+        },
+        Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+            let (x, y) = to_image(x, y);
+            Some(PanelInput::Button { x, y, pressed: true })
+        },
+        Event::MouseButtonUp { mouse_btn: MouseButton::Left, x, y, .. } => {
+            let (x, y) = to_image(x, y);
+            Some(PanelInput::Button { x, y, pressed: false })
+        },
+        Event::MouseWheel { x, y, .. } => Some(PanelInput::Wheel(x as f32, y as f32)),
+        Event::TextInput { ref text, .. } => Some(PanelInput::Text(text.clone())),
+        Event::KeyDown { keycode: Some(keycode), keymod, .. } | Event::KeyUp { keycode: Some(keycode), keymod, .. } => {
+            let key = match keycode {
+                Keycode::Backspace => PanelKey::Backspace,
+                Keycode::Delete => PanelKey::Delete,
+                Keycode::Return | Keycode::KpEnter => PanelKey::Enter,
+                Keycode::Tab => PanelKey::Tab,
+                Keycode::Escape => PanelKey::Escape,
+                Keycode::Left => PanelKey::Left,
+                Keycode::Right => PanelKey::Right,
+                Keycode::Up => PanelKey::Up,
+                Keycode::Down => PanelKey::Down,
+                Keycode::Home => PanelKey::Home,
+                Keycode::End => PanelKey::End,
+                _ => return None,
+            };
+            Some(PanelInput::Key {
+                key,
+                pressed: matches!(*event, Event::KeyDown { .. }),
+                shift: keymod.intersects(LSHIFTMOD | RSHIFTMOD),
+                ctrl: keymod.intersects(LCTRLMOD | RCTRLMOD),
+            })
+        },
+        _ => None,
+    }
+}
+
+// Where an image `width` by `height` goes in a window of `size`: as
+// large as it fits without changing shape, in the middle, with black
+// bars along the sides or the top and bottom
+fn letterbox(size: (u32, u32), width: u32, height: u32) -> Rect {
+    let scale = (size.0 as f32 / width as f32).min(size.1 as f32 / height as f32);
+    let w = ((width as f32 * scale).round() as u32).clamp(1, size.0.max(1));
+    let h = ((height as f32 * scale).round() as u32).clamp(1, size.1.max(1));
+    Rect::new(((size.0 - w) / 2) as i32, ((size.1 - h) / 2) as i32, w, h)
+}
+
+// How far the image in the window is zoomed in, as a multiple of the
+// size it fits the window at, and how far it has been dragged from
+// the middle of the window, in window pixels
+struct Zoom {
+    scale: f32,
+    pan: (f32, f32),
+}
+
+impl Zoom {
+    fn fit() -> Zoom {
+        Zoom { scale: 1.0, pan: (0.0, 0.0) }
+    }
+
+    // Where the image goes in the window, given where it fits
+    fn place(&self, view: Rect) -> Rect {
+        let (w, h) = (view.width() as f32 * self.scale, view.height() as f32 * self.scale);
+        let x = view.x() as f32 + view.width() as f32 / 2.0 + self.pan.0 - w / 2.0;
+        let y = view.y() as f32 + view.height() as f32 / 2.0 + self.pan.1 - h / 2.0;
+        Rect::new(x.round() as i32, y.round() as i32, w.round() as u32, h.round() as u32)
+    }
+
+    // Zoom in `steps` wheel clicks, or out if it's negative, keeping
+    // the pixel under the pointer where it is
+    fn zoom(&mut self, steps: i32, pointer: (i32, i32), view: Rect) {
+        let scale = (self.scale * ZOOM_STEP.powi(steps)).clamp(1.0, MAX_ZOOM);
+        let ratio = scale / self.scale;
+        let (cx, cy) = (view.x() as f32 + view.width() as f32 / 2.0, view.y() as f32 + view.height() as f32 / 2.0);
+        let (px, py) = (pointer.0 as f32 - cx, pointer.1 as f32 - cy);
+
+        self.pan = (px - (px - self.pan.0) * ratio, py - (py - self.pan.1) * ratio);
+        self.scale = scale;
+        self.pan(0, 0, view);
+    }
+
+    // Drag the image, but not so far that it leaves the middle of the window
+    fn pan(&mut self, dx: i32, dy: i32, view: Rect) {
+        let (w, h) = (view.width() as f32 * self.scale / 2.0, view.height() as f32 * self.scale / 2.0);
+        let (x, y) = (self.pan.0 + dx as f32, self.pan.1 + dy as f32);
+        self.pan = (x.clamp(-w, w), y.clamp(-h, h));
+    }
+}
+
+// Show how the render is going in the window's title
+fn show_stats(canvas: &mut Canvas<Window>, stats: &RenderStats, elapsed: u64, note: &str) {
+    let title = format!("Rust Raytracer - {}{}", stats.summary(elapsed), note);
+    canvas.window_mut().set_title(&title).unwrap();
+}
+
+// The pixel of an image `width` by `height` under a point in the
+// window, when the image is at `place`, as its column and line from
+// the bottom
+fn image_pixel(place: Rect, width: u32, height: u32, (x, y): (i32, i32)) -> (u32, u32) {
+    let column = (x - place.x()) as f32 * width as f32 / place.width() as f32;
+    let row = (y - place.y()) as f32 * height as f32 / place.height() as f32;
+    let column = (column.max(0.0) as u32).min(width - 1);
+    let row = (row.max(0.0) as u32).min(height - 1);
+    (column, height - 1 - row)
+}
+
+// Copy the image to the window, zoomed in as asked, with the settings
+// panel over it if it's open and the rectangle being selected, given
+// by two corners in the window
+fn present(canvas: &mut Canvas<Window>, image: &Texture, overlay: &Texture, panel: &Panel, zoom: &Zoom,
+           selection: Option<((i32, i32), (i32, i32))>) {
+    let query = image.query();
+    let view = letterbox(canvas.output_size().unwrap(), query.width, query.height);
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.copy(image, None, zoom.place(view)).unwrap();
+    if let Some(((x0, y0), (x1, y1))) = selection {
+        canvas.set_draw_color(Color::RGB(255, 255, 0));
+        let rect = Rect::new(x0.min(x1), y0.min(y1), x0.abs_diff(x1) + 1, y0.abs_diff(y1) + 1);
+        canvas.draw_rect(rect).unwrap();
+    }
+    if panel.is_open() {
+        canvas.copy(overlay, None, view).unwrap();
+    }
+    canvas.present();
+}
+
+fn print_camera(lookfrom: &Vec3, lookat: &Vec3) {
+    println!("Camera: lookfrom ({:.2}, {:.2}, {:.2}), lookat ({:.2}, {:.2}, {:.2})",
+             lookfrom.x(), lookfrom.y(), lookfrom.z(), lookat.x(), lookat.y(), lookat.z());
+}
+
+// The bookmark slot of a number key, if it is one
+fn bookmark_slot(keycode: Keycode) -> Option<usize> {
+    match keycode {
+        Keycode::Num1 => Some(1),
+        Keycode::Num2 => Some(2),
+        Keycode::Num3 => Some(3),
+        Keycode::Num4 => Some(4),
+        Keycode::Num5 => Some(5),
+        Keycode::Num6 => Some(6),
+        Keycode::Num7 => Some(7),
+        Keycode::Num8 => Some(8),
+        Keycode::Num9 => Some(9),
+        _ => None,
+    }
+}
+
+///
+/// Keep a bookmark for the rest of the session, and save it into the
+/// scene file if there is one, so it's there next time too.
+///
+fn keep_bookmark(bookmarks: &mut Vec<Bookmark>, bookmark: Bookmark, scene_path: &Option<PathBuf>) {
+    bookmarks.retain(|b| b.slot != bookmark.slot);
+    bookmarks.push(bookmark);
+
+    match *scene_path {
+        Some(ref path) => match scene::save_bookmark(path, &bookmark) {
+            Ok(()) => println!("Saved bookmark {} to {}", bookmark.slot, path.display()),
+            Err(e) => eprintln!("Unable to write {}: {}", path.display(), e),
+        },
+        None => println!("Saved bookmark {} until the window is closed", bookmark.slot),
+    }
+}
+
+/// Save the edits in effect to an edit log, replacing what was there.
+fn write_edit_log(path: &Path, history: &History<SceneEdit>) -> io::Result<()> {
+    let mut text = String::new();
+
+    for edit in history.done() {
+        text.push_str(&edit.log_line());
+        text.push('\n');
+    }
+
+    fs::write(path, text)
+}
+
+fn print_light(light: &Light) {
+    println!("Light \"{}\": center ({:.2}, {:.2}, {:.2}), emit ({:.2}, {:.2}, {:.2})",
+             light.name,
+             light.center.x(), light.center.y(), light.center.z(),
+             light.emit.r(), light.emit.g(), light.emit.b());
+}
+
+fn print_object(scene: &Scene, id: u32) {
+    if let Some((material, offset, _)) = scene.object(id) {
+        println!("Object {}: {}, moved ({:.2}, {:.2}, {:.2})", id, material, offset.x(), offset.y(), offset.z());
+    }
+}
+
+/// Save every light group of a film next to `path`, one PFM file each.
+fn write_light_groups(path: &Path, film: &Film, world: &World) {
+    for (group, name) in world.light_groups.iter().enumerate() {
+        let group_path = output::light_group_path(path, name);
+
+        if let Err(e) = output::write_pfm(&group_path, film.width, film.height, &film.light_group_rgb(group)) {
+            eprintln!("Unable to write {}: {}", group_path.display(), e);
         }
     }
 }
 
-#[derive(Debug)]
-struct RenderResult {
-    offset: usize,
-    data: Vec<u8>
+/// Render and save the AOVs asked for next to `path`.
+fn write_aovs(path: &Path, settings: &Settings, world: &World, camera: &Camera) {
+    let aovs = render_aovs(settings, world, camera);
+
+    for &aov in &settings.aovs {
+        let aov_path = aov.path(path);
+        let result = match aov {
+            Aov::ObjectId => output::write_image(&aov_path, aovs.width as u32, aovs.height as u32, &aovs.id_rgb8()),
+            _ => output::write_pfm(&aov_path, aovs.width, aovs.height, &aovs.rgb(aov)),
+        };
+
+        if let Err(e) = result {
+            eprintln!("Unable to write {}: {}", aov_path.display(), e);
+        }
+    }
 }
 
-fn render_line(line: u32, world: &Arc<World>, camera: &Arc<Camera>, pitch: usize) -> RenderResult {
-    let mut data: Vec<u8> = Vec::new();
-    let offset = (NY - 1 - line) as usize * pitch;
-    let y = line as usize;
-    let mut rng = thread_rng();
+fn write_proxy(proxy: &mut Proxy, film: &Film, settings: &Settings) {
+    if let Err(e) = proxy.write(film, settings) {
+        eprintln!("Unable to write the proxy: {}", e);
+    }
+}
 
-    for i in 0..NX {
-        let x = i as usize;
+// Save the render so far to `path`, without the denoising, glare,
+// bloom and depth of field that are only added once it's finished
+fn write_autosave(autosave: &mut Autosave, path: &Path, film: &Film, settings: &Settings) {
+    let settings = Settings { bloom: None, ..settings.clone() };
+    if let Err(e) = autosave.save(path, |p| write_finished(p, film, &settings, &None, &None, &None)) {
+        eprintln!("Unable to save {}: {}", path.display(), e);
+    }
+}
 
-        let mut col: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+// Everything keeping an eye on a render as it goes
+struct Monitors {
+    proxy: Option<Proxy>,
+    autosave: Option<Autosave>,
+    progress: Option<ProgressReport>,
+}
 
-        for _ in 0..NS {
-            let ir: f32 = rng.gen();
-            let jr: f32 = rng.gen();
-            let u: f32 = (x as f32 + ir) / NX as f32;
-            let v: f32 = (y as f32 + jr) / NY as f32;
+// Print a message about the render: on stdout, unless that's kept for
+// --progress-json events
+fn status(settings: &Settings, message: &str) {
+    if settings.progress_json {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
 
-            let r: Ray = camera.get_ray(u, v);
-            col += color(&r, &world, 0);
+// Render a frame without a window, keeping the proxy up to date,
+// autosaving to `path` and reporting progress as it goes, and writing
+// the proxy once more when the frame is done
+fn render_frame(settings: &Arc<Settings>, world: Arc<World>, camera: Arc<Camera>, mask: Option<Arc<BlueNoiseMask>>,
+                path: &Path, monitors: &mut Monitors) -> Film {
+    let film = render_film_with_progress(settings.clone(), world, camera, mask, |film| {
+        if let Some(ref mut proxy) = monitors.proxy {
+            if proxy.due() {
+                write_proxy(proxy, film, settings);
+            }
+        }
+        if let Some(ref mut autosave) = monitors.autosave {
+            if autosave.due() {
+                write_autosave(autosave, path, film, settings);
+            }
+        }
+        if let Some(ref mut progress) = monitors.progress {
+            progress.tile_done(film.samples_taken());
         }
+    });
 
-        col /= NS as f32;
+    if let Some(ref mut proxy) = monitors.proxy {
+        write_proxy(proxy, &film, settings);
+    }
+    film
+}
 
-        // Adjust gamma
-        col.e[0] = col.e[0].sqrt();
-        col.e[1] = col.e[1].sqrt();
-        col.e[2] = col.e[2].sqrt();
+// Report a finished frame saved to `path`, if progress is being reported
+fn frame_done(monitors: &mut Monitors, path: &Path, film: &Film) {
+    if let Some(ref mut progress) = monitors.progress {
+        progress.frame_done(path, film.samples_taken());
+    }
+}
+
+fn finish_progress(monitors: &Monitors) {
+    if let Some(ref progress) = monitors.progress {
+        progress.finish();
+    }
+}
 
-        let ir: u8 = (255.99 * col.r()) as u8;
-        let ig: u8 = (255.99 * col.g()) as u8;
-        let ib: u8 = (255.99 * col.b()) as u8;
+fn write_heatmap(path: &Path, film: &Film, max_samples: u32) {
+    let data = film.heatmap_rgb8(max_samples);
 
-        data.push(ir);
-        data.push(ig);
-        data.push(ib);
+    if let Err(e) = output::write_image(path, film.width as u32, film.height as u32, &data) {
+        eprintln!("Unable to write {}: {}", path.display(), e);
     }
+}
 
-    RenderResult {
-        offset,
-        data
+// The finished image as linear RGB, denoised, blurred by depth and
+// with glare and bloom added if asked for
+fn finished_rgb(film: &Film, settings: &Settings, denoiser: &Option<Denoiser>, glare: &Option<Glare>,
+                dof: &Option<DepthOfField>) -> Vec<Vec3> {
+    let mut data = film.rgb();
+    if let Some(ref denoiser) = *denoiser {
+        denoiser.apply(&mut data, settings.threads as usize);
+    }
+    if let Some(ref dof) = *dof {
+        dof.apply(&mut data, settings.threads as usize);
+    }
+    if let Some(ref glare) = *glare {
+        glare.apply(film.width, film.height, &mut data);
+    }
+    if let Some(strength) = settings.bloom {
+        Bloom::new(strength, settings.bloom_threshold, settings.bloom_radius).apply(film.width, film.height, &mut data);
     }
+    data
 }
 
-fn now() -> u64 {
-    let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    t.as_secs() * 1000 + t.subsec_nanos() as u64 / 1_000_000
+// Save the finished image, as linear floats or through the display
+// transform, depending on the kind of file asked for
+fn write_finished(path: &Path, film: &Film, settings: &Settings, denoiser: &Option<Denoiser>,
+                  glare: &Option<Glare>, dof: &Option<DepthOfField>) -> io::Result<()> {
+    let data = finished_rgb(film, settings, denoiser, glare, dof);
+
+    if settings.transparent {
+        let alpha = film.alpha();
+        return if ImageFormat::from_path(path).is_float() {
+            output::write_exr_rgba(path, film.width, film.height, &data, &alpha)
+        } else if settings.bit_depth == 16 {
+            let rgba = film::rgba16(&data, &alpha, film.width, &settings.metered_post_chain(&data));
+            output::write_png16_rgba(path, film.width as u32, film.height as u32, &rgba)
+        } else {
+            let rgba = film::rgba8(&data, &alpha, film.width, &settings.metered_post_chain(&data));
+            output::write_png_rgba(path, film.width as u32, film.height as u32, &rgba)
+        };
+    }
+
+    if ImageFormat::from_path(path).is_float() {
+        output::write_float_image(path, film.width, film.height, &data)
+    } else if settings.bit_depth == 16 {
+        let rgb = film::rgb16(&data, film.width, &settings.metered_post_chain(&data));
+        output::write_image16(path, film.width as u32, film.height as u32, &rgb)
+    } else {
+        let rgb = film::rgb8(&data, film.width, &settings.metered_post_chain(&data));
+        output::write_image(path, film.width as u32, film.height as u32, &rgb)
+    }
+}
+
+// The video an animation is saved as, if its output is a video file
+fn start_video(path: &Path, settings: &Settings) -> Option<Video> {
+    if !video::is_video(path) {
+        return None;
+    }
+
+    match Video::start(path, settings.width, settings.height, settings.fps) {
+        Ok(video) => Some(video),
+        Err(e) => {
+            eprintln!("Unable to write {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+// Save a finished frame of an animation: into the video if there is
+// one, and otherwise to a file of its own at `frame_path`
+fn write_frame(video: &mut Option<Video>, frame_path: &Path, film: &Film, settings: &Settings,
+               denoiser: &Option<Denoiser>, glare: &Option<Glare>, dof: &Option<DepthOfField>) -> io::Result<()> {
+    match *video {
+        Some(ref mut video) => {
+            let data = finished_rgb(film, settings, denoiser, glare, dof);
+            video.write_frame(&film::rgb8(&data, film.width, &settings.metered_post_chain(&data)))
+        },
+        None => write_finished(frame_path, film, settings, denoiser, glare, dof),
+    }
+}
+
+fn finish_video(video: Option<Video>, path: &Path) {
+    if let Some(video) = video {
+        if let Err(e) = video.finish() {
+            eprintln!("Unable to write {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+// A denoiser for the finished image, guided by the AOVs of what the
+// camera sees, if it's to be denoised
+fn post_denoiser(settings: &Settings, world: &World, camera: &Camera) -> Option<Denoiser> {
+    if !settings.denoise {
+        return None;
+    }
+
+    let denoiser = Denoiser::new(render_aovs(settings, world, camera));
+    Some(if settings.oidn { denoiser.with_oidn() } else { denoiser })
+}
+
+// Depth of field to blur the finished image with, if it's to be faked
+// after rendering rather than traced through the lens
+fn post_depth_of_field(settings: &Settings, world: &World, camera: &Camera, lookfrom: Vec3,
+                       lookat: Vec3) -> Option<DepthOfField> {
+    match settings.aperture {
+        Some(aperture) if settings.post_dof => {
+            let focus_distance = settings.focus_distance.unwrap_or_else(|| (lookat - lookfrom).length());
+            let depth = render_depth(settings, world, camera);
+            Some(DepthOfField::new(camera, aperture, focus_distance, settings.width as usize,
+                                   settings.height as usize, depth))
+        },
+        _ => None,
+    }
+}
+
+// The camera asked for by the settings, looking from `lookfrom` to
+// `lookat` with a vertical field of view of `vfov` degrees
+fn build_camera(settings: &Settings, lens: &Option<LensSystem>, lookfrom: Vec3, lookat: Vec3, vup: Vec3,
+                vfov: f32) -> Result<Camera, String> {
+    let aspect = settings.width as f32 / settings.height as f32;
+    let camera: Camera = match (settings.orthographic, settings.fisheye) {
+        _ if settings.equirectangular => Camera::equirectangular(lookfrom, lookat, vup),
+        (Some(height), _) => Camera::orthographic(lookfrom, lookat, vup, height, aspect),
+        (_, Some(fov)) => Camera::fisheye(lookfrom, lookat, vup, fov, settings.fisheye_mapping, aspect),
+        _ => Camera::new(lookfrom, lookat, vup, vfov, aspect)
+            .shifted(settings.lens_shift.0, settings.lens_shift.1),
+    };
+
+    let focus_distance = settings.focus_distance.unwrap_or_else(|| (lookat - lookfrom).length());
+    match (settings.aperture, lens) {
+        (Some(_), _) if settings.post_dof => Ok(camera),
+        (Some(aperture), _) => {
+            let camera = camera.with_lens(aperture, focus_distance, settings.tilt);
+            Ok(match settings.blades {
+                Some(blades) => camera.with_blades(blades, settings.blade_rotation),
+                None => camera,
+            })
+        },
+        (None, Some(system)) => camera.with_lens_system(system, focus_distance),
+        (None, None) => Ok(camera),
+    }
 }
 
 fn main() {
-    let start_time = now();
+    let mut settings = match Settings::from_args(env::args()) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, Settings::usage());
+            process::exit(1);
+        }
+    };
+
+    if !settings.mix.is_empty() {
+        let path = settings.output.clone().unwrap();
+        let result = output::mix_pfm(&settings.mix).and_then(|(width, height, data)| {
+            if ImageFormat::from_path(&path).is_float() {
+                output::write_float_image(&path, width, height, &data)
+            } else if settings.bit_depth == 16 {
+                let rgb = film::rgb16(&data, width, &PostChain::metered(&settings, width, height, &data));
+                output::write_image16(&path, width as u32, height as u32, &rgb)
+            } else {
+                let rgb = film::rgb8(&data, width, &PostChain::metered(&settings, width, height, &data));
+                output::write_image(&path, width as u32, height as u32, &rgb)
+            }
+        });
+
+        if let Err(e) = result {
+            eprintln!("Unable to mix light groups into {}: {}", path.display(), e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut start_time = now();
     let mut time_displayed = false;
+    let nx = settings.width;
+    let ny = settings.height;
+
+    let registry = Registry::new();
+    if let (Some(path), Some(budget)) = (settings.scene.as_ref(), settings.max_memory) {
+        if let Err(e) = memory::check_file(path, budget) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    let scene = match settings.scene {
+        Some(ref path) => Scene::load(path, &registry),
+        None => match settings.cover {
+            Some(seed) => Scene::parse(&cover::scene(seed), &registry),
+            None => Scene::parse(DEFAULT_SCENE, &registry),
+        },
+    };
+    let mut scene = match scene {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    for name in &settings.hide {
+        if let Err(e) = scene.hide(name) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    // A rig given on the command line takes over from the scene's
+    let rig = settings.rig.or(scene.rig);
+    if let Some(rig) = rig {
+        scene.add_rig(&registry, rig);
+    }
+    let sky = rig.map_or(Sky::default(), |r| r.sky());
+
+    // And so does --post
+    if settings.post.is_none() {
+        settings.post = scene.post.clone();
+    }
+
+    // And --accelerator, unless it's left to pick one
+    if let (AcceleratorKind::Auto, Some(kind)) = (settings.accelerator, scene.accelerator) {
+        settings.accelerator = kind;
+    }
+
+    let mut lights = scene.lights.clone();
+    let mut history = match settings.edit_log {
+        Some(ref path) => match read_edit_log(path, &mut scene, &mut lights, &registry) {
+            Ok(history) => history,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => History::default(),
+    };
+    let world: World = build_world(&scene, &registry, sky, clouds(&settings), &lights, &settings, 0.0);
+    let mut first_light = world.objects.len() - lights.len();
+
+    let vup = Vec3::new(0.0, 1.0, 0.0);
+
+    let (lookfrom, lookat) = match settings.cover {
+        Some(_) => (Vec3::new(cover::LOOKFROM[0], cover::LOOKFROM[1], cover::LOOKFROM[2]),
+                    Vec3::new(cover::LOOKAT[0], cover::LOOKAT[1], cover::LOOKAT[2])),
+        None => (Vec3::new(-2.0, 2.0, 1.0), Vec3::new(0.0, 0.0, -1.0)),
+    };
+
+    let lens: Option<LensSystem> = settings.lens.as_ref().map(|path| match LensSystem::load(path) {
+        Ok(system) => system,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    });
+
+    let camera_path: Option<CameraPath> = settings.camera_path.as_ref().map(|path| match CameraPath::load(path) {
+        Ok(camera_path) => camera_path,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    });
+
+    // let camera: Camera = Camera::default();
+    let camera: Camera = match build_camera(&settings, &lens, lookfrom, lookat, vup, 50.0) {
+        Ok(camera) => camera,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Some(budget) = settings.max_memory {
+        let views = match (settings.panorama, settings.stereo) {
+            (Some(degrees), _) => Panorama::new(&camera, vup).views(degrees),
+            (_, Some(_)) => 2,
+            _ => 1,
+        };
+        if let Err(e) = MemoryEstimate::for_render(&settings, &world, views).check(budget) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    // Missing Open Image Denoise isn't worth giving up the render for
+    if settings.oidn {
+        if let Err(e) = oidn::available() {
+            eprintln!("{}; using the built-in denoiser instead", e);
+            settings.oidn = false;
+        }
+    }
+
+    let mask: Option<Arc<BlueNoiseMask>> = if settings.blue_noise {
+        Some(Arc::new(BlueNoiseMask::new()))
+    } else {
+        None
+    };
+
+    let glare: Option<Glare> = settings.glare.map(|strength| {
+        let glare = Glare::new(strength, settings.glare_threshold, settings.blades, settings.blade_rotation);
+        match settings.lens_dirt {
+            Some(ref path) => match output::read_png(path) {
+                Ok((width, height, data)) => glare.with_dirt(width, height, data),
+                Err(e) => {
+                    eprintln!("Unable to read {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            },
+            None => glare,
+        }
+    });
+
+    let machine = Machine::detect();
+    status(&settings, &format!("Found {}, rendering on the {} with {} threads in {}x{} tiles", machine.describe(),
+                               machine.backend(), settings.threads, settings.tile_size, settings.tile_size));
+    status(&settings, &format!("Rendering {}x{} pixels, {} samples per pixel, up to {} bounces", settings.width,
+                               settings.height, settings.samples, settings.max_depth));
+    status(&settings, &format!("Scene has {} objects, using accelerator: {}", world.objects.len(),
+                               world.accelerator.name()));
+
+    let settings = Arc::new(settings);
+    let mut shared_world = Arc::new(world);
+    let shared_camera = Arc::new(camera.clone());
+    let mut monitors = Monitors {
+        proxy: settings.proxy.as_ref().map(|path| Proxy::new(path, settings.proxy_interval)),
+        autosave: settings.save_every.map(Autosave::new),
+        progress: None,
+    };
+
+    if let Some(frames) = settings.time_lapse {
+        let path = settings.output.clone().unwrap();
+        let time_of_day = TimeOfDay { latitude: settings.latitude, day: settings.day, frames };
+        let mut video = start_video(&path, &settings);
+        if settings.progress_json {
+            monitors.progress = Some(ProgressReport::start(&settings, frames));
+        }
+
+        for frame in 0..frames {
+            let world = Arc::new(build_world(&scene, &registry, time_of_day.sky(frame), clouds(&settings), &lights,
+                                             &settings, 0.0));
+            let frame_path = output::frame_path(&path, frame + 1);
+            let film = render_frame(&settings, world.clone(), shared_camera.clone(), mask.clone(), &frame_path,
+                                    &mut monitors);
+            let dof = post_depth_of_field(&settings, &world, &camera, lookfrom, lookat);
+            let denoiser = post_denoiser(&settings, &world, &camera);
+
+            if settings.light_groups {
+                write_light_groups(&frame_path, &film, &world);
+            }
+
+            if !settings.aovs.is_empty() {
+                write_aovs(&frame_path, &settings, &world, &camera);
+            }
+
+            if let Some(ref heatmap) = settings.heatmap {
+                write_heatmap(&output::frame_path(heatmap, frame + 1), &film, settings.samples);
+            }
+
+            let saved_to = if video.is_some() { &path } else { &frame_path };
+            if let Err(e) = write_frame(&mut video, &frame_path, &film, &settings, &denoiser, &glare, &dof) {
+                eprintln!("Unable to write {}: {}", saved_to.display(), e);
+                process::exit(1);
+            }
+            frame_done(&mut monitors, saved_to, &film);
+            status(&settings, &format!("Frame {}/{} ({:.2}h): {}", frame + 1, frames, time_of_day.hour(frame),
+                                       saved_to.display()));
+        }
+        finish_video(video, &path);
+
+        finish_progress(&monitors);
+        status(&settings, &format!("Rendering {} frames with seed {} took: {} ms", frames, settings.seed,
+                                   now() - start_time));
+        return;
+    }
+
+    if camera_path.is_some() || settings.frames.is_some() {
+        let path = settings.output.clone().unwrap();
+        let start = camera_path.as_ref().map_or(0.0, |p| p.start());
+        let frames = settings.frames.unwrap_or_else(|| camera_path.as_ref().unwrap().frames(settings.fps));
+        let mut video = start_video(&path, &settings);
+        if settings.progress_json {
+            monitors.progress = Some(ProgressReport::start(&settings, frames));
+        }
+
+        for frame in 0..frames {
+            let time = start + frame as f32 / settings.fps;
+            let (from, at, vfov) = match camera_path {
+                Some(ref camera_path) => {
+                    let key = camera_path.at(time);
+                    (key.lookfrom, key.lookat, key.vfov)
+                },
+                None => (lookfrom, lookat, 50.0),
+            };
+            let camera = match build_camera(&settings, &lens, from, at, vup, vfov) {
+                Ok(camera) => camera,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            };
+
+            // The world only needs building again if something in it moves
+            let world = if scene.is_animated() {
+                Arc::new(build_world(&scene, &registry, sky, clouds(&settings), &lights,
+                                     &settings, time))
+            } else {
+                shared_world.clone()
+            };
+            let dof = post_depth_of_field(&settings, &world, &camera, from, at);
+            let denoiser = post_denoiser(&settings, &world, &camera);
+            let frame_path = output::frame_path(&path, frame + 1);
+
+            if !settings.aovs.is_empty() {
+                write_aovs(&frame_path, &settings, &world, &camera);
+            }
+
+            let film = render_frame(&settings, world.clone(), Arc::new(camera), mask.clone(), &frame_path,
+                                    &mut monitors);
+
+            if settings.light_groups {
+                write_light_groups(&frame_path, &film, &world);
+            }
+
+            if let Some(ref heatmap) = settings.heatmap {
+                write_heatmap(&output::frame_path(heatmap, frame + 1), &film, settings.samples);
+            }
+
+            let saved_to = if video.is_some() { &path } else { &frame_path };
+            if let Err(e) = write_frame(&mut video, &frame_path, &film, &settings, &denoiser, &glare, &dof) {
+                eprintln!("Unable to write {}: {}", saved_to.display(), e);
+                process::exit(1);
+            }
+            frame_done(&mut monitors, saved_to, &film);
+            status(&settings, &format!("Frame {}/{} ({:.2}s): {}", frame + 1, frames, time, saved_to.display()));
+        }
+        finish_video(video, &path);
+
+        finish_progress(&monitors);
+        status(&settings, &format!("Rendering {} frames with seed {} took: {} ms", frames, settings.seed,
+                                   now() - start_time));
+        return;
+    }
+
+    if let Some(frames) = settings.turntable {
+        let path = settings.output.clone().unwrap();
+        // Lights go last, and are left out of what the camera circles
+        let turntable = Turntable::new(&shared_world.objects[..first_light], 50.0);
+        let mut images: Vec<Vec<u8>> = Vec::with_capacity(frames as usize);
+        if settings.progress_json {
+            monitors.progress = Some(ProgressReport::start(&settings, frames));
+        }
+
+        for frame in 0..frames {
+            let (from, at) = turntable.at(frame, frames);
+            let camera = match build_camera(&settings, &lens, from, at, vup, 50.0) {
+                Ok(camera) => camera,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            };
+            let dof = post_depth_of_field(&settings, &shared_world, &camera, from, at);
+            let denoiser = post_denoiser(&settings, &shared_world, &camera);
+            let film = render_frame(&settings, shared_world.clone(), Arc::new(camera), mask.clone(), &path,
+                                    &mut monitors);
+
+            if let Some(ref heatmap) = settings.heatmap {
+                write_heatmap(&output::frame_path(heatmap, frame + 1), &film, settings.samples);
+            }
+
+            let data = finished_rgb(&film, &settings, &denoiser, &glare, &dof);
+            images.push(film::rgb8(&data, film.width, &settings.metered_post_chain(&data)));
+            frame_done(&mut monitors, &path, &film);
+            status(&settings, &format!("Frame {}/{}", frame + 1, frames));
+        }
+
+        match output::write_gif(&path, nx, ny, &images, settings.fps) {
+            Ok(_) => {
+                finish_progress(&monitors);
+                status(&settings, &format!("Rendering a {} frame turntable with seed {} took: {} ms",
+                                           frames, settings.seed, now() - start_time));
+            },
+            Err(e) => {
+                eprintln!("Unable to write {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(degrees) = settings.panorama {
+        let path = settings.output.clone().unwrap();
+        let panorama = Panorama::new(&camera, vup);
+        if settings.progress_json {
+            monitors.progress = Some(ProgressReport::start(&settings, 1));
+        }
+        let (width, height, data) = panorama.render(degrees, settings.clone(), shared_world, mask);
+
+        match output::write_image(&path, width, height, &data) {
+            Ok(_) => {
+                finish_progress(&monitors);
+                status(&settings, &format!("Rendering a {}x{} panorama with seed {} took: {} ms",
+                                           width, height, settings.seed, now() - start_time));
+            },
+            Err(e) => {
+                eprintln!("Unable to write {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(interocular) = settings.stereo {
+        let path = settings.output.clone().unwrap();
+        let convergence = settings.convergence.unwrap_or_else(|| (lookat - lookfrom).length());
+        if settings.progress_json {
+            monitors.progress = Some(ProgressReport::start(&settings, 1));
+        }
+        let (width, height, data) = stereo::render_stereo(&camera, interocular, convergence, settings.stereo_layout,
+                                                          settings.clone(), shared_world, mask);
+
+        match output::write_image(&path, width, height, &data) {
+            Ok(_) => {
+                finish_progress(&monitors);
+                status(&settings, &format!("Rendering a {}x{} stereo pair with seed {} took: {} ms",
+                                           width, height, settings.seed, now() - start_time));
+            },
+            Err(e) => {
+                eprintln!("Unable to write {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if settings.stream {
+        let path = settings.output.clone().unwrap();
+        if settings.progress_json {
+            monitors.progress = Some(ProgressReport::start(&settings, 1));
+        }
+        let mut samples: u64 = 0;
+        let result = ImageStream::create(&path, nx, ny).and_then(|mut stream| {
+            render_to_stream(settings.clone(), shared_world, shared_camera, mask, &mut stream, |result| {
+                samples += result.data.iter().map(|p| p.count as u64).sum::<u64>();
+                if let Some(ref mut progress) = monitors.progress {
+                    progress.tile_done(samples);
+                }
+            })?;
+            stream.finish()
+        });
+
+        match result {
+            Ok(_) => {
+                if let Some(ref mut progress) = monitors.progress {
+                    progress.frame_done(&path, samples);
+                    progress.finish();
+                }
+                status(&settings, &format!("Rendering with {} threads and seed {} took: {} ms",
+                                           settings.threads, settings.seed, now() - start_time));
+            },
+            Err(e) => {
+                eprintln!("Unable to write {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem.window("Rust Raytracer", NX, NY)
+    let window = video_subsystem.window("Rust Raytracer", nx, ny)
         .position_centered()
+        .resizable()
         .build()
         .unwrap();
     let mut canvas = window.into_canvas().build().unwrap();
 
     let texture_creator = canvas.texture_creator();
     let mut texture = texture_creator.create_texture_streaming(
-        PixelFormatEnum::RGB24, NX, NY).unwrap();
-
-    let world: World = World {
-        objects: vec![
-            // Middle sphere
-            Box::new(Sphere::new(Vec3::new(0.0, 0.0, -1.0),
-                                 0.5,
-                                 Box::new(Lambertian::new(Vec3::new(0.8, 0.3, 0.3))))),
-            // Right sphere
-            Box::new(Sphere::new(Vec3::new(1.5, 0.2, -1.5),
-                                 0.7,
-                                 Box::new(Metal::new(Vec3::new(0.6, 0.6, 0.9))))),
-
-            // Left sphere
-            Box::new(Sphere::new(Vec3::new(-1.0, 0.0, -1.0),
-                                 0.5,
-                                 Box::new(Dialectric::new(2.0)))),
-
-            // Giant "ground" sphere
-            Box::new(Sphere::new(Vec3::new(0.0, -100.5, -1.0),
-                                 100.0,
-                                 Box::new(Lambertian::new(Vec3::new(0.3, 0.3, 0.3))))),
-        ],
-    };
+        PixelFormatEnum::RGB24, nx, ny).unwrap();
 
-    // let camera: Camera = Camera::default();
-    let camera: Camera = Camera::new(
-        Vec3::new(-2.0, 2.0, 1.0),
-        Vec3::new(0.0, 0.0, -1.0),
-        Vec3::new(0.0, 1.0, 0.0),
-        50.0,
-        NX as f32 / NY as f32
-    );
+    // The settings panel, toggled with F1, is drawn over the image
+    // from a texture of its own, so the image underneath is kept
+    let mut vfov = 50.0;
+    let mut panel = Panel::new(nx, ny, PanelValues::new(&settings, vfov));
+    let mut overlay = texture_creator.create_texture_streaming(
+        PixelFormatEnum::ABGR8888, nx, ny).unwrap();
+    overlay.set_blend_mode(BlendMode::Blend);
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut j = NY;
-    let pitch = NX as usize * PixelFormatEnum::RGB24.byte_size_per_pixel();
-
-    let shared_world = Arc::new(world);
-    let shared_camera = Arc::new(camera);
-    let (tx, rx) = channel();
-
-    for thread_num in 0..NUM_THREADS {
-        let sw = shared_world.clone();
-        let sc = shared_camera.clone();
-        let tx = tx.clone();
-        let lines_per_block = NY / NUM_THREADS;
-        let start_line = thread_num * lines_per_block;
-        let end_line = (thread_num + 1) * lines_per_block;
-        thread::spawn(move || {
-            for line in start_line..end_line {
-                let result = render_line(line, &sw, &sc, pitch);
-                tx.send(result).unwrap();
-            }
-        });
-    }
+    // With --resize-render, the image follows the window's size
+    let (mut nx, mut ny) = (nx, ny);
+
+    // The image can be zoomed in on around the mouse pointer
+    let mut zoom = Zoom::fit();
+    let mut pointer = (0, 0);
+
+    // A rectangle dragged out with Shift held, from the corner where
+    // the drag started to the pointer, is rendered again with more
+    // samples. Passes are numbered on from those the film already has.
+    let mut selection: Option<((i32, i32), (i32, i32))> = None;
+    // Where the left button went down, until the pointer moves and
+    // makes it a drag; let go there, it's a click on that pixel
+    let mut click: Option<(i32, i32)> = None;
+    let mut region: Option<Tile> = None;
+    let mut passes = settings.samples.div_ceil(SAMPLES_PER_PASS);
+    let mut film_passes = passes;
+    let mut film = Film::new(nx as usize, ny as usize, light_group_count(&settings, &shared_world), settings.half_float);
+    let mut tiles_remaining = passes * Tile::cover(nx, ny, settings.tile_size).len() as u32;
+    let mut stats = RenderStats::new(tiles_remaining);
+    let mut last_stats = now();
+    let mut error: Option<f32> = None;
+    let mut active_remaining = nx * ny;
+    let mut selected_light = 0;
+    let mut selected_object: Option<u32> = None;
+    let mut bookmarks = scene.bookmarks.clone();
+
+    // While the camera is being moved around, only a single pass is
+    // rendered, so that the view keeps up
+    let (mut lookfrom, mut lookat) = (lookfrom, lookat);
+    let mut camera = camera;
+    let mut shared_camera = shared_camera;
+    let mut navigating = false;
+    let mut last_move = now();
+
+    // With --denoise-preview, the window is redrawn denoised after
+    // every pass, and the noisy lines of the next pass aren't shown
+    let mut preview_denoiser: Option<Denoiser> = None;
+    let mut denoised_shown = false;
+
+    // With --auto-exposure, the window is metered again after every
+    // pass, from the film so far
+    let mut settings = settings;
+    let mut post = settings.post_chain();
+    let mut preview_settings = Arc::new(preview(&settings));
+
+    let mut scripts: Vec<PathBuf> = scene.scripts().iter().map(|p| p.to_path_buf()).collect();
+    let mut script_times: Vec<Option<SystemTime>> = scripts.iter().map(|p| modified_time(p)).collect();
+    let mut last_script_check = now();
+    let mut scene_paths = scene_files(&settings.scene, &scene);
+    let mut scene_times: Vec<Option<SystemTime>> = scene_paths.iter().map(|p| modified_time(p)).collect();
+
+    let mut render = ProgressiveRender::start(settings.clone(), shared_world.clone(),
+                                              shared_camera.clone(), mask.clone());
+
+    // Time spent paused doesn't count towards the render time
+    let mut paused_at = 0;
 
     'running: loop {
-        if j > 0 {
-            j -= 1;
-            let result = rx.recv().unwrap();
-            let slice = result.data.as_slice();
+        if tiles_remaining > 0 && !render.is_paused() {
+            tiles_remaining -= 1;
+            let result = render.results.recv().unwrap();
+            let tile = result.tile;
+            stats.tile_done(&result);
+
+            film.add_result(&result);
+            let rgb = film.tile_rgb8(&tile, &post);
+
+            if let Some(threshold) = settings.adaptive {
+                for line in tile.y as usize..(tile.y + tile.height) as usize {
+                    for x in tile.x as usize..(tile.x + tile.width) as usize {
+                        let pixel = &render.active[line * nx as usize + x];
+                        if pixel.load(Ordering::Relaxed) && film.pixel(x, line).converged(threshold) {
+                            pixel.store(false, Ordering::Relaxed);
+                            active_remaining -= 1;
+                        }
+                    }
+                }
+
+                if active_remaining == 0 {
+                    render.stop();
+                    tiles_remaining = 0;
+                }
+            }
+
+            if !denoised_shown {
+                let row = tile.width as usize * 3;
+                let pitch = nx as usize * PixelFormatEnum::RGB24.byte_size_per_pixel();
+                texture.with_lock(None, |buffer: &mut [u8], _: usize| {
+                    for (i, slice) in rgb.chunks(row).enumerate() {
+                        let line = (tile.y + tile.height - 1) as usize - i;
+                        let offset = (ny as usize - 1 - line) * pitch + tile.x as usize * 3;
+                        buffer[offset..offset + row].copy_from_slice(slice);
+                    }
+                }).unwrap();
+
+                present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+            }
+
+            // Re-estimate the noise level once per pass worth of tiles
+            if tiles_remaining.is_multiple_of(render.tiles as u32) {
+                error = film.error();
+
+                if let (Some(target), Some(e), None) = (settings.quality, error, region) {
+                    if e < target {
+                        render.stop();
+                        tiles_remaining = 0;
+                    }
+                }
 
-            texture.with_lock(None, |buffer: &mut [u8], _: usize| {
-                for n in 0..slice.len() {
-                    buffer[result.offset + n] = slice[n];
+                if settings.auto_exposure.is_some() {
+                    post = settings.metered_post_chain(&film.rgb());
                 }
-            }).unwrap();
 
-            canvas.copy(&texture, None, Some(Rect::new(0, 0, NX, NY))).unwrap();
-            canvas.present();
+                if settings.denoise_preview && !navigating {
+                    let denoiser = preview_denoiser.get_or_insert_with(|| {
+                        Denoiser::new(render_aovs(&settings, &shared_world, &camera))
+                    });
+                    let mut data = film.rgb();
+                    denoiser.apply(&mut data, settings.threads as usize);
+                    let rgb = film::rgb8(&data, film.width, &post);
+
+                    texture.with_lock(None, |buffer: &mut [u8], _: usize| {
+                        buffer[..rgb.len()].copy_from_slice(&rgb);
+                    }).unwrap();
+
+                    present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+                    denoised_shown = true;
+                }
+            }
+
+            if let Some(ref mut proxy) = monitors.proxy {
+                if proxy.due() {
+                    write_proxy(proxy, &film, &settings);
+                }
+            }
+
+            // Previews while navigating aren't worth keeping
+            if let (Some(autosave), Some(path)) = (monitors.autosave.as_mut(), settings.output.as_ref()) {
+                if autosave.due() && !navigating {
+                    write_autosave(autosave, path, &film, &settings);
+                }
+            }
         }
 
+        let mut edited = false;
+        let mut objects_edited = false;
+        let mut moved = false;
+        let mut restarted = false;
+        let mut resized = false;
+        let mut selected: Option<Tile> = None;
+        let view = letterbox(canvas.output_size().unwrap(), nx, ny);
+
         for event in event_pump.poll_iter() {
+            if let Some(input) = panel_input(&event, view, nx, ny) {
+                if panel.handle(input) {
+                    continue;
+                }
+            }
+            if let Event::MouseMotion { x, y, .. } = event {
+                pointer = (x, y);
+            }
+
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     break 'running
                 },
+                Event::KeyDown { keycode: Some(Keycode::F1), .. } => {
+                    panel.toggle();
+                },
+                Event::MouseWheel { y, direction, .. } => {
+                    let steps = if direction == MouseWheelDirection::Flipped { -y } else { y };
+                    zoom.zoom(steps, pointer, view);
+                    present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+                },
+                Event::MouseMotion { mousestate, xrel, yrel, .. } if mousestate.right() => {
+                    zoom.pan(xrel, yrel, view);
+                    present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F), .. } => {
+                    zoom = Zoom::fit();
+                    present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+                },
+                Event::Window { win_event: WindowEvent::SizeChanged(width, height), .. } => {
+                    let size = (width.max(1) as u32, height.max(1) as u32);
+                    if settings.resize_render && size != (nx, ny) {
+                        nx = size.0;
+                        ny = size.1;
+                        resized = true;
+                    } else {
+                        present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::Space), .. } if tiles_remaining > 0 => {
+                    if render.is_paused() {
+                        render.set_paused(false);
+                        start_time += now() - paused_at;
+                        println!("Resumed");
+                    } else {
+                        render.set_paused(true);
+                        paused_at = now();
+                        println!("Paused");
+                        show_stats(&mut canvas, &stats, paused_at - start_time, " (paused)");
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::C), .. } if tiles_remaining > 0 => {
+                    render.stop();
+                    tiles_remaining = 0;
+                    println!("Cancelled");
+                },
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                    restarted = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } => {
+                    // Back to the lights from a selected object, or on
+                    // to the next light
+                    if selected_object.take().is_none() && !lights.is_empty() {
+                        selected_light = (selected_light + 1) % lights.len();
+                    }
+                    if !lights.is_empty() {
+                        print_light(&lights[selected_light]);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::O), .. } => {
+                    selected_object = next_object(&scene, selected_object);
+                    if let Some(id) = selected_object {
+                        print_object(&scene, id);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::Insert), .. } => {
+                    // A sphere where the camera looks, of the selected
+                    // object's material, or else the first there is
+                    let material = selected_object.and_then(|id| scene.object(id)).map(|(m, _, _)| m)
+                        .or_else(|| scene.material_names().first().cloned());
+                    if let Some(material) = material {
+                        let line = format!("sphere {} {} {} {} {}", material, lookat.x(), lookat.y(), lookat.z(),
+                                           ADDED_RADIUS);
+                        forget_undone(&mut history, &mut scene);
+                        let added = scene.add_object(&line, &registry).and_then(|id| {
+                            let edit = SceneEdit::Add { id, line };
+                            edit.apply(&mut scene, &mut lights, false).map(|()| (id, edit))
+                        });
+                        match added {
+                            Ok((id, edit)) => {
+                                println!("{} (ID {})", edit.log_line(), id);
+                                history.record(edit);
+                                selected_object = Some(id);
+                                objects_edited = true;
+                                edited = true;
+                            },
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
+                },
+                Event::KeyDown { keycode: Some(keycode @ Keycode::Z), keymod, .. }
+                | Event::KeyDown { keycode: Some(keycode @ Keycode::Y), keymod, .. }
+                    if keymod.intersects(LCTRLMOD | RCTRLMOD) => {
+                    let redo = keycode == Keycode::Y || keymod.intersects(LSHIFTMOD | RSHIFTMOD);
+
+                    if let Some(edit) = step_history(&mut history, &mut scene, &mut lights, redo) {
+                        match edit {
+                            SceneEdit::Light { index, .. } => print_light(&lights[index]),
+                            _ => println!("{}: {}", if redo { "Redone" } else { "Undone" }, edit.log_line()),
+                        }
+                        objects_edited |= edit.changes_objects();
+                        edited = true;
+
+                        // An object whose adding was undone can't be edited
+                        if selected_object.and_then(|id| scene.object(id)).is_some_and(|(_, _, hidden)| hidden) {
+                            selected_object = None;
+                        }
+                    }
+                },
+                // Keys that don't edit the selected object still edit
+                // the selected light
+                Event::KeyDown { keycode: Some(keycode), keymod, .. }
+                    if selected_object.and_then(|id| edit_object(&scene, id, keycode, keymod)).is_some() => {
+                    let edit = edit_object(&scene, selected_object.unwrap(), keycode, keymod).unwrap();
+                    match edit.apply(&mut scene, &mut lights, false) {
+                        Ok(()) => {
+                            println!("{}", edit.log_line());
+                            if let SceneEdit::Delete { .. } = edit {
+                                selected_object = None;
+                            }
+                            forget_undone(&mut history, &mut scene);
+                            history.record(edit);
+                            objects_edited = true;
+                            edited = true;
+                        },
+                        Err(e) => eprintln!("{}", e),
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::S), keymod, .. } if keymod.intersects(LCTRLMOD | RCTRLMOD) => {
+                    let rgb = film::rgb8(&film.rgb(), film.width, &post);
+                    match save_screenshot(nx, ny, &rgb) {
+                        Ok(path) => println!("Saved {}", path.display()),
+                        Err(e) => eprintln!("Unable to save screenshot: {}", e),
+                    }
+                },
+                Event::KeyDown { keycode: Some(keycode), .. } if move_camera(lookfrom, lookat, vup, keycode).is_some() => {
+                    let (from, at) = move_camera(lookfrom, lookat, vup, keycode).unwrap();
+                    lookfrom = from;
+                    lookat = at;
+                    moved = true;
+                },
+                Event::KeyDown { keycode: Some(keycode), keymod, .. } if bookmark_slot(keycode).is_some() => {
+                    let slot = bookmark_slot(keycode).unwrap();
+
+                    if keymod.intersects(LCTRLMOD | RCTRLMOD) {
+                        keep_bookmark(&mut bookmarks, Bookmark { slot, lookfrom, lookat }, &settings.scene);
+                    } else if let Some(bookmark) = bookmarks.iter().find(|b| b.slot == slot) {
+                        lookfrom = bookmark.lookfrom;
+                        lookat = bookmark.lookat;
+                        moved = true;
+                    }
+                },
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. }
+                    if sdl_context.keyboard().mod_state().intersects(LSHIFTMOD | RSHIFTMOD) => {
+                    selection = Some(((x, y), (x, y)));
+                },
+                Event::MouseMotion { x, y, .. } if selection.is_some() => {
+                    selection = selection.map(|(start, _)| (start, (x, y)));
+                    present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+                },
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } if selection.is_some() => {
+                    let (start, end) = selection.take().unwrap();
+                    let place = zoom.place(view);
+                    let (x0, y0) = image_pixel(place, nx, ny, start);
+                    let (x1, y1) = image_pixel(place, nx, ny, end);
+                    selected = Some(Tile { x: x0.min(x1), y: y0.min(y1), width: x0.abs_diff(x1) + 1,
+                                           height: y0.abs_diff(y1) + 1 });
+                    present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+                },
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    click = Some((x, y));
+                },
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } if click.is_some() => {
+                    let (x, y) = image_pixel(zoom.place(view), nx, ny, click.take().unwrap());
+                    println!("{}", inspect(&shared_world, &camera, &film, x as usize, y as usize));
+                },
+                Event::MouseMotion { mousestate, xrel, yrel, .. } if mousestate.left() => {
+                    click = None;
+                    lookat = turn_camera(lookfrom, lookat, vup, xrel, yrel);
+                    moved = true;
+                },
+                Event::KeyDown { keycode: Some(keycode), keymod, .. } if !lights.is_empty() => {
+                    if let Some(light) = edit_light(&lights[selected_light], keycode, keymod) {
+                        let edit = SceneEdit::Light {
+                            index: selected_light,
+                            before: lights[selected_light].clone(),
+                            after: light,
+                        };
+                        match edit.apply(&mut scene, &mut lights, false) {
+                            Ok(()) => {
+                                print_light(&lights[selected_light]);
+                                forget_undone(&mut history, &mut scene);
+                                history.record(edit);
+                                edited = true;
+                            },
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
+                },
                 _ => {}
             }
         }
 
-        if j == 0 {
-            if !time_displayed {
-                println!("Rendering with {} threads took: {} ms", NUM_THREADS, now() - start_time);
+        let update = panel.update();
+        if update.repainted {
+            if panel.is_open() {
+                overlay.update(None, panel.overlay(), nx as usize * 4).unwrap();
+            }
+            present(&mut canvas, &texture, &overlay, &panel, &zoom, selection);
+        }
+
+        // Settings from the panel take effect as if they'd been given
+        // on the command line
+        let applied = update.applied.is_some();
+        if let Some(values) = update.applied {
+            settings = Arc::new(values.apply(&settings));
+            preview_settings = Arc::new(preview(&settings));
+            vfov = values.vfov;
+            post = settings.post_chain();
+            match build_camera(&settings, &lens, lookfrom, lookat, vup, vfov) {
+                Ok(c) => camera = c,
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        // Rebuild the whole world when the scene file changes, and
+        // just the objects when a script material does
+        let mut new_scene: Option<Scene> = None;
+        let mut reloaded: Option<Vec<BoxedObject>> = None;
+
+        if now() - last_script_check >= SCRIPT_CHECK_INTERVAL {
+            last_script_check = now();
+
+            if let Some(ref path) = settings.scene {
+                let times: Vec<Option<SystemTime>> = scene_paths.iter().map(|p| modified_time(p)).collect();
+                if times != scene_times {
+                    scene_times = times;
+                    let loaded = Scene::load(path, &registry).map_err(|e| e.to_string()).and_then(|mut s| {
+                        settings.hide.iter().try_for_each(|name| s.hide(name))?;
+                        Ok(s)
+                    });
+                    match loaded {
+                        Ok(s) => {
+                            println!("Reloaded {}", path.display());
+                            new_scene = Some(s);
+                        },
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+            }
+
+            let times: Vec<Option<SystemTime>> = scripts.iter().map(|p| modified_time(p)).collect();
+            if times != script_times && new_scene.is_none() {
+                script_times = times;
+                match scene.try_objects(&registry) {
+                    Ok(objects) => {
+                        println!("Reloaded script materials");
+                        reloaded = Some(objects);
+                    },
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        }
+
+        // Once the camera has stopped moving and its preview is done,
+        // render the view at full quality
+        let settled = navigating && !moved && tiles_remaining == 0 && now() - last_move >= CAMERA_SETTLE;
+        if settled {
+            navigating = false;
+            print_camera(&lookfrom, &lookat);
+        }
+
+        if moved {
+            match build_camera(&settings, &lens, lookfrom, lookat, vup, vfov) {
+                Ok(c) => camera = c,
+                Err(e) => eprintln!("{}", e),
+            }
+            navigating = true;
+            last_move = now();
+        }
+
+        if edited || reloaded.is_some() || new_scene.is_some() || moved || settled || applied || restarted || resized {
+            if edited {
+                if let Some(ref path) = settings.edit_log {
+                    if let Err(e) = write_edit_log(path, &history) {
+                        eprintln!("Unable to write {}: {}", path.display(), e);
+                    }
+                }
+            }
+
+            // Once the workers are gone nothing else holds the world,
+            // and the edited lights and reloaded objects can be
+            // swapped in
+            render.finish();
+            if let Some(mut s) = new_scene {
+                // The scene's lights and objects replace the edited
+                // ones, which can't be undone any more
+                let rig = settings.rig.or(s.rig);
+                if let Some(rig) = rig {
+                    s.add_rig(&registry, rig);
+                }
+                let sky = rig.map_or(Sky::default(), |r| r.sky());
+                lights = s.lights.clone();
+                history = History::default();
+                selected_light = 0;
+                selected_object = None;
+                bookmarks = s.bookmarks.clone();
+                scripts = s.scripts().iter().map(|p| p.to_path_buf()).collect();
+                script_times = scripts.iter().map(|p| modified_time(p)).collect();
+                scene_paths = scene_files(&settings.scene, &s);
+                scene_times = scene_paths.iter().map(|p| modified_time(p)).collect();
+                shared_world = Arc::new(build_world(&s, &registry, sky, clouds(&settings), &lights, &settings, 0.0));
+                first_light = shared_world.objects.len() - lights.len();
+                scene = s;
+            } else if objects_edited {
+                // Edited objects can come and go, moving the lights
+                // along, so the world is built again around them
+                let sky = shared_world.sky;
+                shared_world = Arc::new(build_world(&scene, &registry, sky, clouds(&settings), &lights, &settings, 0.0));
+                first_light = shared_world.objects.len() - lights.len();
+            }
+            {
+                let world = Arc::get_mut(&mut shared_world).unwrap();
+                for (i, object) in reloaded.into_iter().flatten().enumerate() {
+                    world.apply(WorldEdit::Replace(i, object));
+                }
+                for (i, light) in lights.iter().enumerate() {
+                    let sphere = light_sphere(world, light);
+                    world.apply(WorldEdit::Replace(first_light + i, sphere));
+                }
+                world.commit();
+            }
+
+            if resized {
+                settings = Arc::new(Settings { width: nx, height: ny, ..(*settings).clone() });
+                preview_settings = Arc::new(preview(&settings));
+                post = settings.post_chain();
+                match build_camera(&settings, &lens, lookfrom, lookat, vup, vfov) {
+                    Ok(c) => camera = c,
+                    Err(e) => eprintln!("{}", e),
+                }
+                texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, nx, ny).unwrap();
+                overlay = texture_creator.create_texture_streaming(PixelFormatEnum::ABGR8888, nx, ny).unwrap();
+                overlay.set_blend_mode(BlendMode::Blend);
+                panel.resize(nx, ny);
+            }
+
+            // Start accumulating again from scratch with the changes
+            let render_settings = if navigating { &preview_settings } else { &settings };
+            shared_camera = Arc::new(camera.clone());
+            render = ProgressiveRender::start(render_settings.clone(), shared_world.clone(),
+                                              shared_camera.clone(), mask.clone());
+            film = Film::new(nx as usize, ny as usize, light_group_count(&settings, &shared_world), settings.half_float);
+            passes = render_settings.samples.div_ceil(SAMPLES_PER_PASS);
+            film_passes = passes;
+            region = None;
+            tiles_remaining = passes * render.tiles as u32;
+            stats = RenderStats::new(tiles_remaining);
+            active_remaining = nx * ny;
+            error = None;
+            start_time = now();
+            time_displayed = false;
+            preview_denoiser = None;
+            denoised_shown = false;
+        } else if let Some(tile) = selected {
+            // Only the selected pixels get more samples, added to
+            // those the film already has
+            render.finish();
+            render = ProgressiveRender::start_region(settings.clone(), shared_world.clone(), shared_camera.clone(),
+                                                     mask.clone(), tile, film_passes);
+            println!("Rendering {} more samples per pixel in {}", settings.samples, tile);
+            passes = settings.samples.div_ceil(SAMPLES_PER_PASS);
+            film_passes += passes;
+            region = Some(tile);
+            tiles_remaining = passes * render.tiles as u32;
+            stats = RenderStats::new(tiles_remaining);
+            active_remaining = tile.pixels() as u32;
+            error = None;
+            start_time = now();
+            time_displayed = false;
+            denoised_shown = false;
+        }
+
+        if tiles_remaining > 0 && !render.is_paused() && now() - last_stats >= STATS_INTERVAL {
+            last_stats = now();
+            show_stats(&mut canvas, &stats, now() - start_time, "");
+        }
+
+        if tiles_remaining == 0 {
+            if !time_displayed && !navigating {
+                println!("Rendering with {} threads and seed {} took: {} ms",
+                         settings.threads, settings.seed, now() - start_time);
+                stats.stop();
+                show_stats(&mut canvas, &stats, now() - start_time, "");
+                if let Some(e) = error {
+                    println!("Estimated relative error: {:.4}", e);
+                }
+                if let Some(ref path) = settings.output {
+                    let dof = post_depth_of_field(&settings, &shared_world, &camera, lookfrom, lookat);
+                    let denoiser = post_denoiser(&settings, &shared_world, &camera);
+                    if let Err(e) = write_finished(path, &film, &settings, &denoiser, &glare, &dof) {
+                        eprintln!("Unable to write {}: {}", path.display(), e);
+                    }
+                    if settings.light_groups {
+                        write_light_groups(path, &film, &shared_world);
+                    }
+                    if !settings.aovs.is_empty() {
+                        write_aovs(path, &settings, &shared_world, &camera);
+                    }
+                }
+                if let Some(ref heatmap) = settings.heatmap {
+                    write_heatmap(heatmap, &film, settings.samples);
+                }
+                if let Some(ref mut proxy) = monitors.proxy {
+                    write_proxy(proxy, &film, &settings);
+                }
                 time_displayed = true;
             }
             thread::sleep(time::Duration::from_millis(10));
+        } else if render.is_paused() {
+            thread::sleep(time::Duration::from_millis(10));
         }
     }
 }
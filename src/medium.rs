@@ -0,0 +1,253 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+use std::f32::consts;
+
+use aabb::Aabb;
+use hittable::*;
+use ray::Ray;
+use rng::Pcg32;
+use sampler::{hash_combine, Sampler};
+use shader::fractal_noise;
+use vec3::Vec3;
+
+// Octaves of noise in a density field
+const OCTAVES: u32 = 5;
+
+// Noise values over which a density field goes from empty to full,
+// which leaves gaps between wisps
+const NOISE_EMPTY: f32 = 0.35;
+const NOISE_FULL: f32 = 0.65;
+
+// Tentative collisions tried along a ray before giving up on it
+// scattering, which only cuts off rays crossing a great many mean
+// free paths of almost empty space
+const MAX_STEPS: u32 = 1024;
+
+///
+/// How thick a medium is at each point: the same everywhere, like
+/// fog, or wisps of fractal noise (see shader::fractal_noise) with
+/// features about `1 / scale` across, for smoke and clouds. `sigma`
+/// is the chance per unit of light being scattered where the medium
+/// is at its thickest.
+///
+#[derive(Copy, Clone, Debug)]
+pub enum Density {
+    Constant { sigma: f32 },
+    Noise { sigma: f32, scale: f32, seed: u32 },
+}
+
+impl Density {
+    pub fn at(&self, p: &Vec3) -> f32 {
+        match *self {
+            Density::Constant { sigma } => sigma,
+            Density::Noise { sigma, scale, seed } => {
+                let n = fractal_noise(&(scale * *p), OCTAVES, seed);
+                let t = ((n - NOISE_EMPTY) / (NOISE_FULL - NOISE_EMPTY)).clamp(0.0, 1.0);
+                sigma * t * t * (3.0 - 2.0 * t)
+            },
+        }
+    }
+
+    // The most the density can be anywhere
+    fn majorant(&self) -> f32 {
+        match *self {
+            Density::Constant { sigma } | Density::Noise { sigma, .. } => sigma,
+        }
+    }
+}
+
+/// The shape a medium fills.
+#[derive(Copy, Clone, Debug)]
+pub enum Bounds {
+    Sphere { center: Vec3, radius: f32 },
+    Box(Aabb),
+}
+
+impl Bounds {
+    // The distances along `r` between which it's inside the bounds
+    fn span(&self, r: &Ray) -> Option<(f32, f32)> {
+        match *self {
+            Bounds::Sphere { center, radius } => {
+                let oc = r.origin() - center;
+                let a = Vec3::dot(&r.direction(), &r.direction());
+                let b = Vec3::dot(&oc, &r.direction());
+                let c = Vec3::dot(&oc, &oc) - radius * radius;
+                let discriminant = b * b - a * c;
+                if discriminant <= 0.0 {
+                    return None;
+                }
+                let root = discriminant.sqrt();
+                Some(((-b - root) / a, (-b + root) / a))
+            },
+            Bounds::Box(b) => {
+                let (mut near, mut far) = (f32::NEG_INFINITY, f32::INFINITY);
+                for axis in 0..3 {
+                    let inverse = 1.0 / r.direction().e[axis];
+                    let t0 = (b.min.e[axis] - r.origin().e[axis]) * inverse;
+                    let t1 = (b.max.e[axis] - r.origin().e[axis]) * inverse;
+                    near = near.max(t0.min(t1));
+                    far = far.min(t0.max(t1));
+                }
+                if near < far { Some((near, far)) } else { None }
+            },
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match *self {
+            Bounds::Sphere { center, radius } => {
+                let r = Vec3::new(radius, radius, radius);
+                Aabb::new(center - r, center + r)
+            },
+            Bounds::Box(b) => b,
+        }
+    }
+}
+
+///
+/// A participating medium, such as fog or smoke, filling a sphere or
+/// box. Light crossing it is scattered at random points along the way,
+/// more often where it's thicker, and its material (see
+/// HenyeyGreenstein) sends it on in a new direction. Where it's
+/// scattered is found by delta tracking: tentative collisions are
+/// taken as if the medium were everywhere as thick as it gets, and
+/// each is kept with the chance of the density there over that
+/// thickest density. Rays that are never kept pass straight through.
+///
+/// Hits are found without a sampler, so the random numbers come from
+/// a hash of the ray, which is different for every ray traced.
+///
+pub struct Volume {
+    pub bounds: Bounds,
+    pub density: Density,
+    pub material: SharedMaterial,
+}
+
+impl Volume {
+    pub fn new(bounds: Bounds, density: Density, material: SharedMaterial) -> Volume {
+        Volume { bounds, density, material }
+    }
+}
+
+// A random number generator for a ray, from its origin and direction
+fn ray_rng(r: &Ray) -> Pcg32 {
+    let (o, d) = (r.origin(), r.direction());
+    let seed = o.e.iter().chain(d.e.iter()).fold(0, |h, x| hash_combine(h, x.to_bits()));
+    Pcg32::new(u64::from(seed), 0)
+}
+
+impl Hittable for Volume {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let (near, far) = self.bounds.span(r)?;
+        let (near, far) = (near.max(t_min), far.min(t_max));
+        let majorant = self.density.majorant();
+        if near >= far || majorant <= 0.0 {
+            return None;
+        }
+
+        // Distances along the ray are in units of its direction
+        let speed = r.direction().length();
+        let mut rng = ray_rng(r);
+        let mut t = near;
+        for _ in 0..MAX_STEPS {
+            t -= (1.0 - rng.next_f32()).ln() / (majorant * speed);
+            if t >= far {
+                return None;
+            }
+            let p = r.point_at_parameter(t);
+            if rng.next_f32() * majorant < self.density.at(&p) {
+                let normal = -r.direction() / speed;
+                return Some(Hit { t, p, normal, color: None, object: self });
+            }
+        }
+        None
+    }
+
+    fn material(&self) -> &dyn Material {
+        &*self.material
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds.bounding_box())
+    }
+}
+
+///
+/// The material of a medium, scattering light with the
+/// Henyey-Greenstein phase function: evenly in every direction with
+/// an `anisotropy` of 0, mostly onwards towards 1, as in clouds and
+/// haze, or mostly back the way it came towards -1. `albedo` is the
+/// color of the light that's scattered rather than absorbed.
+///
+pub struct HenyeyGreenstein {
+    albedo: Vec3,
+    anisotropy: f32,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(albedo: Vec3, anisotropy: f32) -> HenyeyGreenstein {
+        HenyeyGreenstein { albedo, anisotropy }
+    }
+
+    // The cosine of the angle between the ray coming in and going out,
+    // sampled in proportion to the phase function
+    fn sample_cosine(&self, u: f32) -> f32 {
+        let g = self.anisotropy;
+        if g.abs() < 1e-3 {
+            return 1.0 - 2.0 * u;
+        }
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+        ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Reflection {
+        let (u, v) = sampler.next_2d();
+        let cosine = self.sample_cosine(u);
+        let sine = (1.0 - cosine * cosine).max(0.0).sqrt();
+        let phi = 2.0 * consts::PI * v;
+
+        // A frame around the incoming direction
+        let w = Vec3::unit_vector(&r_in.direction());
+        let a = if w.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let s = Vec3::unit_vector(&Vec3::cross(&w, &a));
+        let t = Vec3::cross(&w, &s);
+        let direction = sine * phi.cos() * s + sine * phi.sin() * t + cosine * w;
+
+        Reflection {
+            scattered: Ray::new(hit.p, direction),
+            attenuation: self.albedo,
+            reflected: true,
+        }
+    }
+
+    // Straight through, as if the volume weren't there
+    fn preview_scatter(&self, r_in: &Ray, hit: &Hit, _: &mut dyn Sampler) -> Reflection {
+        Reflection {
+            scattered: Ray::new(hit.p, r_in.direction()),
+            attenuation: Vec3::new(1.0, 1.0, 1.0),
+            reflected: true,
+        }
+    }
+
+    fn albedo(&self) -> Vec3 {
+        self.albedo
+    }
+}
@@ -0,0 +1,154 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::fs;
+use std::path::Path;
+
+use film::Film;
+use hittable::World;
+use settings::Settings;
+
+// A rough allowance for each object: the object itself, its
+// material, its definition in the scene and its place in the
+// accelerator
+const OBJECT_BYTES: u64 = 256;
+
+// Per pixel: a 3D position, a depth, an albedo and an ID, and the
+// image each is saved from
+const AOV_BYTES: u64 = 12 + 4 + 12 + 4 + 12;
+
+// Per pixel: the depth, the blur radius and a copy of the image
+const DEPTH_OF_FIELD_BYTES: u64 = 4 + 4 + 12;
+
+// Per pixel: the light over the threshold and the glare it spreads
+const GLARE_BYTES: u64 = 12 + 12;
+
+///
+/// Read a size in bytes, like `512M`, `2G` or `1.5GB`. The suffixes
+/// `K`, `M`, `G` and `T` are powers of 1024, and a plain number is a
+/// number of bytes.
+///
+pub fn parse_size(s: &str) -> Option<u64> {
+    let upper = s.trim().to_uppercase();
+    let number = upper.trim_end_matches('B').trim_end_matches('I');
+    let (digits, scale) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+
+    match digits.trim().parse::<f64>() {
+        Ok(n) if n > 0.0 => Some((n * scale as f64) as u64),
+        _ => None,
+    }
+}
+
+/// A size in bytes, in the largest unit that keeps it above 1.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+///
+/// Refuse a scene file that couldn't be read into the memory budget,
+/// before reading it.
+///
+pub fn check_file(path: &Path, budget: u64) -> Result<(), String> {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size > budget {
+        return Err(format!("{} is {}, more than the {} allowed by --max-memory",
+                           path.display(), format_size(size), format_size(budget)));
+    }
+    Ok(())
+}
+
+///
+/// How much memory a render will need, roughly, in the parts that
+/// grow with the size of the image or the scene. The estimate is
+/// made before rendering starts, so that a render that won't fit
+/// fails straight away instead of hours in.
+///
+pub struct MemoryEstimate {
+    parts: Vec<(&'static str, u64)>,
+}
+
+impl MemoryEstimate {
+    ///
+    /// The memory needed to render `world` with `settings`, with
+    /// `views` images held at once (two for a stereo pair, one for
+    /// each view of a panorama).
+    ///
+    pub fn for_render(settings: &Settings, world: &World, views: usize) -> MemoryEstimate {
+        let pixels = settings.width as u64 * settings.height as u64;
+        let light_groups = if settings.light_groups { world.light_groups.len() } else { 0 };
+        let mut parts = vec![("scene", world.objects.len() as u64 * OBJECT_BYTES)];
+
+        // Streamed images are only ever a few rows
+        if !settings.stream {
+            let film = Film::bytes_needed(settings.width as usize, settings.height as usize, light_groups,
+                                          settings.half_float, settings.transparent);
+            parts.push(("image", film as u64 * views as u64));
+            // The finished image as floats, and as it's saved
+            parts.push(("finished image", pixels * (12 + 8) * views as u64));
+        }
+        if !settings.aovs.is_empty() {
+            parts.push(("AOVs", pixels * AOV_BYTES));
+        }
+        if settings.post_dof {
+            parts.push(("depth of field", pixels * DEPTH_OF_FIELD_BYTES));
+        }
+        if settings.glare.is_some() {
+            parts.push(("glare", pixels * GLARE_BYTES));
+        }
+
+        MemoryEstimate { parts }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.parts.iter().map(|p| p.1).sum()
+    }
+
+    /// An error listing where the memory goes if it's over `budget`.
+    pub fn check(&self, budget: u64) -> Result<(), String> {
+        if self.total() <= budget {
+            return Ok(());
+        }
+
+        let mut message = format!("The render needs about {}, more than the {} allowed by --max-memory:",
+                                  format_size(self.total()), format_size(budget));
+        for &(name, bytes) in self.parts.iter().filter(|p| p.1 > 0) {
+            message.push_str(&format!("\n    {}: {}", name, format_size(bytes)));
+        }
+        Err(message)
+    }
+}
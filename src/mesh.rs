@@ -0,0 +1,132 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use aabb::Aabb;
+use hittable::*;
+use ray::Ray;
+use vec3::Vec3;
+
+// Triangles lying flat along an axis get boxes this thick, so that
+// rays still find their way into them
+const BOX_PADDING: f32 = 1e-4;
+
+///
+/// A triangle, the building block of meshes. Its normal faces out of
+/// the side its corners go anticlockwise around, like the outside of a
+/// sphere, so glass meshes need their triangles wound consistently.
+/// With a normal at each corner, the normal is blended between them
+/// across the triangle, smoothing out a curved surface made of flat
+/// pieces. Colors at the corners are blended the same way, and passed
+/// on to the material with each hit (see Node::VertexColor).
+///
+pub struct Triangle {
+    pub corners: [Vec3; 3],
+    pub normals: Option<[Vec3; 3]>,
+    pub colors: Option<[Vec3; 3]>,
+    pub material: SharedMaterial,
+}
+
+impl Triangle {
+    pub fn new(corners: [Vec3; 3], material: SharedMaterial) -> Triangle {
+        Triangle { corners, normals: None, colors: None, material }
+    }
+
+    pub fn smooth(corners: [Vec3; 3], normals: [Vec3; 3], material: SharedMaterial) -> Triangle {
+        Triangle { corners, normals: Some(normals), colors: None, material }
+    }
+
+    ///
+    /// A triangle from the numbers given for one in a scene: the x, y
+    /// and z of each corner, then optionally of the normal at each,
+    /// then optionally the red, green and blue of each.
+    ///
+    pub fn from_params(params: &[f32], material: SharedMaterial) -> Result<Triangle, String> {
+        if params.len() != 9 && params.len() != 18 && params.len() != 27 {
+            return Err(format!("triangle takes 9, 18 or 27 numbers, not {}", params.len()));
+        }
+
+        let point = |i: usize| Vec3::new(params[i], params[i + 1], params[i + 2]);
+        let corners = [point(0), point(3), point(6)];
+        let area = Vec3::cross(&(corners[1] - corners[0]), &(corners[2] - corners[0])).length();
+        if area.is_nan() || area <= 0.0 {
+            return Err("triangle corners must not all lie on a line".to_string());
+        }
+
+        Ok(Triangle {
+            corners,
+            normals: if params.len() >= 18 { Some([point(9), point(12), point(15)]) } else { None },
+            colors: if params.len() == 27 { Some([point(18), point(21), point(24)]) } else { None },
+            material,
+        })
+    }
+}
+
+impl Hittable for Triangle {
+    // Möller and Trumbore's test, which finds how far along the ray
+    // and where in the triangle the hit is all at once
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let [a, b, c] = self.corners;
+        let ab = b - a;
+        let ac = c - a;
+        let p = Vec3::cross(&r.direction(), &ac);
+        let det = Vec3::dot(&ab, &p);
+        if det == 0.0 {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = r.origin() - a;
+        let u = Vec3::dot(&s, &p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = Vec3::cross(&s, &ab);
+        let v = Vec3::dot(&r.direction(), &q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = Vec3::dot(&ac, &q) * inv_det;
+        if t >= t_max || t <= t_min {
+            return None;
+        }
+
+        let blend = |[a, b, c]: [Vec3; 3]| (1.0 - u - v) * a + u * b + v * c;
+        let normal = match self.normals {
+            Some(normals) => Vec3::unit_vector(&blend(normals)),
+            None => Vec3::unit_vector(&Vec3::cross(&ab, &ac)),
+        };
+        Some(Hit { t, p: r.point_at_parameter(t), normal, color: self.colors.map(blend), object: self })
+    }
+
+    fn material(&self) -> &dyn Material {
+        &*self.material
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [a, b, c] = self.corners;
+        let pad = Vec3::new(BOX_PADDING, BOX_PADDING, BOX_PADDING);
+        let min = Vec3::new(a.x().min(b.x()).min(c.x()), a.y().min(b.y()).min(c.y()), a.z().min(b.z()).min(c.z()));
+        let max = Vec3::new(a.x().max(b.x()).max(c.x()), a.y().max(b.y()).max(c.y()), a.z().max(b.z()).max(c.z()));
+        Some(Aabb::new(min - pad, max + pad))
+    }
+}
@@ -0,0 +1,121 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use film;
+use vec3::Vec3;
+
+///
+/// How auto-exposure measures the brightness of an image. Both build
+/// a histogram of the brightness of the pixels, in stops, and take
+/// the average of the middle of it, leaving out the darkest and
+/// brightest few percent, so that a bright sky or a handful of
+/// fireflies don't darken the whole image. `Average` counts every
+/// pixel the same; `Center` counts the pixels near the middle of the
+/// image most, where the subject usually is.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Metering {
+    Average,
+    Center,
+}
+
+// The brightness the middle of the histogram is brought to: the mid
+// grey of a photographer's grey card
+const KEY: f32 = 0.18;
+
+// The histogram's range and resolution, in stops. Pixels darker than
+// its range, such as black backgrounds, have nothing to expose for
+// and are left out.
+const MIN_STOPS: f32 = -16.0;
+const MAX_STOPS: f32 = 16.0;
+const BINS_PER_STOP: f32 = 2.0;
+
+// The share of the histogram left out at either end
+const LOW_PERCENTILE: f32 = 0.05;
+const HIGH_PERCENTILE: f32 = 0.95;
+
+// How quickly center weighting falls off towards the edges, as a
+// fraction of the distance from the middle to the corners
+const CENTER_SPREAD: f32 = 0.35;
+
+impl Metering {
+    pub fn from_name(name: &str) -> Option<Metering> {
+        match name {
+            "average" => Some(Metering::Average),
+            "center" => Some(Metering::Center),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The scale that brings an image of linear RGB, `width` by
+    /// `height` pixels, to a sensible brightness. An image with
+    /// nothing in it to measure is left as it is.
+    ///
+    pub fn exposure(self, data: &[Vec3], width: usize, height: usize) -> f32 {
+        let bins = ((MAX_STOPS - MIN_STOPS) * BINS_PER_STOP) as usize;
+        // The weight of the pixels in each bin, and of their stops
+        let mut histogram: Vec<f32> = vec![0.0; bins];
+        let mut stops_sum: Vec<f32> = vec![0.0; bins];
+
+        for (i, col) in data.iter().enumerate() {
+            let stops = film::luminance(col).log2();
+            if stops >= MIN_STOPS {
+                let bin = (((stops - MIN_STOPS) * BINS_PER_STOP) as usize).min(bins - 1);
+                let weight = self.weight(i % width, i / width, width, height);
+                histogram[bin] += weight;
+                stops_sum[bin] += weight * stops;
+            }
+        }
+
+        let total: f32 = histogram.iter().sum();
+        let (low, high) = (LOW_PERCENTILE * total, HIGH_PERCENTILE * total);
+        let (mut seen, mut sum, mut weight) = (0.0, 0.0, 0.0);
+
+        for (bin, &count) in histogram.iter().enumerate() {
+            let inside = (seen + count).min(high) - seen.max(low);
+            if inside > 0.0 {
+                sum += inside * stops_sum[bin] / count;
+                weight += inside;
+            }
+            seen += count;
+        }
+
+        if weight > 0.0 {
+            KEY / (sum / weight).exp2()
+        } else {
+            1.0
+        }
+    }
+
+    // How much the pixel at (x, y) counts
+    fn weight(self, x: usize, y: usize, width: usize, height: usize) -> f32 {
+        match self {
+            Metering::Average => 1.0,
+            Metering::Center => {
+                let dx = 2.0 * (x as f32 + 0.5) / width as f32 - 1.0;
+                let dy = 2.0 * (y as f32 + 0.5) / height as f32 - 1.0;
+                let r2 = (dx * dx + dy * dy) / 2.0;
+                (-r2 / (2.0 * CENTER_SPREAD * CENTER_SPREAD)).exp()
+            },
+        }
+    }
+}
@@ -0,0 +1,63 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use aabb::Aabb;
+use hittable::*;
+use ray::Ray;
+use registry::BoxedObject;
+use vec3::Vec3;
+
+///
+/// An object moved by `offset` from where it was built, e.g. by an
+/// edit from the preview window, or to where a moving object has got
+/// to in a frame of an animation. Rays are moved the other way to hit
+/// the object where it was built, so any shape can be moved without
+/// being built again.
+///
+pub struct Moved {
+    pub object: BoxedObject,
+    pub offset: Vec3,
+}
+
+impl Moved {
+    pub fn new(object: BoxedObject, offset: Vec3) -> Moved {
+        Moved { object, offset }
+    }
+}
+
+impl Hittable for Moved {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let moved = Ray::new(r.origin() - self.offset, r.direction());
+        let h = self.object.hit(&moved, t_min, t_max)?;
+        Some(Hit { p: h.p + self.offset, ..h })
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.object.material()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box().map(|b| Aabb::new(b.min + self.offset, b.max + self.offset))
+    }
+
+    fn as_sphere(&self) -> Option<(Vec3, f32)> {
+        self.object.as_sphere().map(|(center, radius)| (center + self.offset, radius))
+    }
+}
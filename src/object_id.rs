@@ -0,0 +1,132 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::collections::{HashMap, HashSet};
+
+use aabb::Aabb;
+use hittable::*;
+use ray::Ray;
+use registry::BoxedObject;
+use sampler::hash_combine;
+use vec3::Vec3;
+
+///
+/// Hands out stable IDs for a scene's objects, for ID passes and
+/// picking. IDs are hashed from what an object is rather than where
+/// it comes in the scene file, so compositing mattes keep picking out
+/// the same objects when the file is reordered. Named objects are
+/// hashed by their name, so they also keep their ID when they are
+/// moved or resized; unnamed ones by their shape, material and
+/// numbers, with identical copies told apart by the order they come
+/// in. ID 0 is never handed out, and means no object at all.
+///
+#[derive(Default)]
+pub struct ObjectIds {
+    taken: HashSet<u32>,
+    names: HashMap<String, u32>,
+}
+
+// FNV-1a, which unlike the standard library's hasher is guaranteed
+// to give the same hash in every build
+fn hash_str(s: &str) -> u32 {
+    s.bytes().fold(0x811c_9dc5, |h, b| (h ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+impl ObjectIds {
+    /// The ID of an object with a name, which must be unique.
+    pub fn named(&mut self, name: &str) -> Result<u32, String> {
+        if self.names.contains_key(name) {
+            return Err(format!("Duplicate object name: {}", name));
+        }
+        let id = self.assign(&format!("name {}", name));
+        self.names.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    /// The ID of an unnamed object, from its definition.
+    pub fn defined(&mut self, shape: &str, material: &str, params: &[f32]) -> u32 {
+        let numbers: Vec<String> = params.iter().map(|p| format!("{:08x}", p.to_bits())).collect();
+        self.assign(&format!("{} {} {}", shape, material, numbers.join(" ")))
+    }
+
+    /// The ID of a copy of the object with ID `id` in an instance of a
+    /// group, from the instance's name.
+    pub fn instanced(&mut self, instance: &str, id: u32) -> u32 {
+        self.assign(&format!("instance {} {:08x}", instance, id))
+    }
+
+    /// The ID of a light, from its name. Lights may share a name.
+    pub fn light(&mut self, name: &str) -> u32 {
+        self.assign(&format!("light {}", name))
+    }
+
+    /// Hand back the ID of an object that has been taken out of the
+    /// scene, so the next object defined the same way gets it again.
+    pub fn release(&mut self, id: u32) {
+        self.taken.remove(&id);
+        self.names.retain(|_, named| *named != id);
+    }
+
+    // Rehash until the ID is one that hasn't been handed out
+    fn assign(&mut self, key: &str) -> u32 {
+        let mut id = hash_str(key);
+        while id == 0 || !self.taken.insert(id) {
+            id = hash_combine(id, 1);
+        }
+        id
+    }
+}
+
+///
+/// An object tagged with its ID. Hits on it report it as the object
+/// hit, so the ID can be read back from the hit.
+///
+pub struct Identified {
+    pub object: BoxedObject,
+    pub id: u32,
+}
+
+impl Identified {
+    pub fn new(object: BoxedObject, id: u32) -> Identified {
+        Identified { object, id }
+    }
+}
+
+impl Hittable for Identified {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let h = self.object.hit(r, t_min, t_max)?;
+        Some(Hit { object: self, ..h })
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.object.material()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn as_sphere(&self) -> Option<(Vec3, f32)> {
+        self.object.as_sphere()
+    }
+}
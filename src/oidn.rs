@@ -0,0 +1,171 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+#[cfg(feature = "oidn")]
+use std::ffi::{c_void, CStr};
+#[cfg(feature = "oidn")]
+use std::os::raw::c_char;
+#[cfg(feature = "oidn")]
+use std::ptr;
+
+#[cfg(feature = "oidn")]
+use libloading::{Library, Symbol};
+
+use vec3::Vec3;
+
+// The names the library is installed under on Linux, macOS and
+// Windows
+#[cfg(feature = "oidn")]
+const LIBRARY_NAMES: [&str; 4] = [
+    "libOpenImageDenoise.so.2",
+    "libOpenImageDenoise.so",
+    "libOpenImageDenoise.dylib",
+    "OpenImageDenoise.dll",
+];
+
+// Constants from OpenImageDenoise/oidn.h
+#[cfg(feature = "oidn")]
+const DEVICE_TYPE_DEFAULT: i32 = 0;
+#[cfg(feature = "oidn")]
+const FORMAT_FLOAT3: i32 = 3;
+#[cfg(feature = "oidn")]
+const ERROR_NONE: i32 = 0;
+
+#[cfg(feature = "oidn")]
+type Handle = *mut c_void;
+
+#[cfg(feature = "oidn")]
+fn load() -> Result<Library, String> {
+    let mut error = String::new();
+
+    for name in &LIBRARY_NAMES {
+        match unsafe { Library::new(name) } {
+            Ok(library) => return Ok(library),
+            Err(e) => error = e.to_string(),
+        }
+    }
+
+    Err(format!("Unable to load Open Image Denoise: {}", error))
+}
+
+#[cfg(feature = "oidn")]
+fn symbol<'a, T>(library: &'a Library, name: &[u8]) -> Result<Symbol<'a, T>, String> {
+    unsafe { library.get(name) }.map_err(|e| format!("Open Image Denoise is missing a function: {}", e))
+}
+
+///
+/// Check that Intel Open Image Denoise can be used: the renderer has
+/// to be built with the `oidn` feature, and the library installed
+/// where the system looks for shared libraries.
+///
+#[cfg(feature = "oidn")]
+pub fn available() -> Result<(), String> {
+    load().map(|_| ())
+}
+
+#[cfg(not(feature = "oidn"))]
+pub fn available() -> Result<(), String> {
+    Err(no_oidn())
+}
+
+///
+/// Denoise an image of linear RGB with Open Image Denoise's ray
+/// tracing filter, guided by its albedo and normal AOVs. All three
+/// are `width` by `height` pixels, in the same order.
+///
+#[cfg(feature = "oidn")]
+pub fn denoise(width: usize, height: usize, color: &mut [Vec3], albedo: &[Vec3], normal: &[Vec3]) -> Result<(), String> {
+    let library = load()?;
+
+    let flatten = |data: &[Vec3]| -> Vec<f32> { data.iter().flat_map(|c| c.e).collect() };
+    let mut input = flatten(color);
+    let mut albedo = flatten(albedo);
+    let mut normal = flatten(normal);
+    let mut output: Vec<f32> = vec![0.0; input.len()];
+
+    unsafe {
+        let new_device: Symbol<unsafe extern "C" fn(i32) -> Handle> = symbol(&library, b"oidnNewDevice\0")?;
+        let commit_device: Symbol<unsafe extern "C" fn(Handle)> = symbol(&library, b"oidnCommitDevice\0")?;
+        let new_filter: Symbol<unsafe extern "C" fn(Handle, *const c_char) -> Handle> =
+            symbol(&library, b"oidnNewFilter\0")?;
+        let set_image: Symbol<unsafe extern "C" fn(Handle, *const c_char, *mut c_void, i32, usize, usize, usize,
+                                                   usize, usize)> = symbol(&library, b"oidnSetSharedFilterImage\0")?;
+        // Renamed in version 2
+        let set_bool: Symbol<unsafe extern "C" fn(Handle, *const c_char, bool)> =
+            symbol(&library, b"oidnSetFilterBool\0").or_else(|_| symbol(&library, b"oidnSetFilter1b\0"))?;
+        let commit_filter: Symbol<unsafe extern "C" fn(Handle)> = symbol(&library, b"oidnCommitFilter\0")?;
+        let execute_filter: Symbol<unsafe extern "C" fn(Handle)> = symbol(&library, b"oidnExecuteFilter\0")?;
+        let get_error: Symbol<unsafe extern "C" fn(Handle, *mut *const c_char) -> i32> =
+            symbol(&library, b"oidnGetDeviceError\0")?;
+        let release_filter: Symbol<unsafe extern "C" fn(Handle)> = symbol(&library, b"oidnReleaseFilter\0")?;
+        let release_device: Symbol<unsafe extern "C" fn(Handle)> = symbol(&library, b"oidnReleaseDevice\0")?;
+
+        let device = new_device(DEVICE_TYPE_DEFAULT);
+        if device.is_null() {
+            return Err("Open Image Denoise couldn't create a device".to_string());
+        }
+        commit_device(device);
+
+        let filter = new_filter(device, b"RT\0".as_ptr() as *const c_char);
+        let images: [(&[u8], &mut Vec<f32>); 4] = [
+            (b"color\0", &mut input),
+            (b"albedo\0", &mut albedo),
+            (b"normal\0", &mut normal),
+            (b"output\0", &mut output),
+        ];
+        for (name, data) in images {
+            set_image(filter, name.as_ptr() as *const c_char, data.as_mut_ptr() as *mut c_void, FORMAT_FLOAT3,
+                      width, height, 0, 0, 0);
+        }
+        set_bool(filter, b"hdr\0".as_ptr() as *const c_char, true);
+        commit_filter(filter);
+        execute_filter(filter);
+
+        let mut message: *const c_char = ptr::null();
+        let result = if get_error(device, &mut message) == ERROR_NONE {
+            Ok(())
+        } else if message.is_null() {
+            Err("Open Image Denoise failed".to_string())
+        } else {
+            Err(format!("Open Image Denoise failed: {}", CStr::from_ptr(message).to_string_lossy()))
+        };
+
+        release_filter(filter);
+        release_device(device);
+        result?;
+    }
+
+    for (col, c) in color.iter_mut().zip(output.chunks(3)) {
+        *col = Vec3::new(c[0], c[1], c[2]);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "oidn"))]
+pub fn denoise(_: usize, _: usize, _: &mut [Vec3], _: &[Vec3], _: &[Vec3]) -> Result<(), String> {
+    Err(no_oidn())
+}
+
+#[cfg(not(feature = "oidn"))]
+fn no_oidn() -> String {
+    "Open Image Denoise support isn't built in; build with --features oidn".to_string()
+}
@@ -0,0 +1,468 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "openexr")]
+use exr;
+#[cfg(feature = "gif")]
+use gif;
+#[cfg(feature = "jpeg")]
+use jpeg_encoder;
+#[cfg(feature = "png")]
+use png;
+
+use vec3::Vec3;
+
+///
+/// The kinds of image file that can be written, picked by the file's
+/// extension: binary PPM for `.ppm`, PFM for `.pfm`, OpenEXR for
+/// `.exr`, and PNG for anything else. PPM and PFM need nothing beyond
+/// the standard library, so they work even in a build without PNG
+/// support; OpenEXR needs the `openexr` feature.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Pfm,
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> ImageFormat {
+        match path.extension().map(|e| e.to_string_lossy().to_lowercase()) {
+            Some(ref e) if e == "ppm" => ImageFormat::Ppm,
+            Some(ref e) if e == "pfm" => ImageFormat::Pfm,
+            Some(ref e) if e == "exr" => ImageFormat::Exr,
+            _ => ImageFormat::Png,
+        }
+    }
+
+    /// True for formats that keep linear float values rather than 8-bit ones.
+    pub fn is_float(self) -> bool {
+        self == ImageFormat::Pfm || self == ImageFormat::Exr
+    }
+}
+
+#[cfg(feature = "png")]
+fn png_encoder(path: &Path, width: u32, height: u32, color: png::ColorType, depth: png::BitDepth)
+               -> io::Result<png::Encoder<'static, BufWriter<File>>> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    Ok(encoder)
+}
+
+/// The path of one frame of an animation: "out.png" becomes "out_0001.png".
+pub fn frame_path(path: &Path, frame: u32) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:04}.{}", stem, frame, ext.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    };
+    path.with_file_name(name)
+}
+
+/// The path of a light group's buffer: "out.png" becomes "out_sky.pfm".
+pub fn light_group_path(path: &Path, group: &str) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}_{}.pfm", stem, group))
+}
+
+///
+/// Write linear float RGB data as a PFM (portable float map) file.
+/// As the format requires, the data is stored bottom row first.
+///
+pub fn write_pfm(path: &Path, width: usize, height: usize, data: &[Vec3]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    // A negative scale marks the data as little-endian
+    write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+    for col in data {
+        for c in &col.e {
+            writer.write_all(&c.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()
+}
+
+///
+/// Write linear float RGB data, bottom row first, as an OpenEXR file
+/// of 32-bit floats.
+///
+#[cfg(feature = "openexr")]
+pub fn write_exr(path: &Path, width: usize, height: usize, data: &[Vec3]) -> io::Result<()> {
+    // EXR images are stored top row first
+    exr::prelude::write_rgb_file(path, width, height, |x, y| {
+        let col = data[(height - 1 - y) * width + x];
+        (col.x(), col.y(), col.z())
+    }).map_err(|e| io::Error::other(e.to_string()))
+}
+
+#[cfg(not(feature = "openexr"))]
+pub fn write_exr(_: &Path, _: usize, _: usize, _: &[Vec3]) -> io::Result<()> {
+    Err(no_exr())
+}
+
+///
+/// Write premultiplied linear float RGB and alpha, bottom row first,
+/// as an OpenEXR file of 32-bit floats.
+///
+#[cfg(feature = "openexr")]
+pub fn write_exr_rgba(path: &Path, width: usize, height: usize, data: &[Vec3], alpha: &[f32]) -> io::Result<()> {
+    exr::prelude::write_rgba_file(path, width, height, |x, y| {
+        let i = (height - 1 - y) * width + x;
+        (data[i].x(), data[i].y(), data[i].z(), alpha[i])
+    }).map_err(|e| io::Error::other(e.to_string()))
+}
+
+#[cfg(not(feature = "openexr"))]
+pub fn write_exr_rgba(_: &Path, _: usize, _: usize, _: &[Vec3], _: &[f32]) -> io::Result<()> {
+    Err(no_exr())
+}
+
+#[cfg(not(feature = "openexr"))]
+fn no_exr() -> io::Error {
+    io::Error::other("OpenEXR support isn't built in; save as .pfm instead")
+}
+
+///
+/// Write linear float RGB data, bottom row first, as a PFM or OpenEXR
+/// file depending on the path's extension.
+///
+pub fn write_float_image(path: &Path, width: usize, height: usize, data: &[Vec3]) -> io::Result<()> {
+    match ImageFormat::from_path(path) {
+        ImageFormat::Exr => write_exr(path, width, height, data),
+        ImageFormat::Pfm => write_pfm(path, width, height, data),
+        _ => Err(invalid_data("only PFM and OpenEXR files hold float images")),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Read an RGB PFM file. Returns the width, height and pixels, bottom row first.
+pub fn read_pfm(path: &Path) -> io::Result<(usize, usize, Vec<Vec3>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header: Vec<String> = Vec::new();
+
+    // The header holds four tokens: "PF", the width and height, and a scale
+    while header.len() < 4 {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("truncated PFM header"));
+        }
+        header.extend(line.split_whitespace().map(|t| t.to_string()));
+    }
+
+    if header[0] != "PF" {
+        return Err(invalid_data("not an RGB PFM file"));
+    }
+
+    let width: usize = header[1].parse().map_err(|_| invalid_data("bad PFM width"))?;
+    let height: usize = header[2].parse().map_err(|_| invalid_data("bad PFM height"))?;
+    let scale: f32 = header[3].parse().map_err(|_| invalid_data("bad PFM scale"))?;
+
+    let mut bytes: Vec<u8> = vec![0; width * height * 12];
+    reader.read_exact(&mut bytes)?;
+
+    let data = bytes.chunks(12).map(|p| {
+        let mut e = [0.0f32; 3];
+        for (c, b) in e.iter_mut().zip(p.chunks(4)) {
+            let raw = [b[0], b[1], b[2], b[3]];
+            *c = if scale < 0.0 { f32::from_le_bytes(raw) } else { f32::from_be_bytes(raw) };
+        }
+        Vec3 { e }
+    }).collect();
+
+    Ok((width, height, data))
+}
+
+///
+/// Add up light group buffers, each scaled by its weight. All of them
+/// must have the same size. Returns the width, height and pixels,
+/// bottom row first.
+///
+pub fn mix_pfm(inputs: &[(PathBuf, Vec3)]) -> io::Result<(usize, usize, Vec<Vec3>)> {
+    let mut mixed: Option<(usize, usize, Vec<Vec3>)> = None;
+
+    for &(ref path, weight) in inputs {
+        let (width, height, data) = read_pfm(path)?;
+
+        match mixed {
+            None => {
+                mixed = Some((width, height, data.iter().map(|c| weight * *c).collect()));
+            },
+            Some((w, h, ref mut sum)) => {
+                if w != width || h != height {
+                    return Err(invalid_data(&format!("{} is {}x{}, expected {}x{}",
+                                                     path.display(), width, height, w, h)));
+                }
+                for (s, c) in sum.iter_mut().zip(data.iter()) {
+                    *s += weight * *c;
+                }
+            }
+        }
+    }
+
+    mixed.ok_or_else(|| invalid_data("nothing to mix"))
+}
+
+///
+/// Read a PNG file as RGB, with every channel between 0 and 1; grey
+/// images are read as grey RGB, and alpha is dropped. Returns the
+/// width, height and pixels, bottom row first like a PFM file.
+///
+#[cfg(feature = "png")]
+pub fn read_png(path: &Path) -> io::Result<(usize, usize, Vec<Vec3>)> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info()?;
+    let mut bytes: Vec<u8> = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut bytes)?;
+
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut data: Vec<Vec3> = Vec::with_capacity(width * height);
+
+    for row in bytes[..info.buffer_size()].chunks(info.line_size).rev() {
+        for p in row.chunks(channels).take(width) {
+            let level = |c: usize| p[c] as f32 / 255.0;
+            data.push(if channels < 3 {
+                Vec3::new(level(0), level(0), level(0))
+            } else {
+                Vec3::new(level(0), level(1), level(2))
+            });
+        }
+    }
+
+    Ok((width, height, data))
+}
+
+#[cfg(not(feature = "png"))]
+pub fn read_png(_: &Path) -> io::Result<(usize, usize, Vec<Vec3>)> {
+    Err(no_png())
+}
+
+/// Write a complete 8-bit RGB image, stored top row first.
+#[cfg(feature = "png")]
+pub fn write_png(path: &Path, width: u32, height: u32, data: &[u8]) -> io::Result<()> {
+    let mut writer = png_encoder(path, width, height, png::ColorType::Rgb, png::BitDepth::Eight)?.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "png"))]
+pub fn write_png(_: &Path, _: u32, _: u32, _: &[u8]) -> io::Result<()> {
+    Err(no_png())
+}
+
+/// Write a complete 16-bit RGB image, stored top row first.
+#[cfg(feature = "png")]
+pub fn write_png16(path: &Path, width: u32, height: u32, data: &[u16]) -> io::Result<()> {
+    let mut writer = png_encoder(path, width, height, png::ColorType::Rgb, png::BitDepth::Sixteen)?.write_header()?;
+    writer.write_image_data(&big_endian(data))?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "png"))]
+pub fn write_png16(_: &Path, _: u32, _: u32, _: &[u16]) -> io::Result<()> {
+    Err(no_png())
+}
+
+/// Write a complete 8-bit RGBA image with straight alpha, top row first.
+#[cfg(feature = "png")]
+pub fn write_png_rgba(path: &Path, width: u32, height: u32, data: &[u8]) -> io::Result<()> {
+    let mut writer = png_encoder(path, width, height, png::ColorType::Rgba, png::BitDepth::Eight)?.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "png"))]
+pub fn write_png_rgba(_: &Path, _: u32, _: u32, _: &[u8]) -> io::Result<()> {
+    Err(no_png())
+}
+
+/// Write a complete 16-bit RGBA image with straight alpha, top row first.
+#[cfg(feature = "png")]
+pub fn write_png16_rgba(path: &Path, width: u32, height: u32, data: &[u16]) -> io::Result<()> {
+    let mut writer = png_encoder(path, width, height, png::ColorType::Rgba, png::BitDepth::Sixteen)?.write_header()?;
+    writer.write_image_data(&big_endian(data))?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "png"))]
+pub fn write_png16_rgba(_: &Path, _: u32, _: u32, _: &[u16]) -> io::Result<()> {
+    Err(no_png())
+}
+
+// 16-bit samples as bytes, most significant first, as both PNG and
+// PPM store them
+fn big_endian(data: &[u16]) -> Vec<u8> {
+    data.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+#[cfg(not(feature = "png"))]
+fn no_png() -> io::Error {
+    io::Error::other("PNG support isn't built in; save as .ppm or .pfm instead")
+}
+
+/// Write a complete 8-bit RGB image as a binary PPM file, top row first.
+pub fn write_ppm(path: &Path, width: u32, height: u32, data: &[u8]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+/// Write a complete 8-bit RGB image as a progressive JPEG, top row first.
+#[cfg(feature = "jpeg")]
+pub fn write_jpeg(path: &Path, width: u32, height: u32, data: &[u8], quality: u8) -> io::Result<()> {
+    let mut encoder = jpeg_encoder::Encoder::new_file(path, quality).map_err(|e| io::Error::other(e.to_string()))?;
+    encoder.set_progressive(true);
+    encoder.encode(data, width as u16, height as u16, jpeg_encoder::ColorType::Rgb)
+        .map_err(|e| io::Error::other(e.to_string()))
+}
+
+#[cfg(not(feature = "jpeg"))]
+pub fn write_jpeg(_: &Path, _: u32, _: u32, _: &[u8], _: u8) -> io::Result<()> {
+    Err(io::Error::other("JPEG support isn't built in"))
+}
+
+///
+/// Write frames of 8-bit RGB, top row first, as an animated GIF that
+/// loops forever at `fps` frames per second. Each frame gets its own
+/// palette of 256 colors.
+///
+#[cfg(feature = "gif")]
+pub fn write_gif(path: &Path, width: u32, height: u32, frames: &[Vec<u8>], fps: f32) -> io::Result<()> {
+    let to_io = |e: gif::EncodingError| io::Error::other(e.to_string());
+    let (width, height) = (width as u16, height as u16);
+    let delay = (100.0 / fps).round().max(1.0) as u16;
+
+    let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), width, height, &[]).map_err(to_io)?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(to_io)?;
+
+    for data in frames {
+        let mut frame = gif::Frame::from_rgb_speed(width, height, data, 10);
+        frame.delay = delay;
+        encoder.write_frame(&frame).map_err(to_io)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "gif"))]
+pub fn write_gif(_: &Path, _: u32, _: u32, _: &[Vec<u8>], _: f32) -> io::Result<()> {
+    Err(io::Error::other("GIF support isn't built in"))
+}
+
+/// Write a complete 16-bit RGB image as a binary PPM file, top row first.
+pub fn write_ppm16(path: &Path, width: u32, height: u32, data: &[u16]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "P6\n{} {}\n65535\n", width, height)?;
+    writer.write_all(&big_endian(data))?;
+    writer.flush()
+}
+
+///
+/// Write a complete 8-bit RGB image, top row first, in the format
+/// that the path's extension asks for. PFM and OpenEXR files hold
+/// linear float values, which 8-bit data can't give; use
+/// `write_float_image` for those.
+///
+pub fn write_image(path: &Path, width: u32, height: u32, data: &[u8]) -> io::Result<()> {
+    match ImageFormat::from_path(path) {
+        ImageFormat::Png => write_png(path, width, height, data),
+        ImageFormat::Ppm => write_ppm(path, width, height, data),
+        ImageFormat::Pfm | ImageFormat::Exr => Err(invalid_data("float image formats can't hold 8-bit images")),
+    }
+}
+
+/// Write a complete 16-bit RGB image, top row first, as `write_image` does.
+pub fn write_image16(path: &Path, width: u32, height: u32, data: &[u16]) -> io::Result<()> {
+    match ImageFormat::from_path(path) {
+        ImageFormat::Png => write_png16(path, width, height, data),
+        ImageFormat::Ppm => write_ppm16(path, width, height, data),
+        ImageFormat::Pfm | ImageFormat::Exr => Err(invalid_data("float image formats can't hold 16-bit images")),
+    }
+}
+
+///
+/// An 8-bit image file that is written one row at a time, top row
+/// first, so the image never has to be held in memory all at once.
+/// PNG and PPM files can be streamed, but PFM files can't, since they
+/// are stored bottom row first, and nor can OpenEXR files.
+///
+pub enum ImageStream {
+    #[cfg(feature = "png")]
+    Png(Box<png::StreamWriter<'static, BufWriter<File>>>),
+    Ppm(BufWriter<File>),
+}
+
+impl ImageStream {
+    pub fn create(path: &Path, width: u32, height: u32) -> io::Result<ImageStream> {
+        match ImageFormat::from_path(path) {
+            #[cfg(feature = "png")]
+            ImageFormat::Png => {
+                let writer = png_encoder(path, width, height, png::ColorType::Rgb, png::BitDepth::Eight)?
+                    .write_header()?.into_stream_writer()?;
+                Ok(ImageStream::Png(Box::new(writer)))
+            },
+            #[cfg(not(feature = "png"))]
+            ImageFormat::Png => Err(no_png()),
+            ImageFormat::Ppm => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                write!(writer, "P6\n{} {}\n255\n", width, height)?;
+                Ok(ImageStream::Ppm(writer))
+            },
+            ImageFormat::Pfm | ImageFormat::Exr => Err(invalid_data("float images can't be streamed")),
+        }
+    }
+
+    pub fn write_row(&mut self, data: &[u8]) -> io::Result<()> {
+        match *self {
+            #[cfg(feature = "png")]
+            ImageStream::Png(ref mut writer) => writer.write_all(data),
+            ImageStream::Ppm(ref mut writer) => writer.write_all(data),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "png")]
+            ImageStream::Png(writer) => {
+                writer.finish()?;
+                Ok(())
+            },
+            ImageStream::Ppm(mut writer) => writer.flush(),
+        }
+    }
+}
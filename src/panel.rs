@@ -0,0 +1,497 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+#[cfg(feature = "panel")]
+use std::collections::HashMap;
+#[cfg(feature = "panel")]
+use std::time::Instant;
+
+#[cfg(feature = "panel")]
+use egui::{Color32, Context, Event, Key, Modifiers, PointerButton, Pos2, RawInput, Rect, ViewportId};
+#[cfg(feature = "panel")]
+use egui::epaint::{ClippedPrimitive, ImageData, Primitive, Vertex};
+
+use settings::Settings;
+use tone_map::ToneMap;
+
+// The tone maps offered, with their names
+#[cfg(feature = "panel")]
+const TONE_MAPS: [(ToneMap, &str); 4] = [
+    (ToneMap::Clamp, "clamp"),
+    (ToneMap::Reinhard, "reinhard"),
+    (ToneMap::Aces, "aces"),
+    (ToneMap::Filmic, "filmic"),
+];
+
+///
+/// The settings the panel can change. An aperture of 0 is a pinhole
+/// camera, with everything in focus.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct PanelValues {
+    pub samples: u32,
+    pub max_depth: u32,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub tone_map: ToneMap,
+    pub compensation: f32,
+}
+
+impl PanelValues {
+    pub fn new(settings: &Settings, vfov: f32) -> PanelValues {
+        PanelValues {
+            samples: settings.samples,
+            max_depth: settings.max_depth,
+            vfov,
+            aperture: settings.aperture.unwrap_or(0.0),
+            tone_map: settings.tone_map,
+            compensation: settings.compensation,
+        }
+    }
+
+    /// `settings` with these values in place of their own.
+    pub fn apply(&self, settings: &Settings) -> Settings {
+        Settings {
+            samples: self.samples,
+            max_depth: self.max_depth,
+            aperture: if self.aperture > 0.0 { Some(self.aperture) } else { None },
+            tone_map: self.tone_map,
+            compensation: self.compensation,
+            ..settings.clone()
+        }
+    }
+}
+
+///
+/// Input from the window that the panel responds to: the mouse, with
+/// the left button, text typed and the keys for moving around and
+/// editing text, in window pixels from the top left.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum PanelInput {
+    PointerMoved(f32, f32),
+    Button { x: f32, y: f32, pressed: bool },
+    Wheel(f32, f32),
+    Text(String),
+    Key { key: PanelKey, pressed: bool, shift: bool, ctrl: bool },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PanelKey {
+    Backspace,
+    Delete,
+    Enter,
+    Tab,
+    Escape,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+}
+
+///
+/// What happened in the panel since the last update: whether the
+/// window needs drawing again, and the values to render with if
+/// "Apply & re-render" was clicked.
+///
+pub struct PanelUpdate {
+    pub repainted: bool,
+    pub applied: Option<PanelValues>,
+}
+
+///
+/// A settings panel drawn over the preview window, shown and hidden
+/// with F1. It's drawn with egui, painted into an RGBA overlay the
+/// size of the window: each pixel's color with its alpha, which is 0
+/// wherever the panel isn't. Only built with the `panel` feature;
+/// without it, the panel never opens.
+///
+#[cfg(feature = "panel")]
+pub struct Panel {
+    open: bool,
+    repaint: bool,
+    context: Context,
+    events: Vec<Event>,
+    modifiers: Modifiers,
+    start: Instant,
+    values: PanelValues,
+    textures: HashMap<egui::TextureId, Texture>,
+    width: usize,
+    height: usize,
+    // Premultiplied colors, while the panel is being painted
+    canvas: Vec<[f32; 4]>,
+    overlay: Vec<u8>,
+}
+
+// A texture egui paints with, such as the glyphs of its font
+#[cfg(feature = "panel")]
+struct Texture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color32>,
+}
+
+#[cfg(feature = "panel")]
+impl Panel {
+    pub fn new(width: u32, height: u32, values: PanelValues) -> Panel {
+        let (width, height) = (width as usize, height as usize);
+        Panel {
+            open: false,
+            repaint: false,
+            context: Context::default(),
+            events: Vec::new(),
+            modifiers: Modifiers::default(),
+            start: Instant::now(),
+            values,
+            textures: HashMap::new(),
+            width,
+            height,
+            canvas: vec![[0.0; 4]; width * height],
+            overlay: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.repaint = true;
+    }
+
+    /// Lay the panel out again over an image of a new size.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width as usize;
+        self.height = height as usize;
+        self.canvas = vec![[0.0; 4]; self.width * self.height];
+        self.overlay = vec![0; self.width * self.height * 4];
+        self.repaint = true;
+    }
+
+    ///
+    /// Pass input from the window on to the panel while it's open.
+    /// Returns true if the panel used it, when the pointer is over the
+    /// panel or one of its fields is being typed in, and the rest of
+    /// the window should ignore it.
+    ///
+    pub fn handle(&mut self, input: PanelInput) -> bool {
+        if !self.open {
+            return false;
+        }
+
+        let over = self.context.is_pointer_over_area() || self.context.wants_pointer_input();
+        let typing = self.context.wants_keyboard_input();
+        let used = match input {
+            PanelInput::PointerMoved(x, y) => {
+                self.events.push(Event::PointerMoved(Pos2::new(x, y)));
+                over
+            },
+            PanelInput::Button { x, y, pressed } => {
+                self.events.push(Event::PointerButton {
+                    pos: Pos2::new(x, y),
+                    button: PointerButton::Primary,
+                    pressed,
+                    modifiers: self.modifiers,
+                });
+                over
+            },
+            PanelInput::Wheel(x, y) => {
+                self.events.push(Event::MouseWheel {
+                    unit: egui::MouseWheelUnit::Line,
+                    delta: egui::vec2(x, y),
+                    modifiers: self.modifiers,
+                });
+                over
+            },
+            PanelInput::Text(text) => {
+                if typing {
+                    self.events.push(Event::Text(text));
+                }
+                typing
+            },
+            PanelInput::Key { key, pressed, shift, ctrl } => {
+                self.modifiers = Modifiers { shift, ctrl, command: ctrl, ..Modifiers::default() };
+                self.events.push(Event::Key {
+                    key: ui_key(key),
+                    physical_key: None,
+                    pressed,
+                    repeat: false,
+                    modifiers: self.modifiers,
+                });
+                typing
+            },
+        };
+
+        self.repaint = true;
+        used
+    }
+
+    ///
+    /// Run the panel, if anything has happened that it needs to
+    /// respond to, and paint it again.
+    ///
+    pub fn update(&mut self) -> PanelUpdate {
+        if !self.repaint {
+            return PanelUpdate { repainted: false, applied: None };
+        }
+        self.repaint = false;
+        if !self.open {
+            return PanelUpdate { repainted: true, applied: None };
+        }
+
+        let input = RawInput {
+            screen_rect: Some(Rect::from_min_size(Pos2::ZERO, egui::vec2(self.width as f32, self.height as f32))),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            modifiers: self.modifiers,
+            events: self.events.split_off(0),
+            focused: true,
+            ..RawInput::default()
+        };
+
+        let values = &mut self.values;
+        let mut apply = false;
+        let output = self.context.run(input, |context| {
+            egui::Window::new("Render settings")
+                .anchor(egui::Align2::RIGHT_TOP, [-8.0, 8.0])
+                .resizable(false)
+                .collapsible(false)
+                .show(context, |ui| {
+                    ui.add(egui::Slider::new(&mut values.samples, 1..=4096).logarithmic(true).text("samples"));
+                    ui.add(egui::Slider::new(&mut values.max_depth, 1..=200).logarithmic(true).text("max depth"));
+                    ui.add(egui::Slider::new(&mut values.vfov, 5.0..=120.0).text("field of view"));
+                    ui.add(egui::Slider::new(&mut values.aperture, 0.0..=2.0).text("aperture"));
+                    egui::ComboBox::from_label("tone map")
+                        .selected_text(TONE_MAPS.iter().find(|t| t.0 == values.tone_map).map_or("", |t| t.1))
+                        .show_ui(ui, |ui| {
+                            for &(tone_map, name) in TONE_MAPS.iter() {
+                                ui.selectable_value(&mut values.tone_map, tone_map, name);
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut values.compensation, -5.0..=5.0).text("exposure (EV)"));
+                    apply = ui.button("Apply & re-render").clicked();
+                });
+        });
+
+        for (id, delta) in output.textures_delta.set {
+            let ImageData::Color(ref image) = delta.image;
+            let texture = self.textures.entry(id).or_insert(Texture { width: 0, height: 0, pixels: Vec::new() });
+            match delta.pos {
+                Some([x, y]) => {
+                    for row in 0..image.size[1] {
+                        let start = (y + row) * texture.width + x;
+                        let source = &image.pixels[row * image.size[0]..(row + 1) * image.size[0]];
+                        texture.pixels[start..start + source.len()].copy_from_slice(source);
+                    }
+                },
+                None => {
+                    *texture = Texture { width: image.size[0], height: image.size[1], pixels: image.pixels.clone() };
+                },
+            }
+        }
+
+        // Keep painting while egui animates something
+        if let Some(viewport) = output.viewport_output.get(&ViewportId::ROOT) {
+            self.repaint = viewport.repaint_delay.is_zero();
+        }
+
+        let primitives = self.context.tessellate(output.shapes, output.pixels_per_point);
+        self.paint(&primitives);
+        for id in output.textures_delta.free {
+            self.textures.remove(&id);
+        }
+
+        PanelUpdate {
+            repainted: true,
+            applied: if apply { Some(self.values.clone()) } else { None },
+        }
+    }
+
+    /// The panel as RGBA pixels, a line of the window at a time from the top.
+    pub fn overlay(&self) -> &[u8] {
+        &self.overlay
+    }
+
+    // A simple software rasterizer, which is all egui's few shapes
+    // need: egui hands over triangles, already anti-aliased by
+    // fading their edges out
+    fn paint(&mut self, primitives: &[ClippedPrimitive]) {
+        for pixel in self.canvas.iter_mut() {
+            *pixel = [0.0; 4];
+        }
+
+        for primitive in primitives {
+            let mesh = match primitive.primitive {
+                Primitive::Mesh(ref mesh) => mesh,
+                Primitive::Callback(_) => continue,
+            };
+            let texture = match self.textures.get(&mesh.texture_id) {
+                Some(texture) => texture,
+                None => continue,
+            };
+            for triangle in mesh.indices.chunks_exact(3) {
+                let vertices = [
+                    &mesh.vertices[triangle[0] as usize],
+                    &mesh.vertices[triangle[1] as usize],
+                    &mesh.vertices[triangle[2] as usize],
+                ];
+                fill_triangle(&mut self.canvas, self.width, self.height, vertices, texture, primitive.clip_rect);
+            }
+        }
+
+        for (pixel, bytes) in self.canvas.iter().zip(self.overlay.chunks_exact_mut(4)) {
+            let alpha = pixel[3];
+            for c in 0..3 {
+                let straight = if alpha > 0.0 { pixel[c] / alpha } else { 0.0 };
+                bytes[c] = (straight.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+            bytes[3] = (alpha.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+}
+
+// Paint a triangle over the canvas, with its vertex colors blended
+// across it and multiplied by the texture. Pixels on an edge shared by
+// two triangles are only painted by one of them, so see-through
+// shapes don't get darker seams.
+#[cfg(feature = "panel")]
+fn fill_triangle(canvas: &mut [[f32; 4]], width: usize, height: usize, vertices: [&Vertex; 3], texture: &Texture,
+                 clip: Rect) {
+    let [mut a, mut b, c] = vertices;
+    let mut area = edge(a.pos, b.pos, c.pos);
+    if area == 0.0 || texture.pixels.is_empty() {
+        return;
+    }
+    if area < 0.0 {
+        std::mem::swap(&mut a, &mut b);
+        area = -area;
+    }
+
+    // The bounds of the triangle, cut down to the clip rectangle
+    let bounds = Rect::from_points(&[a.pos, b.pos, c.pos]).intersect(clip);
+    let left = bounds.min.x.max(0.0) as usize;
+    let top = bounds.min.y.max(0.0) as usize;
+    let right = (bounds.max.x.ceil().max(0.0) as usize).min(width);
+    let bottom = (bounds.max.y.ceil().max(0.0) as usize).min(height);
+
+    let colors = [a, b, c].map(|v| v.color.to_array().map(|x| x as f32 / 255.0));
+    for y in top..bottom {
+        for x in left..right {
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let weights = [covers(b.pos, c.pos, p), covers(c.pos, a.pos, p), covers(a.pos, b.pos, p)];
+            let weights = match weights {
+                [Some(wa), Some(wb), Some(wc)] => [wa / area, wb / area, wc / area],
+                _ => continue,
+            };
+
+            let u = weights[0] * a.uv.x + weights[1] * b.uv.x + weights[2] * c.uv.x;
+            let v = weights[0] * a.uv.y + weights[1] * b.uv.y + weights[2] * c.uv.y;
+            let tx = ((u * texture.width as f32) as usize).min(texture.width - 1);
+            let ty = ((v * texture.height as f32) as usize).min(texture.height - 1);
+            let texel = texture.pixels[ty * texture.width + tx].to_array();
+
+            let pixel = &mut canvas[y * width + x];
+            let mut source = [0.0; 4];
+            for (k, s) in source.iter_mut().enumerate() {
+                let color = weights[0] * colors[0][k] + weights[1] * colors[1][k] + weights[2] * colors[2][k];
+                *s = color * texel[k] as f32 / 255.0;
+            }
+            for k in 0..4 {
+                pixel[k] = source[k] + pixel[k] * (1.0 - source[3]);
+            }
+        }
+    }
+}
+
+// Twice the signed area of the triangle a, b, p
+#[cfg(feature = "panel")]
+fn edge(a: Pos2, b: Pos2, p: Pos2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+// How far inside the edge from a to b the point is, if it's inside;
+// points right on the edge count for one side of it only
+#[cfg(feature = "panel")]
+fn covers(a: Pos2, b: Pos2, p: Pos2) -> Option<f32> {
+    let e = edge(a, b, p);
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    if e > 0.0 || (e == 0.0 && (dy > 0.0 || (dy == 0.0 && dx < 0.0))) {
+        Some(e)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "panel")]
+fn ui_key(key: PanelKey) -> Key {
+    match key {
+        PanelKey::Backspace => Key::Backspace,
+        PanelKey::Delete => Key::Delete,
+        PanelKey::Enter => Key::Enter,
+        PanelKey::Tab => Key::Tab,
+        PanelKey::Escape => Key::Escape,
+        PanelKey::Left => Key::ArrowLeft,
+        PanelKey::Right => Key::ArrowRight,
+        PanelKey::Up => Key::ArrowUp,
+        PanelKey::Down => Key::ArrowDown,
+        PanelKey::Home => Key::Home,
+        PanelKey::End => Key::End,
+    }
+}
+
+#[cfg(not(feature = "panel"))]
+pub struct Panel {
+    warned: bool,
+}
+
+#[cfg(not(feature = "panel"))]
+impl Panel {
+    pub fn new(_: u32, _: u32, _: PanelValues) -> Panel {
+        Panel { warned: false }
+    }
+
+    pub fn is_open(&self) -> bool {
+        false
+    }
+
+    pub fn toggle(&mut self) {
+        if !self.warned {
+            eprintln!("The settings panel isn't built in; build with --features panel");
+            self.warned = true;
+        }
+    }
+
+    pub fn resize(&mut self, _: u32, _: u32) {}
+
+    pub fn handle(&mut self, _: PanelInput) -> bool {
+        false
+    }
+
+    pub fn update(&mut self) -> PanelUpdate {
+        PanelUpdate { repainted: false, applied: None }
+    }
+
+    pub fn overlay(&self) -> &[u8] {
+        &[]
+    }
+}
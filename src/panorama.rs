@@ -0,0 +1,185 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::f32::consts;
+use std::sync::Arc;
+
+use vec3::Vec3;
+use hittable::World;
+use camera::Camera;
+use blue_noise::BlueNoiseMask;
+use film;
+use film::Film;
+use post::PostChain;
+use render::render_film;
+use settings::Settings;
+
+// Fraction of each view that overlaps its neighbor
+const OVERLAP: f32 = 0.3;
+
+// Trim the top and bottom of the panorama slightly, since a
+// perspective view covers less pitch towards its left and right edges
+const PITCH_COVERAGE: f32 = 0.9;
+
+///
+/// A panorama is rendered as a row of ordinary perspective views,
+/// each one rotated further around the up vector, and then stitched
+/// into a single image in which x is yaw and y is pitch. Where views
+/// overlap, they are blended with weights that fall off towards the
+/// edge of each view, which hides the seams.
+///
+pub struct Panorama {
+    origin: Vec3,
+    up: Vec3,
+    forward: Vec3,
+    right: Vec3,
+    pitch: f32,
+    vfov: f32,
+    aspect: f32,
+}
+
+impl Panorama {
+    pub fn new(camera: &Camera, vup: Vec3) -> Panorama {
+        let up: Vec3 = Vec3::unit_vector(&vup);
+        let look: Vec3 = camera.forward();
+        let forward: Vec3 = Vec3::unit_vector(&(look - Vec3::dot(&look, &up) * up));
+
+        Panorama {
+            origin: camera.origin,
+            up,
+            forward,
+            right: Vec3::cross(&forward, &up),
+            pitch: Vec3::dot(&look, &up).clamp(-1.0, 1.0).asin(),
+            vfov: camera.vfov() * consts::PI / 180.0,
+            aspect: camera.aspect(),
+        }
+    }
+
+    fn direction(&self, yaw: f32, pitch: f32) -> Vec3 {
+        pitch.cos() * (yaw.cos() * self.forward + yaw.sin() * self.right) + pitch.sin() * self.up
+    }
+
+    fn hfov(&self) -> f32 {
+        2.0 * (self.aspect * (self.vfov / 2.0).tan()).atan()
+    }
+
+    /// Yaw angles of the views needed to cover `coverage` radians.
+    fn view_yaws(&self, coverage: f32) -> Vec<f32> {
+        let hfov = self.hfov();
+
+        if coverage <= hfov {
+            return vec![0.0];
+        }
+
+        let step = hfov * (1.0 - OVERLAP);
+        let views = ((coverage - hfov) / step).ceil() as usize + 1;
+        let spacing = (coverage - hfov) / (views - 1) as f32;
+
+        (0..views).map(|k| -coverage / 2.0 + hfov / 2.0 + k as f32 * spacing).collect()
+    }
+
+    /// The number of views rendered for a panorama covering `degrees`.
+    pub fn views(&self, degrees: f32) -> usize {
+        self.view_yaws(degrees * consts::PI / 180.0).len()
+    }
+
+    ///
+    /// Render and stitch a panorama covering `degrees` horizontally.
+    /// Returns the width, height, and 8-bit RGB pixels of the result,
+    /// top row first.
+    ///
+    pub fn render(&self, degrees: f32, settings: Arc<Settings>, world: Arc<World>,
+                  mask: Option<Arc<BlueNoiseMask>>) -> (u32, u32, Vec<u8>) {
+        let coverage = degrees * consts::PI / 180.0;
+        let span = self.vfov * PITCH_COVERAGE;
+
+        let views: Vec<(Camera, Film)> = self.view_yaws(coverage).iter().map(|&yaw| {
+            let camera = Camera::new(self.origin,
+                                     self.origin + self.direction(yaw, self.pitch),
+                                     self.up,
+                                     self.vfov * 180.0 / consts::PI,
+                                     self.aspect);
+            let film = render_film(settings.clone(), world.clone(), Arc::new(camera.clone()), mask.clone());
+            (camera, film)
+        }).collect();
+
+        let height = settings.height;
+        let width = ((height as f32 * coverage / span).round() as u32).max(1);
+        let (w, h) = (width as usize, height as usize);
+        let mut colors: Vec<Vec3> = Vec::with_capacity(w * h);
+
+        for row in 0..height {
+            let pitch = self.pitch + span / 2.0 - (row as f32 + 0.5) / height as f32 * span;
+
+            for column in 0..width {
+                let yaw = -coverage / 2.0 + (column as f32 + 0.5) / width as f32 * coverage;
+                let direction = self.direction(yaw, pitch);
+
+                let mut sum: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+                let mut weight_sum: f32 = 0.0;
+
+                for (camera, film) in &views {
+                    if let Some((u, v)) = camera.project(&direction) {
+                        let weight = u.min(1.0 - u).min(v.min(1.0 - v));
+                        if weight > 0.0 {
+                            sum += weight * sample(film, u, v);
+                            weight_sum += weight;
+                        }
+                    }
+                }
+
+                let col = if weight_sum > 0.0 {
+                    sum / weight_sum
+                } else {
+                    Vec3::new(0.0, 0.0, 0.0)
+                };
+
+                colors.push(col);
+            }
+        }
+
+        // The image is built top row first, so count pixel indices from
+        // the bottom for the post-processing
+        let post = PostChain::metered(&settings, w, h, &colors);
+        let mut data: Vec<u8> = Vec::with_capacity(w * h * 3);
+        for (i, col) in colors.iter().enumerate() {
+            film::push_rgb8(col, (h - 1 - i / w) * w + i % w, &post, &mut data);
+        }
+
+        (width, height, data)
+    }
+}
+
+// Bilinear lookup of film coordinates (u, v)
+fn sample(film: &Film, u: f32, v: f32) -> Vec3 {
+    let px = (u * film.width as f32 - 0.5).clamp(0.0, film.width as f32 - 1.0);
+    let py = (v * film.height as f32 - 0.5).clamp(0.0, film.height as f32 - 1.0);
+
+    let x0 = px.floor() as usize;
+    let y0 = py.floor() as usize;
+    let x1 = (x0 + 1).min(film.width - 1);
+    let y1 = (y0 + 1).min(film.height - 1);
+    let fx = px - x0 as f32;
+    let fy = py - y0 as f32;
+
+    let bottom = (1.0 - fx) * film.color(x0, y0) + fx * film.color(x1, y0);
+    let top = (1.0 - fx) * film.color(x0, y1) + fx * film.color(x1, y1);
+
+    (1.0 - fy) * bottom + fy * top
+}
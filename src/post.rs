@@ -0,0 +1,398 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::sync::Arc;
+
+use blackbody::blackbody;
+use dither::Dither;
+use film;
+use sampler::hash_combine;
+use response::ResponseCurve;
+use settings::Settings;
+use tone_map::ToneMap;
+use vec3::Vec3;
+
+///
+/// One step in turning the linear light on the film into the pixels
+/// of an image. Every step works on one pixel at a time, in place, so
+/// it can be given a whole image or just a line of one, along with
+/// where those pixels are in the image. Steps before tone mapping see
+/// linear light; the tone mapping step encodes it with the response
+/// curve, and the steps after it see pixel values between 0 and 1.
+///
+pub trait PostProcess {
+    fn apply(&self, data: &mut [Vec3], run: &PixelRun);
+}
+
+// Steps loaded once and shared by every chain, such as a LUT
+impl<T: PostProcess + ?Sized> PostProcess for Arc<T> {
+    fn apply(&self, data: &mut [Vec3], run: &PixelRun) {
+        (**self).apply(data, run)
+    }
+}
+
+///
+/// Where the pixels given to a `PostProcess` are: `start` pixels into
+/// an image `width` by `height` pixels, bottom row first.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct PixelRun {
+    pub width: usize,
+    pub height: usize,
+    pub start: usize,
+}
+
+impl PixelRun {
+    /// The column and row of the `i`th pixel of the run.
+    pub fn position(&self, i: usize) -> (usize, usize) {
+        ((self.start + i) % self.width, (self.start + i) / self.width)
+    }
+}
+
+///
+/// The steps a `PostChain` can be built from, named as they are in
+/// `--post` and in scene files.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PostStage {
+    Exposure,
+    WhiteBalance,
+    Vignette,
+    ToneMap,
+    Grade,
+    Lut,
+    Grain,
+    Quantize,
+}
+
+// The order of the steps unless a scene file or --post says otherwise
+pub const DEFAULT_POST: [PostStage; 8] = [PostStage::Exposure, PostStage::WhiteBalance, PostStage::Vignette,
+                                          PostStage::ToneMap, PostStage::Grade, PostStage::Lut, PostStage::Grain,
+                                          PostStage::Quantize];
+
+impl PostStage {
+    pub fn from_name(name: &str) -> Option<PostStage> {
+        match name {
+            "exposure" => Some(PostStage::Exposure),
+            "whitebalance" => Some(PostStage::WhiteBalance),
+            "vignette" => Some(PostStage::Vignette),
+            "tonemap" => Some(PostStage::ToneMap),
+            "grade" => Some(PostStage::Grade),
+            "lut" => Some(PostStage::Lut),
+            "grain" => Some(PostStage::Grain),
+            "quantize" => Some(PostStage::Quantize),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Parse a list of steps, checking that each is given at most once
+/// and that tone mapping is among them, since nothing else turns
+/// light into pixel values.
+///
+pub fn parse_stages<'a, I>(names: I) -> Result<Vec<PostStage>, String>
+    where I: Iterator<Item = &'a str> {
+    let mut stages: Vec<PostStage> = Vec::new();
+
+    for name in names {
+        let stage = PostStage::from_name(name).ok_or_else(|| format!("Unknown post-processing step: {}", name))?;
+        if stages.contains(&stage) {
+            return Err(format!("{} is given more than once", name));
+        }
+        stages.push(stage);
+    }
+
+    if !stages.contains(&PostStage::ToneMap) {
+        return Err("Post-processing needs a tonemap step".to_string());
+    }
+
+    Ok(stages)
+}
+
+/// Brightens or darkens linear light by a number of stops.
+pub struct Exposure {
+    scale: f32,
+}
+
+impl PostProcess for Exposure {
+    fn apply(&self, data: &mut [Vec3], _: &PixelRun) {
+        for col in data.iter_mut() {
+            *col *= self.scale;
+        }
+    }
+}
+
+// The color temperature that white balancing leaves alone
+const NEUTRAL_KELVIN: f32 = 6500.0;
+
+// How much of the green a tint of 1 takes away, or a tint of -1 adds
+const TINT_RANGE: f32 = 0.5;
+
+///
+/// White balances linear light, the way a camera does, so that light
+/// of a color temperature comes out neutral: balanced for 3200K, a
+/// tungsten-lit scene looks white, and daylight looks blue. The tint
+/// then shifts the image towards magenta, or green if it's negative.
+/// The overall brightness is left as it is.
+///
+pub struct WhiteBalance {
+    scale: Vec3,
+}
+
+impl WhiteBalance {
+    pub fn new(kelvin: f32, tint: f32) -> WhiteBalance {
+        let (white, light) = (blackbody(NEUTRAL_KELVIN), blackbody(kelvin));
+        let scale = Vec3::new(white.r() / light.r(),
+                              white.g() / light.g() * (1.0 - TINT_RANGE * tint),
+                              white.b() / light.b());
+        WhiteBalance { scale: scale / film::luminance(&scale) }
+    }
+}
+
+impl PostProcess for WhiteBalance {
+    fn apply(&self, data: &mut [Vec3], _: &PixelRun) {
+        for col in data.iter_mut() {
+            *col *= self.scale;
+        }
+    }
+}
+
+/// Tone maps linear light and encodes it with a response curve.
+pub struct ToneMapping {
+    tone_map: ToneMap,
+    response: ResponseCurve,
+}
+
+impl PostProcess for ToneMapping {
+    fn apply(&self, data: &mut [Vec3], _: &PixelRun) {
+        for col in data.iter_mut() {
+            for c in col.e.iter_mut() {
+                *c = self.response.apply(self.tone_map.apply(*c));
+            }
+        }
+    }
+}
+
+///
+/// An ASC CDL color grade: each channel is multiplied by its slope,
+/// has its offset added and is raised to its power, and then the
+/// saturation pulls the color towards or away from its luminance.
+///
+#[derive(Copy, Clone)]
+pub struct ColorGrade {
+    pub slope: Vec3,
+    pub offset: Vec3,
+    pub power: Vec3,
+    pub saturation: f32,
+}
+
+impl Default for ColorGrade {
+    fn default() -> ColorGrade {
+        ColorGrade {
+            slope: Vec3::new(1.0, 1.0, 1.0),
+            offset: Vec3::new(0.0, 0.0, 0.0),
+            power: Vec3::new(1.0, 1.0, 1.0),
+            saturation: 1.0,
+        }
+    }
+}
+
+impl PostProcess for ColorGrade {
+    fn apply(&self, data: &mut [Vec3], _: &PixelRun) {
+        for col in data.iter_mut() {
+            for i in 0..3 {
+                col.e[i] = (col.e[i] * self.slope.e[i] + self.offset.e[i]).max(0.0).powf(self.power.e[i]);
+            }
+            let luma = film::luminance(col);
+            *col = Vec3::new(luma, luma, luma) + self.saturation * (*col - Vec3::new(luma, luma, luma));
+        }
+    }
+}
+
+/// Rounds pixel values to the levels of a lower bit depth.
+pub struct Quantize {
+    levels: f32,
+}
+
+impl PostProcess for Quantize {
+    fn apply(&self, data: &mut [Vec3], _: &PixelRun) {
+        for col in data.iter_mut() {
+            for c in col.e.iter_mut() {
+                *c = (c.clamp(0.0, 1.0) * self.levels).round() / self.levels;
+            }
+        }
+    }
+}
+
+///
+/// Darkening towards the edges of the frame, as most lenses let less
+/// light through there. The light is scaled down by `strength` times
+/// the square of the distance from the middle, which reaches 1 in the
+/// corners.
+///
+pub struct Vignette {
+    strength: f32,
+}
+
+impl PostProcess for Vignette {
+    fn apply(&self, data: &mut [Vec3], run: &PixelRun) {
+        let (cx, cy) = (run.width as f32 / 2.0, run.height as f32 / 2.0);
+        let corner = cx * cx + cy * cy;
+
+        for (i, col) in data.iter_mut().enumerate() {
+            let (x, y) = run.position(i);
+            let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+            *col *= 1.0 - self.strength * (dx * dx + dy * dy) / corner;
+        }
+    }
+}
+
+// Scales the grain so that `strength` is its largest size in the
+// midtones, where luma * (1 - luma) peaks at a quarter
+const GRAIN_MIDTONES: f32 = 4.0;
+
+///
+/// Film grain: noise that is strongest in the midtones and fades out
+/// in the shadows and highlights, like the grain of a photograph.
+/// Each pixel's grain is hashed from its position and the seed, so the
+/// same render always gets the same grain.
+///
+pub struct Grain {
+    strength: f32,
+    seed: u32,
+}
+
+impl PostProcess for Grain {
+    fn apply(&self, data: &mut [Vec3], run: &PixelRun) {
+        for (i, col) in data.iter_mut().enumerate() {
+            let (x, y) = run.position(i);
+            let h = hash_combine(hash_combine(self.seed, x as u32), y as u32);
+            // The sum of two uniform numbers, for noise that is
+            // mostly small, between -1 and 1
+            let noise = ((h & 0xffff) as f32 + (h >> 16) as f32) / 65535.0 - 1.0;
+
+            let luma = film::luminance(col).clamp(0.0, 1.0);
+            let amount = self.strength * GRAIN_MIDTONES * luma * (1.0 - luma) * noise;
+            *col += Vec3::new(amount, amount, amount);
+        }
+    }
+}
+
+///
+/// The steps between the film and an image `width` by `height` pixels,
+/// in the order the settings give them. Steps that would leave the
+/// image as it is, such as a color grade with no changes, are left
+/// out.
+///
+pub struct PostChain {
+    stages: Vec<Box<dyn PostProcess>>,
+    dither: Dither,
+    width: usize,
+    height: usize,
+}
+
+impl PostChain {
+    pub fn new(settings: &Settings, width: usize, height: usize) -> PostChain {
+        PostChain::build(settings, width, height, 2f32.powf(settings.compensation))
+    }
+
+    ///
+    /// A chain for the image `data`, `width` by `height` pixels, with
+    /// its exposure set by metering the image if auto-exposure is on.
+    ///
+    pub fn metered(settings: &Settings, width: usize, height: usize, data: &[Vec3]) -> PostChain {
+        let exposure = match settings.auto_exposure {
+            Some(metering) => metering.exposure(data, width, height),
+            None => 1.0,
+        };
+        PostChain::build(settings, width, height, exposure * 2f32.powf(settings.compensation))
+    }
+
+    fn build(settings: &Settings, width: usize, height: usize, exposure: f32) -> PostChain {
+        let order: &[PostStage] = match settings.post {
+            Some(ref stages) => stages,
+            None => &DEFAULT_POST,
+        };
+        let mut stages: Vec<Box<dyn PostProcess>> = Vec::new();
+
+        for stage in order {
+            match *stage {
+                PostStage::Exposure if exposure != 1.0 => {
+                    stages.push(Box::new(Exposure { scale: exposure }));
+                },
+                PostStage::WhiteBalance if settings.white_balance.is_some() || settings.tint != 0.0 => {
+                    let kelvin = settings.white_balance.unwrap_or(NEUTRAL_KELVIN);
+                    stages.push(Box::new(WhiteBalance::new(kelvin, settings.tint)));
+                },
+                PostStage::Vignette if settings.vignette.is_some() => {
+                    stages.push(Box::new(Vignette { strength: settings.vignette.unwrap() }));
+                },
+                PostStage::ToneMap => {
+                    stages.push(Box::new(ToneMapping { tone_map: settings.tone_map, response: settings.response }));
+                },
+                PostStage::Grade => {
+                    if let Some(grade) = settings.grade {
+                        stages.push(Box::new(grade));
+                    }
+                },
+                PostStage::Lut => {
+                    if let Some(ref lut) = settings.lut {
+                        stages.push(Box::new(lut.clone()));
+                    }
+                },
+                PostStage::Grain if settings.grain.is_some() => {
+                    stages.push(Box::new(Grain { strength: settings.grain.unwrap(), seed: settings.seed }));
+                },
+                PostStage::Quantize => {
+                    if let Some(bits) = settings.quantize {
+                        stages.push(Box::new(Quantize { levels: ((1u32 << bits) - 1) as f32 }));
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        PostChain { stages, dither: settings.dither, width, height }
+    }
+
+    /// Put linear light through every step, in place, for pixels `start` pixels into the image.
+    pub fn apply(&self, data: &mut [Vec3], start: usize) {
+        let run = PixelRun { width: self.width, height: self.height, start };
+        for stage in &self.stages {
+            stage.apply(data, &run);
+        }
+    }
+
+    /// The value of the pixel `index` pixels into the image, with each channel between 0 and 1.
+    pub fn pixel(&self, col: &Vec3, index: usize) -> Vec3 {
+        let mut data = [*col];
+        self.apply(&mut data, index);
+        let c = data[0];
+        Vec3::new(c.r().clamp(0.0, 1.0), c.g().clamp(0.0, 1.0), c.b().clamp(0.0, 1.0))
+    }
+
+    /// The 8-bit value of the pixel `index` pixels into the image, dithered.
+    pub fn pixel8(&self, col: &Vec3, index: usize) -> [u8; 3] {
+        let col = self.pixel(col, index);
+        let (x, y) = (index % self.width, index / self.width);
+        [self.dither.level(col.r(), x, y), self.dither.level(col.g(), x, y), self.dither.level(col.b(), x, y)]
+    }
+}
@@ -0,0 +1,137 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use settings::Settings;
+use tile::Tile;
+
+// Least time between two progress events, so that a fast render
+// doesn't flood whatever is reading them
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+///
+/// Progress of a render without a window, reported as one JSON object
+/// per line on stdout for `--progress-json`, so that a render farm or
+/// a GUI wrapping the renderer can follow it without picking apart the
+/// usual messages. Every event has an `event` field saying what it is:
+///
+/// * `start`: the image size, samples per pixel, threads and frames.
+/// * `progress`: the tiles of the current frame done so far, the
+///   average samples per pixel taken in it, and the seconds elapsed
+///   and estimated to remain for the whole render. Sent at most four
+///   times a second.
+/// * `frame`: a finished frame, the file it was saved to, and its
+///   average samples per pixel.
+/// * `done`: the total number of samples taken and seconds elapsed.
+///
+/// Frames are numbered from 1.
+///
+pub struct ProgressReport {
+    started: Instant,
+    last: Option<Instant>,
+    pixels: u64,
+    tiles: u32,
+    frames: u32,
+    frame: u32,
+    tiles_done: u32,
+    samples: u64,
+}
+
+impl ProgressReport {
+    /// Start reporting on a render of `frames` frames, with a `start` event.
+    pub fn start(settings: &Settings, frames: u32) -> ProgressReport {
+        println!("{{\"event\":\"start\",\"width\":{},\"height\":{},\"samples\":{},\"threads\":{},\"frames\":{}}}",
+                 settings.width, settings.height, settings.samples, settings.threads, frames);
+
+        ProgressReport {
+            started: Instant::now(),
+            last: None,
+            pixels: settings.width as u64 * settings.height as u64,
+            tiles: Tile::cover(settings.width, settings.height, settings.tile_size).len() as u32,
+            frames,
+            frame: 0,
+            tiles_done: 0,
+            samples: 0,
+        }
+    }
+
+    /// Another tile of the current frame is done, with `samples` taken in the frame so far.
+    pub fn tile_done(&mut self, samples: u64) {
+        self.tiles_done += 1;
+
+        if self.last.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        self.last = Some(Instant::now());
+
+        let elapsed = self.started.elapsed().as_secs_f32();
+        let done = (self.frame as f32 + self.tiles_done as f32 / self.tiles as f32) / self.frames as f32;
+        let eta = if done > 0.0 {
+            format!("{:.1}", elapsed * (1.0 - done) / done)
+        } else {
+            "null".to_string()
+        };
+
+        println!("{{\"event\":\"progress\",\"frame\":{},\"frames\":{},\"tiles_done\":{},\"tiles\":{},\
+                  \"spp\":{:.2},\"elapsed\":{:.1},\"eta\":{}}}",
+                 self.frame + 1, self.frames, self.tiles_done, self.tiles, self.spp(samples), elapsed, eta);
+    }
+
+    /// The current frame is done and saved to `path`, after taking `samples` samples.
+    pub fn frame_done(&mut self, path: &Path, samples: u64) {
+        println!("{{\"event\":\"frame\",\"frame\":{},\"frames\":{},\"path\":{},\"spp\":{:.2},\"elapsed\":{:.1}}}",
+                 self.frame + 1, self.frames, json_string(&path.to_string_lossy()), self.spp(samples),
+                 self.started.elapsed().as_secs_f32());
+
+        self.frame += 1;
+        self.tiles_done = 0;
+        self.samples += samples;
+        self.last = None;
+    }
+
+    /// The whole render is done.
+    pub fn finish(&self) {
+        println!("{{\"event\":\"done\",\"frames\":{},\"samples\":{},\"elapsed\":{:.1}}}",
+                 self.frames, self.samples, self.started.elapsed().as_secs_f32());
+    }
+
+    fn spp(&self, samples: u64) -> f32 {
+        samples as f32 / self.pixels as f32
+    }
+}
+
+// A string as a quoted JSON string
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
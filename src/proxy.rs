@@ -0,0 +1,102 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use film;
+use film::Film;
+use output;
+use post::PostChain;
+use settings::Settings;
+use vec3::Vec3;
+
+// The proxy's longest side, in pixels
+const PROXY_SIZE: usize = 640;
+const PROXY_QUALITY: u8 = 75;
+
+///
+/// A small JPEG of a render in progress, written every so often so
+/// that someone without a window onto the render can check on it by
+/// refreshing a file, e.g. over a web server or a network share. The
+/// image is shrunk to at most 640 pixels across and put through the
+/// post-processing chain, but skips glare and other finishing touches. Each
+/// proxy is written to a temporary file first and then renamed over
+/// the last one, so a half-written proxy is never seen.
+///
+pub struct Proxy {
+    path: PathBuf,
+    interval: Duration,
+    last: Instant,
+}
+
+impl Proxy {
+    /// A proxy at `path`, updated every `interval` seconds.
+    pub fn new(path: &Path, interval: f32) -> Proxy {
+        Proxy { path: path.to_path_buf(), interval: Duration::from_secs_f32(interval), last: Instant::now() }
+    }
+
+    /// True once it's time for the proxy to be written again.
+    pub fn due(&self) -> bool {
+        self.last.elapsed() >= self.interval
+    }
+
+    /// Write the film's image as it is now.
+    pub fn write(&mut self, film: &Film, settings: &Settings) -> io::Result<()> {
+        self.last = Instant::now();
+
+        let (width, height, data) = shrink(&film.rgb(), film.width, film.height);
+        let rgb = film::rgb8(&data, width, &PostChain::metered(settings, width, height, &data));
+
+        let name = self.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let temporary = self.path.with_file_name(format!(".{}.tmp", name));
+        output::write_jpeg(&temporary, width as u32, height as u32, &rgb, PROXY_QUALITY)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+// Shrink an image so its longest side is at most PROXY_SIZE pixels,
+// averaging the pixels that fall into each new one
+fn shrink(data: &[Vec3], width: usize, height: usize) -> (usize, usize, Vec<Vec3>) {
+    let factor = width.max(height).div_ceil(PROXY_SIZE).max(1);
+    if factor == 1 {
+        return (width, height, data.to_vec());
+    }
+
+    let (w, h) = (width.div_ceil(factor), height.div_ceil(factor));
+    let mut shrunk: Vec<Vec3> = Vec::with_capacity(w * h);
+
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            let mut count = 0;
+            for sy in y * factor..((y + 1) * factor).min(height) {
+                for sx in x * factor..((x + 1) * factor).min(width) {
+                    sum += data[sy * width + sx];
+                    count += 1;
+                }
+            }
+            shrunk.push(sum / count as f32);
+        }
+    }
+
+    (w, h, shrunk)
+}
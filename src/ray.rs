@@ -20,12 +20,25 @@ use vec3::Vec3;
 
 pub struct Ray {
     a: Vec3,
-    b: Vec3
+    b: Vec3,
+    // The wavelength the ray carries in nanometres, when rendering
+    // spectrally
+    wavelength: Option<f32>,
 }
 
 impl Ray {
     pub fn new(a: Vec3, b: Vec3) -> Ray {
-        Ray {a, b}
+        Ray {a, b, wavelength: None}
+    }
+
+    /// The same ray, carrying light of `wavelength` nanometres, or of
+    /// every wavelength if None.
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Ray {
+        Ray { wavelength, ..self }
+    }
+
+    pub fn wavelength(&self) -> Option<f32> {
+        self.wavelength
     }
 
     pub fn origin(&self) -> Vec3 {
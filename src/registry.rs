@@ -0,0 +1,234 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use aabb::Aabb;
+use vec3::Vec3;
+use hittable::*;
+use medium::{Bounds, Density, HenyeyGreenstein, Volume};
+use mesh::Triangle;
+use shader::SharedTexture;
+use spectrum::Dispersion;
+
+pub type BoxedObject = Box<dyn Hittable+Sync+Send>;
+
+type MaterialFactory = Box<dyn Fn(&[f32]) -> Result<SharedMaterial, String>>;
+type ShapeFactory = Box<dyn Fn(&[f32], SharedMaterial) -> Result<BoxedObject, String>>;
+type TextureFactory = Box<dyn Fn(&[f32]) -> Result<SharedTexture, String>>;
+
+///
+/// The kinds of materials, shapes and textures that scenes can use,
+/// by name. Each kind has a factory that builds one from the numbers
+/// given for it in the scene, or explains why it can't. Crates using
+/// the raytracer as a library can register their own Material,
+/// Hittable and Texture implementations alongside the built in ones,
+/// and scenes can then use them like any other.
+///
+pub struct Registry {
+    materials: HashMap<String, MaterialFactory>,
+    shapes: HashMap<String, ShapeFactory>,
+    textures: HashMap<String, TextureFactory>,
+}
+
+impl Registry {
+    /// A registry holding the built in materials and shapes. The
+    /// built in textures are the shader graph's own nodes.
+    pub fn new() -> Registry {
+        let mut registry = Registry { materials: HashMap::new(), shapes: HashMap::new(), textures: HashMap::new() };
+
+        registry.register_material("lambertian", |params| {
+            let [r, g, b] = expect_params("lambertian", params)?;
+            expect_color("lambertian", r, g, b)?;
+            Ok(Arc::new(Lambertian::new(Vec3::new(r, g, b))))
+        });
+        registry.register_material("metal", |params| {
+            let [r, g, b] = expect_params("metal", params)?;
+            expect_color("metal", r, g, b)?;
+            Ok(Arc::new(Metal::new(Vec3::new(r, g, b))))
+        });
+        registry.register_material("dielectric", |params| {
+            let (ref_idx, abbe) = match *params {
+                [ref_idx] => (ref_idx, None),
+                [ref_idx, abbe] => (ref_idx, Some(abbe)),
+                _ => return Err(format!("dielectric takes 1 or 2 numbers, not {}", params.len())),
+            };
+            if ref_idx <= 0.0 {
+                return Err("dielectric index of refraction must be greater than zero".to_string());
+            }
+            match abbe {
+                Some(abbe) if abbe <= 0.0 => Err("dielectric Abbe number must be greater than zero".to_string()),
+                Some(abbe) => Ok(Arc::new(Dialectric::dispersive(Dispersion::from_abbe(ref_idx, abbe)))),
+                None => Ok(Arc::new(Dialectric::new(ref_idx))),
+            }
+        });
+        registry.register_material("sellmeier", |params| {
+            let [b1, b2, b3, c1, c2, c3] = expect_params("sellmeier", params)?;
+            Ok(Arc::new(Dialectric::dispersive(Dispersion::Sellmeier { b: [b1, b2, b3], c: [c1, c2, c3] })))
+        });
+        registry.register_material("medium", |params| {
+            let (r, g, b, anisotropy) = match *params {
+                [r, g, b] => (r, g, b, 0.0),
+                [r, g, b, anisotropy] => (r, g, b, anisotropy),
+                _ => return Err(format!("medium takes 3 or 4 numbers, not {}", params.len())),
+            };
+            expect_color("medium", r, g, b)?;
+            if anisotropy <= -1.0 || anisotropy >= 1.0 {
+                return Err("medium anisotropy must be between -1 and 1".to_string());
+            }
+            Ok(Arc::new(HenyeyGreenstein::new(Vec3::new(r, g, b), anisotropy)))
+        });
+
+        registry.register_shape("sphere", |params, material| {
+            let [x, y, z, radius] = expect_params("sphere", params)?;
+            if radius <= 0.0 {
+                return Err("sphere radius must be greater than zero".to_string());
+            }
+            Ok(Box::new(Sphere::new(Vec3::new(x, y, z), radius, material)))
+        });
+        registry.register_shape("triangle", |params, material| {
+            Ok(Box::new(Triangle::from_params(params, material)?))
+        });
+        registry.register_shape("volume", |params, material| {
+            if params.len() < 5 {
+                return Err(format!("volume takes a center, radius and density, and optionally a noise \
+                                    scale and seed, not {} numbers", params.len()));
+            }
+            let (center, radius) = (Vec3::new(params[0], params[1], params[2]), params[3]);
+            if radius <= 0.0 {
+                return Err("volume radius must be greater than zero".to_string());
+            }
+            let density = parse_density("volume", &params[4..])?;
+            Ok(Box::new(Volume::new(Bounds::Sphere { center, radius }, density, material)))
+        });
+        registry.register_shape("box_volume", |params, material| {
+            if params.len() < 7 {
+                return Err(format!("box_volume takes two corners and a density, and optionally a noise \
+                                    scale and seed, not {} numbers", params.len()));
+            }
+            let (a, b) = (Vec3::new(params[0], params[1], params[2]), Vec3::new(params[3], params[4], params[5]));
+            let bounds = Aabb::new(Vec3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+                                   Vec3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())));
+            let density = parse_density("box_volume", &params[6..])?;
+            Ok(Box::new(Volume::new(Bounds::Box(bounds), density, material)))
+        });
+
+        registry
+    }
+
+    /// Add a kind of material, replacing any other of the same name.
+    pub fn register_material<F>(&mut self, name: &str, factory: F)
+        where F: Fn(&[f32]) -> Result<SharedMaterial, String> + 'static {
+        self.materials.insert(name.to_string(), Box::new(factory));
+    }
+
+    /// Add a kind of shape, replacing any other of the same name.
+    pub fn register_shape<F>(&mut self, name: &str, factory: F)
+        where F: Fn(&[f32], SharedMaterial) -> Result<BoxedObject, String> + 'static {
+        self.shapes.insert(name.to_string(), Box::new(factory));
+    }
+
+    ///
+    /// Add a kind of texture, replacing any other of the same name.
+    /// Scene files use it as a node of a shader graph, e.g.
+    /// `node grain wood 0.5` for one registered as `wood`, which can
+    /// be the input of other nodes like any other.
+    ///
+    pub fn register_texture<F>(&mut self, name: &str, factory: F)
+        where F: Fn(&[f32]) -> Result<SharedTexture, String> + 'static {
+        self.textures.insert(name.to_string(), Box::new(factory));
+    }
+
+    pub fn has_shape(&self, name: &str) -> bool {
+        self.shapes.contains_key(name)
+    }
+
+    pub fn material(&self, kind: &str, params: &[f32]) -> Result<SharedMaterial, String> {
+        match self.materials.get(kind) {
+            Some(factory) => factory(params),
+            None => Err(format!("Unknown material type: {}", kind)),
+        }
+    }
+
+    pub fn shape(&self, kind: &str, params: &[f32], material: SharedMaterial) -> Result<BoxedObject, String> {
+        match self.shapes.get(kind) {
+            Some(factory) => factory(params, material),
+            None => Err(format!("Unknown shape: {}", kind)),
+        }
+    }
+
+    pub fn has_texture(&self, name: &str) -> bool {
+        self.textures.contains_key(name)
+    }
+
+    pub fn texture(&self, kind: &str, params: &[f32]) -> Result<SharedTexture, String> {
+        match self.textures.get(kind) {
+            Some(factory) => factory(params),
+            None => Err(format!("Unknown texture: {}", kind)),
+        }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+///
+/// The parameters given for a material, shape or texture, checked
+/// to be exactly `N` numbers long. Useful for writing factories.
+///
+pub fn expect_params<const N: usize>(kind: &str, params: &[f32]) -> Result<[f32; N], String> {
+    if params.len() != N {
+        return Err(format!("{} takes {} numbers, not {}", kind, N, params.len()));
+    }
+
+    let mut values = [0.0; N];
+    values.copy_from_slice(params);
+    Ok(values)
+}
+
+// The density of a volume, and the scale and seed of its noise if it
+// isn't the same everywhere
+fn parse_density(kind: &str, params: &[f32]) -> Result<Density, String> {
+    let density = match *params {
+        [sigma] => Density::Constant { sigma },
+        [sigma, scale] => Density::Noise { sigma, scale, seed: 0 },
+        [sigma, scale, seed] if seed >= 0.0 && seed.fract() == 0.0 => Density::Noise { sigma, scale, seed: seed as u32 },
+        [_, _, _] => return Err(format!("{} seed must be a whole number", kind)),
+        _ => return Err(format!("{} takes at most 3 numbers after its bounds", kind)),
+    };
+    match density {
+        Density::Constant { sigma } | Density::Noise { sigma, .. } if sigma < 0.0 => {
+            Err(format!("{} density can't be negative", kind))
+        },
+        Density::Noise { scale, .. } if scale <= 0.0 => Err(format!("{} noise scale must be greater than zero", kind)),
+        density => Ok(density),
+    }
+}
+
+// Check a material's color, which can't take away light
+fn expect_color(kind: &str, r: f32, g: f32, b: f32) -> Result<(), String> {
+    if r < 0.0 || g < 0.0 || b < 0.0 {
+        return Err(format!("{} color can't be negative", kind));
+    }
+    Ok(())
+}
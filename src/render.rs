@@ -0,0 +1,758 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::collections::BTreeMap;
+use std::any::Any;
+use std::io;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::thread::JoinHandle;
+
+use vec3::Vec3;
+use ray::Ray;
+use hittable::*;
+use camera::Camera;
+use blue_noise::{BlueNoiseMask, DitheredSampler};
+use film;
+use film::{Film, PixelSamples, Splats};
+use filter::Filter;
+use output::ImageStream;
+use sampler::*;
+use settings::Settings;
+use spectrum;
+use tile::Tile;
+
+// Samples taken per pixel in each progressive pass
+pub const SAMPLES_PER_PASS: u32 = 4;
+
+// Light reaching the camera after at most this many bounces counts
+// as direct light for clamping
+const DIRECT_LIGHT_DEPTH: u32 = 1;
+
+// Times a tile that panicked is rendered again before giving up on it
+const TILE_RETRIES: u32 = 2;
+
+// Paths followed together in one wave, at most, which bounds the
+// memory a tile needs however large it is
+const WAVE_PATHS: usize = 4096;
+
+// A path being followed from the camera, one bounce per wave
+struct Path {
+    // Where the path's sample goes in the wave's list of samples
+    slot: usize,
+    ray: Ray,
+    throughput: Vec3,
+    radiance: Vec3,
+    depth: u32,
+}
+
+// One camera sample: where in the pixel it was taken, and the light
+// and coverage it found there
+#[derive(Copy, Clone)]
+struct CameraSample {
+    offset: (f32, f32),
+    color: Vec3,
+    alpha: f32,
+}
+
+///
+/// Trace a sample for `SAMPLES_PER_PASS` of the pixels, given as
+/// (line, column), as a wavefront: every camera ray is made first,
+/// then the rays are tested against the world together, then every
+/// hit is shaded together, giving the rays for the next bounce, and
+/// so on until all the paths are done. Each stage is one tight loop
+/// over many rays, which keeps the same code and much the same part
+/// of the scene in the cache, and is the shape batched intersection
+/// would need. The sampler is set up again for each path before it's
+/// used, so paths get the same numbers as if they were followed one
+/// at a time.
+///
+/// Along each path, the throughput (how much of the light found
+/// further along still reaches the camera) starts at the weight the
+/// camera's lens gives the ray, and is cut down by every bounce. All
+/// the light found along the way is clamped if the settings ask for
+/// it and added to the light group it came from, so the groups always
+/// sum to the pixel's color. Samples come back pixel by pixel, with
+/// `None` for pixels that `active` turns down, along with the light
+/// groups of each pixel in turn and the number of rays traced.
+///
+fn trace_wave(pixels: &[(u32, u32)], pass: u32, settings: &Settings, world: &World, camera: &Camera,
+              sampler: &mut dyn Sampler, active: &dyn Fn(u32, u32) -> bool)
+              -> (Vec<Option<CameraSample>>, Vec<Vec3>, u64) {
+    let spp = SAMPLES_PER_PASS as usize;
+    let group_count = world.light_groups.len();
+    let mut groups: Vec<Vec3> = vec![Vec3::new(0.0, 0.0, 0.0); pixels.len() * group_count];
+    let exposure = settings.exposure();
+    let mut samples: Vec<Option<CameraSample>> = vec![None; pixels.len() * spp];
+    let mut paths: Vec<Path> = Vec::with_capacity(samples.len());
+    let mut rays: u64 = 0;
+
+    for (pixel, &(line, i)) in pixels.iter().enumerate() {
+        if !active(i, line) {
+            continue;
+        }
+
+        for s in 0..SAMPLES_PER_PASS {
+            sampler.start_sample(i, line, pass * SAMPLES_PER_PASS + s);
+            sampler.start_dimension(PIXEL_DIMENSION);
+
+            let (ir, jr) = sampler.next_2d();
+            let u: f32 = (i as f32 + ir) / settings.width as f32;
+            let v: f32 = (line as f32 + jr) / settings.height as f32;
+
+            let slot = pixel * spp + s as usize;
+            samples[slot] = Some(CameraSample {
+                offset: (ir, jr),
+                color: Vec3::new(0.0, 0.0, 0.0),
+                alpha: 1.0,
+            });
+
+            // Light blocked inside the lens never reaches the film
+            let r: Option<(Ray, f32)> = if camera.lens.is_some() {
+                sampler.start_dimension(LENS_DIMENSION);
+                camera.get_lens_ray(u, v, sampler.next_2d())
+            } else {
+                Some((camera.get_ray(u, v), 1.0))
+            };
+            if let Some((ray, weight)) = r {
+                // Rendering spectrally, each path carries one
+                // wavelength, and the light it finds counts towards
+                // the colors of that wavelength
+                let (ray, throughput) = if settings.spectral {
+                    sampler.start_dimension(WAVELENGTH_DIMENSION);
+                    let wavelength = spectrum::sample_wavelength(sampler.next_1d());
+                    (ray.with_wavelength(Some(wavelength)), weight * spectrum::rgb_weight(wavelength))
+                } else {
+                    (ray, Vec3::new(weight, weight, weight))
+                };
+                paths.push(Path {
+                    slot,
+                    ray,
+                    throughput,
+                    radiance: Vec3::new(0.0, 0.0, 0.0),
+                    depth: 0,
+                });
+            }
+        }
+    }
+
+    while !paths.is_empty() {
+        let hits: Vec<Option<Hit>> = paths.iter().map(|p| world.hit(&p.ray, 0.001, f32::MAX)).collect();
+        rays += hits.len() as u64;
+
+        let mut next: Vec<Path> = Vec::with_capacity(paths.len());
+        for (mut path, hit) in paths.drain(..).zip(hits) {
+            let pixel = path.slot / spp;
+            let groups = &mut groups[pixel * group_count..(pixel + 1) * group_count];
+            let sample = samples[path.slot].as_mut().unwrap();
+            let limit = if path.depth <= DIRECT_LIGHT_DEPTH {
+                settings.clamp_direct
+            } else {
+                settings.clamp_indirect
+            };
+
+            let h: Hit = match hit {
+                Some(h) => h,
+                // With a transparent background, camera rays that miss
+                // everything leave the film empty instead of seeing
+                // the sky
+                None if settings.transparent && path.depth == 0 => {
+                    sample.alpha = 0.0;
+                    continue;
+                },
+                None => {
+                    let sky: Vec3 = clamp_radiance(path.throughput * world.background(&path.ray.direction()), limit);
+                    groups[SKY_LIGHT_GROUP] += sky;
+                    sample.color = exposure * (path.radiance + sky);
+                    continue;
+                }
+            };
+
+            let material = h.object.material();
+            let emitted: Vec3 = clamp_radiance(path.throughput * material.emitted(), limit);
+            groups[material.light_group()] += emitted;
+            path.radiance += emitted;
+
+            if path.depth >= settings.max_depth {
+                sample.color = exposure * path.radiance;
+                continue;
+            }
+
+            let (line, i) = pixels[pixel];
+            sampler.start_sample(i, line, pass * SAMPLES_PER_PASS + (path.slot % spp) as u32);
+            sampler.start_dimension(bsdf_dimension(path.depth));
+            let reflection: Reflection = if settings.simple_materials {
+                material.preview_scatter(&path.ray, &h, sampler)
+            } else {
+                material.scatter(&path.ray, &h, sampler)
+            };
+            if !reflection.reflected {
+                sample.color = exposure * path.radiance;
+                continue;
+            }
+
+            path.throughput *= reflection.attenuation;
+            let wavelength = path.ray.wavelength();
+            path.ray = reflection.scattered.with_wavelength(wavelength);
+            path.depth += 1;
+            next.push(path);
+        }
+        paths = next;
+    }
+
+    (samples, groups, rays)
+}
+
+// Scale a color down, keeping its hue, until no channel is above `limit`
+fn clamp_radiance(col: Vec3, limit: Option<f32>) -> Vec3 {
+    match limit {
+        Some(l) => {
+            let brightest = col.r().max(col.g()).max(col.b());
+            if brightest > l {
+                (l / brightest) * col
+            } else {
+                col
+            }
+        },
+        None => col,
+    }
+}
+
+///
+/// The samples taken for one tile, both per pixel and as spread out
+/// by the reconstruction filter. Pixels come one line of the tile
+/// after the other, bottom line first. When light groups are being
+/// recorded, `groups` holds the summed contribution of every light
+/// group for each pixel, one pixel after the other; otherwise it is
+/// empty. `rays` counts every ray traced for the tile, from the
+/// camera and on every bounce. A tile that kept panicking comes back
+/// `failed`, with no samples.
+///
+#[derive(Debug)]
+pub struct RenderResult {
+    pub tile: Tile,
+    pub pass: u32,
+    pub data: Vec<PixelSamples>,
+    pub splats: Splats,
+    pub groups: Vec<Vec3>,
+    pub rays: u64,
+    pub failed: bool,
+}
+
+impl RenderResult {
+    pub fn merge(&mut self, other: &RenderResult) {
+        for (p, s) in self.data.iter_mut().zip(other.data.iter()) {
+            p.merge(s);
+        }
+        self.splats.merge(&other.splats);
+        for (g, o) in self.groups.iter_mut().zip(other.groups.iter()) {
+            *g += *o;
+        }
+        self.rays += other.rays;
+    }
+}
+
+///
+/// Take one pass worth of samples for every pixel of a tile. Pixels
+/// for which `active` returns false, given their column and line in
+/// the image, are skipped and come back with no samples.
+///
+pub fn render_tile(tile: Tile, pass: u32, settings: &Settings, world: &World, camera: &Camera,
+                   sampler: &mut dyn Sampler, active: &dyn Fn(u32, u32) -> bool) -> RenderResult {
+    let filter: Box<dyn Filter> = settings.filter.create();
+    let mut data: Vec<PixelSamples> = Vec::with_capacity(tile.pixels());
+    let mut splats = Splats::new(&tile, settings.width as usize, settings.height as usize, &*filter);
+    if settings.transparent {
+        splats.record_alpha();
+    }
+    let pixels: Vec<(u32, u32)> = tile_pixels(&tile).collect();
+    let mut groups: Vec<Vec3> = Vec::new();
+    let mut rays: u64 = 0;
+
+    for chunk in pixels.chunks((WAVE_PATHS / SAMPLES_PER_PASS as usize).max(1)) {
+        let (samples, wave_groups, wave_rays) = trace_wave(chunk, pass, settings, world, camera, sampler, active);
+        rays += wave_rays;
+        if settings.light_groups {
+            let exposure = settings.exposure();
+            groups.extend(wave_groups.iter().map(|g| exposure * *g));
+        }
+
+        for (&(line, i), pixel_samples) in chunk.iter().zip(samples.chunks(SAMPLES_PER_PASS as usize)) {
+            let mut pixel = PixelSamples::new();
+            for sample in pixel_samples.iter().flatten() {
+                pixel.add(sample.color);
+                splats.add(&*filter, i, line, sample.offset, sample.color, sample.alpha);
+            }
+            data.push(pixel);
+        }
+    }
+
+    RenderResult {
+        tile,
+        pass,
+        data,
+        splats,
+        groups,
+        rays,
+        failed: false,
+    }
+}
+
+// The (line, column) of every pixel of a tile, bottom line first
+fn tile_pixels(tile: &Tile) -> impl Iterator<Item = (u32, u32)> {
+    let (x, width) = (tile.x, tile.width);
+    (tile.y..tile.y + tile.height).flat_map(move |line| (x..x + width).map(move |i| (line, i)))
+}
+
+///
+/// Like `render_tile`, but a panic while rendering the tile (a NaN
+/// tripping up a new material, say) doesn't take the rest of a long
+/// render down with it. The tile is rendered again with a freshly
+/// seeded sampler, and if it still panics after `TILE_RETRIES` more tries
+/// it is reported and comes back failed and empty. Every try's sampler
+/// is dithered by `mask` the same way, so a retried tile blends in.
+///
+pub fn render_tile_isolated(tile: Tile, pass: u32, settings: &Settings, world: &World, camera: &Camera,
+                            mask: &Option<Arc<BlueNoiseMask>>, active: &dyn Fn(u32, u32) -> bool) -> RenderResult {
+    let first = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut sampler = create_sampler(settings, mask);
+        render_tile(tile, pass, settings, world, camera, &mut *sampler, active)
+    }));
+    let mut error = match first {
+        Ok(result) => return result,
+        Err(e) => e,
+    };
+
+    for attempt in 1..=TILE_RETRIES {
+        eprintln!("Tile {} panicked ({}), rendering it again", tile, panic_message(&*error));
+        let mut retry_sampler = seeded_sampler(settings, mask, settings.seed.wrapping_add(attempt));
+        match panic::catch_unwind(AssertUnwindSafe(|| {
+            render_tile(tile, pass, settings, world, camera, &mut *retry_sampler, active)
+        })) {
+            Ok(result) => return result,
+            Err(e) => error = e,
+        }
+    }
+
+    eprintln!("Tile {} panicked ({}) {} times, leaving it empty", tile, panic_message(&*error),
+              TILE_RETRIES + 1);
+    empty_result(tile, pass, settings, world)
+}
+
+// A tile with no samples in any of its pixels, for one that couldn't
+// be rendered, without tracing anything that might panic again
+fn empty_result(tile: Tile, pass: u32, settings: &Settings, world: &World) -> RenderResult {
+    let filter: Box<dyn Filter> = settings.filter.create();
+    let mut splats = Splats::new(&tile, settings.width as usize, settings.height as usize, &*filter);
+    if settings.transparent {
+        splats.record_alpha();
+    }
+
+    RenderResult {
+        tile,
+        pass,
+        data: vec![PixelSamples::new(); tile.pixels()],
+        splats,
+        groups: vec![Vec3::new(0.0, 0.0, 0.0); tile.pixels() * light_group_count(settings, world)],
+        rays: 0,
+        failed: true,
+    }
+}
+
+// The message a panic was raised with, if it has one
+fn panic_message(error: &(dyn Any + Send)) -> &str {
+    if let Some(s) = error.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = error.downcast_ref::<String>() {
+        s
+    } else {
+        "no message"
+    }
+}
+
+/// The number of light groups the film needs to keep track of.
+pub fn light_group_count(settings: &Settings, world: &World) -> usize {
+    if settings.light_groups {
+        world.light_groups.len()
+    } else {
+        0
+    }
+}
+
+pub fn create_sampler(settings: &Settings, mask: &Option<Arc<BlueNoiseMask>>) -> Box<dyn Sampler> {
+    seeded_sampler(settings, mask, settings.seed)
+}
+
+// The sampler `create_sampler` would make, but with another seed
+fn seeded_sampler(settings: &Settings, mask: &Option<Arc<BlueNoiseMask>>, seed: u32) -> Box<dyn Sampler> {
+    match *mask {
+        Some(ref m) => Box::new(DitheredSampler::new(settings.sampler.create(seed), m.clone())),
+        None => settings.sampler.create(seed),
+    }
+}
+
+///
+/// Render the whole image at full quality without a window, one tile
+/// at a time, in rows of tiles from the top. Each finished tile is
+/// handed to `tile_done` as soon as all tiles before it are done, so
+/// only a few rows of tiles are ever held in memory. With adaptive
+/// sampling, each tile stops sampling its converged pixels locally,
+/// and with --quality each tile stops once its own error is low enough.
+///
+pub fn render_tiles<F>(settings: Arc<Settings>, world: Arc<World>, camera: Arc<Camera>,
+                       mask: Option<Arc<BlueNoiseMask>>, mut tile_done: F) -> io::Result<()>
+    where F: FnMut(&RenderResult) -> io::Result<()> {
+    let tiles: Arc<Vec<Tile>> = Arc::new(Tile::cover(settings.width, settings.height, settings.tile_size));
+    let passes = settings.samples.div_ceil(SAMPLES_PER_PASS);
+    let next_tile = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = channel();
+
+    for _ in 0..settings.threads {
+        let settings = settings.clone();
+        let world = world.clone();
+        let camera = camera.clone();
+        let mask = mask.clone();
+        let tiles = tiles.clone();
+        let next_tile = next_tile.clone();
+        let tx = tx.clone();
+
+        thread::spawn(move || {
+            loop {
+                let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                if index >= tiles.len() {
+                    return;
+                }
+
+                let tile = tiles[index];
+                let mut total = render_tile_isolated(tile, 0, &settings, &world, &camera, &mask,
+                                                     &|_, _| true);
+                // The even passes' samples, kept apart from the rest so
+                // --quality can stop the tile once the two agree
+                let mut half: Vec<PixelSamples> = total.data.clone();
+
+                for pass in 1..passes {
+                    if total.failed {
+                        break;
+                    }
+
+                    let active: Vec<bool> = total.data.iter().map(|p| match settings.adaptive {
+                        Some(threshold) => !p.converged(threshold),
+                        None => true,
+                    }).collect();
+
+                    if !active.contains(&true) {
+                        break;
+                    }
+
+                    let result = render_tile_isolated(tile, pass, &settings, &world, &camera, &mask,
+                                                      &|x, y| active[((y - tile.y) * tile.width + x - tile.x) as usize]);
+                    if result.failed {
+                        break;
+                    }
+                    total.merge(&result);
+
+                    if let Some(target) = settings.quality {
+                        if pass & 1 == 0 {
+                            for (h, p) in half.iter_mut().zip(result.data.iter()) {
+                                h.merge(p);
+                            }
+                        }
+                        if film::tile_error(&half, &total.data).is_some_and(|e| e < target) {
+                            break;
+                        }
+                    }
+                }
+
+                if tx.send((index, total)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    drop(tx);
+
+    // Tiles can finish out of order; hold on to them until it's their turn
+    let mut pending: BTreeMap<usize, RenderResult> = BTreeMap::new();
+    let mut done = 0;
+
+    for (index, result) in rx {
+        pending.insert(index, result);
+
+        while let Some(result) = pending.remove(&done) {
+            tile_done(&result)?;
+            done += 1;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// A progressive render running in the background. The worker threads
+/// share a queue of every tile of every pass, from the top of the
+/// image down, one pass after another; each takes the next tile as
+/// soon as it's done with its last and sends it on. Tiles full of
+/// geometry take longer, but no thread is left idle waiting on the
+/// rest. Pixels can be switched off through `active` once they have
+/// converged. The render can be paused, which lets the workers finish
+/// the tiles they're on and then wait. Dropping the render stops the
+/// workers.
+///
+pub struct ProgressiveRender {
+    pub results: Receiver<RenderResult>,
+    pub active: Arc<Vec<AtomicBool>>,
+    pub tiles: usize,
+    stop: Arc<AtomicBool>,
+    // Whether the render is paused, and how the workers waiting
+    // because of it are woken
+    paused: Arc<(Mutex<bool>, Condvar)>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ProgressiveRender {
+    pub fn start(settings: Arc<Settings>, world: Arc<World>, camera: Arc<Camera>,
+                 mask: Option<Arc<BlueNoiseMask>>) -> ProgressiveRender {
+        let whole = Tile { x: 0, y: 0, width: settings.width, height: settings.height };
+        ProgressiveRender::start_region(settings, world, camera, mask, whole, 0)
+    }
+
+    ///
+    /// Like `start`, but only render the pixels in `region`, in the
+    /// tiles that overlap it. Passes are numbered from `first_pass`,
+    /// so that a film which already has that many passes gets new
+    /// samples added to it rather than the same ones again.
+    ///
+    pub fn start_region(settings: Arc<Settings>, world: Arc<World>, camera: Arc<Camera>,
+                        mask: Option<Arc<BlueNoiseMask>>, region: Tile, first_pass: u32) -> ProgressiveRender {
+        let nx = settings.width;
+        let ny = settings.height;
+        let passes = settings.samples.div_ceil(SAMPLES_PER_PASS);
+        let stop = Arc::new(AtomicBool::new(false));
+        let paused = Arc::new((Mutex::new(false), Condvar::new()));
+        let active: Arc<Vec<AtomicBool>> = Arc::new(
+            (0..nx * ny).map(|i| AtomicBool::new(region.contains(i % nx, i / nx))).collect());
+        let mut tiles: Vec<Tile> = Tile::cover(nx, ny, settings.tile_size).into_iter()
+            .filter(|tile| tile.overlaps(&region))
+            .collect();
+        settings.tile_order.arrange(&mut tiles, settings.seed);
+        let tiles: Arc<Vec<Tile>> = Arc::new(tiles);
+        let (tx, rx) = channel();
+        let next_job = Arc::new(AtomicUsize::new(0));
+        // Tiles given up on aren't tried again in later passes
+        let failed: Arc<Vec<AtomicBool>> = Arc::new(tiles.iter().map(|_| AtomicBool::new(false)).collect());
+        let mut workers: Vec<JoinHandle<()>> = Vec::with_capacity(settings.threads as usize);
+
+        for _ in 0..settings.threads {
+            let settings = settings.clone();
+            let sw = world.clone();
+            let sc = camera.clone();
+            let stop = stop.clone();
+            let paused = paused.clone();
+            let tx = tx.clone();
+            let mask = mask.clone();
+            let active = active.clone();
+            let next_job = next_job.clone();
+            let tiles = tiles.clone();
+            let failed = failed.clone();
+            workers.push(thread::spawn(move || {
+                loop {
+                    let (ref lock, ref resumed) = *paused;
+                    let mut waiting = lock.lock().unwrap();
+                    while *waiting && !stop.load(Ordering::Relaxed) {
+                        waiting = resumed.wait(waiting).unwrap();
+                    }
+                    drop(waiting);
+
+                    let job = next_job.fetch_add(1, Ordering::Relaxed);
+                    if job >= passes as usize * tiles.len() || stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    let (pass, index) = (first_pass + (job / tiles.len()) as u32, job % tiles.len());
+                    if failed[index].load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    let result = render_tile_isolated(tiles[index], pass, &settings, &sw, &sc, &mask,
+                                                      &|x, y| active[(y * nx + x) as usize].load(Ordering::Relaxed));
+                    if result.failed {
+                        failed[index].store(true, Ordering::Relaxed);
+                    }
+                    if tx.send(result).is_err() {
+                        return;
+                    }
+                }
+            }));
+        }
+
+        ProgressiveRender { results: rx, active, tiles: tiles.len(), stop, paused, workers }
+    }
+
+    /// Ask the workers to finish up. Tiles already sent can still be read.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.set_paused(false);
+    }
+
+    /// Pause or resume the render. Tiles already being rendered while
+    /// it's paused are still finished and sent.
+    pub fn set_paused(&self, paused: bool) {
+        let (ref lock, ref resumed) = *self.paused;
+        *lock.lock().unwrap() = paused;
+        resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.0.lock().unwrap()
+    }
+
+    ///
+    /// Stop the workers and wait for them to exit. Afterwards they no
+    /// longer hold on to the world, so a caller holding the only other
+    /// reference can edit it with `Arc::get_mut`.
+    ///
+    pub fn finish(mut self) {
+        self.stop();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for ProgressiveRender {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+///
+/// Render straight into an image file, top row first. Since the filter
+/// spreads samples onto neighboring lines, each line is only written
+/// once every row of tiles it can receive samples from is done. Every
+/// tile rendered is also shown to `tile_done`, in the order they're
+/// finished.
+///
+pub fn render_to_stream<F>(settings: Arc<Settings>, world: Arc<World>, camera: Arc<Camera>,
+                           mask: Option<Arc<BlueNoiseMask>>, stream: &mut ImageStream, mut tile_done: F) -> io::Result<()>
+    where F: FnMut(&RenderResult) {
+    let width = settings.width as usize;
+    let reach = (settings.filter.create().radius() - 0.5).ceil().max(0.0) as u32;
+
+    // Lines still collecting samples, keyed by line number
+    let mut lines: BTreeMap<u32, (Vec<Vec3>, Vec<f32>)> = BTreeMap::new();
+    let mut next_line = settings.height;
+    let post = settings.post_chain();
+
+    let write_line = |stream: &mut ImageStream, line: u32, (sum, weight): (Vec<Vec3>, Vec<f32>)| {
+        let mut data: Vec<u8> = Vec::with_capacity(width * 3);
+        for (x, (col, w)) in sum.iter().zip(weight.iter()).enumerate() {
+            film::push_rgb8(&film::filtered_color(col, *w), line as usize * width + x, &post, &mut data);
+        }
+        stream.write_row(&data)
+    };
+
+    render_tiles(settings.clone(), world, camera, mask, |result| {
+        tile_done(result);
+        let splats = &result.splats;
+        let first_column = splats.first_column as usize;
+        let rows = splats.color.chunks(splats.width).zip(splats.weight.chunks(splats.width));
+        for (row, (sum, weight)) in rows.enumerate() {
+            let line = lines.entry(splats.first_line + row as u32).or_insert_with(|| {
+                (vec![Vec3::new(0.0, 0.0, 0.0); width], vec![0.0; width])
+            });
+            for x in 0..splats.width {
+                line.0[first_column + x] += sum[x];
+                line.1[first_column + x] += weight[x];
+            }
+        }
+
+        // Lines are finished from the top down, a row of tiles at a time
+        if !result.tile.ends_row(settings.width) {
+            return Ok(());
+        }
+        while next_line > result.tile.y + reach {
+            next_line -= 1;
+            write_line(stream, next_line, lines.remove(&next_line).unwrap())?;
+        }
+        Ok(())
+    })?;
+
+    while next_line > 0 {
+        next_line -= 1;
+        write_line(stream, next_line, lines.remove(&next_line).unwrap())?;
+    }
+
+    Ok(())
+}
+
+/// Render into an in-memory Film.
+pub fn render_film(settings: Arc<Settings>, world: Arc<World>, camera: Arc<Camera>,
+                   mask: Option<Arc<BlueNoiseMask>>) -> Film {
+    render_film_with_progress(settings, world, camera, mask, |_| ())
+}
+
+///
+/// Like `render_film`, but shows the film to `progress` every time a
+/// tile has been added to it, for keeping an eye on a long render.
+///
+pub fn render_film_with_progress<F>(settings: Arc<Settings>, world: Arc<World>, camera: Arc<Camera>,
+                                    mask: Option<Arc<BlueNoiseMask>>, mut progress: F) -> Film
+    where F: FnMut(&Film) {
+    let mut film = Film::new(settings.width as usize, settings.height as usize,
+                             light_group_count(&settings, &world), settings.half_float);
+
+    render_tiles(settings, world, camera, mask, |result| {
+        film.add_result(result);
+        progress(&film);
+        Ok(())
+    }).unwrap();
+
+    film
+}
+
+///
+/// The distance to the first surface seen through the middle of every
+/// pixel, measured along the view direction, bottom row first. Pixels
+/// that see only sky are infinitely far away. One pinhole ray is
+/// traced per pixel, so this is much quicker than the render itself.
+///
+pub fn render_depth(settings: &Settings, world: &World, camera: &Camera) -> Vec<f32> {
+    let (width, height) = (settings.width as usize, settings.height as usize);
+    let forward: Vec3 = camera.forward();
+    let mut depth: Vec<f32> = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let u: f32 = (x as f32 + 0.5) / width as f32;
+            let v: f32 = (y as f32 + 0.5) / height as f32;
+            let r: Ray = camera.get_ray(u, v);
+
+            depth.push(match world.hit(&r, 0.001, f32::MAX) {
+                Some(h) => Vec3::dot(&(h.p - r.origin()), &forward),
+                None => f32::INFINITY,
+            });
+        }
+    }
+
+    depth
+}
@@ -0,0 +1,109 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use tone_map::ToneMap;
+
+///
+/// How a camera turns the light reaching it into pixel values. The
+/// default is the renderer's usual gamma of 2; the others reproduce
+/// the curves of particular cameras and films, so that a render can
+/// be graded alongside footage shot with them. Log curves keep a wide
+/// range of brightness at the cost of a flat look, and are meant to
+/// be graded afterwards, just like the footage.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResponseCurve {
+    Gamma,
+    Srgb,
+    LogC,
+    SLog3,
+    Cineon,
+    Filmic,
+}
+
+// ARRI LogC (v3) at EI 800
+const LOGC_CUT: f32 = 0.010591;
+const LOGC_A: f32 = 5.555556;
+const LOGC_B: f32 = 0.052272;
+const LOGC_C: f32 = 0.247190;
+const LOGC_D: f32 = 0.385537;
+const LOGC_E: f32 = 5.367655;
+const LOGC_F: f32 = 0.092809;
+
+// Code values of a Cineon scan of a 10-bit negative, for reference
+// white and black
+const CINEON_WHITE: f32 = 685.0;
+const CINEON_BLACK: f32 = 95.0;
+
+impl ResponseCurve {
+    pub fn from_name(name: &str) -> Option<ResponseCurve> {
+        match name {
+            "gamma" => Some(ResponseCurve::Gamma),
+            "srgb" => Some(ResponseCurve::Srgb),
+            "logc" => Some(ResponseCurve::LogC),
+            "slog3" => Some(ResponseCurve::SLog3),
+            "cineon" => Some(ResponseCurve::Cineon),
+            "filmic" => Some(ResponseCurve::Filmic),
+            _ => None,
+        }
+    }
+
+    /// The pixel value, between 0 and 1, for one channel of linear light.
+    pub fn apply(self, x: f32) -> f32 {
+        let x = x.max(0.0);
+
+        let value = match self {
+            ResponseCurve::Gamma => x.sqrt(),
+            ResponseCurve::Srgb => {
+                if x <= 0.0031308 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                }
+            },
+            ResponseCurve::LogC => {
+                if x > LOGC_CUT {
+                    LOGC_C * (LOGC_A * x + LOGC_B).log10() + LOGC_D
+                } else {
+                    LOGC_E * x + LOGC_F
+                }
+            },
+            ResponseCurve::SLog3 => {
+                if x >= 0.01125 {
+                    (420.0 + ((x + 0.01) / (0.18 + 0.01)).log10() * 261.5) / 1023.0
+                } else {
+                    (x * (171.2103 - 95.0) / 0.01125 + 95.0) / 1023.0
+                }
+            },
+            ResponseCurve::Cineon => {
+                // Film density goes up by 0.002 per code value, and a
+                // negative's gamma of 0.6 turns that into 300 code
+                // values per tenfold increase in light
+                let black = 10f32.powf((CINEON_BLACK - CINEON_WHITE) / 300.0);
+                (CINEON_WHITE + 300.0 * (x * (1.0 - black) + black).log10()) / 1023.0
+            },
+            ResponseCurve::Filmic => {
+                // The filmic tone map followed by gamma 2.2
+                ToneMap::Filmic.apply(x).powf(1.0 / 2.2)
+            },
+        };
+
+        value.min(1.0)
+    }
+}
@@ -0,0 +1,116 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::f32::consts;
+
+use accelerator;
+use registry::BoxedObject;
+use sky::Sky;
+use vec3::Vec3;
+
+// Light arriving from the key light, whatever the size of the scene
+const KEY_IRRADIANCE: f32 = 1.5;
+
+///
+/// A ready-made lighting setup, for showing off a scene (or a model
+/// dropped into one) without having to place any lights. The lights
+/// are placed around the objects, scaled to their size, with the
+/// front of the scene towards +z. Very large objects, like a ground
+/// sphere, don't count towards the size.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rig {
+    /// Key, fill and rim lights in front of a dark grey backdrop.
+    Studio,
+    /// A soft, shadowless dome of overcast sky.
+    Overcast,
+    /// A low, warm sun behind and to the left, with a cool fill.
+    Sunset,
+}
+
+///
+/// A light of a rig: its name, where it sits relative to the middle
+/// of the objects in units of their radius, its radius in the same
+/// units, and the color of the light it sheds on them.
+///
+struct RigLight {
+    name: &'static str,
+    offset: [f32; 3],
+    radius: f32,
+    color: [f32; 3],
+}
+
+const STUDIO_LIGHTS: [RigLight; 3] = [
+    RigLight { name: "rig_key", offset: [-2.0, 2.5, 2.0], radius: 1.2, color: [1.0, 0.95, 0.85] },
+    RigLight { name: "rig_fill", offset: [3.0, 0.8, 2.0], radius: 1.5, color: [0.25, 0.27, 0.3] },
+    RigLight { name: "rig_rim", offset: [1.0, 3.0, -3.0], radius: 1.0, color: [0.8, 0.8, 0.8] },
+];
+
+const SUNSET_LIGHTS: [RigLight; 1] = [
+    RigLight { name: "rig_fill", offset: [2.0, 1.0, 3.0], radius: 1.0, color: [0.15, 0.18, 0.25] },
+];
+
+impl Rig {
+    pub fn from_name(name: &str) -> Option<Rig> {
+        match name {
+            "studio" => Some(Rig::Studio),
+            "overcast" => Some(Rig::Overcast),
+            "sunset" => Some(Rig::Sunset),
+            _ => None,
+        }
+    }
+
+    pub fn sky(self) -> Sky {
+        match self {
+            Rig::Studio => Sky::Uniform(Vec3::new(0.05, 0.05, 0.05)),
+            Rig::Overcast => Sky::Overcast,
+            Rig::Sunset => Sky::Sun(Vec3::unit_vector(&Vec3::new(-1.0, 0.07, -1.0))),
+        }
+    }
+
+    ///
+    /// The rig's lights, placed around `objects`, as names, centers,
+    /// radii and emitted colors.
+    ///
+    pub fn lights(self, objects: &[BoxedObject]) -> Vec<(&'static str, Vec3, f32, Vec3)> {
+        let bounds = accelerator::subject_bounds(objects);
+        let (center, size) = if bounds.is_empty() {
+            (Vec3::new(0.0, 0.0, -1.0), 1.0)
+        } else {
+            (bounds.centroid(), (0.5 * bounds.diagonal()).max(0.1))
+        };
+
+        let lights: &[RigLight] = match self {
+            Rig::Studio => &STUDIO_LIGHTS,
+            Rig::Overcast => &[],
+            Rig::Sunset => &SUNSET_LIGHTS,
+        };
+
+        lights.iter().map(|l| {
+            let offset = Vec3::new(l.offset[0], l.offset[1], l.offset[2]);
+            let color = Vec3::new(l.color[0], l.color[1], l.color[2]);
+            // A sphere of radius r and brightness L lights things d
+            // away with about L r^2 / d^2 times pi, so the light
+            // reaching the objects doesn't change with their size
+            let emit = KEY_IRRADIANCE * offset.squared_length() / (consts::PI * l.radius * l.radius) * color;
+            (l.name, center + size * offset, size * l.radius, emit)
+        }).collect()
+    }
+}
@@ -0,0 +1,299 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use rng::Pcg32;
+
+// Sample dimensions are assigned to fixed uses, so that a given
+// dimension of a low-discrepancy sequence always feeds the same
+// decision. Each bounce gets its own block of BSDF dimensions.
+pub const PIXEL_DIMENSION: u32 = 0;
+pub const LENS_DIMENSION: u32 = 2;
+pub const TIME_DIMENSION: u32 = 4;
+pub const WAVELENGTH_DIMENSION: u32 = 5;
+pub const BSDF_DIMENSION: u32 = 6;
+pub const BSDF_DIMENSIONS_PER_BOUNCE: u32 = 3;
+
+pub fn bsdf_dimension(depth: u32) -> u32 {
+    BSDF_DIMENSION + depth * BSDF_DIMENSIONS_PER_BOUNCE
+}
+
+///
+/// A Sampler produces the random numbers used to build a path.
+/// Callers start a sample for a pixel, seek to the dimension block
+/// they own, and then draw values from it in order.
+///
+pub trait Sampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn start_dimension(&mut self, dimension: u32);
+
+    fn next_1d(&mut self) -> f32;
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let u = self.next_1d();
+        let v = self.next_1d();
+        (u, v)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SamplerKind {
+    Random,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "random" => Some(SamplerKind::Random),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+
+    /// Create a sampler whose values depend only on `seed`, the pixel
+    /// and the sample index.
+    pub fn create(self, seed: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Box::new(RandomSampler::new(seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+// Integer hash (PCG output permutation), used to derive
+// per-pixel and per-dimension scrambling seeds.
+pub fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
+pub fn hash_combine(seed: u32, v: u32) -> u32 {
+    hash(seed ^ v.wrapping_add(0x9e37_79b9).wrapping_add(seed << 6).wrapping_add(seed >> 2))
+}
+
+fn pixel_seed(seed: u32, x: u32, y: u32) -> u32 {
+    hash_combine(hash_combine(hash(seed), x), y)
+}
+
+// Map 32 random bits onto [0, 1) without ever rounding up to 1.0
+pub fn to_unit_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 * (1.0 / 16_777_216.0)
+}
+
+///
+/// Independent uniform random numbers. Each pixel gets its own PCG32
+/// stream, reseeded for every sample and dimension, so the numbers
+/// don't depend on which thread renders the pixel, in what order, or
+/// how many numbers were drawn for earlier dimensions.
+///
+pub struct RandomSampler {
+    seed: u32,
+    pixel: u32,
+    index: u32,
+    rng: Pcg32,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u32) -> RandomSampler {
+        RandomSampler { seed, pixel: 0, index: 0, rng: Pcg32::new(seed as u64, 0) }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = pixel_seed(self.seed, x, y);
+        self.index = index;
+        self.start_dimension(0);
+    }
+
+    fn start_dimension(&mut self, dimension: u32) {
+        let seed = (dimension as u64) << 32 | self.index as u64;
+        self.rng = Pcg32::new(seed, self.pixel as u64);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+}
+
+///
+/// The Halton sequence, with a different prime base for every
+/// dimension. Each pixel applies its own random toroidal shift
+/// (Cranley-Patterson rotation) so neighboring pixels don't share
+/// the same sample pattern.
+///
+pub struct HaltonSampler {
+    primes: Vec<u32>,
+    global_seed: u32,
+    seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+const HALTON_DIMENSIONS: usize = 256;
+
+impl HaltonSampler {
+    pub fn new(global_seed: u32) -> HaltonSampler {
+        let mut primes: Vec<u32> = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut candidate: u32 = 2;
+
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes.iter().take_while(|&&p| p * p <= candidate).all(|&p| !candidate.is_multiple_of(p)) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+
+        HaltonSampler { primes, global_seed, seed: 0, index: 0, dimension: 0 }
+    }
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inv_base: f64 = 1.0 / base as f64;
+    let mut inv_base_n: f64 = 1.0;
+    let mut reversed: u64 = 0;
+
+    while index > 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * base as u64 + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+
+    (reversed as f64 * inv_base_n) as f32
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.seed = pixel_seed(self.global_seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn start_dimension(&mut self, dimension: u32) {
+        self.dimension = dimension;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.dimension % HALTON_DIMENSIONS as u32;
+        let base = self.primes[dimension as usize];
+        let shift = to_unit_float(hash_combine(self.seed, self.dimension));
+        self.dimension += 1;
+
+        let value = radical_inverse(base, self.index) + shift;
+        if value >= 1.0 {
+            (value - 1.0).max(0.0)
+        } else {
+            value
+        }
+    }
+}
+
+///
+/// Owen-scrambled Sobol points, following Burley's "Practical
+/// Hash-based Owen Scrambling". Every pair of dimensions uses the
+/// first two Sobol dimensions, decorrelated from the other pairs by
+/// shuffling the sample index and scrambling with a per-pixel,
+/// per-dimension seed. This needs no direction number tables, yet
+/// keeps each 2D projection well stratified.
+///
+pub struct SobolSampler {
+    global_seed: u32,
+    seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(global_seed: u32) -> SobolSampler {
+        SobolSampler { global_seed, seed: 0, index: 0, dimension: 0 }
+    }
+
+    fn dimension_seed(&self, dimension: u32) -> u32 {
+        hash_combine(self.seed, dimension)
+    }
+}
+
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_1(mut index: u32) -> u32 {
+    let mut result: u32 = 0;
+    let mut v: u32 = 1 << 31;
+
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.seed = pixel_seed(self.global_seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn start_dimension(&mut self, dimension: u32) {
+        self.dimension = dimension;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let seed = self.dimension_seed(self.dimension);
+        self.dimension += 1;
+
+        let index = nested_uniform_scramble(self.index, seed);
+        to_unit_float(nested_uniform_scramble(sobol_0(index), hash(seed)))
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let seed = self.dimension_seed(self.dimension);
+        self.dimension += 2;
+
+        let index = nested_uniform_scramble(self.index, seed);
+        let u = nested_uniform_scramble(sobol_0(index), hash(seed));
+        let v = nested_uniform_scramble(sobol_1(index), hash(seed ^ 0x5bd1_e995));
+        (to_unit_float(u), to_unit_float(v))
+    }
+}
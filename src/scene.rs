@@ -0,0 +1,1262 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use vec3::Vec3;
+use accelerator::AcceleratorKind;
+use blackbody;
+use cover;
+use heightfield::Heightfield;
+use import;
+use import::Import;
+use motion::Moved;
+use object_id::{Identified, ObjectIds};
+use hittable::SharedMaterial;
+use registry::{BoxedObject, Registry};
+use post;
+use post::PostStage;
+use rig::Rig;
+use script;
+use shader::{Node, NodeId, ShadedMaterial, ShaderGraph};
+use transform::{Transform, Transformed};
+use visibility::VisibilityRange;
+
+///
+/// A spherical light. Lights are kept apart from the rest of the
+/// scene so they can be edited while rendering.
+///
+#[derive(Clone, Debug)]
+pub struct Light {
+    pub name: String,
+    pub center: Vec3,
+    pub radius: f32,
+    pub emit: Vec3,
+    pub id: u32,
+}
+
+///
+/// A saved camera viewpoint, recalled with one of the number keys
+/// 1 to 9 in the preview window.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Bookmark {
+    pub slot: usize,
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+}
+
+// The number keys bookmarks can be saved under
+pub const BOOKMARK_SLOTS: usize = 9;
+
+enum MaterialDef {
+    Registered { kind: String, params: Vec<f32> },
+    Shaded { albedo: NodeId, specular: NodeId, specular_color: NodeId },
+    Script(PathBuf),
+}
+
+#[derive(Clone)]
+struct ObjectDef {
+    shape: String,
+    material: String,
+    params: Vec<f32>,
+    visibility: Option<Visibility>,
+    velocity: Option<Vec3>,
+    name: Option<String>,
+    hidden: bool,
+    // How far the object has been moved since it was read
+    offset: Vec3,
+    id: u32,
+}
+
+// A named group of objects and other groups, placed by a transform
+// within the group it's in
+#[derive(Clone)]
+struct GroupDef {
+    name: String,
+    transform: Transform,
+    hidden: bool,
+    children: Vec<SceneNode>,
+}
+
+#[derive(Clone)]
+enum SceneNode {
+    Object(ObjectDef),
+    Group(GroupDef),
+}
+
+impl GroupDef {
+    fn new(name: &str, transform: Transform, hidden: bool) -> GroupDef {
+        GroupDef { name: name.to_string(), transform, hidden, children: Vec::new() }
+    }
+
+    // The group of that name within this one
+    fn find(&self, name: &str) -> Option<&GroupDef> {
+        self.children.iter().find_map(|node| match *node {
+            SceneNode::Group(ref g) if g.name == name => Some(g),
+            SceneNode::Group(ref g) => g.find(name),
+            SceneNode::Object(_) => None,
+        })
+    }
+
+    // Whether the group or named object `name` within this one is
+    // hidden, to be changed
+    fn hidden_mut(&mut self, name: &str) -> Option<&mut bool> {
+        self.children.iter_mut().find_map(|node| match *node {
+            SceneNode::Group(ref mut g) => {
+                if g.name == name {
+                    Some(&mut g.hidden)
+                } else {
+                    g.hidden_mut(name)
+                }
+            },
+            SceneNode::Object(ref mut o) if o.name.as_deref() == Some(name) => Some(&mut o.hidden),
+            SceneNode::Object(_) => None,
+        })
+    }
+
+    // The object with ID `id` within this one, to be changed
+    fn object_mut(&mut self, id: u32) -> Option<&mut ObjectDef> {
+        self.children.iter_mut().find_map(|node| match *node {
+            SceneNode::Object(ref mut o) if o.id == id => Some(o),
+            SceneNode::Object(_) => None,
+            SceneNode::Group(ref mut g) => g.object_mut(id),
+        })
+    }
+
+    fn objects(&self) -> Vec<&ObjectDef> {
+        self.children.iter().flat_map(|node| match *node {
+            SceneNode::Object(ref o) => vec![o],
+            SceneNode::Group(ref g) => g.objects(),
+        }).collect()
+    }
+}
+
+// Words that end an object's numbers and start an optional suffix
+const SUFFIXES: [&str; 3] = ["visible", "move", "name"];
+
+// Words that place a group, and the number of numbers they take
+const TRANSFORMS: [(&str, usize); 4] = [("translate", 3), ("rotate", 3), ("scale", 1), ("hidden", 0)];
+
+///
+/// A mistake in a scene file, and where it is: the file (unless the
+/// scene was read from text), the line (0 if the file couldn't be
+/// read at all), and the word on the line it's in, if it's in one
+/// word rather than the line as a whole. Written out as
+/// `file:line:column: message`.
+///
+#[derive(Clone, Debug)]
+pub struct SceneError {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if self.line > 0 {
+            write!(f, "{}:", self.line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, "{}:", column)?;
+        }
+        write!(f, " {}", self.message)
+    }
+}
+
+impl Error for SceneError {}
+
+// A mistake on a line, and the word it's in
+struct LineError<'a> {
+    field: Option<&'a str>,
+    message: String,
+}
+
+impl<'a> From<String> for LineError<'a> {
+    fn from(message: String) -> LineError<'a> {
+        LineError { field: None, message }
+    }
+}
+
+// A mistake in the word `field` of a line
+fn at(field: &str, message: String) -> LineError<'_> {
+    LineError { field: Some(field), message }
+}
+
+impl SceneError {
+    // Where `error` is on line `n` (counting from 0), `text`
+    fn on_line(n: usize, text: &str, error: LineError) -> SceneError {
+        // The words are slices of the line; one that isn't was made up
+        // for a missing word, and has no column
+        let column = error.field.and_then(|field| {
+            let start = (field.as_ptr() as usize).wrapping_sub(text.as_ptr() as usize);
+            match start.checked_add(field.len()) {
+                Some(end) if end <= text.len() => Some(text[..start].chars().count() + 1),
+                _ => None,
+            }
+        });
+        SceneError {
+            file: None,
+            line: n + 1,
+            column,
+            field: error.field.map(|f| f.to_string()),
+            message: error.message,
+        }
+    }
+}
+
+// The distances along a ray between which an object can be seen,
+// and the distance it fades out over at either end
+#[derive(Copy, Clone)]
+struct Visibility {
+    near: f32,
+    far: f32,
+    fade: f32,
+}
+
+///
+/// A scene, as described by a scene file. Scene files are plain text
+/// with one entry per line; blank lines and anything after a `#` are
+/// ignored. Materials are named, and defined before objects use them:
+///
+/// ```text
+/// material <name> <type> <numbers...>
+/// ```
+///
+/// Objects give their shape, the name of their material, and the
+/// numbers the shape needs, and can be limited to being seen between
+/// `near` and `far` units along a ray, fading out over `fade` units
+/// (see VisibilityRange). Objects in animations can move in a
+/// straight line, `x`, `y` and `z` units a second from where they are
+/// at time 0. Each object gets a stable ID (see ObjectIds), from its
+/// name if it's given one:
+///
+/// ```text
+/// <shape> <material> <numbers...> [visible <near> <far> [<fade>]] [move <x> <y> <z>] [name <name>]
+/// ```
+///
+/// Material types and shapes are looked up in a Registry, except for
+/// `shaded` materials, which take the names of nodes of the scene's
+/// shader graph (see ShaderGraph::parse_node) instead of numbers:
+///
+/// ```text
+/// node <name> <type> <inputs...>
+/// material <name> shaded <albedo> [<specular> [<specular color>]]
+/// ```
+///
+/// and `script` materials take the file of a Rhai script (see
+/// script::load_material):
+///
+/// ```text
+/// material <name> script <file>
+/// ```
+///
+/// Lights are given by their name, center, radius and emitted color,
+/// or color temperature (see blackbody::blackbody) and brightness:
+///
+/// ```text
+/// light <name> <x> <y> <z> <radius> <r> <g> <b>
+/// light <name> <x> <y> <z> <radius> <kelvin>K <brightness>
+/// ```
+///
+/// A lighting rig (see Rig) can light the scene, adding its lights to
+/// the scene's own:
+///
+/// ```text
+/// rig <studio|overcast|sunset>
+/// ```
+///
+/// The scene can choose the structure rays find objects with (see
+/// Accelerator), for scenes known to suit one:
+///
+/// ```text
+/// accelerator <auto|none|grid|bvh|kdtree>
+/// ```
+///
+/// The meshes, materials and cameras of a glTF 2.0 file can be brought
+/// in (see import::load_gltf). Its materials are named after the file
+/// and the material, e.g. `car:paint`, and its cameras take the
+/// bookmark slots still free:
+///
+/// ```text
+/// gltf <file>
+/// ```
+///
+/// So can the meshes of an OBJ file with the materials of its MTL files
+/// (see import::load_obj), named the same way, unless a material of
+/// the scene's is given for all of them:
+///
+/// ```text
+/// obj <file> [<material>]
+/// ```
+///
+/// and the mesh of an STL file (see import::load_stl), made of a
+/// material of the scene's:
+///
+/// ```text
+/// stl <file> <material>
+/// ```
+///
+/// or of a PLY file (see import::load_ply), whose vertex colors the
+/// material can use through a `vertex` node:
+///
+/// ```text
+/// ply <file> <material>
+/// ```
+///
+/// Terrain can be made from the heights in an image, or from noise
+/// drawn from a seed (see Heightfield), `<size>` units across and
+/// from `<low>` to `<high>` units up:
+///
+/// ```text
+/// heightfield <material> <file> <size> <low> <high>
+/// heightfield <material> noise <seed> <size> <low> <high>
+/// ```
+///
+/// Part of a scene can be built by a Rhai script (see
+/// script::run_scene), which adds lines to the scene as if they were
+/// written out in its place, drawing random numbers from a seed, 0
+/// unless given:
+///
+/// ```text
+/// script <file> [<seed>]
+/// ```
+///
+/// Another scene file can be read in as if its lines were written in
+/// its place, e.g. a library of materials shared by several scenes.
+/// Files are found relative to the scene file they're named in:
+///
+/// ```text
+/// include <file>
+/// ```
+///
+/// The scene on the cover of "Ray Tracing in One Weekend" can be added,
+/// drawn from a seed (see cover::scene):
+///
+/// ```text
+/// cover <seed>
+/// ```
+///
+/// Objects can be gathered into named groups, which can be nested,
+/// and placed together: scaled, then turned `<x>`, `<y>` and `<z>`
+/// degrees about each axis in that order, then moved. What's in a
+/// group can be copied by an instance of it, which is itself a group,
+/// placed by its own transform rather than the original's, and shown
+/// even if the original is hidden, so that a hidden group can be a
+/// template. Groups and named objects can be hidden where they're
+/// defined or later:
+///
+/// ```text
+/// group <name> [translate <x> <y> <z>] [rotate <x> <y> <z>] [scale <s>] [hidden]
+/// ...
+/// end
+/// instance <group> <name> [translate <x> <y> <z>] [rotate <x> <y> <z>] [scale <s>] [hidden]
+/// hide <name>
+/// ```
+///
+/// Camera bookmarks are given by their slot, from 1 to 9, and where
+/// the camera looks from and at:
+///
+/// ```text
+/// bookmark <slot> <x> <y> <z> <x> <y> <z>
+/// ```
+///
+pub struct Scene {
+    materials: HashMap<String, MaterialDef>,
+    // The objects, in a tree of groups under a root group, and the
+    // groups still being defined, innermost last
+    root: GroupDef,
+    open: Vec<GroupDef>,
+    // How many groups were open when each file being read started, so
+    // an included file can't end the groups of the file including it
+    file_groups: Vec<usize>,
+    graph: ShaderGraph,
+    ids: ObjectIds,
+    pub lights: Vec<Light>,
+    pub bookmarks: Vec<Bookmark>,
+    pub rig: Option<Rig>,
+    pub accelerator: Option<AcceleratorKind>,
+    pub post: Option<Vec<PostStage>>,
+    // The directory files are found relative to, the scene files being
+    // read, outermost first, and the files besides the outermost one
+    // the scene was read from
+    dir: PathBuf,
+    including: Vec<PathBuf>,
+    sources: Vec<PathBuf>,
+}
+
+impl Scene {
+    ///
+    /// Read a scene file. The files it refers to are found relative to
+    /// the directory it's in.
+    ///
+    pub fn load(path: &Path, registry: &Registry) -> Result<Scene, SceneError> {
+        let mut scene = Scene::new();
+        scene.include(path, registry)?;
+        Ok(scene)
+    }
+
+    ///
+    /// Read a scene from the text of a scene file. Every material and
+    /// object is built once, and the files and names they refer to are
+    /// checked, so that mistakes are reported here rather than while
+    /// rendering, with the line and word they're in. Files are found
+    /// relative to the current directory.
+    ///
+    pub fn parse(text: &str, registry: &Registry) -> Result<Scene, SceneError> {
+        let mut scene = Scene::new();
+        scene.parse_text(text, registry)?;
+        Ok(scene)
+    }
+
+    fn new() -> Scene {
+        Scene {
+            materials: HashMap::new(),
+            root: GroupDef::new("", Transform::identity(), false),
+            open: Vec::new(),
+            file_groups: Vec::new(),
+            graph: ShaderGraph::default(),
+            ids: ObjectIds::default(),
+            lights: Vec::new(),
+            bookmarks: Vec::new(),
+            rig: None,
+            accelerator: None,
+            post: None,
+            dir: PathBuf::new(),
+            including: Vec::new(),
+            sources: Vec::new(),
+        }
+    }
+
+    // Read the lines of a scene file into the scene. Groups started in
+    // a file must end in it, and it can only end the groups it started
+    fn parse_text(&mut self, text: &str, registry: &Registry) -> Result<(), SceneError> {
+        let open = self.open.len();
+        self.file_groups.push(open);
+        let result = text.lines().enumerate().try_for_each(|(n, text)| {
+            let line = text.split('#').next().unwrap();
+            self.parse_line(line, registry).map_err(|e| SceneError::on_line(n, text, e))
+        });
+        self.file_groups.pop();
+        result?;
+
+        if self.open.len() > open {
+            return Err(SceneError {
+                file: None,
+                line: text.lines().count(),
+                column: None,
+                field: None,
+                message: format!("group {} has no end", self.open.last().unwrap().name),
+            });
+        }
+        Ok(())
+    }
+
+    // Read the scene file at `path` into the scene, with the files it
+    // refers to relative to it
+    fn include(&mut self, path: &Path, registry: &Registry) -> Result<(), SceneError> {
+        let error = |line: usize, message: String| SceneError {
+            file: Some(path.to_path_buf()),
+            line,
+            column: None,
+            field: None,
+            message,
+        };
+
+        let text = fs::read_to_string(path).map_err(|e| error(0, e.to_string()))?;
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.including.contains(&canonical) {
+            return Err(error(0, "includes itself".to_string()));
+        }
+
+        let dir = path.parent().map(|d| d.to_path_buf()).unwrap_or_default();
+        let outer = mem::replace(&mut self.dir, dir);
+        self.including.push(canonical);
+        let result = self.parse_text(&text, registry);
+        self.including.pop();
+        self.dir = outer;
+
+        result.map_err(|e| SceneError { file: Some(path.to_path_buf()), ..e })
+    }
+
+    // A file the scene refers to, relative to the scene file
+    fn asset(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    ///
+    /// The files the scene was read from besides its own: the scene
+    /// files it includes and the scripts that build it, so they can be
+    /// watched for changes too.
+    ///
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    fn parse_line<'a>(&mut self, line: &'a str, registry: &Registry) -> Result<(), LineError<'a>> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        match fields.first() {
+            None => {},
+            Some(&"material") => {
+                if fields.len() < 3 {
+                    return Err("material needs a name and a type".to_string().into());
+                }
+
+                let def = if fields[2] == "shaded" {
+                    self.parse_shaded(&fields[3..])?
+                } else if fields[2] == "script" {
+                    if fields.len() != 4 {
+                        return Err("script takes the file of a script".to_string().into());
+                    }
+                    let path = self.asset(fields[3]);
+                    script::load_material(&path).map_err(|e| at(fields[3], e))?;
+                    MaterialDef::Script(path)
+                } else {
+                    let params = parse_numbers(&fields[3..])?;
+                    registry.material(fields[2], &params).map_err(|e| at(fields[2], e))?;
+                    MaterialDef::Registered { kind: fields[2].to_string(), params }
+                };
+                self.materials.insert(fields[1].to_string(), def);
+            },
+            Some(&"node") => {
+                if fields.len() < 3 {
+                    return Err("node needs a name and a type".to_string().into());
+                }
+
+                let node = self.graph.parse_node(fields[2], &fields[3..], registry).map_err(|e| at(fields[2], e))?;
+                self.graph.add_named(fields[1], node);
+            },
+            Some(&"light") => {
+                if !self.open.is_empty() {
+                    return Err("lights can't be put in groups".to_string().into());
+                }
+                if fields.len() != 9 && fields.len() != 8 {
+                    return Err("light needs a name, center, radius and color".to_string().into());
+                }
+
+                let v = parse_numbers(&fields[2..6])?;
+                if v[3] <= 0.0 {
+                    return Err(at(fields[5], "light radius must be greater than zero".to_string()));
+                }
+
+                let emit = if fields.len() == 8 {
+                    parse_temperature(fields[6], fields[7])?
+                } else {
+                    let c = parse_numbers(&fields[6..])?;
+                    if let Some(i) = c.iter().position(|&c| c < 0.0) {
+                        return Err(at(fields[6 + i], "light color can't be negative".to_string()));
+                    }
+                    Vec3::new(c[0], c[1], c[2])
+                };
+
+                self.lights.push(Light {
+                    name: fields[1].to_string(),
+                    center: Vec3::new(v[0], v[1], v[2]),
+                    radius: v[3],
+                    emit,
+                    id: self.ids.light(fields[1]),
+                });
+            },
+            Some(&"rig") => {
+                if fields.len() != 2 {
+                    return Err("rig takes the name of a rig".to_string().into());
+                }
+                match Rig::from_name(fields[1]) {
+                    Some(rig) => self.rig = Some(rig),
+                    None => return Err(at(fields[1], format!("Unknown rig: {}", fields[1]))),
+                }
+            },
+            Some(&"accelerator") => {
+                if fields.len() != 2 {
+                    return Err("accelerator takes the name of an accelerator".to_string().into());
+                }
+                match AcceleratorKind::from_name(fields[1]) {
+                    Some(kind) => self.accelerator = Some(kind),
+                    None => return Err(at(fields[1], format!("Unknown accelerator: {}", fields[1]))),
+                }
+            },
+            Some(&"post") => {
+                self.post = Some(post::parse_stages(fields[1..].iter().cloned())?);
+            },
+            Some(&"gltf") => {
+                if fields.len() != 2 {
+                    return Err("gltf takes the file of a glTF scene".to_string().into());
+                }
+                let import = import::load_gltf(&self.asset(fields[1])).map_err(|e| at(fields[1], e))?;
+                self.add_import(import, None);
+            },
+            Some(&"obj") => {
+                if fields.len() != 2 && fields.len() != 3 {
+                    return Err("obj takes the file of a mesh, and optionally a material".to_string().into());
+                }
+                if fields.len() == 3 && !self.materials.contains_key(fields[2]) {
+                    return Err(at(fields[2], format!("Unknown material: {}", fields[2])));
+                }
+                let import = import::load_obj(&self.asset(fields[1])).map_err(|e| at(fields[1], e))?;
+                self.add_import(import, fields.get(2).cloned());
+            },
+            Some(&"stl") => {
+                if fields.len() != 3 {
+                    return Err("stl takes the file of a mesh and a material".to_string().into());
+                }
+                if !self.materials.contains_key(fields[2]) {
+                    return Err(at(fields[2], format!("Unknown material: {}", fields[2])));
+                }
+                let import = import::load_stl(&self.asset(fields[1])).map_err(|e| at(fields[1], e))?;
+                self.add_import(import, Some(fields[2]));
+            },
+            Some(&"ply") => {
+                if fields.len() != 3 {
+                    return Err("ply takes the file of a mesh and a material".to_string().into());
+                }
+                if !self.materials.contains_key(fields[2]) {
+                    return Err(at(fields[2], format!("Unknown material: {}", fields[2])));
+                }
+                let import = import::load_ply(&self.asset(fields[1])).map_err(|e| at(fields[1], e))?;
+                self.add_import(import, Some(fields[2]));
+            },
+            Some(&"heightfield") => {
+                if fields.len() != 6 && fields.len() != 7 {
+                    return Err("heightfield takes a material, an image or noise and a seed, a size, and a low \
+                                and high height".to_string().into());
+                }
+                if !self.materials.contains_key(fields[1]) {
+                    return Err(at(fields[1], format!("Unknown material: {}", fields[1])));
+                }
+                let (heights, rest) = match (fields[2], fields.len()) {
+                    ("noise", 7) => {
+                        let seed = fields[3].parse::<u32>()
+                            .map_err(|_| at(fields[3], format!("Invalid seed: {}", fields[3])))?;
+                        (Heightfield::noise(seed), &fields[4..])
+                    },
+                    ("noise", _) => return Err(at(fields[2], "noise takes a seed".to_string())),
+                    (file, 6) => (Heightfield::load(&self.asset(file)).map_err(|e| at(file, e))?, &fields[3..]),
+                    (file, _) => return Err(at(file, "heightfield takes one image".to_string())),
+                };
+                let v = parse_numbers(rest)?;
+                if v[0] <= 0.0 {
+                    return Err(at(rest[0], "heightfield size must be greater than zero".to_string()));
+                }
+                self.add_import(heights.triangulate(v[0], v[1], v[2]), Some(fields[1]));
+            },
+            Some(&"script") => {
+                if fields.len() != 2 && fields.len() != 3 {
+                    return Err("script takes the file of a script, and optionally a seed".to_string().into());
+                }
+                let seed = match fields.get(2) {
+                    Some(f) => f.parse::<u32>().map_err(|_| at(f, format!("Invalid seed: {}", f)))?,
+                    None => 0,
+                };
+                let path = self.asset(fields[1]);
+                let lines = script::run_scene(&path, seed).map_err(|e| at(fields[1], e))?;
+                self.sources.push(path);
+                for line in lines {
+                    let line = line.split('#').next().unwrap();
+                    self.parse_line(line, registry)
+                        .map_err(|e| at(fields[1], format!("{}: {}: {}", fields[1], line.trim(), e.message)))?;
+                }
+            },
+            Some(&"include") => {
+                if fields.len() != 2 {
+                    return Err("include takes the file of a scene".to_string().into());
+                }
+                let path = self.asset(fields[1]);
+                self.include(&path, registry).map_err(|e| at(fields[1], e.to_string()))?;
+                self.sources.push(path);
+            },
+            Some(&"cover") => {
+                if fields.len() != 2 {
+                    return Err("cover takes a seed".to_string().into());
+                }
+                let seed = fields[1].parse::<u32>().map_err(|_| at(fields[1], format!("Invalid seed: {}", fields[1])))?;
+                for line in cover::scene(seed).lines() {
+                    self.parse_line(line, registry).map_err(|e| at(fields[0], e.message))?;
+                }
+            },
+            Some(&"group") => {
+                if fields.len() < 2 {
+                    return Err("group needs a name".to_string().into());
+                }
+                self.check_name(fields[1])?;
+                let (transform, hidden) = parse_transform(&fields[2..])?;
+                self.open.push(GroupDef::new(fields[1], transform, hidden));
+            },
+            Some(&"end") => {
+                if fields.len() != 1 {
+                    return Err("end takes nothing".to_string().into());
+                }
+                if self.open.len() == self.file_groups.last().cloned().unwrap_or(0) {
+                    return Err(at(fields[0], "end without a group".to_string()));
+                }
+                let group = self.open.pop().unwrap();
+                self.push_node(SceneNode::Group(group));
+            },
+            Some(&"instance") => {
+                if fields.len() < 3 {
+                    return Err("instance needs a group and a name".to_string().into());
+                }
+                self.check_name(fields[2])?;
+                let children = match self.find_group(fields[1]) {
+                    Some(group) => group.children.clone(),
+                    None => return Err(at(fields[1], format!("Unknown group: {}", fields[1]))),
+                };
+                let (transform, hidden) = parse_transform(&fields[3..])?;
+                let mut instance = GroupDef { name: fields[2].to_string(), transform, hidden, children };
+                self.identify_instance(&mut instance, fields[2]);
+                self.push_node(SceneNode::Group(instance));
+            },
+            Some(&"hide") => {
+                if fields.len() != 2 {
+                    return Err("hide takes the name of a group or object".to_string().into());
+                }
+                self.hide(fields[1]).map_err(|e| at(fields[1], e))?;
+            },
+            Some(&"bookmark") => {
+                let bookmark = parse_bookmark(&fields[1..])?;
+                self.bookmarks.retain(|b| b.slot != bookmark.slot);
+                self.bookmarks.push(bookmark);
+            },
+            Some(shape) => {
+                if !registry.has_shape(shape) {
+                    return Err(at(shape, format!("Unknown shape: {}", shape)));
+                }
+                if fields.len() < 2 {
+                    return Err(format!("{} needs a material", shape).into());
+                }
+                if !self.materials.contains_key(fields[1]) {
+                    return Err(at(fields[1], format!("Unknown material: {}", fields[1])));
+                }
+
+                let end = fields[2..].iter().position(|f| SUFFIXES.contains(f)).map_or(fields.len(), |i| i + 2);
+                let params = parse_numbers(&fields[2..end])?;
+
+                let mut visibility = None;
+                let mut velocity = None;
+                let mut name = None;
+                let mut rest = &fields[end..];
+                while let Some((suffix, args)) = rest.split_first() {
+                    let n = args.iter().position(|f| SUFFIXES.contains(f)).unwrap_or(args.len());
+                    match *suffix {
+                        "visible" => visibility = Some(parse_visibility(suffix, &args[..n])?),
+                        "move" => velocity = Some(parse_velocity(suffix, &args[..n])?),
+                        _ if n == 1 => name = Some(args[0]),
+                        _ => return Err(at(suffix, "name takes a single word".to_string())),
+                    }
+                    rest = &args[n..];
+                }
+
+                let id = match name {
+                    Some(name) => {
+                        self.check_name(name)?;
+                        self.ids.named(name).map_err(|e| at(name, e))?
+                    },
+                    None => self.ids.defined(shape, fields[1], &params),
+                };
+
+                let def = ObjectDef {
+                    shape: shape.to_string(),
+                    material: fields[1].to_string(),
+                    params,
+                    visibility,
+                    velocity,
+                    name: name.map(|n| n.to_string()),
+                    hidden: false,
+                    offset: Vec3::new(0.0, 0.0, 0.0),
+                    id,
+                };
+                // Shaded materials aren't evaluated by building the
+                // object, so checking it doesn't need the real graph
+                self.build(&def, registry, &Arc::new(ShaderGraph::default()), &mut HashMap::new(),
+                           &Transform::identity(), 0.0)
+                    .map_err(|e| at(shape, e))?;
+                self.push_node(SceneNode::Object(def));
+            },
+        }
+
+        Ok(())
+    }
+
+    // Add the materials and triangles read from another format, or
+    // give every triangle `material`, and bookmark its cameras in the
+    // slots still free
+    fn add_import(&mut self, import: Import, material: Option<&str>) {
+        for material in &import.materials {
+            let def = match (material.kind.as_str(), material.params.as_slice()) {
+                ("shaded", &[r, g, b, weight, sr, sg, sb]) => MaterialDef::Shaded {
+                    albedo: self.graph.add(Node::Constant(Vec3::new(r, g, b))),
+                    specular: self.graph.add(Node::Constant(Vec3::new(weight, weight, weight))),
+                    specular_color: self.graph.add(Node::Constant(Vec3::new(sr, sg, sb))),
+                },
+                _ => MaterialDef::Registered { kind: material.kind.clone(), params: material.params.clone() },
+            };
+            self.materials.insert(material.name.clone(), def);
+        }
+
+        for (i, params) in import.triangles {
+            let material = match material {
+                Some(m) => m,
+                None => &import.materials[i].name,
+            };
+            let id = self.ids.defined("triangle", material, &params);
+            self.push_node(SceneNode::Object(ObjectDef {
+                shape: "triangle".to_string(),
+                material: material.to_string(),
+                params,
+                visibility: None,
+                velocity: None,
+                name: None,
+                hidden: false,
+                offset: Vec3::new(0.0, 0.0, 0.0),
+                id,
+            }));
+        }
+
+        for (lookfrom, lookat) in import.cameras {
+            match (1..=BOOKMARK_SLOTS).find(|&slot| self.bookmarks.iter().all(|b| b.slot != slot)) {
+                Some(slot) => self.bookmarks.push(Bookmark { slot, lookfrom, lookat }),
+                None => break,
+            }
+        }
+    }
+
+    // Add an object or group to the innermost group being defined, or
+    // to the scene itself
+    fn push_node(&mut self, node: SceneNode) {
+        self.open.last_mut().unwrap_or(&mut self.root).children.push(node);
+    }
+
+    // A group that has been ended, so it can be copied
+    fn find_group(&self, name: &str) -> Option<&GroupDef> {
+        self.open.iter().rev().chain(Some(&self.root)).find_map(|g| g.find(name))
+    }
+
+    // Names are shared by groups and objects, and must be unique
+    fn check_name<'a>(&mut self, name: &'a str) -> Result<(), LineError<'a>> {
+        let taken = self.open.iter().any(|g| g.name == name)
+            || self.open.iter_mut().chain(Some(&mut self.root)).any(|g| g.hidden_mut(name).is_some());
+        if taken {
+            return Err(at(name, format!("Duplicate name: {}", name)));
+        }
+        Ok(())
+    }
+
+    // Give the objects copied into an instance IDs of their own, from
+    // the instance's name and the IDs of the originals, so ID passes
+    // can tell the copies apart. Named objects keep their names, which
+    // then pick out the originals
+    fn identify_instance(&mut self, group: &mut GroupDef, instance: &str) {
+        for node in &mut group.children {
+            match *node {
+                SceneNode::Object(ref mut o) => {
+                    o.id = self.ids.instanced(instance, o.id);
+                    o.name = None;
+                },
+                SceneNode::Group(ref mut g) => {
+                    g.name = format!("{}/{}", instance, g.name);
+                    self.identify_instance(g, instance);
+                },
+            }
+        }
+    }
+
+    ///
+    /// Hide the group or object called `name`, with everything in it,
+    /// e.g. to leave part of a scene out of a render from the command
+    /// line without editing the file.
+    ///
+    pub fn hide(&mut self, name: &str) -> Result<(), String> {
+        match self.open.iter_mut().chain(Some(&mut self.root)).find_map(|g| g.hidden_mut(name)) {
+            Some(hidden) => {
+                *hidden = true;
+                Ok(())
+            },
+            None => Err(format!("Unknown group or object: {}", name)),
+        }
+    }
+
+    fn parse_shaded<'a>(&mut self, args: &[&'a str]) -> Result<MaterialDef, LineError<'a>> {
+        if args.is_empty() || args.len() > 3 {
+            return Err("shaded takes an albedo, and optionally a specular weight and color".to_string().into());
+        }
+
+        let mut input = |arg: &'a str| self.graph.input(arg).map_err(|e| at(arg, e));
+        Ok(MaterialDef::Shaded {
+            albedo: input(args[0])?,
+            specular: input(args.get(1).unwrap_or(&"0"))?,
+            specular_color: input(args.get(2).unwrap_or(&"1"))?,
+        })
+    }
+
+    fn material(&self, def: &MaterialDef, registry: &Registry, graph: &Arc<ShaderGraph>) -> Result<SharedMaterial, String> {
+        match *def {
+            MaterialDef::Registered { ref kind, ref params } => registry.material(kind, params),
+            MaterialDef::Shaded { albedo, specular, specular_color } => {
+                Ok(Arc::new(ShadedMaterial { graph: graph.clone(), albedo, specular, specular_color }))
+            },
+            MaterialDef::Script(ref path) => script::load_material(path),
+        }
+    }
+
+    // Build an object, sharing its material with the other objects
+    // built with `materials`
+    fn build(&self, def: &ObjectDef, registry: &Registry, graph: &Arc<ShaderGraph>,
+             materials: &mut HashMap<String, SharedMaterial>, transform: &Transform,
+             time: f32) -> Result<BoxedObject, String> {
+        let material = match materials.get(&def.material) {
+            Some(m) => m.clone(),
+            None => {
+                let m = match self.materials.get(&def.material) {
+                    Some(m) => self.material(m, registry, graph)?,
+                    None => return Err(format!("Unknown material: {}", def.material)),
+                };
+                materials.insert(def.material.clone(), m.clone());
+                m
+            },
+        };
+
+        let object = registry.shape(&def.shape, &def.params, material)?;
+        let object: BoxedObject = match def.visibility {
+            Some(v) => Box::new(VisibilityRange::new(object, v.near, v.far, v.fade)),
+            None => object,
+        };
+        let object: BoxedObject = match def.velocity {
+            Some(v) if time != 0.0 => Box::new(Moved::new(object, v * time)),
+            _ => object,
+        };
+        // Objects are moved in world space, whatever group they're in
+        let transform = transform.then(&Transform::translate(def.offset));
+        let object: BoxedObject = if transform.is_identity() {
+            object
+        } else {
+            Box::new(Transformed::new(object, transform))
+        };
+        Ok(Box::new(Identified::new(object, def.id)))
+    }
+
+    ///
+    /// Add an object given as a line of a scene file, e.g.
+    /// `sphere red 0 1 0 0.5`, to the scene after it has been read,
+    /// and return its ID.
+    ///
+    pub fn add_object(&mut self, line: &str, registry: &Registry) -> Result<u32, String> {
+        match line.split_whitespace().next() {
+            Some(shape) if registry.has_shape(shape) => {},
+            _ => return Err(format!("Not an object: {}", line)),
+        }
+
+        self.parse_line(line, registry).map_err(|e| e.message)?;
+        match self.root.children.last() {
+            Some(SceneNode::Object(def)) => Ok(def.id),
+            _ => unreachable!("an object line adds an object"),
+        }
+    }
+
+    /// Take an object added with `add_object` out of the scene
+    /// altogether, and hand its ID back, e.g. when it was added by an
+    /// edit that can no longer be redone.
+    pub fn remove_object(&mut self, id: u32) -> Result<(), String> {
+        let position = self.root.children.iter().position(|node| match *node {
+            SceneNode::Object(ref o) => o.id == id,
+            SceneNode::Group(_) => false,
+        });
+        match position {
+            Some(i) => {
+                self.root.children.remove(i);
+                self.ids.release(id);
+                Ok(())
+            },
+            None => Err(format!("Unknown object: {}", id)),
+        }
+    }
+
+    /// Show or hide the object with ID `id`.
+    pub fn show_object(&mut self, id: u32, shown: bool) -> Result<(), String> {
+        self.object_mut(id)?.hidden = !shown;
+        Ok(())
+    }
+
+    /// Move the object with ID `id` to `offset` from where it was read.
+    pub fn place_object(&mut self, id: u32, offset: Vec3) -> Result<(), String> {
+        self.object_mut(id)?.offset = offset;
+        Ok(())
+    }
+
+    /// Make the object with ID `id` of the material called `material`.
+    pub fn set_material(&mut self, id: u32, material: &str) -> Result<(), String> {
+        if !self.materials.contains_key(material) {
+            return Err(format!("Unknown material: {}", material));
+        }
+        self.object_mut(id)?.material = material.to_string();
+        Ok(())
+    }
+
+    /// The material of the object with ID `id`, how far it has been
+    /// moved, and whether it's hidden, or None if the scene has no
+    /// such object.
+    pub fn object(&self, id: u32) -> Option<(&str, Vec3, bool)> {
+        self.root.objects().into_iter().find(|o| o.id == id).map(|o| (o.material.as_str(), o.offset, o.hidden))
+    }
+
+    /// The IDs of the scene's objects, including hidden ones, in the
+    /// order they come in the scene.
+    pub fn object_ids(&self) -> Vec<u32> {
+        self.root.objects().into_iter().map(|o| o.id).collect()
+    }
+
+    /// The names of the scene's materials, in order.
+    pub fn material_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.materials.keys().map(|n| n.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    fn object_mut(&mut self, id: u32) -> Result<&mut ObjectDef, String> {
+        self.root.object_mut(id).ok_or_else(|| format!("Unknown object: {}", id))
+    }
+
+    ///
+    /// Build the scene's objects, not including its lights. This can
+    /// be done as many times as needed, e.g. once per frame, with the
+    /// registry the scene was read with.
+    ///
+    pub fn objects(&self, registry: &Registry) -> Vec<BoxedObject> {
+        self.objects_at(registry, 0.0)
+    }
+
+    /// Build the scene's objects where they are `time` seconds into
+    /// an animation.
+    pub fn objects_at(&self, registry: &Registry, time: f32) -> Vec<BoxedObject> {
+        self.build_objects(registry, time).expect("objects were built when the scene was read")
+    }
+
+    /// Add the lights of a rig, placed around the scene's objects.
+    pub fn add_rig(&mut self, registry: &Registry, rig: Rig) {
+        for (name, center, radius, emit) in rig.lights(&self.objects(registry)) {
+            let id = self.ids.light(name);
+            self.lights.push(Light { name: name.to_string(), center, radius, emit, id });
+        }
+    }
+
+    /// True if any of the scene's objects move.
+    pub fn is_animated(&self) -> bool {
+        self.root.objects().iter().any(|def| def.velocity.is_some())
+    }
+
+    ///
+    /// Build the scene's objects again, reading any script materials
+    /// afresh. Unlike the rest of the scene, the scripts may have
+    /// changed since it was read, and may no longer work.
+    ///
+    pub fn try_objects(&self, registry: &Registry) -> Result<Vec<BoxedObject>, String> {
+        self.build_objects(registry, 0.0)
+    }
+
+    fn build_objects(&self, registry: &Registry, time: f32) -> Result<Vec<BoxedObject>, String> {
+        let graph = Arc::new(self.graph.clone());
+        let mut materials: HashMap<String, SharedMaterial> = HashMap::new();
+
+        self.build_group(&self.root, &Transform::identity(), registry, &graph, &mut materials, time)
+    }
+
+    // Build the objects of a group that isn't hidden, placed by its
+    // transform within `outer`, the transform of the group it's in
+    fn build_group(&self, group: &GroupDef, outer: &Transform, registry: &Registry, graph: &Arc<ShaderGraph>,
+                   materials: &mut HashMap<String, SharedMaterial>, time: f32) -> Result<Vec<BoxedObject>, String> {
+        let mut objects = Vec::new();
+        if group.hidden {
+            return Ok(objects);
+        }
+
+        let transform = group.transform.then(outer);
+        for node in &group.children {
+            match *node {
+                SceneNode::Object(ref def) if !def.hidden => {
+                    objects.push(self.build(def, registry, graph, materials, &transform, time)?);
+                },
+                SceneNode::Object(_) => {},
+                SceneNode::Group(ref g) => objects.extend(self.build_group(g, &transform, registry, graph, materials, time)?),
+            }
+        }
+        Ok(objects)
+    }
+
+    /// The files of the scene's script materials.
+    pub fn scripts(&self) -> Vec<&Path> {
+        self.materials.values().filter_map(|m| match *m {
+            MaterialDef::Script(ref path) => Some(path.as_path()),
+            _ => None,
+        }).collect()
+    }
+}
+
+fn parse_visibility<'a>(suffix: &'a str, fields: &[&'a str]) -> Result<Visibility, LineError<'a>> {
+    // Unlike other numbers, distances can be `inf`, for no limit
+    let v = fields.iter()
+        .map(|f| match f.parse::<f32>() {
+            Ok(v) if !v.is_nan() => Ok(v),
+            _ => Err(at(f, format!("Invalid number: {}", f))),
+        })
+        .collect::<Result<Vec<f32>, _>>()?;
+    if v.len() != 2 && v.len() != 3 {
+        return Err(at(suffix, "visible takes a near and far distance, and optionally a fade distance".to_string()));
+    }
+
+    let visibility = Visibility { near: v[0], far: v[1], fade: v.get(2).cloned().unwrap_or(0.0) };
+    if visibility.near < 0.0 || visibility.far <= visibility.near || visibility.fade < 0.0 {
+        return Err(at(suffix, "visible distances must be positive, with far beyond near".to_string()));
+    }
+
+    Ok(visibility)
+}
+
+// A group's transform, and whether it's hidden
+fn parse_transform<'a>(fields: &[&'a str]) -> Result<(Transform, bool), LineError<'a>> {
+    let (mut scale, mut rotate, mut translate) = (Transform::identity(), Transform::identity(), Transform::identity());
+    let mut hidden = false;
+
+    let mut rest = fields;
+    while let Some((word, args)) = rest.split_first() {
+        let n = match TRANSFORMS.iter().find(|&&(w, _)| w == *word) {
+            Some(&(_, n)) if args.len() >= n => n,
+            Some(&(_, n)) => return Err(at(word, format!("{} takes {} numbers", word, n))),
+            None => return Err(at(word, format!("Unknown group setting: {}", word))),
+        };
+        let v = parse_numbers(&args[..n])?;
+        match *word {
+            "translate" => translate = Transform::translate(Vec3::new(v[0], v[1], v[2])),
+            "rotate" => rotate = Transform::rotate(Vec3::new(v[0], v[1], v[2])),
+            "scale" if v[0] > 0.0 => scale = Transform::scale(v[0]),
+            "scale" => return Err(at(args[0], "scale must be greater than zero".to_string())),
+            _ => hidden = true,
+        }
+        rest = &args[n..];
+    }
+
+    Ok((rotate.then(&scale).then(&translate), hidden))
+}
+
+fn parse_velocity<'a>(suffix: &'a str, fields: &[&'a str]) -> Result<Vec3, LineError<'a>> {
+    let v = parse_numbers(fields)?;
+    if v.len() != 3 {
+        return Err(at(suffix, "move takes a speed along x, y and z".to_string()));
+    }
+
+    Ok(Vec3::new(v[0], v[1], v[2]))
+}
+
+fn parse_bookmark<'a>(fields: &[&'a str]) -> Result<Bookmark, LineError<'a>> {
+    if fields.len() != 7 {
+        return Err("bookmark needs a slot, and where to look from and at".to_string().into());
+    }
+
+    let slot = match fields[0].parse::<usize>() {
+        Ok(slot) if (1..=BOOKMARK_SLOTS).contains(&slot) => slot,
+        _ => return Err(at(fields[0], format!("bookmark slot must be between 1 and {}", BOOKMARK_SLOTS))),
+    };
+
+    let v = parse_numbers(&fields[1..])?;
+    let bookmark = Bookmark { slot, lookfrom: Vec3::new(v[0], v[1], v[2]), lookat: Vec3::new(v[3], v[4], v[5]) };
+
+    // The camera is kept upright, so it can't look straight up or down
+    let view = bookmark.lookat - bookmark.lookfrom;
+    if view.length() < MIN_VIEW_DISTANCE {
+        return Err(at(fields[4], "bookmark must look at a point away from where it looks from".to_string()));
+    }
+    if view.x().hypot(view.z()) < MIN_VIEW_DISTANCE * view.length() {
+        return Err(at(fields[4], "bookmark can't look straight up or down".to_string()));
+    }
+
+    Ok(bookmark)
+}
+
+// How far apart the points a bookmark looks from and at must be, and
+// how far to the side of straight up or down it must look, relatively
+const MIN_VIEW_DISTANCE: f32 = 1e-4;
+
+///
+/// Save a bookmark into the scene file at `path`, replacing the line
+/// of any bookmark already in its slot and leaving the rest of the
+/// file as it was.
+///
+pub fn save_bookmark(path: &Path, bookmark: &Bookmark) -> io::Result<()> {
+    let text = fs::read_to_string(path)?;
+    let line = format!("bookmark {} {} {} {} {} {} {}", bookmark.slot,
+                       bookmark.lookfrom.x(), bookmark.lookfrom.y(), bookmark.lookfrom.z(),
+                       bookmark.lookat.x(), bookmark.lookat.y(), bookmark.lookat.z());
+
+    let slot = bookmark.slot.to_string();
+    let mut lines: Vec<String> = Vec::new();
+    let mut replaced = false;
+
+    for l in text.lines() {
+        let fields: Vec<&str> = l.split('#').next().unwrap().split_whitespace().collect();
+        if fields.len() >= 2 && fields[0] == "bookmark" && fields[1] == slot {
+            if !replaced {
+                lines.push(line.clone());
+                replaced = true;
+            }
+        } else {
+            lines.push(l.to_string());
+        }
+    }
+
+    if !replaced {
+        lines.push(line);
+    }
+
+    fs::write(path, lines.join("\n") + "\n")
+}
+
+fn parse_numbers<'a>(fields: &[&'a str]) -> Result<Vec<f32>, LineError<'a>> {
+    fields.iter()
+        .map(|f| match f.parse::<f32>() {
+            Ok(v) if v.is_finite() => Ok(v),
+            Ok(_) => Err(at(f, format!("Numbers must be finite: {}", f))),
+            Err(_) => Err(at(f, format!("Invalid number: {}", f))),
+        })
+        .collect()
+}
+
+// The color of a light given as a color temperature, like "3200K",
+// and a brightness
+fn parse_temperature<'a>(temperature: &'a str, brightness: &'a str) -> Result<Vec3, LineError<'a>> {
+    let kelvin: f32 = temperature.strip_suffix('K')
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| at(temperature, format!("Invalid color temperature: {}", temperature)))?;
+    if !(blackbody::MIN_KELVIN..=blackbody::MAX_KELVIN).contains(&kelvin) {
+        return Err(at(temperature, format!("Color temperature must be between {}K and {}K", blackbody::MIN_KELVIN,
+                                           blackbody::MAX_KELVIN)));
+    }
+
+    let brightness = parse_numbers(&[brightness])?[0];
+    Ok(brightness * blackbody::blackbody(kelvin))
+}
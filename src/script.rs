@@ -0,0 +1,252 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::path::Path;
+
+use hittable::SharedMaterial;
+
+///
+/// Materials written as Rhai scripts, for trying out ideas without
+/// rebuilding the renderer. The script is run for every ray that hits
+/// the material, which is far slower than a built in material. It
+/// defines one function:
+///
+/// ```text
+/// fn scatter(p, normal, direction, u, v) {
+///     // p, normal and direction are [x, y, z] arrays, and u and v
+///     // are random numbers in [0, 1). Return the color the surface
+///     // lets through and the direction the ray leaves in, or () if
+///     // the ray is absorbed.
+///     #{ attenuation: [r, g, b], direction: [x, y, z] }
+/// }
+/// ```
+///
+/// Scripts need the `scripting` feature.
+///
+pub fn load_material(path: &Path) -> Result<SharedMaterial, String> {
+    imp::load_material(path)
+}
+
+///
+/// Run a Rhai script that builds part of a scene, for arrangements
+/// too large or too regular to write out by hand. Returns the lines
+/// of scene file it adds, in the order it adds them. The script is
+/// run once, top to bottom, and can call:
+///
+/// ```text
+/// material(name, type, [numbers])     // material <name> <type> <numbers...>
+/// sphere(material, [x, y, z], radius)
+/// object(shape, material, [numbers])  // any other shape
+/// light(name, [x, y, z], radius, [r, g, b])
+/// scene(line)                         // any line of a scene file
+/// rand()                              // a random number in [0, 1)
+/// ```
+///
+/// Random numbers are drawn from `seed`, so a script builds the same
+/// scene every time it's run with the same seed.
+///
+/// Scripts need the `scripting` feature.
+///
+pub fn run_scene(path: &Path, seed: u32) -> Result<Vec<String>, String> {
+    imp::run_scene(path, seed)
+}
+
+#[cfg(feature = "scripting")]
+mod imp {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST, FLOAT};
+
+    use vec3::Vec3;
+    use ray::Ray;
+    use hittable::*;
+    use rng::Pcg32;
+    use sampler::Sampler;
+
+    // The PCG stream scene scripts draw random numbers from
+    const SCENE_STREAM: u64 = 0x5c7;
+
+    struct ScriptMaterial {
+        name: String,
+        engine: Engine,
+        ast: AST,
+        // Errors happen for every ray, so only the first is reported
+        failed: AtomicBool,
+    }
+
+    pub fn load_material(path: &Path) -> Result<SharedMaterial, String> {
+        let engine = Engine::new();
+        let ast = engine.compile_file(path.to_path_buf()).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(Arc::new(ScriptMaterial {
+            name: path.display().to_string(),
+            engine,
+            ast,
+            failed: AtomicBool::new(false),
+        }))
+    }
+
+    fn to_array(v: &Vec3) -> Array {
+        vec![Dynamic::from(v.x() as FLOAT), Dynamic::from(v.y() as FLOAT), Dynamic::from(v.z() as FLOAT)]
+    }
+
+    fn to_vec3(value: Option<&Dynamic>) -> Option<Vec3> {
+        let array = value?.clone().try_cast::<Array>()?;
+        if array.len() != 3 {
+            return None;
+        }
+
+        let mut e = [0.0; 3];
+        for (x, d) in e.iter_mut().zip(array.iter()) {
+            *x = d.as_float().ok().or_else(|| d.as_int().ok().map(|i| i as FLOAT))? as f32;
+        }
+        Some(Vec3::new(e[0], e[1], e[2]))
+    }
+
+    // A number given to a scene function, which may have been
+    // written as an integer
+    fn number(d: &Dynamic) -> Result<f32, Box<EvalAltResult>> {
+        match d.as_float().ok().or_else(|| d.as_int().ok().map(|i| i as FLOAT)) {
+            Some(x) => Ok(x as f32),
+            None => Err(format!("expected a number, not {}", d.type_name()).into()),
+        }
+    }
+
+    // The numbers of an array, written out as they are in scene files
+    fn numbers(array: &Array, len: Option<usize>) -> Result<String, Box<EvalAltResult>> {
+        if let Some(len) = len {
+            if array.len() != len {
+                return Err(format!("expected an array of {} numbers", len).into());
+            }
+        }
+        let numbers = array.iter().map(number).collect::<Result<Vec<f32>, _>>()?;
+        Ok(numbers.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(" "))
+    }
+
+    pub fn run_scene(path: &Path, seed: u32) -> Result<Vec<String>, String> {
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let rng = Arc::new(Mutex::new(Pcg32::new(u64::from(seed), SCENE_STREAM)));
+        let mut engine = Engine::new();
+
+        let out = lines.clone();
+        engine.register_fn("material", move |name: &str, kind: &str, params: Array| {
+            let line = format!("material {} {} {}", name, kind, numbers(&params, None)?);
+            out.lock().unwrap().push(line);
+            Ok::<(), Box<EvalAltResult>>(())
+        });
+        let out = lines.clone();
+        engine.register_fn("sphere", move |material: &str, center: Array, radius: Dynamic| {
+            let line = format!("sphere {} {} {}", material, numbers(&center, Some(3))?, number(&radius)?);
+            out.lock().unwrap().push(line);
+            Ok::<(), Box<EvalAltResult>>(())
+        });
+        let out = lines.clone();
+        engine.register_fn("object", move |shape: &str, material: &str, params: Array| {
+            let line = format!("{} {} {}", shape, material, numbers(&params, None)?);
+            out.lock().unwrap().push(line);
+            Ok::<(), Box<EvalAltResult>>(())
+        });
+        let out = lines.clone();
+        engine.register_fn("light", move |name: &str, center: Array, radius: Dynamic, color: Array| {
+            let line = format!("light {} {} {} {}", name, numbers(&center, Some(3))?, number(&radius)?,
+                               numbers(&color, Some(3))?);
+            out.lock().unwrap().push(line);
+            Ok::<(), Box<EvalAltResult>>(())
+        });
+        let out = lines.clone();
+        engine.register_fn("scene", move |line: &str| out.lock().unwrap().push(line.to_string()));
+        engine.register_fn("rand", move || rng.lock().unwrap().next_f32() as FLOAT);
+
+        engine.run_file(path.to_path_buf()).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let lines = lines.lock().unwrap().clone();
+        Ok(lines)
+    }
+
+    impl ScriptMaterial {
+        fn call(&self, r_in: &Ray, hit: &Hit, (u, v): (f32, f32)) -> Result<Option<(Vec3, Vec3)>, String> {
+            let args = (to_array(&hit.p), to_array(&hit.normal), to_array(&r_in.direction()), u as FLOAT, v as FLOAT);
+            let result: Dynamic = self.engine.call_fn(&mut Scope::new(), &self.ast, "scatter", args)
+                .map_err(|e| e.to_string())?;
+
+            if result.is_unit() {
+                return Ok(None);
+            }
+
+            let map = result.try_cast::<Map>().ok_or("scatter must return a map or ()")?;
+            match (to_vec3(map.get("attenuation")), to_vec3(map.get("direction"))) {
+                (Some(attenuation), Some(direction)) => Ok(Some((attenuation, direction))),
+                _ => Err("scatter must return an attenuation and a direction, as [x, y, z] arrays".to_string()),
+            }
+        }
+    }
+
+    impl Material for ScriptMaterial {
+        fn scatter(&self, r_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Reflection {
+            match self.call(r_in, hit, sampler.next_2d()) {
+                Ok(Some((attenuation, direction))) => Reflection {
+                    scattered: Ray::new(hit.p, direction),
+                    attenuation,
+                    reflected: true,
+                },
+                result => {
+                    if let Err(e) = result {
+                        if !self.failed.swap(true, Ordering::Relaxed) {
+                            eprintln!("{}: {}", self.name, e);
+                        }
+                    }
+
+                    Reflection {
+                        scattered: Ray::new(hit.p, hit.normal),
+                        attenuation: Vec3::new(0.0, 0.0, 0.0),
+                        reflected: false,
+                    }
+                },
+            }
+        }
+
+        // Scripts can vary their color as they like; this is just a
+        // stand-in
+        fn albedo(&self) -> Vec3 {
+            Vec3::new(0.5, 0.5, 0.5)
+        }
+
+        // Skip running the script while the camera moves
+        fn preview_scatter(&self, r_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Reflection {
+            Lambertian::new(self.albedo()).scatter(r_in, hit, sampler)
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod imp {
+    use std::path::Path;
+
+    use hittable::SharedMaterial;
+
+    pub fn load_material(path: &Path) -> Result<SharedMaterial, String> {
+        Err(format!("{}: script materials need the renderer to be built with --features scripting", path.display()))
+    }
+
+    pub fn run_scene(path: &Path, _: u32) -> Result<Vec<String>, String> {
+        Err(format!("{}: scene scripts need the renderer to be built with --features scripting", path.display()))
+    }
+}
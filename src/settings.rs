@@ -0,0 +1,1255 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::str::FromStr;
+
+use rand;
+
+use accelerator::AcceleratorKind;
+use aov::Aov;
+use blackbody;
+use camera::FisheyeMapping;
+use dither::Dither;
+use filter::FilterKind;
+use lut::Lut;
+use machine::Machine;
+use memory;
+use metering::Metering;
+use output::ImageFormat;
+use post;
+use post::{ColorGrade, PostChain, PostStage};
+use response::ResponseCurve;
+use rig::Rig;
+use sampler::SamplerKind;
+use tile::TileOrder;
+use stereo::StereoLayout;
+use tone_map::ToneMap;
+use vec3::Vec3;
+use video;
+
+const DEFAULT_WIDTH: u32 = 640;
+const DEFAULT_HEIGHT: u32 = 480;
+const DEFAULT_SAMPLES: u32 = 100;
+const DEFAULT_MAX_DEPTH: u32 = 50;
+
+// Reference renders run long enough, and let paths bounce for long
+// enough, that what's left is noise rather than missing light
+const REFERENCE_SAMPLES: u32 = 4096;
+const REFERENCE_MAX_DEPTH: u32 = 1000;
+
+// Time-lapse defaults: mid-northern latitudes on the June solstice
+const DEFAULT_LATITUDE: f32 = 40.0;
+const DEFAULT_DAY: u32 = 172;
+
+// Seconds between updates of a --proxy image
+const DEFAULT_PROXY_INTERVAL: f32 = 10.0;
+
+// Flythroughs play at the usual film frame rate
+const DEFAULT_FPS: f32 = 24.0;
+
+// Glare spreads light from anything brighter than this
+const DEFAULT_GLARE_THRESHOLD: f32 = 1.0;
+
+// Bloom spreads light from anything brighter than this, over a few
+// hundredths of the image's diagonal
+const DEFAULT_BLOOM_THRESHOLD: f32 = 1.0;
+const DEFAULT_BLOOM_RADIUS: f32 = 0.01;
+
+// Below this, a black body has almost no blue left to white balance
+const MIN_WHITE_BALANCE: f32 = 2000.0;
+
+// Exposure that leaves radiance unchanged: ISO 100, one second at f/1
+const BASE_ISO: f32 = 100.0;
+
+// Sample cap used when only a quality target is given
+const QUALITY_SAMPLE_CAP: u32 = 65536;
+
+///
+/// Render settings, as supplied on the command line.
+///
+#[derive(Clone)]
+pub struct Settings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub max_depth: u32,
+    pub simple_preview: bool,
+    pub resize_render: bool,
+    // Set on the settings for passes rendered while the camera moves,
+    // when --simple-preview is given
+    pub simple_materials: bool,
+    pub spectral: bool,
+    pub reference: bool,
+    pub quality: Option<f32>,
+    pub adaptive: Option<f32>,
+    pub sampler: SamplerKind,
+    pub filter: FilterKind,
+    pub accelerator: AcceleratorKind,
+    pub bvh_cache: Option<PathBuf>,
+    pub seed: u32,
+    pub threads: u32,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub max_memory: Option<u64>,
+    pub blue_noise: bool,
+    pub half_float: bool,
+    pub scene: Option<PathBuf>,
+    pub cover: Option<u32>,
+    pub hide: Vec<String>,
+    pub output: Option<PathBuf>,
+    pub bit_depth: u32,
+    pub transparent: bool,
+    pub heatmap: Option<PathBuf>,
+    pub proxy: Option<PathBuf>,
+    pub proxy_interval: f32,
+    pub save_every: Option<f32>,
+    pub progress_json: bool,
+    pub edit_log: Option<PathBuf>,
+    pub stream: bool,
+    pub panorama: Option<f32>,
+    pub orthographic: Option<f32>,
+    pub fisheye: Option<f32>,
+    pub fisheye_mapping: FisheyeMapping,
+    pub equirectangular: bool,
+    pub lens_shift: (f32, f32),
+    pub aperture: Option<f32>,
+    pub focus_distance: Option<f32>,
+    pub tilt: f32,
+    pub post_dof: bool,
+    pub denoise: bool,
+    pub denoise_preview: bool,
+    pub oidn: bool,
+    pub lens: Option<PathBuf>,
+    pub stereo: Option<f32>,
+    pub convergence: Option<f32>,
+    pub stereo_layout: StereoLayout,
+    pub iso: f32,
+    pub shutter: f32,
+    pub f_stop: f32,
+    pub response: ResponseCurve,
+    pub tone_map: ToneMap,
+    pub compensation: f32,
+    pub auto_exposure: Option<Metering>,
+    pub white_balance: Option<f32>,
+    pub tint: f32,
+    pub post: Option<Vec<PostStage>>,
+    pub grade: Option<ColorGrade>,
+    pub lut: Option<Arc<Lut>>,
+    pub quantize: Option<u32>,
+    pub dither: Dither,
+    pub vignette: Option<f32>,
+    pub grain: Option<f32>,
+    pub glare: Option<f32>,
+    pub glare_threshold: f32,
+    pub bloom: Option<f32>,
+    pub bloom_threshold: f32,
+    pub bloom_radius: f32,
+    pub blades: Option<u32>,
+    pub blade_rotation: f32,
+    pub lens_dirt: Option<PathBuf>,
+    pub time_lapse: Option<u32>,
+    pub camera_path: Option<PathBuf>,
+    pub frames: Option<u32>,
+    pub turntable: Option<u32>,
+    pub fps: f32,
+    pub latitude: f32,
+    pub day: u32,
+    pub rig: Option<Rig>,
+    pub clouds: Option<f32>,
+    pub cloud_density: f32,
+    pub light_groups: bool,
+    pub aovs: Vec<Aov>,
+    pub clamp_direct: Option<f32>,
+    pub clamp_indirect: Option<f32>,
+    pub mix: Vec<(PathBuf, Vec3)>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            samples: DEFAULT_SAMPLES,
+            max_depth: DEFAULT_MAX_DEPTH,
+            simple_preview: false,
+            resize_render: false,
+            simple_materials: false,
+            spectral: false,
+            reference: false,
+            quality: None,
+            adaptive: None,
+            sampler: SamplerKind::Random,
+            filter: FilterKind::Box,
+            accelerator: AcceleratorKind::Auto,
+            bvh_cache: None,
+            seed: 0,
+            threads: Machine::detect().default_threads(),
+            tile_size: 32,
+            tile_order: TileOrder::Rows,
+            max_memory: None,
+            blue_noise: false,
+            half_float: false,
+            scene: None,
+            cover: None,
+            hide: Vec::new(),
+            output: None,
+            bit_depth: 8,
+            transparent: false,
+            heatmap: None,
+            proxy: None,
+            proxy_interval: DEFAULT_PROXY_INTERVAL,
+            save_every: None,
+            progress_json: false,
+            edit_log: None,
+            stream: false,
+            panorama: None,
+            orthographic: None,
+            fisheye: None,
+            fisheye_mapping: FisheyeMapping::Equidistant,
+            equirectangular: false,
+            lens_shift: (0.0, 0.0),
+            aperture: None,
+            focus_distance: None,
+            tilt: 0.0,
+            post_dof: false,
+            denoise: false,
+            denoise_preview: false,
+            oidn: false,
+            lens: None,
+            stereo: None,
+            convergence: None,
+            stereo_layout: StereoLayout::SideBySide,
+            iso: BASE_ISO,
+            shutter: 1.0,
+            f_stop: 1.0,
+            response: ResponseCurve::Gamma,
+            tone_map: ToneMap::Clamp,
+            compensation: 0.0,
+            auto_exposure: None,
+            white_balance: None,
+            tint: 0.0,
+            post: None,
+            grade: None,
+            lut: None,
+            quantize: None,
+            dither: Dither::BlueNoise,
+            vignette: None,
+            grain: None,
+            glare: None,
+            glare_threshold: DEFAULT_GLARE_THRESHOLD,
+            bloom: None,
+            bloom_threshold: DEFAULT_BLOOM_THRESHOLD,
+            bloom_radius: DEFAULT_BLOOM_RADIUS,
+            blades: None,
+            blade_rotation: 0.0,
+            lens_dirt: None,
+            time_lapse: None,
+            camera_path: None,
+            frames: None,
+            turntable: None,
+            fps: DEFAULT_FPS,
+            latitude: DEFAULT_LATITUDE,
+            day: DEFAULT_DAY,
+            rig: None,
+            clouds: None,
+            cloud_density: 1.0,
+            light_groups: false,
+            aovs: Vec::new(),
+            clamp_direct: None,
+            clamp_indirect: None,
+            mix: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        let mut samples: Option<u32> = None;
+        let mut max_depth: Option<u32> = None;
+        let mut seed: Option<u32> = None;
+        let mut tile_size: Option<u32> = None;
+        let mut args = args.skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--width" => {
+                    settings.width = parse_value(&arg, args.next())?;
+                },
+                "--height" => {
+                    settings.height = parse_value(&arg, args.next())?;
+                },
+                "--samples" => {
+                    samples = Some(parse_value(&arg, args.next())?);
+                },
+                "--max-depth" => {
+                    max_depth = Some(parse_value(&arg, args.next())?);
+                },
+                "--simple-preview" => {
+                    settings.simple_preview = true;
+                },
+                "--resize-render" => {
+                    settings.resize_render = true;
+                },
+                "--max-memory" => {
+                    let size: String = parse_value(&arg, args.next())?;
+                    settings.max_memory = Some(memory::parse_size(&size).ok_or_else(|| format!("Invalid value for {}: {}", arg, size))?);
+                },
+                "--threads" => {
+                    settings.threads = parse_value(&arg, args.next())?;
+                    if settings.threads == 0 {
+                        return Err(format!("{} must be at least 1", arg));
+                    }
+                },
+                "--tile-size" => {
+                    let size: u32 = parse_value(&arg, args.next())?;
+                    if size == 0 {
+                        return Err(format!("{} must be at least 1", arg));
+                    }
+                    tile_size = Some(size);
+                },
+                "--tile-order" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.tile_order = match TileOrder::from_name(&name) {
+                        Some(order) => order,
+                        None => return Err(format!("Unknown tile order: {}", name)),
+                    };
+                },
+                "--reference" => {
+                    settings.reference = true;
+                },
+                "--quality" => {
+                    let quality: f32 = parse_value(&arg, args.next())?;
+                    if quality <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    settings.quality = Some(quality);
+                },
+                "--adaptive" => {
+                    let threshold: f32 = parse_value(&arg, args.next())?;
+                    if threshold <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    settings.adaptive = Some(threshold);
+                },
+                "--sampler" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.sampler = match SamplerKind::from_name(&name) {
+                        Some(kind) => kind,
+                        None => return Err(format!("Unknown sampler: {}", name)),
+                    };
+                },
+                "--filter" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.filter = match FilterKind::from_name(&name) {
+                        Some(kind) => kind,
+                        None => return Err(format!("Unknown filter: {}", name)),
+                    };
+                },
+                "--accelerator" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.accelerator = match AcceleratorKind::from_name(&name) {
+                        Some(kind) => kind,
+                        None => return Err(format!("Unknown accelerator: {}", name)),
+                    };
+                },
+                "--bvh-cache" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.bvh_cache = Some(PathBuf::from(path));
+                },
+                "--seed" => {
+                    seed = Some(parse_value(&arg, args.next())?);
+                },
+                "--blue-noise" => {
+                    settings.blue_noise = true;
+                },
+                "--half-float" => {
+                    settings.half_float = true;
+                },
+                "--scene" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.scene = Some(PathBuf::from(path));
+                },
+                "--cover" => {
+                    settings.cover = Some(parse_value(&arg, args.next())?);
+                },
+                "--hide" => {
+                    settings.hide.push(parse_value(&arg, args.next())?);
+                },
+                "--output" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.output = Some(PathBuf::from(path));
+                },
+                "--bit-depth" => {
+                    settings.bit_depth = parse_value(&arg, args.next())?;
+                    if settings.bit_depth != 8 && settings.bit_depth != 16 {
+                        return Err(format!("{} must be 8 or 16", arg));
+                    }
+                },
+                "--spectral" => {
+                    settings.spectral = true;
+                },
+                "--transparent" => {
+                    settings.transparent = true;
+                },
+                "--heatmap" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.heatmap = Some(PathBuf::from(path));
+                },
+                "--proxy" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.proxy = Some(PathBuf::from(path));
+                },
+                "--proxy-interval" => {
+                    settings.proxy_interval = parse_value(&arg, args.next())?;
+                    if settings.proxy_interval <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                },
+                "--save-every" => {
+                    let seconds: f32 = parse_value(&arg, args.next())?;
+                    if seconds <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    settings.save_every = Some(seconds);
+                },
+                "--progress-json" => {
+                    settings.progress_json = true;
+                },
+                "--edit-log" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.edit_log = Some(PathBuf::from(path));
+                },
+                "--stream" => {
+                    settings.stream = true;
+                },
+                "--panorama" => {
+                    let degrees: f32 = parse_value(&arg, args.next())?;
+                    if degrees <= 0.0 || degrees > 360.0 {
+                        return Err(format!("{} must be between 0 and 360 degrees", arg));
+                    }
+                    settings.panorama = Some(degrees);
+                },
+                "--orthographic" => {
+                    let height: f32 = parse_value(&arg, args.next())?;
+                    if height <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    settings.orthographic = Some(height);
+                },
+                "--fisheye" => {
+                    let degrees: f32 = parse_value(&arg, args.next())?;
+                    if degrees <= 0.0 || degrees > 360.0 {
+                        return Err(format!("{} must be greater than 0 and at most 360", arg));
+                    }
+                    settings.fisheye = Some(degrees);
+                },
+                "--fisheye-mapping" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.fisheye_mapping = match FisheyeMapping::from_name(&name) {
+                        Some(mapping) => mapping,
+                        None => return Err(format!("Unknown fisheye mapping: {}", name)),
+                    };
+                },
+                "--equirectangular" => {
+                    settings.equirectangular = true;
+                },
+                "--lens-shift" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    settings.lens_shift = parse_pair(&value).ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?;
+                },
+                "--aperture" | "--focus-distance" => {
+                    let distance: f32 = parse_value(&arg, args.next())?;
+                    if distance <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    if arg == "--aperture" {
+                        settings.aperture = Some(distance);
+                    } else {
+                        settings.focus_distance = Some(distance);
+                    }
+                },
+                "--tilt" => {
+                    let degrees: f32 = parse_value(&arg, args.next())?;
+                    if degrees.abs() >= 90.0 {
+                        return Err(format!("{} must be between -90 and 90", arg));
+                    }
+                    settings.tilt = degrees;
+                },
+                "--post-dof" => {
+                    settings.post_dof = true;
+                },
+                "--denoise" => {
+                    settings.denoise = true;
+                },
+                "--denoise-preview" => {
+                    settings.denoise_preview = true;
+                },
+                "--oidn" => {
+                    settings.denoise = true;
+                    settings.oidn = true;
+                },
+                "--lens" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.lens = Some(PathBuf::from(path));
+                },
+                "--stereo" | "--convergence" => {
+                    let distance: f32 = parse_value(&arg, args.next())?;
+                    if distance <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    if arg == "--stereo" {
+                        settings.stereo = Some(distance);
+                    } else {
+                        settings.convergence = Some(distance);
+                    }
+                },
+                "--stereo-layout" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.stereo_layout = match StereoLayout::from_name(&name) {
+                        Some(layout) => layout,
+                        None => return Err(format!("Unknown stereo layout: {}", name)),
+                    };
+                },
+                "--iso" | "--f-stop" => {
+                    let value: f32 = parse_value(&arg, args.next())?;
+                    if value <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    if arg == "--iso" {
+                        settings.iso = value;
+                    } else {
+                        settings.f_stop = value;
+                    }
+                },
+                "--shutter" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    settings.shutter = match parse_shutter(&value) {
+                        Some(seconds) if seconds > 0.0 => seconds,
+                        _ => return Err(format!("Invalid value for {}: {}", arg, value)),
+                    };
+                },
+                "--response" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.response = match ResponseCurve::from_name(&name) {
+                        Some(curve) => curve,
+                        None => return Err(format!("Unknown response curve: {}", name)),
+                    };
+                },
+                "--tone-map" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.tone_map = match ToneMap::from_name(&name) {
+                        Some(tone_map) => tone_map,
+                        None => return Err(format!("Unknown tone map: {}", name)),
+                    };
+                },
+                "--ev" => {
+                    settings.compensation = parse_value(&arg, args.next())?;
+                },
+                "--auto-exposure" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.auto_exposure = match Metering::from_name(&name) {
+                        Some(metering) => Some(metering),
+                        None => return Err(format!("Unknown metering: {}", name)),
+                    };
+                },
+                "--white-balance" => {
+                    let kelvin: f32 = parse_value(&arg, args.next())?;
+                    if !(MIN_WHITE_BALANCE..=blackbody::MAX_KELVIN).contains(&kelvin) {
+                        return Err(format!("{} must be between {} and {}", arg, MIN_WHITE_BALANCE,
+                                           blackbody::MAX_KELVIN));
+                    }
+                    settings.white_balance = Some(kelvin);
+                },
+                "--tint" => {
+                    let tint: f32 = parse_value(&arg, args.next())?;
+                    if !(-1.0..=1.0).contains(&tint) {
+                        return Err(format!("{} must be between -1 and 1", arg));
+                    }
+                    settings.tint = tint;
+                },
+                "--post" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    settings.post = Some(post::parse_stages(value.split(',').map(|s| s.trim()))?);
+                },
+                "--slope" | "--offset" | "--power" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    let color = parse_color(&value).ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?;
+                    let grade = settings.grade.get_or_insert_with(ColorGrade::default);
+                    match arg.as_str() {
+                        "--slope" => grade.slope = color,
+                        "--offset" => grade.offset = color,
+                        _ => grade.power = color,
+                    }
+                },
+                "--saturation" => {
+                    let saturation: f32 = parse_value(&arg, args.next())?;
+                    if saturation < 0.0 {
+                        return Err(format!("{} can't be negative", arg));
+                    }
+                    settings.grade.get_or_insert_with(ColorGrade::default).saturation = saturation;
+                },
+                "--lut" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.lut = Some(Arc::new(Lut::load(Path::new(&path))?));
+                },
+                "--quantize" => {
+                    let bits: u32 = parse_value(&arg, args.next())?;
+                    if !(1..=16).contains(&bits) {
+                        return Err(format!("{} must be between 1 and 16", arg));
+                    }
+                    settings.quantize = Some(bits);
+                },
+                "--dither" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.dither = match Dither::from_name(&name) {
+                        Some(dither) => dither,
+                        None => return Err(format!("Unknown dither: {}", name)),
+                    };
+                },
+                "--vignette" => {
+                    let strength: f32 = parse_value(&arg, args.next())?;
+                    if !(0.0..=1.0).contains(&strength) {
+                        return Err(format!("{} must be between 0 and 1", arg));
+                    }
+                    settings.vignette = Some(strength);
+                },
+                "--grain" => {
+                    let strength: f32 = parse_value(&arg, args.next())?;
+                    if strength <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    settings.grain = Some(strength);
+                },
+                "--glare" | "--glare-threshold" => {
+                    let value: f32 = parse_value(&arg, args.next())?;
+                    if value <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    if arg == "--glare" {
+                        settings.glare = Some(value);
+                    } else {
+                        settings.glare_threshold = value;
+                    }
+                },
+                "--bloom" | "--bloom-threshold" | "--bloom-radius" => {
+                    let value: f32 = parse_value(&arg, args.next())?;
+                    if value <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    match arg.as_str() {
+                        "--bloom" => settings.bloom = Some(value),
+                        "--bloom-threshold" => settings.bloom_threshold = value,
+                        _ => settings.bloom_radius = value,
+                    }
+                },
+                "--blades" => {
+                    let blades: u32 = parse_value(&arg, args.next())?;
+                    if blades < 3 {
+                        return Err(format!("{} must be at least 3", arg));
+                    }
+                    settings.blades = Some(blades);
+                },
+                "--blade-rotation" => {
+                    settings.blade_rotation = parse_value(&arg, args.next())?;
+                },
+                "--lens-dirt" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.lens_dirt = Some(PathBuf::from(path));
+                },
+                "--time-lapse" => {
+                    let frames: u32 = parse_value(&arg, args.next())?;
+                    if frames == 0 {
+                        return Err(format!("{} must be at least 1", arg));
+                    }
+                    settings.time_lapse = Some(frames);
+                },
+                "--camera-path" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    settings.camera_path = Some(PathBuf::from(path));
+                },
+                "--frames" => {
+                    let frames: u32 = parse_value(&arg, args.next())?;
+                    if frames == 0 {
+                        return Err(format!("{} must be at least 1", arg));
+                    }
+                    settings.frames = Some(frames);
+                },
+                "--turntable" => {
+                    let frames: u32 = parse_value(&arg, args.next())?;
+                    if frames == 0 {
+                        return Err(format!("{} must be at least 1", arg));
+                    }
+                    settings.turntable = Some(frames);
+                },
+                "--fps" => {
+                    settings.fps = parse_value(&arg, args.next())?;
+                    if settings.fps <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                },
+                "--latitude" => {
+                    settings.latitude = parse_value(&arg, args.next())?;
+                    if settings.latitude.abs() > 90.0 {
+                        return Err(format!("{} must be between -90 and 90 degrees", arg));
+                    }
+                },
+                "--day" => {
+                    settings.day = parse_value(&arg, args.next())?;
+                    if settings.day < 1 || settings.day > 366 {
+                        return Err(format!("{} must be between 1 and 366", arg));
+                    }
+                },
+                "--rig" => {
+                    let name: String = parse_value(&arg, args.next())?;
+                    settings.rig = match Rig::from_name(&name) {
+                        Some(rig) => Some(rig),
+                        None => return Err(format!("Unknown rig: {}", name)),
+                    };
+                },
+                "--clouds" => {
+                    let coverage: f32 = parse_value(&arg, args.next())?;
+                    if !(0.0..=1.0).contains(&coverage) {
+                        return Err(format!("{} must be between 0 and 1", arg));
+                    }
+                    settings.clouds = Some(coverage);
+                },
+                "--cloud-density" => {
+                    settings.cloud_density = parse_value(&arg, args.next())?;
+                    if settings.cloud_density <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                },
+                "--light-groups" => {
+                    settings.light_groups = true;
+                },
+                "--aov" => {
+                    let names: String = parse_value(&arg, args.next())?;
+                    for name in names.split(',') {
+                        match Aov::from_name(name) {
+                            Some(aov) if !settings.aovs.contains(&aov) => settings.aovs.push(aov),
+                            Some(_) => (),
+                            None => return Err(format!("Unknown AOV: {}", name)),
+                        }
+                    }
+                },
+                "--clamp-direct" | "--clamp-indirect" => {
+                    let limit: f32 = parse_value(&arg, args.next())?;
+                    if limit <= 0.0 {
+                        return Err(format!("{} must be greater than zero", arg));
+                    }
+                    if arg == "--clamp-direct" {
+                        settings.clamp_direct = Some(limit);
+                    } else {
+                        settings.clamp_indirect = Some(limit);
+                    }
+                },
+                "--mix" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    settings.mix.push(parse_mix(&value)?);
+                },
+                _ => {
+                    return Err(format!("Unknown option: {}", arg));
+                }
+            }
+        }
+
+        if settings.width == 0 || settings.height == 0 {
+            return Err("Image width and height must be greater than zero".to_string());
+        }
+
+        if settings.cover.is_some() && settings.scene.is_some() {
+            return Err("--cover can't be used with --scene".to_string());
+        }
+
+        if settings.stream && settings.output.is_none() {
+            return Err("--stream requires --output".to_string());
+        }
+
+        if settings.panorama.is_some() && settings.output.is_none() {
+            return Err("--panorama requires --output".to_string());
+        }
+
+        if settings.panorama.is_some() && settings.orthographic.is_some() {
+            return Err("--panorama can't be used with --orthographic".to_string());
+        }
+
+        if settings.fisheye.is_some() && (settings.orthographic.is_some() || settings.panorama.is_some()) {
+            return Err("--fisheye can't be used with --orthographic or --panorama".to_string());
+        }
+
+        if settings.equirectangular && (settings.orthographic.is_some() || settings.fisheye.is_some() || settings.panorama.is_some()) {
+            return Err("--equirectangular can't be used with --orthographic, --fisheye or --panorama".to_string());
+        }
+
+        let lens_controls = settings.lens_shift != (0.0, 0.0) || settings.aperture.is_some() || settings.lens.is_some();
+        if lens_controls && (settings.orthographic.is_some() || settings.fisheye.is_some()
+                             || settings.equirectangular || settings.panorama.is_some()) {
+            return Err("--lens-shift, --aperture and --lens can't be used with --orthographic, --fisheye, --equirectangular or --panorama".to_string());
+        }
+
+        if settings.lens.is_some() && (settings.aperture.is_some() || settings.lens_shift != (0.0, 0.0)
+                                       || settings.stereo.is_some()) {
+            return Err("--lens can't be used with --aperture, --lens-shift or --stereo".to_string());
+        }
+
+        if settings.tilt != 0.0 && settings.aperture.is_none() {
+            return Err("--tilt requires --aperture".to_string());
+        }
+
+        if settings.post_dof && (settings.aperture.is_none() || settings.tilt != 0.0 || settings.blades.is_some()) {
+            return Err("--post-dof requires --aperture, and can't be used with --tilt or --blades".to_string());
+        }
+
+        if settings.post_dof && (settings.output.is_none() || settings.stream
+                                 || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--post-dof requires --output, and can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        if settings.focus_distance.is_some() && settings.aperture.is_none() && settings.lens.is_none() {
+            return Err("--focus-distance requires --aperture or --lens".to_string());
+        }
+
+        if settings.stereo.is_some() && settings.output.is_none() {
+            return Err("--stereo requires --output".to_string());
+        }
+
+        if settings.stereo.is_some() && (settings.orthographic.is_some() || settings.fisheye.is_some()
+                                         || settings.equirectangular || settings.panorama.is_some()) {
+            return Err("--stereo can't be used with --orthographic, --fisheye, --equirectangular or --panorama".to_string());
+        }
+
+        if settings.stereo.is_some() && (settings.stream || settings.time_lapse.is_some()
+                                         || settings.light_groups || settings.heatmap.is_some()) {
+            return Err("--stereo can't be used with --stream, --time-lapse, --light-groups or --heatmap".to_string());
+        }
+
+        if settings.time_lapse.is_some() && settings.output.is_none() {
+            return Err("--time-lapse requires --output".to_string());
+        }
+
+        if settings.camera_path.is_some() && settings.output.is_none() {
+            return Err("--camera-path requires --output".to_string());
+        }
+
+        if settings.camera_path.is_some() && (settings.stream || settings.time_lapse.is_some()
+                                              || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--camera-path can't be used with --stream, --time-lapse, --panorama or --stereo".to_string());
+        }
+
+        if settings.frames.is_some() && (settings.output.is_none() || settings.stream || settings.time_lapse.is_some()
+                                         || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--frames requires --output, and can't be used with --stream, --time-lapse, --panorama or --stereo".to_string());
+        }
+
+        let gif_output = settings.output.as_ref()
+            .is_some_and(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("gif")));
+        if settings.turntable.is_some() != gif_output {
+            return Err("--turntable saves to a .gif --output, and only it can".to_string());
+        }
+
+        if settings.turntable.is_some() && (settings.stream || settings.time_lapse.is_some()
+                                            || settings.camera_path.is_some() || settings.frames.is_some()
+                                            || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--turntable can't be used with --stream, --time-lapse, --camera-path, --frames, --panorama or --stereo".to_string());
+        }
+
+        if settings.turntable.is_some() && (settings.save_every.is_some() || settings.transparent
+                                            || settings.bit_depth == 16 || !settings.aovs.is_empty()
+                                            || settings.light_groups) {
+            return Err("--turntable can't be used with --save-every, --transparent, --bit-depth 16, --aov or --light-groups".to_string());
+        }
+
+        let video_output = settings.output.as_ref().is_some_and(|p| video::is_video(p));
+        if video_output && settings.time_lapse.is_none() && settings.camera_path.is_none() && settings.frames.is_none() {
+            return Err("Saving a video needs --time-lapse, --camera-path or --frames".to_string());
+        }
+
+        if video_output && (settings.save_every.is_some() || settings.transparent || settings.bit_depth == 16) {
+            return Err("Saving a video can't be used with --save-every, --transparent or --bit-depth 16".to_string());
+        }
+
+        if settings.light_groups && (settings.output.is_none() || settings.stream || settings.panorama.is_some()) {
+            return Err("--light-groups requires --output, and can't be used with --stream or --panorama".to_string());
+        }
+
+        if !settings.aovs.is_empty() && (settings.output.is_none() || settings.stream
+                                         || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--aov requires --output, and can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        if settings.proxy.is_some() && (settings.stream || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--proxy can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        if settings.proxy.is_some() && !cfg!(feature = "jpeg") {
+            return Err("--proxy needs JPEG support, which isn't built in".to_string());
+        }
+
+        if settings.save_every.is_some() && (settings.output.is_none() || settings.stream
+                                             || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--save-every requires --output, and can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        let windowless = settings.stream || settings.time_lapse.is_some() || settings.camera_path.is_some()
+            || settings.frames.is_some() || settings.turntable.is_some() || settings.panorama.is_some()
+            || settings.stereo.is_some();
+        if settings.denoise && (settings.output.is_none() || settings.stream
+                                || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--denoise and --oidn require --output, and can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        if settings.auto_exposure.is_some() && (settings.stream || settings.stereo.is_some()) {
+            return Err("--auto-exposure can't be used with --stream or --stereo".to_string());
+        }
+
+        if settings.denoise_preview && windowless {
+            return Err("--denoise-preview can't be used without the window".to_string());
+        }
+
+        if settings.progress_json && !windowless {
+            return Err("--progress-json requires --stream, --time-lapse, --camera-path, --frames, --turntable, --panorama or --stereo".to_string());
+        }
+
+        if settings.heatmap.is_some() && (settings.stream || settings.panorama.is_some()) {
+            return Err("--heatmap can't be used with --stream or --panorama".to_string());
+        }
+
+        if settings.glare.is_some() && (settings.output.is_none() || settings.stream
+                                        || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--glare requires --output, and can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        if settings.bloom.is_some() && (settings.output.is_none() || settings.stream
+                                        || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--bloom requires --output, and can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        if settings.lens_dirt.is_some() && settings.glare.is_none() {
+            return Err("--lens-dirt requires --glare".to_string());
+        }
+
+        let float_output = settings.output.as_ref().is_some_and(|p| ImageFormat::from_path(p).is_float());
+        if float_output && (settings.stream || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--stream, --panorama and --stereo can't save PFM or EXR files".to_string());
+        }
+
+        if settings.bit_depth == 16 && (settings.output.is_none() || float_output || settings.stream
+                                        || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--bit-depth 16 requires a PNG or PPM --output, and can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        let alpha_output = settings.output.as_ref()
+            .is_some_and(|p| matches!(ImageFormat::from_path(p), ImageFormat::Png | ImageFormat::Exr));
+        if settings.transparent && (!alpha_output || settings.stream
+                                    || settings.panorama.is_some() || settings.stereo.is_some()) {
+            return Err("--transparent requires a PNG or EXR --output, and can't be used with --stream, --panorama or --stereo".to_string());
+        }
+
+        if settings.transparent && (settings.post_dof || settings.glare.is_some() || settings.bloom.is_some()
+                                    || !settings.mix.is_empty()) {
+            return Err("--transparent can't be used with --post-dof, --glare, --bloom or --mix".to_string());
+        }
+
+        if settings.heatmap.as_ref().is_some_and(|p| ImageFormat::from_path(p).is_float()) {
+            return Err("--heatmap can't be saved as a PFM or EXR file".to_string());
+        }
+
+        if !settings.mix.is_empty() && settings.output.is_none() {
+            return Err("--mix requires --output".to_string());
+        }
+
+        if settings.reference && (settings.quality.is_some() || settings.adaptive.is_some()
+                                  || settings.clamp_direct.is_some() || settings.clamp_indirect.is_some()) {
+            return Err("--reference can't be used with --quality, --adaptive, --clamp-direct or --clamp-indirect".to_string());
+        }
+
+        if settings.reference && (settings.sampler != SamplerKind::Random || settings.blue_noise
+                                  || settings.half_float || settings.post_dof) {
+            return Err("--reference can't be used with --sampler, --blue-noise, --half-float or --post-dof".to_string());
+        }
+
+        if settings.reference && (settings.denoise || settings.denoise_preview) {
+            return Err("--reference can't be used with --denoise, --denoise-preview or --oidn".to_string());
+        }
+
+        // Without an explicit seed every render is different, but the
+        // seed is reported so that a render can be reproduced later
+        settings.seed = match seed {
+            Some(s) => s,
+            None => rand::random(),
+        };
+
+        settings.samples = match (samples, settings.quality) {
+            (Some(s), _) => s.max(1),
+            (None, Some(_)) => QUALITY_SAMPLE_CAP,
+            (None, None) if settings.reference => REFERENCE_SAMPLES,
+            (None, None) => DEFAULT_SAMPLES,
+        };
+
+        settings.tile_size = match tile_size {
+            Some(size) => size,
+            None => Machine::detect().default_tile_size(settings.width, settings.height, settings.threads),
+        };
+
+        settings.max_depth = match max_depth {
+            Some(d) => d,
+            None if settings.reference => REFERENCE_MAX_DEPTH,
+            None => DEFAULT_MAX_DEPTH,
+        };
+
+        Ok(settings)
+    }
+
+    ///
+    /// How much the camera scales the radiance reaching it, from its
+    /// ISO, shutter speed and f-stop. Each doubling of the ISO or of
+    /// the shutter time, or each full stop the aperture is opened,
+    /// doubles the brightness of the image. ISO 100 with the shutter
+    /// open for one second at f/1 leaves radiance unchanged.
+    ///
+    pub fn exposure(&self) -> f32 {
+        (self.iso / BASE_ISO) * self.shutter / (self.f_stop * self.f_stop)
+    }
+
+    /// How linear light on the film becomes pixel values, for images the size of the render.
+    pub fn post_chain(&self) -> PostChain {
+        PostChain::new(self, self.width as usize, self.height as usize)
+    }
+
+    /// As `post_chain`, but metered from the whole image `data` if auto-exposure is on.
+    pub fn metered_post_chain(&self, data: &[Vec3]) -> PostChain {
+        PostChain::metered(self, self.width as usize, self.height as usize, data)
+    }
+
+    pub fn usage() -> &'static str {
+        "Usage: raytracer [options]\n\
+         \n\
+         Options:\n\
+         \x20   --width <n>       Image width in pixels (default 640)\n\
+         \x20   --height <n>      Image height in pixels (default 480)\n\
+         \x20   --samples <n>     Maximum samples per pixel (default 100)\n\
+         \x20   --max-depth <n>   Maximum number of bounces per path (default 50)\n\
+         \x20   --simple-preview  While the camera moves, draw glass as a mirror,\n\
+         \x20                     script materials as plain diffuse surfaces and skip\n\
+         \x20                     volumes\n\
+         \x20   --resize-render   Render again at the new size when the preview window is\n\
+         \x20                     resized, instead of scaling the image to fit\n\
+         \x20   --reference       Render a ground-truth image with no shortcuts: 4096\n\
+         \x20                     samples and 1000 bounces unless given, independent\n\
+         \x20                     random samples, and no clamping or early stopping\n\
+         \x20   --quality <err>   Stop once the estimated relative error drops below <err>\n\
+         \x20   --adaptive <t>    Stop sampling pixels once their 95% confidence interval\n\
+         \x20                     is within <t> times their brightness\n\
+         \x20   --sampler <name>  Sample generator: random (default), halton or sobol\n\
+         \x20   --filter <name>   Pixel filter: box (default), tent, gaussian or mitchell\n\
+         \x20   --accelerator <name>\n\
+         \x20                     Ray intersection structure: auto (default), none,\n\
+         \x20                     grid, bvh or kdtree\n\
+         \x20   --bvh-cache <dir> Keep built BVHs in <dir>, to load instead of rebuilding\n\
+         \x20   --seed <n>        Seed for all random numbers, for reproducible renders\n\
+         \x20   --threads <n>     Render threads to use (default: one per logical core)\n\
+         \x20   --tile-size <n>   Width and height of the tiles rendered (default: 16 to 64,\n\
+         \x20                     small enough to give every thread several)\n\
+         \x20   --tile-order <name>\n\
+         \x20                     Order tiles are rendered in while previewing: rows\n\
+         \x20                     (default), spiral, hilbert or random\n\
+         \x20   --max-memory <size>\n\
+         \x20                     Refuse renders that would need more than <size>\n\
+         \x20                     of memory, e.g. 512M or 2G\n\
+         \x20   --blue-noise      Decorrelate pixels with a blue-noise dither mask\n\
+         \x20   --spectral        Trace one wavelength per path, so glass with an Abbe\n\
+         \x20                     number or Sellmeier coefficients splits light\n\
+         \x20                     into rainbows\n\
+         \x20   --half-float      Accumulate colors at half precision to save memory\n\
+         \x20   --scene <file>    Render the scene in <file> instead of the default one\n\
+         \x20   --cover <seed>    Render the random spheres of the \"Ray Tracing in One\n\
+         \x20                     Weekend\" cover, drawn from <seed>, instead of the\n\
+         \x20                     default scene\n\
+         \x20   --hide <name>     Leave out the scene's group or object called <name>;\n\
+         \x20                     can be given more than once\n\
+         \x20   --output <file>   Save the finished image as a PNG, PPM, PFM or EXR\n\
+         \x20                     file, picked by its extension; animations can also\n\
+         \x20                     be saved as MP4, MOV, MKV or WebM videos with ffmpeg\n\
+         \x20   --bit-depth <n>   Bits per channel of a PNG or PPM --output: 8 (default)\n\
+         \x20                     or 16\n\
+         \x20   --transparent     Leave the background see-through: rays that miss\n\
+         \x20                     everything save alpha 0 in a PNG or EXR --output\n\
+         \x20   --heatmap <file>  Save a false-color map of the samples taken per pixel\n\
+         \x20   --proxy <file>    Keep a small JPEG of the render in progress in <file>\n\
+         \x20   --proxy-interval <s>\n\
+         \x20                     Seconds between updates of the proxy (default 10)\n\
+         \x20   --save-every <s>  Save the render in progress to --output every <s> seconds\n\
+         \x20   --progress-json   Report progress as lines of JSON on stdout, moving the\n\
+         \x20                     usual messages to stderr\n\
+         \x20   --edit-log <file> Replay scene edits from <file>, and record new ones to it\n\
+         \x20   --stream          Render without a window, streaming rows into --output\n\
+         \x20   --panorama <deg>  Stitch a panorama covering <deg> degrees into --output\n\
+         \x20   --orthographic <h>\n\
+         \x20                     Use parallel rays, with a view <h> units tall\n\
+         \x20   --fisheye <deg>   Use a fisheye lens seeing <deg> degrees across\n\
+         \x20   --fisheye-mapping <name>\n\
+         \x20                     Fisheye lens mapping: equidistant (default), equisolid\n\
+         \x20   --equirectangular Render a 360 degree environment map (use a 2:1 frame)\n\
+         \x20   --lens-shift <x>,<y>\n\
+         \x20                     Shift the lens by fractions of the frame's width and height\n\
+         \x20   --aperture <d>    Give the camera a lens <d> units across, for depth of field\n\
+         \x20   --focus-distance <d>\n\
+         \x20                     Distance to the plane in focus (default: lookat)\n\
+         \x20   --tilt <deg>      Tilt the plane in focus, swinging its top away\n\
+         \x20   --blades <n>      Give the aperture <n> straight blades, shaping out of focus\n\
+         \x20                     highlights and glare spikes\n\
+         \x20   --blade-rotation <deg>\n\
+         \x20                     Turn the aperture blades anticlockwise\n\
+         \x20   --lens <file>     Look through the lens elements described in <file>\n\
+         \x20   --stereo <d>      Render a stereo pair into --output, eyes <d> units apart\n\
+         \x20   --convergence <d> Distance at which the stereo eyes converge (default: lookat)\n\
+         \x20   --stereo-layout <name>\n\
+         \x20                     Stereo frame layout: side-by-side (default), top-bottom\n\
+         \x20   --iso <n>         Film speed for the exposure (default 100)\n\
+         \x20   --shutter <t>     Shutter time in seconds, such as 1/125 (default 1)\n\
+         \x20   --f-stop <n>      Aperture for the exposure, as an f-number (default 1)\n\
+         \x20   --response <name> Camera response curve: gamma (default), srgb, logc,\n\
+         \x20                     slog3, cineon or filmic\n\
+         \x20   --tone-map <name> Squeeze highlights before the response curve: clamp\n\
+         \x20                     (default), reinhard, aces or filmic\n\
+         \x20   --ev <stops>      Brighten (or, if negative, darken) the image by\n\
+         \x20                     <stops> before tone mapping\n\
+         \x20   --auto-exposure <metering>\n\
+         \x20                     Set the exposure from the image's brightness, metered\n\
+         \x20                     over the average of the image or its center\n\
+         \x20   --white-balance <kelvin>\n\
+         \x20                     Make light of <kelvin> degrees look white\n\
+         \x20   --tint <t>        Shift the white balance towards magenta, or green if\n\
+         \x20                     negative (-1 to 1)\n\
+         \x20   --post <steps>    Order of the post-processing steps, separated by\n\
+         \x20                     commas (default exposure,whitebalance,vignette,\n\
+         \x20                     tonemap,grade,lut,grain,quantize)\n\
+         \x20   --slope <r>,<g>,<b>\n\
+         \x20   --offset <r>,<g>,<b>\n\
+         \x20   --power <r>,<g>,<b>\n\
+         \x20                     Grade the colors with an ASC CDL slope, offset and power\n\
+         \x20   --saturation <s>  Scale the colorfulness of the graded image\n\
+         \x20   --lut <file>      Grade the colors with the 3D LUT in a .cube file\n\
+         \x20   --quantize <bits> Round pixel values to the levels of <bits> bits\n\
+         \x20   --dither <name>   Dither 8-bit images to hide banding: blue-noise\n\
+         \x20                     (default), ordered or none\n\
+         \x20   --vignette <s>    Darken the corners of the image by <s> (0 to 1)\n\
+         \x20   --grain <s>       Add film grain with a strength of <s>\n\
+         \x20   --glare <s>       Add glare around bright lights in --output, spreading\n\
+         \x20                     out <s> of the light above the threshold\n\
+         \x20   --glare-threshold <t>\n\
+         \x20                     Brightness above which light spreads into glare (default 1)\n\
+         \x20   --lens-dirt <file>\n\
+         \x20                     Smudge the glare with the dirt in a PNG image\n\
+         \x20   --bloom <s>       Add a wide glow around bright parts of --output,\n\
+         \x20                     spreading out <s> of the light above the threshold\n\
+         \x20   --bloom-threshold <t>\n\
+         \x20                     Brightness above which light blooms (default 1)\n\
+         \x20   --bloom-radius <r>\n\
+         \x20                     Width of the bloom's core, as a fraction of the image's\n\
+         \x20                     diagonal (default 0.01)\n\
+         \x20   --time-lapse <n>  Render <n> frames sweeping the sun from sunrise to sunset\n\
+         \x20   --latitude <deg>  Latitude for the time-lapse sun position (default 40)\n\
+         \x20   --day <n>         Day of the year for the time-lapse (default 172)\n\
+         \x20   --rig <name>      Light the scene with a ready-made rig: studio, overcast\n\
+         \x20                     or sunset\n\
+         \x20   --clouds <c>      Cover <c> of the sky, from 0 to 1, with clouds\n\
+         \x20   --cloud-density <n>\n\
+         \x20                     How thick the clouds are (default 1)\n\
+         \x20   --camera-path <file>\n\
+         \x20                     Render a flythrough along the keyframes in <file>\n\
+         \x20   --frames <n>      Render <n> frames of the scene's animation, and of the\n\
+         \x20                     --camera-path if one is given\n\
+         \x20   --turntable <n>   Orbit the camera around the scene over <n> frames,\n\
+         \x20                     saved as an animated GIF to a .gif --output\n\
+         \x20   --fps <n>         Frames per second of animations (default 24)\n\
+         \x20   --clamp-direct <n>\n\
+         \x20                     Limit the brightness of light reaching the camera\n\
+         \x20                     directly or after one bounce, to tame fireflies\n\
+         \x20   --clamp-indirect <n>\n\
+         \x20                     Limit the brightness of light found after more bounces\n\
+         \x20   --light-groups    Also save each light group as a PFM file next to --output\n\
+         \x20   --aov <list>      Also save the AOVs in <list> next to --output: any of\n\
+         \x20                     normal, depth, albedo and id, separated by commas\n\
+         \x20   --denoise         Denoise --output, guided by the normal and albedo AOVs\n\
+         \x20   --denoise-preview Show the window denoised after every pass\n\
+         \x20   --oidn            Denoise --output with Intel Open Image Denoise\n\
+         \x20   --mix <f>=<w>     Instead of rendering, add up light group files into\n\
+         \x20                     --output, scaled by <w> (a number or r,g,b)"
+    }
+}
+
+fn parse_value<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    match value {
+        Some(v) => v.parse::<T>().map_err(|_| format!("Invalid value for {}: {}", option, v)),
+        None => Err(format!("Missing value for {}", option)),
+    }
+}
+
+// Two numbers separated by a comma
+fn parse_pair(value: &str) -> Option<(f32, f32)> {
+    let mut parts = value.split(',').map(|p| p.trim().parse::<f32>());
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(x)), Some(Ok(y)), None) => Some((x, y)),
+        _ => None,
+    }
+}
+
+// Three numbers separated by commas
+fn parse_color(value: &str) -> Option<Vec3> {
+    let mut parts = value.split(',').map(|p| p.trim().parse::<f32>());
+
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(r)), Some(Ok(g)), Some(Ok(b)), None) => Some(Vec3::new(r, g, b)),
+        _ => None,
+    }
+}
+
+// A shutter time in seconds, either a plain number or a fraction like "1/125"
+fn parse_shutter(value: &str) -> Option<f32> {
+    match value.find('/') {
+        Some(i) => {
+            let numerator = value[..i].trim().parse::<f32>().ok()?;
+            let denominator = value[i + 1..].trim().parse::<f32>().ok()?;
+            Some(numerator / denominator)
+        },
+        None => value.trim().parse::<f32>().ok(),
+    }
+}
+
+// A light group file and its weight, as in "key.pfm=0.5" or "fill.pfm=1,0.8,0.6"
+fn parse_mix(value: &str) -> Result<(PathBuf, Vec3), String> {
+    let (file, weight) = match value.rfind('=') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => (value, "1"),
+    };
+
+    let parts: Result<Vec<f32>, _> = weight.split(',').map(|w| w.trim().parse::<f32>()).collect();
+    let weight = match parts {
+        Ok(ref p) if p.len() == 1 => Vec3::new(p[0], p[0], p[0]),
+        Ok(ref p) if p.len() == 3 => Vec3::new(p[0], p[1], p[2]),
+        _ => return Err(format!("Invalid light group weight: {}", weight)),
+    };
+
+    Ok((PathBuf::from(file), weight))
+}
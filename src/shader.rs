@@ -0,0 +1,310 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::collections::HashMap;
+use std::f32::consts;
+use std::fmt;
+use std::sync::Arc;
+
+use vec3::Vec3;
+use ray::Ray;
+use film::luminance;
+use hittable::*;
+use sampler::{hash_combine, to_unit_float, Sampler};
+use registry::Registry;
+
+pub type NodeId = usize;
+
+///
+/// A texture: a color at every shading point. Crates using the
+/// raytracer as a library can register their own with the Registry,
+/// and scene files can then use them as nodes of a shader graph.
+///
+pub trait Texture {
+    fn color(&self, at: &ShadingPoint) -> Vec3;
+}
+
+pub type SharedTexture = Arc<dyn Texture + Sync + Send>;
+
+impl fmt::Debug for dyn Texture + Sync + Send {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Texture")
+    }
+}
+
+///
+/// A node of a shader graph. Every node gives a color at each shading
+/// point; nodes that compute a single number, like `Fresnel`, give it
+/// as a grey. Inputs are other nodes of the same graph.
+///
+#[derive(Clone, Debug)]
+pub enum Node {
+    Constant(Vec3),
+    // 3D checkerboard with squares 1 / scale across
+    Checker { scale: f32, even: NodeId, odd: NodeId },
+    // Smooth value noise between 0 and 1, with features about 1 /
+    // scale across and finer detail added by each extra octave
+    Noise { scale: f32, octaves: u32 },
+    // Schlick's approximation of how much light a dielectric surface
+    // with this index of refraction reflects, at the viewing angle
+    Fresnel { ior: f32 },
+    // a where t is 0 to b where t is 1, channel by channel
+    Mix { a: NodeId, b: NodeId, t: NodeId },
+    Add(NodeId, NodeId),
+    Subtract(NodeId, NodeId),
+    Multiply(NodeId, NodeId),
+    // The color painted on a mesh's vertices, blended across its
+    // triangles, or white where there is none
+    VertexColor,
+    // A texture registered with the Registry
+    Texture(SharedTexture),
+}
+
+///
+/// Where a shader graph is being evaluated, from which direction, and
+/// the color painted on the surface there (white if it isn't).
+///
+pub struct ShadingPoint {
+    pub p: Vec3,
+    pub normal: Vec3,
+    pub direction: Vec3,
+    pub color: Vec3,
+}
+
+///
+/// A graph of nodes, evaluated at every shading point to give the
+/// colors and weights of a ShadedMaterial. Nodes can only take
+/// earlier nodes as inputs, so the graph has no cycles.
+///
+#[derive(Clone, Default)]
+pub struct ShaderGraph {
+    nodes: Vec<Node>,
+    names: HashMap<String, NodeId>,
+}
+
+impl ShaderGraph {
+    pub fn add(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    pub fn add_named(&mut self, name: &str, node: Node) -> NodeId {
+        let id = self.add(node);
+        self.names.insert(name.to_string(), id);
+        id
+    }
+
+    ///
+    /// The node an input refers to: either the name of a node, or a
+    /// number, which is added as a constant grey.
+    ///
+    pub fn input(&mut self, arg: &str) -> Result<NodeId, String> {
+        if let Ok(value) = arg.parse::<f32>() {
+            return Ok(self.add(Node::Constant(Vec3::new(value, value, value))));
+        }
+
+        match self.names.get(arg) {
+            Some(&id) => Ok(id),
+            None => Err(format!("Unknown node: {}", arg)),
+        }
+    }
+
+    ///
+    /// A node described by its type and arguments, as written in a
+    /// scene file. Inputs are given as for `input`. Types that aren't
+    /// built in are looked for among the textures in `registry`, and
+    /// given their arguments as numbers.
+    ///
+    pub fn parse_node(&mut self, kind: &str, args: &[&str], registry: &Registry) -> Result<Node, String> {
+        let number = |arg: &str| arg.parse::<f32>().map_err(|_| format!("Invalid number: {}", arg));
+
+        if registry.has_texture(kind) {
+            let params: Vec<f32> = args.iter().map(|a| number(a)).collect::<Result<_, _>>()?;
+            return Ok(Node::Texture(registry.texture(kind, &params)?));
+        }
+
+        let count = match kind {
+            "constant" => if args.len() == 1 { 1 } else { 3 },
+            "noise" => if args.len() == 1 { 1 } else { 2 },
+            "fresnel" => 1,
+            "vertex" => 0,
+            "checker" | "mix" => 3,
+            "add" | "subtract" | "multiply" => 2,
+            _ => return Err(format!("Unknown node type: {}", kind)),
+        };
+
+        if args.len() != count {
+            return Err(format!("{} node takes {} arguments, not {}", kind, count, args.len()));
+        }
+
+        Ok(match kind {
+            "constant" => {
+                let v: Vec<f32> = args.iter().map(|a| number(a)).collect::<Result<_, _>>()?;
+                Node::Constant(if count == 1 { Vec3::new(v[0], v[0], v[0]) } else { Vec3::new(v[0], v[1], v[2]) })
+            },
+            "noise" => {
+                let octaves: u32 = match args.get(1) {
+                    Some(arg) => arg.parse().map_err(|_| format!("Invalid number of octaves: {}", arg))?,
+                    None => 1,
+                };
+                Node::Noise { scale: number(args[0])?, octaves: octaves.max(1) }
+            },
+            "fresnel" => Node::Fresnel { ior: number(args[0])? },
+            "vertex" => Node::VertexColor,
+            "checker" => Node::Checker { scale: number(args[0])?, even: self.input(args[1])?, odd: self.input(args[2])? },
+            "mix" => Node::Mix { a: self.input(args[0])?, b: self.input(args[1])?, t: self.input(args[2])? },
+            "add" => Node::Add(self.input(args[0])?, self.input(args[1])?),
+            "subtract" => Node::Subtract(self.input(args[0])?, self.input(args[1])?),
+            _ => Node::Multiply(self.input(args[0])?, self.input(args[1])?),
+        })
+    }
+
+    pub fn evaluate(&self, id: NodeId, at: &ShadingPoint) -> Vec3 {
+        match self.nodes[id] {
+            Node::Constant(col) => col,
+            Node::Checker { scale, even, odd } => {
+                let p: Vec3 = scale * consts::PI * at.p;
+                if (p.x().sin() * p.y().sin() * p.z().sin()) >= 0.0 {
+                    self.evaluate(even, at)
+                } else {
+                    self.evaluate(odd, at)
+                }
+            },
+            Node::Noise { scale, octaves } => {
+                let n = fractal_noise(&(scale * at.p), octaves, 0);
+                Vec3::new(n, n, n)
+            },
+            Node::Fresnel { ior } => {
+                let cosine = (-Vec3::dot(&Vec3::unit_vector(&at.direction), &at.normal)).clamp(0.0, 1.0);
+                let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+                let r = r0 + (1.0 - r0) * (1.0 - cosine).powi(5);
+                Vec3::new(r, r, r)
+            },
+            Node::Mix { a, b, t } => {
+                let (a, b, t) = (self.evaluate(a, at), self.evaluate(b, at), self.evaluate(t, at));
+                a + t * (b - a)
+            },
+            Node::Add(a, b) => self.evaluate(a, at) + self.evaluate(b, at),
+            Node::Subtract(a, b) => self.evaluate(a, at) - self.evaluate(b, at),
+            Node::Multiply(a, b) => self.evaluate(a, at) * self.evaluate(b, at),
+            Node::VertexColor => at.color,
+            Node::Texture(ref texture) => texture.color(at),
+        }
+    }
+}
+
+///
+/// Octaves of value noise at `p`, each twice the frequency and half
+/// the strength of the last, between 0 and 1. Octaves are seeded
+/// `seed`, `seed + 1` and so on.
+///
+pub fn fractal_noise(p: &Vec3, octaves: u32, seed: u32) -> f32 {
+    let (mut sum, mut amplitude, mut total) = (0.0, 1.0, 0.0);
+    for octave in 0..octaves {
+        sum += amplitude * value_noise(&((1 << octave) as f32 * *p), seed.wrapping_add(octave));
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    sum / total
+}
+
+// Trilinearly interpolated random values at the corners of a unit
+// lattice, eased so that the lattice doesn't show
+fn value_noise(p: &Vec3, seed: u32) -> f32 {
+    let cell = [p.x().floor(), p.y().floor(), p.z().floor()];
+    let f = [p.x() - cell[0], p.y() - cell[1], p.z() - cell[2]];
+    let s = f.map(|t| t * t * (3.0 - 2.0 * t));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let h = hash_combine(hash_combine(hash_combine(seed, (cell[0] as i32 + dx) as u32),
+                                          (cell[1] as i32 + dy) as u32),
+                             (cell[2] as i32 + dz) as u32);
+        to_unit_float(h)
+    };
+
+    let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), s[0]);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), s[0]);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), s[0]);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), s[0]);
+
+    lerp(lerp(x00, x10, s[1]), lerp(x01, x11, s[1]), s[2])
+}
+
+///
+/// A material whose look comes from a shader graph. It mixes a
+/// diffuse surface colored by the `albedo` node with a mirror
+/// colored by `specular_color`, using the `specular` node's brightness
+/// as the weight of the mirror, so e.g. a Fresnel node gives a
+/// glossy coat that reflects more at grazing angles.
+///
+pub struct ShadedMaterial {
+    pub graph: Arc<ShaderGraph>,
+    pub albedo: NodeId,
+    pub specular: NodeId,
+    pub specular_color: NodeId,
+}
+
+impl Material for ShadedMaterial {
+    fn scatter(&self, r_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Reflection {
+        let at = ShadingPoint {
+            p: hit.p,
+            normal: hit.normal,
+            direction: r_in.direction(),
+            color: hit.color.unwrap_or(Vec3::new(1.0, 1.0, 1.0)),
+        };
+        let weight = luminance(&self.graph.evaluate(self.specular, &at)).clamp(0.0, 1.0);
+        let (u, v) = sampler.next_2d();
+
+        if u < weight {
+            let reflected: Vec3 = Vec3::reflect(&Vec3::unit_vector(&r_in.direction()), &hit.normal);
+
+            Reflection {
+                scattered: Ray::new(hit.p, reflected),
+                attenuation: self.graph.evaluate(self.specular_color, &at),
+                reflected: Vec3::dot(&reflected, &hit.normal) > 0.0,
+            }
+        } else {
+            // Reuse the lobe choice, stretched back over [0, 1), so a
+            // diffuse bounce needs no more sample dimensions
+            let u = (u - weight) / (1.0 - weight);
+            let z: f32 = 1.0 - 2.0 * u;
+            let r: f32 = (1.0 - z * z).max(0.0).sqrt();
+            let phi: f32 = 2.0 * consts::PI * v;
+
+            Reflection {
+                scattered: Ray::new(hit.p, hit.normal + Vec3::new(r * phi.cos(), r * phi.sin(), z)),
+                attenuation: self.graph.evaluate(self.albedo, &at),
+                reflected: true,
+            }
+        }
+    }
+
+    // The color varies over the surface; this is it at the origin,
+    // seen head on
+    fn albedo(&self) -> Vec3 {
+        let at = ShadingPoint {
+            p: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+            color: Vec3::new(1.0, 1.0, 1.0),
+        };
+        self.graph.evaluate(self.albedo, &at)
+    }
+}
@@ -0,0 +1,221 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::f32::consts;
+
+use vec3::Vec3;
+
+// Angular radius of the sun disk, exaggerated so that diffuse
+// surfaces pick up sunlight without needing millions of samples
+const SUN_RADIUS: f32 = 4.0 * consts::PI / 180.0;
+const SUN_INTENSITY: f32 = 20.0;
+
+// Color of the overcast sky straight overhead
+const OVERCAST_ZENITH: Vec3 = Vec3 { e: [1.0, 1.0, 1.02] };
+
+///
+/// What a ray sees when it escapes the scene.
+///
+#[derive(Copy, Clone, Debug, Default)]
+pub enum Sky {
+    /// The original white-to-blue gradient.
+    #[default]
+    Gradient,
+    /// A simple analytic daylight sky lit by a sun in the given
+    /// direction (a unit vector, y up).
+    Sun(Vec3),
+    /// The same color in every direction, like a studio backdrop.
+    Uniform(Vec3),
+    /// A heavily overcast sky: soft, shadowless light, three times
+    /// brighter overhead than at the horizon.
+    Overcast,
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// How much daylight there is, and how warm it is, with the sun at
+// the given elevation (the y of its direction)
+fn daylight(elevation: f32) -> (f32, f32) {
+    (smoothstep(-0.1, 0.2, elevation), 1.0 - smoothstep(0.0, 0.4, elevation))
+}
+
+fn sun_color(warmth: f32) -> Vec3 {
+    lerp(Vec3::new(1.0, 0.95, 0.9), Vec3::new(1.0, 0.45, 0.15), warmth)
+}
+
+impl Sky {
+    pub fn color(&self, direction: &Vec3) -> Vec3 {
+        let unit_direction: Vec3 = Vec3::unit_vector(direction);
+
+        match *self {
+            Sky::Gradient => {
+                let t: f32 = 0.5 * (unit_direction.y() + 1.0);
+                (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+            },
+            Sky::Sun(sun) => {
+                let elevation: f32 = sun.y();
+                let (day, warmth) = daylight(elevation);
+
+                let night = Vec3::new(0.01, 0.01, 0.03);
+                let zenith = lerp(night, Vec3::new(0.25, 0.45, 1.0), day);
+                let horizon = lerp(night, lerp(Vec3::new(0.8, 0.9, 1.0), Vec3::new(1.0, 0.55, 0.3), warmth), day);
+                let sun_color = sun_color(warmth);
+
+                let up: f32 = unit_direction.y();
+                let sky = if up >= 0.0 {
+                    lerp(horizon, zenith, up.sqrt())
+                } else {
+                    0.3 * horizon
+                };
+
+                let cosine: f32 = Vec3::dot(&unit_direction, &sun);
+                let glow: f32 = 0.5 * cosine.max(0.0).powi(64) * day;
+
+                if cosine > SUN_RADIUS.cos() && elevation > -SUN_RADIUS {
+                    SUN_INTENSITY * sun_color
+                } else {
+                    sky + glow * sun_color
+                }
+            },
+            Sky::Uniform(color) => color,
+            Sky::Overcast => {
+                // The CIE overcast sky, with light bounced off the
+                // ground below the horizon
+                let up: f32 = unit_direction.y();
+                let brightness = if up >= 0.0 { (1.0 + 2.0 * up) / 3.0 } else { 0.2 };
+                brightness * OVERCAST_ZENITH
+            }
+        }
+    }
+
+    ///
+    /// Unit vector towards the sun, and the color of its light, for
+    /// lighting the sky itself. The gradient has no sun of its own, so
+    /// it gets a white one high in the south; skies without a sun are
+    /// lit from straight above.
+    ///
+    pub fn sun(&self) -> (Vec3, Vec3) {
+        match *self {
+            Sky::Gradient => (Vec3::unit_vector(&Vec3::new(0.0, 1.0, 0.6)), Vec3::new(1.0, 1.0, 1.0)),
+            Sky::Sun(sun) => {
+                let (day, warmth) = daylight(sun.y());
+                (sun, day * sun_color(warmth))
+            },
+            Sky::Uniform(color) => (Vec3::new(0.0, 1.0, 0.0), color),
+            Sky::Overcast => (Vec3::new(0.0, 1.0, 0.0), OVERCAST_ZENITH),
+        }
+    }
+}
+
+///
+/// Position of the sun in the sky, in radians. Elevation is measured
+/// up from the horizon, azimuth clockwise from north.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct SolarPosition {
+    pub elevation: f32,
+    pub azimuth: f32,
+}
+
+// Solar declination for a day of the year (1-365)
+fn declination(day: u32) -> f32 {
+    -23.44f32.to_radians() * (2.0 * consts::PI / 365.0 * (day as f32 + 10.0)).cos()
+}
+
+impl SolarPosition {
+    ///
+    /// Where the sun is at a given latitude (degrees, north positive),
+    /// day of the year, and local solar time (hours, noon = 12).
+    ///
+    pub fn new(latitude: f32, day: u32, hour: f32) -> SolarPosition {
+        let phi = latitude.to_radians();
+        let delta = declination(day);
+        let hour_angle = (hour - 12.0) * 15.0f32.to_radians();
+
+        let sin_elevation = phi.sin() * delta.sin() + phi.cos() * delta.cos() * hour_angle.cos();
+        let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+
+        let cos_azimuth = ((delta.sin() - elevation.sin() * phi.sin()) / (elevation.cos() * phi.cos()))
+            .clamp(-1.0, 1.0);
+        let azimuth = if hour_angle > 0.0 {
+            2.0 * consts::PI - cos_azimuth.acos()
+        } else {
+            cos_azimuth.acos()
+        };
+
+        SolarPosition { elevation, azimuth }
+    }
+
+    /// Unit vector towards the sun, with y up and north along -z.
+    pub fn direction(&self) -> Vec3 {
+        Vec3::new(self.azimuth.sin() * self.elevation.cos(),
+                  self.elevation.sin(),
+                  -self.azimuth.cos() * self.elevation.cos())
+    }
+}
+
+///
+/// Local solar times of sunrise and sunset. During polar day or polar
+/// night, the whole 24 hours are returned.
+///
+pub fn daylight_hours(latitude: f32, day: u32) -> (f32, f32) {
+    let phi = latitude.to_radians();
+    let cos_hour_angle = -phi.tan() * declination(day).tan();
+
+    if cos_hour_angle.abs() >= 1.0 {
+        return (0.0, 24.0);
+    }
+
+    let hours = cos_hour_angle.acos().to_degrees() / 15.0;
+    (12.0 - hours, 12.0 + hours)
+}
+
+///
+/// Drives the sun across one day in a fixed number of frames, from
+/// sunrise to sunset, for time-lapse lighting studies.
+///
+pub struct TimeOfDay {
+    pub latitude: f32,
+    pub day: u32,
+    pub frames: u32,
+}
+
+impl TimeOfDay {
+    /// Local solar time of the given frame.
+    pub fn hour(&self, frame: u32) -> f32 {
+        let (sunrise, sunset) = daylight_hours(self.latitude, self.day);
+
+        if self.frames <= 1 {
+            (sunrise + sunset) / 2.0
+        } else {
+            sunrise + (sunset - sunrise) * frame as f32 / (self.frames - 1) as f32
+        }
+    }
+
+    pub fn sky(&self, frame: u32) -> Sky {
+        Sky::Sun(SolarPosition::new(self.latitude, self.day, self.hour(frame)).direction())
+    }
+}
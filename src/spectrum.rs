@@ -0,0 +1,124 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+use std::sync::OnceLock;
+
+use vec3::Vec3;
+
+/// The shortest and longest wavelengths traced, in nanometres.
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+/// The wavelength indices of refraction are usually given at, the
+/// yellow helium d line, in nanometres.
+pub const D_LINE: f32 = 587.6;
+
+// Entries of the color table, one per nanometre
+const TABLE_SIZE: usize = (MAX_WAVELENGTH - MIN_WAVELENGTH) as usize + 1;
+
+/// A wavelength, in nanometres, for a uniform sample `u` in [0, 1).
+pub fn sample_wavelength(u: f32) -> f32 {
+    MIN_WAVELENGTH + u * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+}
+
+///
+/// What light of a single wavelength, sampled uniformly, counts for in
+/// linear RGB: the CIE color matching functions at that wavelength,
+/// taken to RGB and scaled so that light of every wavelength averages
+/// out to white. Pure colors outside the RGB gamut have a negative
+/// channel, which averages out with the rest of the spectrum.
+///
+pub fn rgb_weight(wavelength: f32) -> Vec3 {
+    let table = TABLE.get_or_init(build_table);
+    let x = (wavelength - MIN_WAVELENGTH).clamp(0.0, (TABLE_SIZE - 1) as f32);
+    let i = (x as usize).min(TABLE_SIZE - 2);
+    let t = x - i as f32;
+    (1.0 - t) * table[i] + t * table[i + 1]
+}
+
+static TABLE: OnceLock<Vec<Vec3>> = OnceLock::new();
+
+fn build_table() -> Vec<Vec3> {
+    let rgb: Vec<Vec3> = (0..TABLE_SIZE).map(|i| xyz_to_rgb(&cie_xyz(MIN_WAVELENGTH + i as f32))).collect();
+    let mean = rgb.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, c| sum + *c) / TABLE_SIZE as f32;
+    rgb.iter().map(|c| Vec3::new(c.r() / mean.r(), c.g() / mean.g(), c.b() / mean.b())).collect()
+}
+
+// The CIE 1931 color matching functions, from Wyman, Sloan and
+// Shirley's multi-lobe Gaussian fit
+fn cie_xyz(wavelength: f32) -> Vec3 {
+    let g = |mu: f32, below: f32, above: f32| {
+        let t = (wavelength - mu) / if wavelength < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+              0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+              1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8))
+}
+
+fn xyz_to_rgb(c: &Vec3) -> Vec3 {
+    Vec3::new(3.2406 * c.x() - 1.5372 * c.y() - 0.4986 * c.z(),
+              -0.9689 * c.x() + 1.8758 * c.y() + 0.0415 * c.z(),
+              0.0557 * c.x() - 0.2040 * c.y() + 1.0570 * c.z())
+}
+
+///
+/// How a glass's index of refraction changes with wavelength, which
+/// is what splits white light into a rainbow through a prism.
+///
+#[derive(Copy, Clone, Debug)]
+pub enum Dispersion {
+    /// The same index at every wavelength.
+    None(f32),
+    /// Cauchy's equation, `a + b / λ²`, with λ in micrometres.
+    Cauchy { a: f32, b: f32 },
+    /// The Sellmeier equation, with `c` in square micrometres, as
+    /// glass catalogs give it.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+// The hydrogen F and C lines the Abbe number is measured between, in
+// micrometres
+const F_LINE: f32 = 0.4861;
+const C_LINE: f32 = 0.6563;
+
+impl Dispersion {
+    ///
+    /// Cauchy's equation for a glass with index `index` at the d line
+    /// and Abbe number `abbe`, which is lower the more the glass
+    /// disperses light: around 64 for crown glass, 30 to 40 for flint
+    /// glass and 55 for diamond.
+    ///
+    pub fn from_abbe(index: f32, abbe: f32) -> Dispersion {
+        let d = D_LINE / 1000.0;
+        let b = (index - 1.0) / abbe / (1.0 / (F_LINE * F_LINE) - 1.0 / (C_LINE * C_LINE));
+        Dispersion::Cauchy { a: index - b / (d * d), b }
+    }
+
+    /// The index of refraction at `wavelength`, in nanometres.
+    pub fn index(&self, wavelength: f32) -> f32 {
+        let l2 = (wavelength / 1000.0) * (wavelength / 1000.0);
+        match *self {
+            Dispersion::None(index) => index,
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>()).max(1.0).sqrt()
+            },
+        }
+    }
+}
@@ -0,0 +1,125 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use vec3::Vec3;
+use ray::Ray;
+use hittable::{Hit, Hittable};
+
+// The most spheres tested together; runs longer than this are tested
+// a batch at a time
+pub const BATCH: usize = 16;
+
+///
+/// Spheres kept as a structure of arrays, one array for each
+/// coordinate of their centers and one for their radii, so that a ray
+/// can be tested against a run of them with the same few instructions
+/// for each, which the compiler turns into SIMD. Entries for objects
+/// that aren't plain spheres have a NaN radius and are never hit by
+/// `hit`; those objects have to be tested the usual way.
+///
+pub struct Spheres {
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+    radius: Vec<f32>,
+}
+
+impl Spheres {
+    pub fn new() -> Spheres {
+        Spheres { x: Vec::new(), y: Vec::new(), z: Vec::new(), radius: Vec::new() }
+    }
+
+    /// Add an entry for an object, a sphere if `as_sphere` says it is one.
+    pub fn push(&mut self, object: &dyn Hittable) {
+        self.x.push(0.0);
+        self.y.push(0.0);
+        self.z.push(0.0);
+        self.radius.push(f32::NAN);
+        self.set(self.radius.len() - 1, object);
+    }
+
+    /// Replace entry `index` with one for another object.
+    pub fn set(&mut self, index: usize, object: &dyn Hittable) {
+        let (center, radius) = object.as_sphere().unwrap_or((Vec3::new(0.0, 0.0, 0.0), f32::NAN));
+        self.x[index] = center.x();
+        self.y[index] = center.y();
+        self.z[index] = center.z();
+        self.radius[index] = radius;
+    }
+
+    pub fn is_sphere(&self, index: usize) -> bool {
+        !self.radius[index].is_nan()
+    }
+
+    ///
+    /// The nearest of entries `first..first + count` that the ray hits
+    /// between `t_min` and `t_max`, and how far along the ray it is.
+    /// Like `Sphere::hit`, only the side of a sphere facing the ray's
+    /// origin can be hit.
+    ///
+    pub fn hit(&self, first: usize, count: usize, r: &Ray, t_min: f32, t_max: f32) -> Option<(usize, f32)> {
+        let origin = r.origin();
+        let d = r.direction();
+        let a = Vec3::dot(&d, &d);
+        let mut nearest: Option<(usize, f32)> = None;
+        let mut closest_so_far = t_max;
+
+        for start in (first..first + count).step_by(BATCH) {
+            let end = (start + BATCH).min(first + count);
+            let mut ts = [f32::NAN; BATCH];
+
+            // No branches in here, so that it vectorizes. Misses,
+            // including non-spheres, come out as NaN.
+            let lanes = self.x[start..end].iter().zip(&self.y[start..end]).zip(&self.z[start..end])
+                .zip(&self.radius[start..end]);
+            for (t, (((&x, &y), &z), &radius)) in ts.iter_mut().zip(lanes) {
+                let (ox, oy, oz) = (origin.x() - x, origin.y() - y, origin.z() - z);
+                let b = ox * d.x() + oy * d.y() + oz * d.z();
+                let c = ox * ox + oy * oy + oz * oz - radius * radius;
+                let discriminant = b * b - a * c;
+                let root = (-b - discriminant.sqrt()) / a;
+                *t = if discriminant > 0.0 { root } else { f32::NAN };
+            }
+
+            for (i, &t) in ts[..end - start].iter().enumerate() {
+                if t < closest_so_far && t > t_min {
+                    closest_so_far = t;
+                    nearest = Some((start + i, t));
+                }
+            }
+        }
+
+        nearest
+    }
+
+    /// The hit on entry `index`, found by `hit` at `t` along the ray, reported as a hit on `object`.
+    pub fn hit_on<'a>(&self, index: usize, t: f32, r: &Ray, object: &'a dyn Hittable) -> Hit<'a> {
+        let center = Vec3::new(self.x[index], self.y[index], self.z[index]);
+        let p: Vec3 = r.point_at_parameter(t);
+        Hit { t, p, normal: (p - center) / self.radius[index], color: None, object }
+    }
+}
+
+impl Default for Spheres {
+    fn default() -> Spheres {
+        Spheres::new()
+    }
+}
@@ -0,0 +1,98 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use render::RenderResult;
+
+///
+/// Live statistics of a render in progress, shown in the preview
+/// window's title bar as results come in: rays traced per second,
+/// samples and tiles done, the time taken so far and an estimate of
+/// the time left.
+///
+pub struct RenderStats {
+    rays: u64,
+    samples: u64,
+    tiles_done: u32,
+    tiles: u32,
+    stopped: bool,
+}
+
+impl RenderStats {
+    /// Statistics for a render of `tiles` tiles, counting every pass.
+    pub fn new(tiles: u32) -> RenderStats {
+        RenderStats { rays: 0, samples: 0, tiles_done: 0, tiles, stopped: false }
+    }
+
+    /// The render has stopped, whether every tile is done or not.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    pub fn tile_done(&mut self, result: &RenderResult) {
+        self.rays += result.rays;
+        self.samples += result.data.iter().map(|p| p.count as u64).sum::<u64>();
+        self.tiles_done += 1;
+    }
+
+    ///
+    /// A one-line summary after `elapsed` ms of rendering, such as
+    /// "4.2M rays/s, 1.5M samples, 96/480 tiles, 0:03 elapsed, 0:12
+    /// left". Once the render stops there is nothing left to
+    /// estimate, and before any tile is done there is nothing to go
+    /// on.
+    ///
+    pub fn summary(&self, elapsed: u64) -> String {
+        let seconds = elapsed as f64 / 1000.0;
+        let rate = if seconds > 0.0 { self.rays as f64 / seconds } else { 0.0 };
+        let mut summary = format!("{} rays/s, {} samples, {}/{} tiles, {} elapsed", count(rate), count(self.samples as f64),
+                                  self.tiles_done, self.tiles, clock(seconds));
+
+        if !self.stopped && self.tiles_done > 0 && self.tiles_done < self.tiles {
+            let left = seconds * (self.tiles - self.tiles_done) as f64 / self.tiles_done as f64;
+            summary.push_str(&format!(", {} left", clock(left)));
+        }
+
+        summary
+    }
+}
+
+// A count with a metric suffix, to three significant figures or so
+fn count(n: f64) -> String {
+    if n >= 1e9 {
+        format!("{:.1}G", n / 1e9)
+    } else if n >= 1e6 {
+        format!("{:.1}M", n / 1e6)
+    } else if n >= 1e3 {
+        format!("{:.1}k", n / 1e3)
+    } else {
+        format!("{:.0}", n)
+    }
+}
+
+// Seconds as minutes and seconds, or hours, minutes and seconds
+fn clock(seconds: f64) -> String {
+    let s = seconds.round() as u64;
+    if s >= 3600 {
+        format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+    } else {
+        format!("{}:{:02}", s / 60, s % 60)
+    }
+}
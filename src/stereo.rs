@@ -0,0 +1,80 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use std::sync::Arc;
+
+use camera::Camera;
+use blue_noise::BlueNoiseMask;
+use hittable::World;
+use render::render_film;
+use settings::Settings;
+
+///
+/// How the two eyes of a stereo pair are arranged in one frame. Each
+/// eye gets a full `--width` by `--height` image, so a side-by-side
+/// frame is twice as wide and a top-bottom one twice as tall. The
+/// left eye goes on the left, or on top.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    SideBySide,
+    TopBottom,
+}
+
+impl StereoLayout {
+    pub fn from_name(name: &str) -> Option<StereoLayout> {
+        match name {
+            "side-by-side" => Some(StereoLayout::SideBySide),
+            "top-bottom" => Some(StereoLayout::TopBottom),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Render a stereo pair for a perspective camera, with the eyes
+/// `interocular` units apart and converging `convergence` units
+/// ahead, and arrange them in one frame. Returns the width, height,
+/// and 8-bit RGB pixels of the frame, top row first.
+///
+pub fn render_stereo(camera: &Camera, interocular: f32, convergence: f32, layout: StereoLayout,
+                     settings: Arc<Settings>, world: Arc<World>,
+                     mask: Option<Arc<BlueNoiseMask>>) -> (u32, u32, Vec<u8>) {
+    let eyes: Vec<Vec<u8>> = [-0.5, 0.5].iter().map(|&side| {
+        let eye = camera.stereo_eye(side * interocular, convergence);
+        render_film(settings.clone(), world.clone(), Arc::new(eye), mask.clone()).rgb8(&settings.post_chain())
+    }).collect();
+
+    let (width, height) = (settings.width, settings.height);
+
+    match layout {
+        StereoLayout::SideBySide => {
+            let row = width as usize * 3;
+            let mut data: Vec<u8> = Vec::with_capacity(eyes[0].len() * 2);
+
+            for (left, right) in eyes[0].chunks(row).zip(eyes[1].chunks(row)) {
+                data.extend_from_slice(left);
+                data.extend_from_slice(right);
+            }
+
+            (width * 2, height, data)
+        },
+        StereoLayout::TopBottom => (width, height * 2, eyes.concat()),
+    }
+}
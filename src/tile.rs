@@ -0,0 +1,192 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use rng::Pcg32;
+
+///
+/// A rectangle of the image, rendered as one piece of work: `width`
+/// by `height` pixels with its bottom left corner at (`x`, `y`),
+/// counting rows from the bottom like the film.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    ///
+    /// The tiles covering an image `width` by `height` pixels, in rows
+    /// from the top of the image down and from left to right along
+    /// each row. Tiles are `size` pixels square, except along the
+    /// right and bottom edges of the image, where they're cut short.
+    ///
+    pub fn cover(width: u32, height: u32, size: u32) -> Vec<Tile> {
+        let mut tiles: Vec<Tile> = Vec::new();
+        let mut top = height;
+
+        while top > 0 {
+            let y = top.saturating_sub(size);
+            for x in (0..width).step_by(size as usize) {
+                tiles.push(Tile { x, y, width: size.min(width - x), height: top - y });
+            }
+            top = y;
+        }
+
+        tiles
+    }
+
+    pub fn pixels(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    pub fn overlaps(&self, other: &Tile) -> bool {
+        self.x < other.x + other.width && other.x < self.x + self.width &&
+            self.y < other.y + other.height && other.y < self.y + self.height
+    }
+
+    /// Whether the tile reaches the right edge of an image `width` pixels across.
+    pub fn ends_row(&self, width: u32) -> bool {
+        self.x + self.width == width
+    }
+}
+
+///
+/// The order tiles are handed out in. Rows go from the top of the
+/// image down, as `Tile::cover` gives them; the others bring the
+/// middle of the image, where the eye goes first, in sooner while
+/// the preview window fills in.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileOrder {
+    Rows,
+    /// Out from the middle of the image, ring by ring.
+    Spiral,
+    /// Along a Hilbert curve, which keeps the tiles in flight close
+    /// together.
+    Hilbert,
+    /// Shuffled, so the whole image comes in evenly.
+    Random,
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "rows" => Some(TileOrder::Rows),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            "random" => Some(TileOrder::Random),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Put tiles from `Tile::cover` (or some of them) in this order.
+    /// The random order is the same every time for the same `seed`.
+    ///
+    pub fn arrange(self, tiles: &mut [Tile], seed: u32) {
+        let size = tiles.iter().map(|t| t.width.max(t.height)).max().unwrap_or(1);
+        let width = tiles.iter().map(|t| t.x + t.width).max().unwrap_or(0);
+        let height = tiles.iter().map(|t| t.y + t.height).max().unwrap_or(0);
+        // Tiles are numbered in columns from the left and rows from
+        // the top, where Tile::cover starts them
+        let cell = |t: &Tile| (t.x / size, (height - t.y - t.height) / size);
+
+        match self {
+            TileOrder::Rows => {},
+            TileOrder::Spiral => {
+                // Rings are squares of tiles around the middle, taken
+                // clockwise from the top left corner of each
+                let columns = width.div_ceil(size) as f32;
+                let rows = height.div_ceil(size) as f32;
+                let place = |t: &Tile| {
+                    let (column, row) = cell(t);
+                    let dx = column as f32 + 0.5 - columns / 2.0;
+                    let dy = row as f32 + 0.5 - rows / 2.0;
+                    let ring = dx.abs().max(dy.abs()).ceil();
+                    (ring, dy.atan2(dx))
+                };
+                tiles.sort_by(|a, b| {
+                    let (ring_a, angle_a) = place(a);
+                    let (ring_b, angle_b) = place(b);
+                    ring_a.partial_cmp(&ring_b).unwrap_or(Ordering::Equal)
+                        .then(angle_a.partial_cmp(&angle_b).unwrap_or(Ordering::Equal))
+                });
+            },
+            TileOrder::Hilbert => {
+                let columns = width.div_ceil(size);
+                let rows = height.div_ceil(size);
+                let side = columns.max(rows).next_power_of_two();
+                tiles.sort_by_key(|t| {
+                    let (column, row) = cell(t);
+                    hilbert_index(side, column, row)
+                });
+            },
+            TileOrder::Random => {
+                let mut rng = Pcg32::new(seed as u64, 0x7469_6c65);
+                for i in (1..tiles.len()).rev() {
+                    let j = ((rng.next_u32() as u64 * (i as u64 + 1)) >> 32) as usize;
+                    tiles.swap(i, j);
+                }
+            },
+        }
+    }
+}
+
+// How far along the Hilbert curve filling a grid `side` cells square
+// (a power of two) the cell at (`x`, `y`) is
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index: u64 = 0;
+    let mut s = side / 2;
+
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+
+        // Turn the quadrant so the curve through it joins up
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+
+    index
+}
+
+impl fmt::Display for Tile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{} at {}, {}", self.width, self.height, self.x, self.y)
+    }
+}
@@ -0,0 +1,99 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+///
+/// How the unbounded brightness of linear light is squeezed into the
+/// range a display can show, before the response curve encodes it.
+/// `Clamp` simply cuts everything brighter than white off, which
+/// keeps midtones exactly as rendered but burns out bright lights and
+/// their reflections; the others roll the highlights off gradually,
+/// at the cost of some contrast.
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    Aces,
+    Filmic,
+}
+
+// Brightness the extended Reinhard curve maps to white
+const REINHARD_WHITE: f32 = 4.0;
+
+// Krzysztof Narkowicz's fit of the ACES reference rendering transform,
+// and the exposure it expects
+const ACES_A: f32 = 2.51;
+const ACES_B: f32 = 0.03;
+const ACES_C: f32 = 2.43;
+const ACES_D: f32 = 0.59;
+const ACES_E: f32 = 0.14;
+const ACES_EXPOSURE: f32 = 0.6;
+
+// Shape of the filmic curve's toe and shoulder, and the exposure that
+// comes out white
+const FILMIC_SHOULDER: f32 = 0.15;
+const FILMIC_LINEAR: f32 = 0.5;
+const FILMIC_ANGLE: f32 = 0.1;
+const FILMIC_TOE: f32 = 0.2;
+const FILMIC_TOE_NUMERATOR: f32 = 0.02;
+const FILMIC_TOE_DENOMINATOR: f32 = 0.3;
+const FILMIC_WHITE: f32 = 11.2;
+const FILMIC_EXPOSURE: f32 = 2.0;
+
+impl ToneMap {
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        match name {
+            "clamp" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "aces" => Some(ToneMap::Aces),
+            "filmic" => Some(ToneMap::Filmic),
+            _ => None,
+        }
+    }
+
+    /// Displayable light, between 0 and 1, for one channel of linear light.
+    pub fn apply(self, x: f32) -> f32 {
+        let x = x.max(0.0);
+
+        let value = match self {
+            ToneMap::Clamp => x,
+            ToneMap::Reinhard => {
+                x * (1.0 + x / (REINHARD_WHITE * REINHARD_WHITE)) / (1.0 + x)
+            },
+            ToneMap::Aces => {
+                let x = ACES_EXPOSURE * x;
+                (x * (ACES_A * x + ACES_B)) / (x * (ACES_C * x + ACES_D) + ACES_E)
+            },
+            ToneMap::Filmic => {
+                // John Hable's curve, which rolls highlights off
+                // gently like a film print
+                filmic(FILMIC_EXPOSURE * x) / filmic(FILMIC_WHITE)
+            },
+        };
+
+        value.min(1.0)
+    }
+}
+
+fn filmic(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (FILMIC_SHOULDER, FILMIC_LINEAR, FILMIC_ANGLE, FILMIC_TOE,
+                              FILMIC_TOE_NUMERATOR, FILMIC_TOE_DENOMINATOR);
+
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
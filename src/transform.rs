@@ -0,0 +1,163 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+use aabb::Aabb;
+use hittable::*;
+use ray::Ray;
+use registry::BoxedObject;
+use vec3::Vec3;
+
+///
+/// A change of place, turn and size, as of a group of objects: a
+/// rotation, then a uniform scale, then an offset. Scaling the same
+/// way along every axis keeps spheres round and normals at right
+/// angles to their surfaces, so both carry straight over.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    // The rows of the rotation matrix
+    rotation: [Vec3; 3],
+    scale: f32,
+    offset: Vec3,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            rotation: [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)],
+            scale: 1.0,
+            offset: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn translate(offset: Vec3) -> Transform {
+        Transform { offset, ..Transform::identity() }
+    }
+
+    pub fn scale(scale: f32) -> Transform {
+        Transform { scale, ..Transform::identity() }
+    }
+
+    /// A turn of `degrees` about x, then about y, then about z.
+    pub fn rotate(degrees: Vec3) -> Transform {
+        let (sx, cx) = degrees.x().to_radians().sin_cos();
+        let (sy, cy) = degrees.y().to_radians().sin_cos();
+        let (sz, cz) = degrees.z().to_radians().sin_cos();
+
+        let x = [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, cx, -sx), Vec3::new(0.0, sx, cx)];
+        let y = [Vec3::new(cy, 0.0, sy), Vec3::new(0.0, 1.0, 0.0), Vec3::new(-sy, 0.0, cy)];
+        let z = [Vec3::new(cz, -sz, 0.0), Vec3::new(sz, cz, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+        Transform { rotation: multiply(&z, &multiply(&y, &x)), ..Transform::identity() }
+    }
+
+    /// This transform followed by `outer`, e.g. a group's inside the
+    /// group it's in.
+    pub fn then(&self, outer: &Transform) -> Transform {
+        Transform {
+            rotation: multiply(&outer.rotation, &self.rotation),
+            scale: outer.scale * self.scale,
+            offset: outer.point(&self.offset),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        let identity = Transform::identity();
+        self.scale == 1.0 && self.offset.e == identity.offset.e
+            && self.rotation.iter().zip(identity.rotation.iter()).all(|(a, b)| a.e == b.e)
+    }
+
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        self.offset + self.vector(p)
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        self.scale * rotate(&self.rotation, v)
+    }
+
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        rotate(&self.rotation, n)
+    }
+
+    fn inverse_point(&self, p: &Vec3) -> Vec3 {
+        self.inverse_vector(&(*p - self.offset))
+    }
+
+    // The rotation is orthonormal, so its transpose undoes it
+    fn inverse_vector(&self, v: &Vec3) -> Vec3 {
+        let r = &self.rotation;
+        (v.x() * r[0] + v.y() * r[1] + v.z() * r[2]) / self.scale
+    }
+}
+
+fn rotate(rows: &[Vec3; 3], v: &Vec3) -> Vec3 {
+    Vec3::new(Vec3::dot(&rows[0], v), Vec3::dot(&rows[1], v), Vec3::dot(&rows[2], v))
+}
+
+fn multiply(a: &[Vec3; 3], b: &[Vec3; 3]) -> [Vec3; 3] {
+    let column = |j: usize| Vec3::new(b[0].e[j], b[1].e[j], b[2].e[j]);
+    let row = |i: usize| Vec3::new(Vec3::dot(&a[i], &column(0)), Vec3::dot(&a[i], &column(1)),
+                                   Vec3::dot(&a[i], &column(2)));
+    [row(0), row(1), row(2)]
+}
+
+///
+/// An object placed by a Transform, e.g. one in a group. Rays are
+/// taken into the object's own space to hit it where it was built, as
+/// with Moved, so any shape can be placed without being built again.
+/// Distances along the ray are the same in both spaces, since the
+/// direction is transformed along with the origin.
+///
+pub struct Transformed {
+    pub object: BoxedObject,
+    pub transform: Transform,
+}
+
+impl Transformed {
+    pub fn new(object: BoxedObject, transform: Transform) -> Transformed {
+        Transformed { object, transform }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let local = Ray::new(self.transform.inverse_point(&r.origin()), self.transform.inverse_vector(&r.direction()));
+        let h = self.object.hit(&local, t_min, t_max)?;
+        Some(Hit { p: self.transform.point(&h.p), normal: self.transform.normal(&h.normal), ..h })
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.object.material()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let b = self.object.bounding_box()?;
+        let mut bounds = Aabb::empty();
+        for i in 0..8 {
+            let corner = Vec3::new(if i & 1 == 0 { b.min.x() } else { b.max.x() },
+                                   if i & 2 == 0 { b.min.y() } else { b.max.y() },
+                                   if i & 4 == 0 { b.min.z() } else { b.max.z() });
+            let p = self.transform.point(&corner);
+            bounds = bounds.union(&Aabb::new(p, p));
+        }
+        Some(bounds)
+    }
+
+    fn as_sphere(&self) -> Option<(Vec3, f32)> {
+        self.object.as_sphere().map(|(center, radius)| (self.transform.point(&center), self.transform.scale * radius))
+    }
+}
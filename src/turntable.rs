@@ -0,0 +1,64 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::f32::consts;
+
+use accelerator;
+use registry::BoxedObject;
+use vec3::Vec3;
+
+// How far above the objects the camera looks down from, in radians
+const ELEVATION: f32 = 20.0 * consts::PI / 180.0;
+
+// Room left around the objects, as a share of their size
+const MARGIN: f32 = 1.15;
+
+///
+/// A camera orbiting once around the objects of a scene, looking at
+/// the middle of their bounding sphere from just far enough away to
+/// keep them all in view. Very large objects, like a ground sphere,
+/// are left out of the bounds.
+///
+pub struct Turntable {
+    center: Vec3,
+    distance: f32,
+}
+
+impl Turntable {
+    /// An orbit for a camera with a vertical field of view of `vfov` degrees.
+    pub fn new(objects: &[BoxedObject], vfov: f32) -> Turntable {
+        let bounds = accelerator::subject_bounds(objects);
+        let (center, radius) = if bounds.is_empty() {
+            (Vec3::new(0.0, 0.0, -1.0), 1.0)
+        } else {
+            (bounds.centroid(), (0.5 * bounds.diagonal()).max(0.1))
+        };
+
+        let half_angle = 0.5 * vfov * consts::PI / 180.0;
+        Turntable { center, distance: MARGIN * radius / half_angle.sin() }
+    }
+
+    /// Where the camera looks from and at in `frame` of `frames`.
+    pub fn at(&self, frame: u32, frames: u32) -> (Vec3, Vec3) {
+        let angle = 2.0 * consts::PI * frame as f32 / frames as f32;
+        let offset = Vec3::new(ELEVATION.cos() * angle.sin(), ELEVATION.sin(), ELEVATION.cos() * angle.cos());
+        (self.center + self.distance * offset, self.center)
+    }
+}
@@ -0,0 +1,91 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+// Extensions of the video files ffmpeg is asked to make
+const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "mov", "mkv", "webm"];
+
+/// True if `path` names a video file rather than an image.
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| VIDEO_EXTENSIONS.contains(&e.as_str()))
+}
+
+///
+/// A video being encoded by an ffmpeg child process, which is handed
+/// each frame as raw 8-bit RGB as soon as it's finished. The codec
+/// follows the file's extension: VP9 for `.webm`, and H.264 for the
+/// rest, which plays almost anywhere.
+///
+pub struct Video {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl Video {
+    pub fn start(path: &Path, width: u32, height: u32, fps: f32) -> io::Result<Video> {
+        let codec: &[&str] = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("webm")) {
+            &["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "30"]
+        } else {
+            &["-c:v", "libx264", "-crf", "18"]
+        };
+
+        let mut child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .args(["-s", &format!("{}x{}", width, height), "-r", &fps.to_string(), "-i", "-"])
+            .args(codec)
+            // Chroma subsampling needs an even width and height
+            .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2", "-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => io::Error::new(e.kind(), "ffmpeg wasn't found; install it to save videos"),
+                _ => e,
+            })?;
+
+        let stdin = child.stdin.take();
+        Ok(Video { child, stdin })
+    }
+
+    /// Add a frame of 8-bit RGB, top row first.
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        match self.stdin {
+            Some(ref mut stdin) => stdin.write_all(rgb),
+            None => Err(io::Error::other("the video is already finished")),
+        }
+    }
+
+    /// Wait for ffmpeg to encode the last frames and finish the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ffmpeg failed ({})", status)))
+        }
+    }
+}
@@ -0,0 +1,98 @@
+///
+/// This file is part of The Rust Raytracer.
+///
+/// The Rust Raytracer is free software: you can redistribute it
+/// and/or modify it under the terms of the GNU General Public License
+/// as published by the Free Software Foundation, either version 3 of
+/// the License, or (at your option) any later version.
+///
+/// The Rust Raytracer is distributed in the hope that it will be
+/// useful, but WITHOUT ANY WARRANTY; without even the implied
+/// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+/// See the GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with The Rust Raytracer. If not, see
+/// <https://www.gnu.org/licenses/>.
+///
+
+
+use aabb::Aabb;
+use hittable::*;
+use ray::Ray;
+use registry::BoxedObject;
+use sampler::{hash_combine, to_unit_float};
+
+///
+/// An object that rays only see between `near` and `far` units from
+/// where they start, and pass straight through anywhere else. Camera
+/// rays start at the camera, so this can swap objects for simpler
+/// ones in the distance; rays bouncing off other surfaces start
+/// there, so an object can be kept out of the reflections of things
+/// close by.
+///
+/// With a `fade` distance, the object doesn't vanish all at once at
+/// the edges of its range but thins out over that distance, like a
+/// screen door: each ray passes through with a chance that grows
+/// towards the edge, so the object fades out as samples add up.
+///
+pub struct VisibilityRange {
+    pub object: BoxedObject,
+    pub near: f32,
+    pub far: f32,
+    pub fade: f32,
+}
+
+impl VisibilityRange {
+    pub fn new(object: BoxedObject, near: f32, far: f32, fade: f32) -> VisibilityRange {
+        VisibilityRange { object, near, far, fade }
+    }
+
+    // How solid the object is at `distance` along a ray, from 0 to 1
+    fn opacity(&self, distance: f32) -> f32 {
+        if self.fade <= 0.0 {
+            return 1.0;
+        }
+
+        let from_far = (self.far - distance) / self.fade;
+        let from_near = if self.near > 0.0 { (distance - self.near) / self.fade } else { 1.0 };
+        from_near.min(from_far).min(1.0)
+    }
+}
+
+// A number in [0, 1) that is the same every time for the same ray,
+// but different for every sample, to decide whether a ray passes
+// through a fading object
+fn dither(r: &Ray) -> f32 {
+    let (o, d) = (r.origin(), r.direction());
+    let bits = [o.x(), o.y(), o.z(), d.x(), d.y(), d.z()].iter()
+        .fold(0, |seed, v| hash_combine(seed, v.to_bits()));
+    to_unit_float(bits)
+}
+
+impl Hittable for VisibilityRange {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let length = r.direction().length();
+        let near = t_min.max(self.near / length);
+        let far = t_max.min(self.far / length);
+        if near >= far {
+            return None;
+        }
+
+        let h = self.object.hit(r, near, far)?;
+        let opacity = self.opacity(h.t * length);
+        if opacity < 1.0 && dither(r) >= opacity {
+            return None;
+        }
+
+        Some(h)
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.object.material()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+}